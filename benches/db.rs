@@ -0,0 +1,97 @@
+//! Benchmarks of the write and read paths of the database, without the HTTP layer in front of them (which
+//! `src/bin/loadgen.rs` measures against a running server).
+//!
+//! ```text
+//! $ cargo bench --bench db
+//! ```
+
+use anchor_test::{
+    db::{Db, IdScheme, SheetId},
+    sheet::{Cell, CellValue, ReadOptions, Schema},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+const SCHEMA: &str =
+    r#"{"columns": [{"name": "values", "type": "int"}, {"name": "chain", "type": "int"}]}"#;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+async fn new_sheet() -> (Db, SheetId) {
+    let db = Db::new_memory().await.unwrap();
+    let schema: Schema = serde_json::from_str(SCHEMA).unwrap();
+    let sheetid = db
+        .new_sheet(&schema, IdScheme::default(), None, None)
+        .await
+        .unwrap();
+    (db, sheetid)
+}
+
+fn cells(column: &str, count: i64, value: impl Fn(i64) -> CellValue) -> Vec<Cell> {
+    (0..count)
+        .map(|row| Cell {
+            column: column.into(),
+            row,
+            value: value(row),
+        })
+        .collect()
+}
+
+/// Cells/sec for writing cells one at a time and in batches.
+fn writes(c: &mut Criterion) {
+    let rt = runtime();
+    let (db, sheetid) = rt.block_on(new_sheet());
+
+    let mut group = c.benchmark_group("writes");
+    for count in [100, 1000] {
+        let cells = cells("values", count, CellValue::Int);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("single", count), &cells, |b, cells| {
+            b.to_async(&rt).iter(|| async {
+                for cell in cells {
+                    db.insert_cell(&sheetid, cell, false).await.unwrap();
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("batch", count), &cells, |b, cells| {
+            b.to_async(&rt)
+                .iter(|| async { db.insert_cells(&sheetid, cells, false).await.unwrap() })
+        });
+    }
+    group.finish();
+}
+
+/// How long reading a sheet takes when it's a single lookup chain, each cell pointing at the one before it.
+fn lookup_chains(c: &mut Criterion) {
+    let rt = runtime();
+
+    let mut group = c.benchmark_group("lookup_chains");
+    for length in [10, 100, 1000] {
+        let (db, sheetid) = rt.block_on(async {
+            let (db, sheetid) = new_sheet().await;
+            let chain = cells("chain", length, |row| match row {
+                0 => CellValue::Int(1),
+                row => CellValue::String(format!(r#"lookup("chain", {})"#, row - 1)),
+            });
+            db.insert_cells(&sheetid, &chain, false).await.unwrap();
+            (db, sheetid)
+        });
+
+        group.bench_function(BenchmarkId::from_parameter(length), |b| {
+            b.to_async(&rt).iter(|| async {
+                db.get_sheet(&sheetid, ReadOptions::default())
+                    .await
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, writes, lookup_chains);
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+#![no_main]
+
+use anchor_test::sheet::Cell;
+use libfuzzer_sys::fuzz_target;
+
+// the body of `POST /sheet/:sheetid`
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cell) = serde_json::from_slice::<Cell>(data) {
+        let _ = cell.value.is_lookup();
+        let _ = cell.value.unescaped();
+    }
+});
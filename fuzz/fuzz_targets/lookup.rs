@@ -0,0 +1,15 @@
+#![no_main]
+
+use anchor_test::sheet::CellValue;
+use libfuzzer_sys::fuzz_target;
+
+// lookup detection runs on every string value that's written to a sheet
+fuzz_target!(|data: &str| {
+    let value = CellValue::String(data.into());
+    if let Some(lookup) = value.is_lookup() {
+        assert!(data.starts_with("lookup("));
+        assert!(!lookup.target_col.is_empty());
+        assert!(lookup.target_row >= 0);
+    }
+    let _ = value.unescaped();
+});
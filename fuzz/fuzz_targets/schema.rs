@@ -0,0 +1,13 @@
+#![no_main]
+
+use anchor_test::sheet::Schema;
+use libfuzzer_sys::fuzz_target;
+
+// the body of `POST /sheet`, followed by the same checks the handler does
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut schema) = serde_json::from_slice::<Schema>(data) {
+        schema.normalize();
+        let _ = schema.validate(false);
+        let _ = schema.validate(true);
+    }
+});
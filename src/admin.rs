@@ -0,0 +1,661 @@
+//! Operational endpoints, which are protected by `ADMIN_TOKEN`, and closed entirely when it isn't set.
+
+use actix_files::NamedFile;
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse, Responder};
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_readonly)
+        .service(post_readonly)
+        .service(post_maintenance)
+        .service(post_check)
+        .service(get_stats)
+        .service(get_snapshot)
+        .service(get_archives)
+        .service(post_rehydrate);
+}
+
+/// Checks the request's bearer token against the configured admin token. Without one, every request is rejected,
+/// since the admin endpoints can read and change every sheet.
+fn is_authorized(req: &HttpRequest, data: &crate::AppData) -> bool {
+    let Some(expected) = &data.config.admin_token else {
+        return false;
+    };
+    let Some(token) = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    // the MACs are compared in constant time, so that how long the comparison takes doesn't leak the token
+    let mac = |token: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(expected.as_bytes())
+            .expect("hmac accepts keys of any length");
+        mac.update(token.as_bytes());
+        mac
+    };
+    mac(expected)
+        .verify_slice(&mac(token).finalize().into_bytes())
+        .is_ok()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum ReadOnlyResponse {
+    Success { read_only: bool },
+    Failure { error: String },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct SetReadOnly {
+    enabled: bool,
+}
+
+fn unauthorized() -> actix_web::CustomizeResponder<web::Json<ReadOnlyResponse>> {
+    web::Json(ReadOnlyResponse::Failure {
+        error: "missing or invalid admin token".into(),
+    })
+    .customize()
+    .with_status(StatusCode::UNAUTHORIZED)
+}
+
+#[get("/readonly")]
+async fn get_readonly(req: HttpRequest, data: web::Data<crate::AppData>) -> impl Responder {
+    if !is_authorized(&req, &data) {
+        return unauthorized();
+    }
+
+    web::Json(ReadOnlyResponse::Success {
+        read_only: data.is_read_only(),
+    })
+    .customize()
+}
+
+#[post("/readonly")]
+async fn post_readonly(
+    req: HttpRequest,
+    data: web::Data<crate::AppData>,
+    body: Option<web::Json<SetReadOnly>>,
+) -> impl Responder {
+    if !is_authorized(&req, &data) {
+        return unauthorized();
+    }
+
+    let Some(body) = body else {
+        return web::Json(ReadOnlyResponse::Failure {
+            error: "invalid request body".into(),
+        })
+        .customize()
+        .with_status(StatusCode::BAD_REQUEST);
+    };
+
+    data.set_read_only(body.enabled);
+    log::info!("read-only mode {}", if body.enabled { "enabled" } else { "disabled" });
+
+    web::Json(ReadOnlyResponse::Success {
+        read_only: body.enabled,
+    })
+    .customize()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum MaintenanceResponse {
+    Success(crate::db::MaintenanceReport),
+    Failure { error: String },
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct MaintenanceOptions {
+    #[serde(default)]
+    incremental: bool,
+}
+
+#[post("/maintenance")]
+async fn post_maintenance(
+    req: HttpRequest,
+    data: web::Data<crate::AppData>,
+    options: Option<web::Json<MaintenanceOptions>>,
+) -> impl Responder {
+    if !is_authorized(&req, &data) {
+        return web::Json(MaintenanceResponse::Failure {
+            error: "missing or invalid admin token".into(),
+        })
+        .customize()
+        .with_status(StatusCode::UNAUTHORIZED);
+    }
+
+    // an empty body is fine, and means a full run
+    let options = options.map(web::Json::into_inner).unwrap_or_default();
+    match data.db.maintenance(options.incremental).await {
+        Ok(report) => {
+            log::info!("maintenance reclaimed {} bytes", report.reclaimed);
+            web::Json(MaintenanceResponse::Success(report)).customize()
+        }
+        Err(why) => {
+            log::error!("maintenance failed: {why}");
+            web::Json(MaintenanceResponse::Failure {
+                error: why.to_string(),
+            })
+            .customize()
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum CheckResponse {
+    Success(crate::db::ConsistencyReport),
+    Failure { error: String },
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct CheckOptions {
+    #[serde(default)]
+    repair: bool,
+}
+
+#[post("/check")]
+async fn post_check(
+    req: HttpRequest,
+    data: web::Data<crate::AppData>,
+    options: Option<web::Json<CheckOptions>>,
+) -> impl Responder {
+    if !is_authorized(&req, &data) {
+        return web::Json(CheckResponse::Failure {
+            error: "missing or invalid admin token".into(),
+        })
+        .customize()
+        .with_status(StatusCode::UNAUTHORIZED);
+    }
+
+    // an empty body is fine, and means only checking
+    let options = options.map(web::Json::into_inner).unwrap_or_default();
+    match data.db.check_consistency(options.repair).await {
+        Ok(report) => {
+            if !report.violations.is_empty() {
+                log::warn!(
+                    "consistency check found {} violations{}",
+                    report.violations.len(),
+                    if report.repaired {
+                        ", which were repaired"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            web::Json(CheckResponse::Success(report)).customize()
+        }
+        Err(why) => {
+            log::error!("consistency check failed: {why}");
+            web::Json(CheckResponse::Failure {
+                error: why.to_string(),
+            })
+            .customize()
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum StatsResponse {
+    Success {
+        sheets: Vec<crate::traffic::TrafficEntry>,
+    },
+    Failure {
+        error: String,
+    },
+}
+
+/// The number of sheets listed when no limit is given.
+const DEFAULT_STATS_LIMIT: i64 = 20;
+const MAX_STATS_LIMIT: i64 = 1000;
+
+#[derive(Deserialize, Clone, Debug)]
+struct StatsQuery {
+    #[serde(default)]
+    by: crate::traffic::TrafficOrder,
+    limit: Option<i64>,
+}
+
+#[get("/stats")]
+async fn get_stats(
+    req: HttpRequest,
+    data: web::Data<crate::AppData>,
+    query: Result<web::Query<StatsQuery>, actix_web::Error>,
+) -> impl Responder {
+    if !is_authorized(&req, &data) {
+        return web::Json(StatsResponse::Failure {
+            error: "missing or invalid admin token".into(),
+        })
+        .customize()
+        .with_status(StatusCode::UNAUTHORIZED);
+    }
+
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => {
+            return web::Json(StatsResponse::Failure {
+                error: format!("invalid query: {why}"),
+            })
+            .customize()
+            .with_status(StatusCode::BAD_REQUEST)
+        }
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_STATS_LIMIT)
+        .clamp(1, MAX_STATS_LIMIT);
+
+    // the statistics should include everything up to this request, rather than up to the last scheduled flush
+    let result = match data.traffic.flush(&data.db).await {
+        Ok(()) => data.db.traffic(query.by, limit).await,
+        Err(why) => Err(why),
+    };
+    match result {
+        Ok(sheets) => web::Json(StatsResponse::Success { sheets }).customize(),
+        Err(why) => {
+            log::error!("reading traffic statistics failed: {why}");
+            web::Json(StatsResponse::Failure {
+                error: why.to_string(),
+            })
+            .customize()
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct SnapshotFailure {
+    error: String,
+}
+
+/// Responds with a fresh snapshot of the whole database, e.g. for seeding a standby.
+#[get("/snapshot")]
+async fn get_snapshot(req: HttpRequest, data: web::Data<crate::AppData>) -> HttpResponse {
+    if !is_authorized(&req, &data) {
+        return HttpResponse::Unauthorized().json(SnapshotFailure {
+            error: "missing or invalid admin token".into(),
+        });
+    }
+
+    let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+    let path = std::env::temp_dir().join(format!("anchor-snapshot-{name}.sqlite"));
+    let result = match data.db.snapshot(&path).await {
+        Ok(_) => NamedFile::open_async(&path)
+            .await
+            .map_err(anyhow::Error::from),
+        Err(why) => Err(why),
+    };
+    // the open file keeps streaming after it's unlinked, so the snapshot never outlives the response
+    let _ = std::fs::remove_file(&path);
+
+    match result {
+        Ok(snapshot) => snapshot
+            .set_content_type("application/vnd.sqlite3".parse().unwrap())
+            .disable_content_disposition()
+            .into_response(&req),
+        Err(why) => {
+            log::error!("taking a snapshot failed: {why}");
+            HttpResponse::InternalServerError().json(SnapshotFailure {
+                error: why.to_string(),
+            })
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum ArchivesResponse {
+    Success {
+        sheets: Vec<crate::archive::ArchivedSheet>,
+    },
+    Failure {
+        error: String,
+    },
+}
+
+/// Lists the sheets which were moved to cold storage through `POST /sheet/:sheetid/archive`.
+#[get("/archives")]
+async fn get_archives(req: HttpRequest, data: web::Data<crate::AppData>) -> impl Responder {
+    if !is_authorized(&req, &data) {
+        return web::Json(ArchivesResponse::Failure {
+            error: "missing or invalid admin token".into(),
+        })
+        .customize()
+        .with_status(StatusCode::UNAUTHORIZED);
+    }
+
+    match data.db.archives().await {
+        Ok(sheets) => web::Json(ArchivesResponse::Success { sheets }).customize(),
+        Err(why) => {
+            log::error!("listing archived sheets failed: {why}");
+            web::Json(ArchivesResponse::Failure {
+                error: why.to_string(),
+            })
+            .customize()
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum RehydrateResponse {
+    Success { sheet_id: String },
+    Failure { error: String },
+}
+
+/// Brings an archived sheet back to life under its id.
+#[post("/archives/{id}/rehydrate")]
+async fn post_rehydrate(
+    req: HttpRequest,
+    data: web::Data<crate::AppData>,
+    id: web::Path<String>,
+) -> impl Responder {
+    if !is_authorized(&req, &data) {
+        return web::Json(RehydrateResponse::Failure {
+            error: "missing or invalid admin token".into(),
+        })
+        .customize()
+        .with_status(StatusCode::UNAUTHORIZED);
+    }
+
+    let result = match crate::db::SheetId::try_from(id.as_str()) {
+        Ok(sheetid) => data.db.rehydrate(&sheetid).await,
+        Err(_) => Err(crate::db::SheetNotFound.into()),
+    };
+    match result {
+        Ok(()) => web::Json(RehydrateResponse::Success {
+            sheet_id: id.into_inner(),
+        })
+        .customize(),
+        Err(why) => {
+            let status = crate::sheet::web::error_status(&why);
+            if status.is_server_error() {
+                log::error!("rehydrating {id} failed: {why}");
+            }
+            web::Json(RehydrateResponse::Failure {
+                error: why.to_string(),
+            })
+            .customize()
+            .with_status(status)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header::ContentType, StatusCode};
+    use actix_web::test;
+
+    use crate::config::AppConfig;
+    use crate::sheet::tests::VALID_POST_PAYLOAD;
+
+    const ADMIN_TOKEN: &str = "hunter2";
+
+    fn admin_config() -> AppConfig {
+        AppConfig {
+            admin_token: Some(ADMIN_TOKEN.into()),
+            ..Default::default()
+        }
+    }
+
+    fn admin(req: test::TestRequest) -> test::TestRequest {
+        req.insert_header(("Authorization", format!("Bearer {ADMIN_TOKEN}")))
+    }
+
+    fn post_sheet() -> actix_http::Request {
+        test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(VALID_POST_PAYLOAD)
+            .insert_header(ContentType::json())
+            .to_request()
+    }
+
+    fn set_read_only(enabled: bool, token: Option<&str>) -> actix_http::Request {
+        let mut req = test::TestRequest::post()
+            .uri("/admin/readonly")
+            .set_payload(format!(r#"{{"enabled": {enabled}}}"#))
+            .insert_header(ContentType::json());
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {token}")));
+        }
+        req.to_request()
+    }
+
+    #[actix_web::test]
+    async fn read_only_rejects_writes() {
+        let app = init_service!(AppConfig {
+            read_only: true,
+            ..Default::default()
+        });
+
+        let resp = test::call_service(&app, post_sheet()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["error"].is_string());
+
+        // reads still go through to the handlers
+        let req = test::TestRequest::get()
+            .uri("/sheet/aaaaaaaaaaaaaaaaaaaaaaaa")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn replicas_reject_or_redirect_writes() {
+        let app = init_service!(AppConfig {
+            replica: true,
+            ..admin_config()
+        });
+
+        let resp = test::call_service(&app, post_sheet()).await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let resp = test::call_service(&app, set_read_only(false, None)).await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let req = admin(test::TestRequest::get())
+            .uri("/admin/readonly")
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let app = init_service!(AppConfig {
+            replica: true,
+            primary_url: Some("http://primary:8080/".into()),
+            ..Default::default()
+        });
+        let req = test::TestRequest::post()
+            .uri("/sheet/aaaaaaaaaaaaaaaaaaaaaaaa?dry_run=1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            resp.headers().get("Location").unwrap(),
+            "http://primary:8080/sheet/aaaaaaaaaaaaaaaaaaaaaaaa?dry_run=1"
+        );
+    }
+
+    #[actix_web::test]
+    async fn read_only_can_be_toggled() {
+        let app = init_service!(admin_config());
+
+        let resp = test::call_service(&app, set_read_only(true, Some(ADMIN_TOKEN))).await;
+        assert!(resp.status().is_success());
+        let resp = test::call_service(&app, post_sheet()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let req = admin(test::TestRequest::get())
+            .uri("/admin/readonly")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["read_only"], true);
+
+        let resp = test::call_service(&app, set_read_only(false, Some(ADMIN_TOKEN))).await;
+        assert!(resp.status().is_success());
+        let resp = test::call_service(&app, post_sheet()).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn maintenance_reports_sizes() {
+        let app = init_service!(admin_config());
+
+        let resp = test::call_service(&app, post_sheet()).await;
+        assert!(resp.status().is_success());
+
+        for body in ["", r#"{"incremental": true}"#] {
+            let req = admin(test::TestRequest::post())
+                .uri("/admin/maintenance")
+                .set_payload(body)
+                .insert_header(ContentType::json())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+
+            let report: crate::db::MaintenanceReport = test::read_body_json(resp).await;
+            assert_eq!(report.incremental, !body.is_empty());
+            assert!(report.size_before > 0);
+            assert_eq!(report.reclaimed, report.size_before.saturating_sub(report.size_after));
+        }
+    }
+
+    #[actix_web::test]
+    async fn check_passes_on_healthy_sheets() {
+        let app = init_service!(admin_config());
+
+        let resp = test::call_service(&app, post_sheet()).await;
+        assert!(resp.status().is_success());
+
+        let req = admin(test::TestRequest::post())
+            .uri("/admin/check")
+            .set_payload(r#"{"repair": true}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        let report: crate::db::ConsistencyReport = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(report.sheets_checked, 1);
+        assert!(report.violations.is_empty());
+        assert!(!report.repaired);
+    }
+
+    #[actix_web::test]
+    async fn stats_rank_sheets_by_traffic() {
+        let app = init_service!(admin_config());
+
+        let mut ids = vec![];
+        for _ in 0..2 {
+            let resp = test::call_service(&app, post_sheet()).await;
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            ids.push(body["sheet_id"].as_str().unwrap().to_string());
+        }
+        for _ in 0..3 {
+            let req = test::TestRequest::get()
+                .uri(&format!("/sheet/{}", ids[1]))
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+        // sheets which don't exist aren't counted
+        let req = test::TestRequest::get()
+            .uri("/sheet/aaaaaaaaaaaaaaaaaaaaaaaa")
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let req = admin(test::TestRequest::get())
+            .uri("/admin/stats")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let sheets = body["sheets"].as_array().unwrap();
+        assert_eq!(sheets.len(), 1);
+        assert_eq!(sheets[0]["sheet_id"], ids[1]);
+        assert_eq!(sheets[0]["reads"], 3);
+        assert_eq!(sheets[0]["writes"], 0);
+        assert!(sheets[0]["bytes_served"].as_u64().unwrap() > 0);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{}", ids[0]))
+            .set_payload(r#"{"column": "A", "row": 1, "value": true}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        // counts are kept across flushes
+        let req = admin(test::TestRequest::get())
+            .uri("/admin/stats?by=requests&limit=1")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["sheets"].as_array().unwrap().len(), 1);
+        assert_eq!(body["sheets"][0]["reads"], 3);
+
+        let req = admin(test::TestRequest::get())
+            .uri("/admin/stats?by=nothing")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn snapshots_contain_the_database() {
+        let dir = std::env::temp_dir().join(format!("anchor-snapshot-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = crate::db::Db::new(dir.join("data.sqlite").to_str().unwrap())
+            .await
+            .unwrap();
+        let app = init_service!(data: crate::AppData::new(db, admin_config()));
+
+        let resp = test::call_service(&app, post_sheet()).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let sheet_id = body["sheet_id"].as_str().unwrap();
+
+        let req = admin(test::TestRequest::get())
+            .uri("/admin/snapshot")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let snapshot = test::read_body(resp).await;
+
+        let path = dir.join("snapshot.sqlite");
+        std::fs::write(&path, snapshot).unwrap();
+        let db = crate::db::Db::new(path.to_str().unwrap()).await.unwrap();
+        let id = crate::db::SheetId::try_from(sheet_id).unwrap();
+        let result = db.get_sheet(&id, Default::default()).await;
+        db.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_ok());
+
+        // in-memory databases can't be copied into a file
+        let app = init_service!(admin_config());
+        let req = admin(test::TestRequest::get())
+            .uri("/admin/snapshot")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_web::test]
+    async fn admin_token_is_required() {
+        // without a token, nobody can use the admin endpoints
+        let app = init_service!();
+        let resp = test::call_service(&app, set_read_only(true, None)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let req = test::TestRequest::get().uri("/admin/snapshot").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        let app = init_service!(admin_config());
+
+        let resp = test::call_service(&app, set_read_only(true, None)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = test::call_service(&app, set_read_only(true, Some("hunter"))).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&app, set_read_only(true, Some("hunter2"))).await;
+        assert!(resp.status().is_success());
+    }
+}
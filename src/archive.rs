@@ -0,0 +1,102 @@
+//! The format sheets are archived in (see [`crate::db::Db::archive`]). An archive is a bundle of every row stored
+//! about a sheet, from its own tables and from the tables shared by all sheets, which is serialized as JSON and
+//! compressed with zstd. Rows are kept as sqlite stored them, so that rehydrating a sheet brings it back exactly
+//! as it was.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// An entry in the listing of archived sheets. Timestamps are in milliseconds since the unix epoch.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedSheet {
+    pub id: String,
+    pub archived_at: i64,
+    /// The number of populated cells the sheet had, including lookups.
+    pub cells: i64,
+    /// The size of the compressed bundle, in bytes.
+    pub size: i64,
+}
+
+/// Everything stored about a sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Bundle {
+    /// The database version the bundle was made in. Shared tables only ever gain columns with defaults, and the
+    /// sheet's own tables are migrated when it's rehydrated, so bundles of older versions can still be rehydrated.
+    pub version: i64,
+    /// The statements which create the sheet's own tables, followed by those which create their indexes.
+    pub schema: Vec<String>,
+    pub tables: Vec<BundleTable>,
+}
+
+/// The rows of a single table which belong to the sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BundleTable {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<BundleValue>>,
+}
+
+/// A value as sqlite stores it, which isn't necessarily the type of its column.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleValue {
+    Null,
+    Integer(i64),
+    /// Kept as text, since parsing JSON numbers doesn't always give back the exact same double.
+    Real(#[serde(with = "real")] f64),
+    Text(String),
+}
+
+mod real {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+impl Bundle {
+    /// Serializes and compresses the bundle.
+    pub fn compress(&self) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec(self)?;
+        Ok(zstd::encode_all(json.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)?)
+    }
+
+    /// Reverses [`Self::compress`].
+    pub fn decompress(compressed: &[u8]) -> Result<Self> {
+        let json = zstd::decode_all(compressed)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bundle, BundleTable, BundleValue};
+
+    #[test]
+    fn bundles_survive_compression() {
+        let bundle = Bundle {
+            version: 1,
+            schema: vec!["CREATE TABLE sheet_x (row INTEGER PRIMARY KEY);".into()],
+            tables: vec![BundleTable {
+                name: "sheet_x".into(),
+                columns: vec!["row".into(), "col0".into()],
+                rows: vec![
+                    vec![BundleValue::Integer(1), BundleValue::Real(0.1 + 0.2)],
+                    vec![BundleValue::Integer(i64::MIN), BundleValue::Real(f64::MAX)],
+                    vec![BundleValue::Integer(2), BundleValue::Text("1.5".into())],
+                    vec![BundleValue::Integer(3), BundleValue::Null],
+                ],
+            }],
+        };
+        let compressed = bundle.compress().unwrap();
+        assert_eq!(Bundle::decompress(&compressed).unwrap(), bundle);
+        assert!(Bundle::decompress(b"not zstd").is_err());
+    }
+}
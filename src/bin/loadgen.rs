@@ -0,0 +1,159 @@
+//! A small load generator for a running server, measuring the write and read paths.
+//!
+//! ```text
+//! $ cargo run --release --bin loadgen -- [address] [cells] [chain length] [threads]
+//! ```
+//!
+//! It creates a fresh sheet, writes `cells` plain cells from `threads` threads and reports cells/sec,
+//! then builds a lookup chain of the given length and reports how long resolving the sheet takes.
+//! This intentionally only uses the standard library, so that it doesn't pull anything into the server.
+
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+const SCHEMA: &str =
+    r#"{"columns": [{"name": "values", "type": "int"}, {"name": "chain", "type": "int"}]}"#;
+const GET_ITERATIONS: u32 = 20;
+
+/// A minimal HTTP/1.1 client over a single keep-alive connection.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    address: String,
+}
+
+impl Connection {
+    fn open(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+            address: address.into(),
+        })
+    }
+
+    /// Sends a request and returns the status code and the response body.
+    fn request(&mut self, method: &str, path: &str, body: &str) -> io::Result<(u16, String)> {
+        write!(
+            self.reader.get_mut(),
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            self.address,
+            body.len()
+        )?;
+
+        let mut status_line = String::new();
+        self.reader.read_line(&mut status_line)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad status line"))?;
+
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        self.reader.read_exact(&mut body)?;
+        Ok((status, String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    fn set_cell(&mut self, sheet_id: &str, column: &str, row: i64, value: &str) -> io::Result<()> {
+        let body = format!(r#"{{"column": "{column}", "row": {row}, "value": {value}}}"#);
+        let (status, response) = self.request("POST", &format!("/sheet/{sheet_id}"), &body)?;
+        if status != 200 {
+            eprintln!("setting {column}{row} failed ({status}): {response}");
+        }
+        Ok(())
+    }
+}
+
+fn arg<T: std::str::FromStr>(index: usize, default: T) -> T {
+    env::args()
+        .nth(index)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(default)
+}
+
+fn main() -> io::Result<()> {
+    let address: String = arg(1, "localhost:8080".into());
+    let cells: i64 = arg(2, 10_000);
+    let chain: i64 = arg(3, 200);
+    let threads: i64 = arg(4, 8).max(1);
+
+    let mut conn = Connection::open(&address)?;
+    let (status, response) = conn.request("POST", "/sheet", SCHEMA)?;
+    // the response is `{"sheet_id":"..."}`, which isn't worth pulling in a json parser for
+    let Some(sheet_id) = response
+        .split('"')
+        .nth(3)
+        .filter(|_| status == 200)
+        .map(String::from)
+    else {
+        eprintln!("failed to create a sheet ({status}): {response}");
+        std::process::exit(1);
+    };
+    println!("created sheet {sheet_id}");
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..threads)
+        .map(|t| {
+            let (address, sheet_id) = (address.clone(), sheet_id.clone());
+            thread::spawn(move || -> io::Result<()> {
+                let mut conn = Connection::open(&address)?;
+                for row in (t..cells).step_by(threads as usize) {
+                    conn.set_cell(&sheet_id, "values", row, &row.to_string())?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("worker panicked")?;
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "wrote {cells} cells with {threads} threads in {elapsed:?} ({:.0} cells/sec)",
+        cells as f64 / elapsed.as_secs_f64()
+    );
+
+    // every cell in the chain points at the one before it, so resolving the last one walks the whole chain
+    let start = Instant::now();
+    conn.set_cell(&sheet_id, "chain", 0, "1")?;
+    for row in 1..chain {
+        let lookup = format!(r#""lookup(\"chain\",{})""#, row - 1);
+        conn.set_cell(&sheet_id, "chain", row, &lookup)?;
+    }
+    println!("built a lookup chain of length {chain} in {:?}", start.elapsed());
+
+    let mut total = Duration::ZERO;
+    for _ in 0..GET_ITERATIONS {
+        let start = Instant::now();
+        let (status, _) = conn.request("GET", &format!("/sheet/{sheet_id}"), "")?;
+        total += start.elapsed();
+        if status != 200 {
+            eprintln!("getting the sheet failed ({status})");
+        }
+    }
+    println!(
+        "resolved the sheet in {:?} on average ({GET_ITERATIONS} iterations)",
+        total / GET_ITERATIONS
+    );
+
+    Ok(())
+}
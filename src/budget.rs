@@ -0,0 +1,138 @@
+//! A limit on the memory taken by reading whole sheets, so that a few giant sheets can't exhaust the memory of the
+//! server.
+//!
+//! Reading a sheet reserves the (approximate) size of every cell as it's read from the database, and the
+//! reservation is released once the content has been handed over to be sent. A single sheet which doesn't fit
+//! into the budget on its own can never be read at once ([`BudgetExceeded::TooLarge`]), while one that only
+//! doesn't fit next to the other reads in progress can be read again later ([`BudgetExceeded::Busy`]).
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sheet::CellValue;
+
+/// The memory a cell takes apart from the text of its value: the entry it's read into, the entry it's resolved
+/// into, and its part of the response body.
+const CELL_SIZE: usize = 128;
+
+/// The approximate memory a cell with the given value takes until the response it's a part of is sent.
+pub fn cell_size(value: Option<&CellValue>) -> usize {
+    match value {
+        // the text is kept as read, and again in the response
+        Some(CellValue::String(text)) => CELL_SIZE + 2 * text.len(),
+        _ => CELL_SIZE,
+    }
+}
+
+/// The memory which all reads of whole sheets together may take.
+#[derive(Debug)]
+pub struct ReadBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl ReadBudget {
+    /// Creates a budget of `limit` bytes, or an unlimited one if it's 0.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: if limit == 0 { usize::MAX } else { limit },
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts a read, which reserves memory from the budget until the reservation is dropped.
+    pub fn reserve(&self) -> Reservation<'_> {
+        Reservation {
+            budget: self,
+            bytes: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// The memory reserved by a single read. This can be shared by the concurrent parts of a read.
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a ReadBudget,
+    bytes: AtomicUsize,
+}
+
+impl Reservation<'_> {
+    /// Reserves `bytes` more, if they fit into the budget. Otherwise, nothing is reserved.
+    pub fn add(&self, bytes: usize) -> Result<(), BudgetExceeded> {
+        let limit = self.budget.limit;
+        let own = self
+            .bytes
+            .fetch_add(bytes, Ordering::Relaxed)
+            .saturating_add(bytes);
+        if own > limit {
+            self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+            return Err(BudgetExceeded::TooLarge);
+        }
+
+        let used = self
+            .budget
+            .used
+            .fetch_add(bytes, Ordering::Relaxed)
+            .saturating_add(bytes);
+        if used > limit {
+            self.budget.used.fetch_sub(bytes, Ordering::Relaxed);
+            self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+            return Err(BudgetExceeded::Busy);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.budget
+            .used
+            .fetch_sub(*self.bytes.get_mut(), Ordering::Relaxed);
+    }
+}
+
+/// An error signifying that reading a sheet would take more memory than the [`ReadBudget`] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetExceeded {
+    /// The sheet doesn't fit into the budget even on its own.
+    TooLarge,
+    /// The sheet doesn't fit next to the other sheets being read at the moment.
+    Busy,
+}
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge => write!(
+                f,
+                "the sheet is too large to be read at once, read it a page at a time with ?limit"
+            ),
+            Self::Busy => write!(
+                f,
+                "too many large sheets are being read at the moment, try again later or read the sheet a page at a time with ?limit"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::{BudgetExceeded, ReadBudget};
+
+    #[test]
+    fn reservations_share_the_budget() {
+        let budget = ReadBudget::new(100);
+        let first = budget.reserve();
+        assert_eq!(first.add(60), Ok(()));
+        assert_eq!(first.add(50), Err(BudgetExceeded::TooLarge));
+
+        let second = budget.reserve();
+        assert_eq!(second.add(50), Err(BudgetExceeded::Busy));
+        assert_eq!(second.add(40), Ok(()));
+
+        drop(first);
+        assert_eq!(second.add(50), Ok(()));
+        assert_eq!(ReadBudget::new(0).reserve().add(usize::MAX), Ok(()));
+    }
+}
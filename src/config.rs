@@ -0,0 +1,345 @@
+use std::{env, net::IpAddr, path::PathBuf, time::Duration};
+
+use actix_web::http::KeepAlive;
+use anyhow::Result;
+
+use crate::db::IdScheme;
+
+/// Runtime configuration of the server. Everything here is read from environment variables, so that
+/// the server can be configured without any files. The database can also be chosen on the command line (see
+/// [`AppConfig::with_args`]), for switching between environments by hand.
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    /// Use an in-memory database instead of a file (`MEMORY_DB`). This is mostly useful for testing.
+    pub memory_db: bool,
+    /// The directory the database file is kept in (`DATA_DIR`). It has to exist and be writable.
+    pub data_dir: PathBuf,
+    /// The database file within `data_dir` (`DB_FILE`, or `--db` on the command line), so that a single data
+    /// directory can hold the databases of several environments.
+    pub db_file: PathBuf,
+    /// Omit lookup cells which resolve to nothing from GET responses (`NO_LOOKUP_NULLS`).
+    pub no_lookup_nulls: bool,
+    /// Reject lookups which point at empty cells (`STRICT_LOOKUPS`).
+    pub strict_lookups: bool,
+    /// Consider column names which differ only in case as duplicates (`CASE_INSENSITIVE_COLUMNS`).
+    pub case_insensitive_columns: bool,
+    /// The TCP address to listen on (`BIND_ADDRESS`). Setting it to an empty value disables TCP.
+    pub bind_address: Option<String>,
+    /// A unix domain socket to listen on, in addition to TCP (`UNIX_SOCKET`).
+    pub unix_socket: Option<PathBuf>,
+    /// The number of worker threads (`WORKERS`). Defaults to the number of physical CPU cores.
+    pub workers: Option<usize>,
+    /// How long idle connections are kept open (`KEEP_ALIVE`, in seconds). 0 disables keep-alive.
+    /// Defaults to actix_web's default.
+    pub keep_alive: Option<KeepAlive>,
+    /// How long a client has to send the request head (`CLIENT_TIMEOUT`, in seconds). 0 disables the timeout.
+    /// Defaults to actix_web's default.
+    pub client_timeout: Option<Duration>,
+    /// Start in read-only mode, rejecting all mutating requests (`READ_ONLY`).
+    pub read_only: bool,
+    /// A token which must be given as a bearer token to use the admin endpoints (`ADMIN_TOKEN`).
+    /// If it isn't set, the admin endpoints reject every request.
+    pub admin_token: Option<String>,
+    /// How often database maintenance runs in the background (`MAINTENANCE_INTERVAL`, in seconds).
+    /// Scheduled runs are always incremental. Disabled by default.
+    pub maintenance_interval: Option<Duration>,
+    /// Expose the `/test` endpoints, which make setting up integration tests easier (`TEST_ENDPOINTS`).
+    pub test_endpoints: bool,
+    /// The SMTP relay used for email notifications (`SMTP_SERVER`, as `host:port`).
+    pub smtp_server: Option<String>,
+    /// The sender address of email notifications (`SMTP_FROM`).
+    pub smtp_from: String,
+    /// How ids are generated for new sheets (`SHEET_ID_SCHEME`, one of `alphanumeric`, `uuidv4`, `uuidv7`).
+    pub id_scheme: IdScheme,
+    /// How long a single request may take before it's aborted (`REQUEST_TIMEOUT`, in seconds).
+    /// Setting it to 0 disables the timeout.
+    pub request_timeout: Option<Duration>,
+    /// How long editing sessions stay open without being committed (`SESSION_TTL`, in seconds).
+    pub session_ttl: Duration,
+    /// The largest file that can be imported at once (`MAX_IMPORT_SIZE`, in bytes).
+    pub max_import_size: usize,
+    /// How much memory reading whole sheets may take at once (`READ_MEMORY_BUDGET`, in bytes, see
+    /// [`crate::budget`]). 0 means there's no limit.
+    pub read_memory_budget: usize,
+    /// How often the traffic statistics of sheets are written to the database (`STATS_FLUSH_INTERVAL`, in seconds).
+    pub stats_flush_interval: Duration,
+    /// The secret mutating requests to sheets have to be signed with (`SIGNING_SECRET`, see [`crate::signing`]).
+    /// Without it, requests don't need to be signed.
+    pub signing_secret: Option<String>,
+    /// How far the timestamp of a signed request may be from the current time (`SIGNATURE_WINDOW`, in seconds).
+    pub signature_window: Duration,
+    /// The secret export links are signed with (`EXPORT_LINK_SECRET`). Without it, a random one is used, so links
+    /// stop working when the server restarts, and aren't accepted by other instances.
+    pub export_link_secret: Option<String>,
+    /// The issuer JWTs have to come from (`JWT_ISSUER`, see [`crate::jwt`]). It's required when reading the
+    /// configuration from the environment, so that tokens of other issuers sharing the keys aren't accepted.
+    pub jwt_issuer: Option<String>,
+    /// The audience JWTs have to be for (`JWT_AUDIENCE`). Without it, the audience isn't checked.
+    pub jwt_audience: Option<String>,
+    /// The URL of the issuer's JWKS, which lists the keys JWTs are signed with (`JWT_JWKS_URL`).
+    pub jwt_jwks_url: Option<String>,
+    /// A secret JWTs can be signed with using HMAC (`JWT_SECRET`). Either this or `jwt_jwks_url` enables JWT
+    /// authentication.
+    pub jwt_secret: Option<String>,
+    /// Keep a log of the requests to every sheet, which its owners can read (`ACCESS_LOG`).
+    pub access_log: bool,
+    /// The address of a reverse proxy in front of the server (`TRUSTED_PROXY`), whose `Forwarded` and
+    /// `X-Forwarded-For` headers name the client in the access log. Without it, the headers are ignored, since any
+    /// client could set them.
+    pub trusted_proxy: Option<IpAddr>,
+    /// Where snapshots of the database are written for shipping to a standby (`SNAPSHOT_PATH`). Disabled by default.
+    pub snapshot_path: Option<PathBuf>,
+    /// How often a snapshot is written, if `snapshot_path` is set (`SNAPSHOT_INTERVAL`, in seconds).
+    pub snapshot_interval: Duration,
+    /// Serve a copy of the database which is written by another instance, opening it read-only and rejecting all
+    /// mutating requests (`REPLICA`).
+    pub replica: bool,
+    /// Where replicas redirect mutating requests to, e.g. `http://primary:8080` (`PRIMARY_URL`). Without it,
+    /// they're rejected instead.
+    pub primary_url: Option<String>,
+    /// Sheets which are read once at startup, so that the first requests to them don't have to wait for the disk
+    /// (`WARMUP_SHEETS`, separated by commas).
+    pub warmup_sheets: Vec<String>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            memory_db: false,
+            data_dir: ".".into(),
+            db_file: "data.sqlite".into(),
+            // since this is a test application after all, we use localhost:8080 for now
+            bind_address: Some("localhost:8080".into()),
+            unix_socket: None,
+            workers: None,
+            keep_alive: None,
+            client_timeout: None,
+            no_lookup_nulls: false,
+            strict_lookups: false,
+            case_insensitive_columns: false,
+            read_only: false,
+            admin_token: None,
+            maintenance_interval: None,
+            test_endpoints: false,
+            smtp_server: None,
+            smtp_from: "sheets@localhost".into(),
+            id_scheme: IdScheme::default(),
+            request_timeout: Some(Duration::from_secs(30)),
+            session_ttl: Duration::from_secs(60 * 60),
+            max_import_size: 64 * 1024 * 1024,
+            read_memory_budget: 512 * 1024 * 1024,
+            stats_flush_interval: Duration::from_secs(60),
+            signing_secret: None,
+            signature_window: Duration::from_secs(5 * 60),
+            export_link_secret: None,
+            jwt_issuer: None,
+            jwt_audience: None,
+            jwt_jwks_url: None,
+            jwt_secret: None,
+            access_log: false,
+            trusted_proxy: None,
+            snapshot_path: None,
+            snapshot_interval: Duration::from_secs(5 * 60),
+            replica: false,
+            primary_url: None,
+            warmup_sheets: vec![],
+        }
+    }
+}
+
+impl AppConfig {
+    /// Reads the configuration from the environment, using the defaults for anything that isn't set.
+    ///
+    /// # Errors
+    /// In case one of the variables is set to a value that can't be parsed, or JWT authentication is configured
+    /// without an issuer.
+    pub fn from_env() -> Result<Self> {
+        let default = Self::default();
+
+        let jwt_enabled =
+            env::var_os("JWT_SECRET").is_some() || env::var_os("JWT_JWKS_URL").is_some();
+        if jwt_enabled && env::var_os("JWT_ISSUER").is_none() {
+            anyhow::bail!("JWT_ISSUER has to be set along with JWT_SECRET or JWT_JWKS_URL");
+        }
+
+        Ok(Self {
+            memory_db: flag("MEMORY_DB"),
+            data_dir: env::var_os("DATA_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(default.data_dir),
+            db_file: env::var_os("DB_FILE")
+                .map(PathBuf::from)
+                .unwrap_or(default.db_file),
+            bind_address: match env::var("BIND_ADDRESS") {
+                Ok(address) if address.is_empty() => None,
+                Ok(address) => Some(address),
+                Err(_) => default.bind_address,
+            },
+            unix_socket: env::var_os("UNIX_SOCKET").map(PathBuf::from),
+            workers: parsed("WORKERS")?,
+            keep_alive: parsed("KEEP_ALIVE")?.map(|secs| match secs {
+                0 => KeepAlive::Disabled,
+                secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+            }),
+            client_timeout: parsed("CLIENT_TIMEOUT")?.map(Duration::from_secs),
+            no_lookup_nulls: flag("NO_LOOKUP_NULLS"),
+            strict_lookups: flag("STRICT_LOOKUPS"),
+            case_insensitive_columns: flag("CASE_INSENSITIVE_COLUMNS"),
+            read_only: flag("READ_ONLY"),
+            admin_token: env::var("ADMIN_TOKEN").ok(),
+            maintenance_interval: parsed::<u64>("MAINTENANCE_INTERVAL")?
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            test_endpoints: flag("TEST_ENDPOINTS"),
+            smtp_server: env::var("SMTP_SERVER").ok(),
+            smtp_from: env::var("SMTP_FROM").unwrap_or(default.smtp_from),
+            id_scheme: parsed("SHEET_ID_SCHEME")?.unwrap_or(default.id_scheme),
+            request_timeout: match parsed::<u64>("REQUEST_TIMEOUT")? {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => default.request_timeout,
+            },
+            session_ttl: parsed("SESSION_TTL")?
+                .map(Duration::from_secs)
+                .unwrap_or(default.session_ttl),
+            max_import_size: parsed("MAX_IMPORT_SIZE")?.unwrap_or(default.max_import_size),
+            read_memory_budget: parsed("READ_MEMORY_BUDGET")?.unwrap_or(default.read_memory_budget),
+            stats_flush_interval: parsed::<u64>("STATS_FLUSH_INTERVAL")?
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(default.stats_flush_interval),
+            signing_secret: env::var("SIGNING_SECRET").ok(),
+            signature_window: parsed("SIGNATURE_WINDOW")?
+                .map(Duration::from_secs)
+                .unwrap_or(default.signature_window),
+            export_link_secret: env::var("EXPORT_LINK_SECRET").ok(),
+            jwt_issuer: env::var("JWT_ISSUER").ok(),
+            jwt_audience: env::var("JWT_AUDIENCE").ok(),
+            jwt_jwks_url: env::var("JWT_JWKS_URL").ok(),
+            jwt_secret: env::var("JWT_SECRET").ok(),
+            access_log: flag("ACCESS_LOG"),
+            trusted_proxy: parsed("TRUSTED_PROXY")?,
+            snapshot_path: env::var_os("SNAPSHOT_PATH").map(PathBuf::from),
+            snapshot_interval: parsed::<u64>("SNAPSHOT_INTERVAL")?
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(default.snapshot_interval),
+            replica: flag("REPLICA"),
+            primary_url: env::var("PRIMARY_URL").ok(),
+            warmup_sheets: env::var("WARMUP_SHEETS")
+                .map(|ids| {
+                    ids.split(',')
+                        .map(str::trim)
+                        .filter(|id| !id.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl AppConfig {
+    /// Applies the command line arguments (without the program name) on top of the environment. The only one
+    /// is `--db <file>` (or `--db=<file>`), which overrides `DB_FILE`.
+    ///
+    /// # Errors
+    /// In case of an unknown argument, or `--db` without a file.
+    pub fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let db_file = if arg == "--db" {
+                args.next()
+            } else if let Some(db_file) = arg.strip_prefix("--db=") {
+                Some(db_file.into())
+            } else {
+                anyhow::bail!("unknown argument {arg:?}, the only one is --db <file>");
+            };
+            match db_file {
+                Some(db_file) if !db_file.is_empty() => self.db_file = db_file.into(),
+                _ => anyhow::bail!("--db needs a database file, e.g. --db prod.sqlite"),
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// The path of the database file.
+    pub fn db_path(&self) -> PathBuf {
+        self.data_dir.join(&self.db_file)
+    }
+}
+
+/// Boolean options are enabled simply by being present in the environment.
+fn flag(name: &str) -> bool {
+    env::var(name).is_ok()
+}
+
+fn parsed<T>(name: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    env::var(name)
+        .ok()
+        .map(|value| {
+            value.parse().map_err(|why: T::Err| {
+                why.into()
+                    .context(format!("invalid value for {name}: {value}"))
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use actix_web::http::KeepAlive;
+
+    use super::AppConfig;
+
+    fn args(args: &[&str]) -> anyhow::Result<AppConfig> {
+        AppConfig::default().with_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn the_database_can_be_chosen_on_the_command_line() {
+        assert_eq!(args(&[]).unwrap().db_path(), Path::new("./data.sqlite"));
+        assert_eq!(args(&["--db", "prod.sqlite"]).unwrap().db_file, Path::new("prod.sqlite"));
+        assert_eq!(args(&["--db=staging.sqlite"]).unwrap().db_file, Path::new("staging.sqlite"));
+
+        for broken in [&["--db"][..], &["--db="], &["--dbx"], &["prod.sqlite"]] {
+            assert!(args(broken).is_err(), "{broken:?}");
+        }
+    }
+
+    #[test]
+    fn server_tuning_is_read_from_the_environment() {
+        // this is the only test which touches these variables, so it can't race with the others
+        let vars = ["WORKERS", "KEEP_ALIVE", "CLIENT_TIMEOUT"];
+        let with_vars = |values: [&str; 3]| {
+            for (name, value) in vars.into_iter().zip(values) {
+                std::env::set_var(name, value);
+            }
+            let config = AppConfig::from_env();
+            for name in vars {
+                std::env::remove_var(name);
+            }
+            config
+        };
+
+        let config = with_vars(["2", "75", "0"]).unwrap();
+        assert_eq!(config.workers, Some(2));
+        assert_eq!(config.keep_alive, Some(KeepAlive::Timeout(Duration::from_secs(75))));
+        assert_eq!(config.client_timeout, Some(Duration::ZERO));
+
+        let config = with_vars(["1", "0", "10"]).unwrap();
+        assert_eq!(config.keep_alive, Some(KeepAlive::Disabled));
+        assert_eq!(config.client_timeout, Some(Duration::from_secs(10)));
+
+        for broken in [["two", "75", "5"], ["2", "-1", "5"], ["2", "75", "5s"]] {
+            assert!(with_vars(broken).is_err(), "{broken:?}");
+        }
+    }
+}
@@ -1,14 +1,56 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use futures_util::TryStreamExt;
 use rand::{
     distributions::{Alphanumeric, DistString},
     Rng,
 };
-use serde::Deserialize;
-use sqlx::{sqlite::SqliteConnectOptions, QueryBuilder, Row, SqlitePool};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    sqlite::{SqliteAutoVacuum, SqliteConnectOptions, SqliteRow},
+    QueryBuilder, Row, SqlitePool, TypeInfo, ValueRef,
+};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::archive::{ArchivedSheet, Bundle, BundleTable, BundleValue};
+use crate::budget::{cell_size, Reservation};
+use crate::intercept::{CellWrite, WriteInterceptor};
+use crate::sheet::view::{ViewDefinition, ViewInfo, ViewRefresh};
+use crate::sheet::{
+    self, CellError, CellValue, LookupError, ReferenceError, SchemaColumnKind, SheetContentColumn,
+};
+
+/// The different ways in which new sheet ids (and row keys) can be generated.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IdScheme {
+    /// A random string of alphanumeric characters.
+    #[default]
+    Alphanumeric,
+    /// A random UUID (version 4).
+    UuidV4,
+    /// A time-ordered UUID (version 7), which makes ids sortable by creation time.
+    UuidV7,
+}
+
+impl std::str::FromStr for IdScheme {
+    type Err = anyhow::Error;
 
-use crate::sheet::{self, CellValue, SchemaColumnKind, SheetContentColumn};
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "alphanumeric" => Ok(Self::Alphanumeric),
+            "uuidv4" => Ok(Self::UuidV4),
+            "uuidv7" => Ok(Self::UuidV7),
+            _ => {
+                anyhow::bail!("unknown id scheme {s}, expected one of alphanumeric, uuidv4, uuidv7")
+            }
+        }
+    }
+}
 
 #[derive(Deserialize)]
 #[serde(try_from = "&str")]
@@ -18,23 +60,79 @@ impl SheetId {
     // arbitrary - should be long enough to support a very, very large amount of sheets without collisions.
     const LENGTH: usize = 24;
 
-    pub fn generate<R: Rng + ?Sized>(r: &mut R) -> Self {
-        let mut inner = String::new();
-        Alphanumeric.append_string(r, &mut inner, Self::LENGTH);
+    pub fn generate<R: Rng + ?Sized>(r: &mut R, scheme: IdScheme) -> Self {
+        match scheme {
+            IdScheme::Alphanumeric => {
+                let mut inner = String::new();
+                Alphanumeric.append_string(r, &mut inner, Self::LENGTH);
 
-        Self(inner)
+                Self(inner)
+            }
+            IdScheme::UuidV4 => Self(
+                uuid::Builder::from_random_bytes(r.gen())
+                    .into_uuid()
+                    .to_string(),
+            ),
+            IdScheme::UuidV7 => Self(Uuid::now_v7().to_string()),
+        }
     }
 
     pub fn inner(&self) -> &str {
         &self.0
     }
+
+    /// Returns a version of the id which is safe to use as part of an SQL identifier (i.e. table names).
+    /// This mapping is unambiguous, since ids never contain underscores.
+    pub fn ident(&self) -> String {
+        self.0.replace('-', "_")
+    }
+}
+
+impl SheetId {
+    const MIN_SLUG_LENGTH: usize = 3;
+    const MAX_SLUG_LENGTH: usize = 64;
+
+    /// Checks whether the value is a valid human-readable slug: lowercase ascii letters, digits and
+    /// hyphens (but not at either end), between 3 and 64 characters long.
+    fn is_slug(value: &str) -> bool {
+        (Self::MIN_SLUG_LENGTH..=Self::MAX_SLUG_LENGTH).contains(&value.len())
+            && value
+                .chars()
+                .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '-')
+            && !value.starts_with('-')
+            && !value.ends_with('-')
+    }
+
+    /// Parses a custom id chosen by the user.
+    pub fn from_slug(value: &str) -> Result<Self> {
+        if !Self::is_slug(value) {
+            anyhow::bail!(
+                "invalid sheet id: must be {} to {} lowercase letters, digits or hyphens, and must not start or end with a hyphen",
+                Self::MIN_SLUG_LENGTH,
+                Self::MAX_SLUG_LENGTH
+            );
+        }
+
+        Ok(Self(value.into()))
+    }
 }
 
 impl TryFrom<&str> for SheetId {
     type Error = anyhow::Error;
 
+    /// Parses an id in any of the supported schemes (regardless of which one is used for new sheets),
+    /// so that changing the scheme doesn't make existing sheets inaccessible.
     fn try_from(value: &str) -> Result<Self> {
-        if value.len() != Self::LENGTH {
+        if Self::is_slug(value) {
+            // this also covers (lowercase) uuids
+            Ok(Self(value.into()))
+        } else if let Ok(uuid) = Uuid::try_parse(value) {
+            // only the canonical form is accepted, so that every sheet has exactly one id
+            if uuid.to_string() != value {
+                anyhow::bail!("invalid content: {value} is not a lowercase hyphenated uuid");
+            }
+            Ok(Self(value.into()))
+        } else if value.len() != Self::LENGTH {
             anyhow::bail!("invalid length: expected {}, got {}", Self::LENGTH, value.len());
         } else if value.chars().any(|x| !x.is_ascii_alphanumeric()) {
             anyhow::bail!("invalid content: {value} is not alphanumeric");
@@ -44,411 +142,6346 @@ impl TryFrom<&str> for SheetId {
     }
 }
 
-pub struct Db {
-    pool: SqlitePool,
+/// An error signifying that a custom sheet id is already in use by another sheet.
+#[derive(Debug, Clone)]
+pub struct IdTaken(pub String);
+
+impl std::fmt::Display for IdTaken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sheet id {} is already taken", self.0)
+    }
 }
 
-impl Db {
-    async fn new_inner(pool: SqlitePool) -> Result<Self> {
-        // create the initial "sheets" indexing table that we will use to easily check for column names.
-        // `IF NOT EXISTS` enables us to not worry if the database file is new or not.
-        sqlx::query(
-            "\
-                CREATE TABLE IF NOT EXISTS sheets(
-                    id      TEXT NOT NULL PRIMARY KEY
-                );",
-        )
-        .execute(&pool)
-        .await?;
+impl std::error::Error for IdTaken {}
 
-        Ok(Self { pool })
+/// An error signifying that the requested sheet doesn't exist.
+#[derive(Debug, Clone)]
+pub struct SheetNotFound;
+
+impl std::fmt::Display for SheetNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sheet doesn't exist")
     }
+}
 
-    /// Creates a new Db instance using the given filename as the name of the sqlite database.
-    pub async fn new(filename: &str) -> Result<Self> {
-        let options = SqliteConnectOptions::new()
-            .filename(filename)
-            // this is necessary so that we don't error when the file doesn't exist. we want to create it anyway
-            .create_if_missing(true);
+impl std::error::Error for SheetNotFound {}
 
-        let pool = SqlitePool::connect_with(options).await?;
+/// An error signifying that a cell is outside of the rows its sheet allows (see
+/// [`sheet::SheetSettings::row_bounds`]).
+#[derive(Debug, Clone)]
+pub struct RowOutOfBounds {
+    pub row: i64,
+    pub bounds: sheet::RowBounds,
+}
 
-        Self::new_inner(pool).await
+impl std::fmt::Display for RowOutOfBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {} is outside of the rows this sheet allows ({})", self.row, self.bounds)
     }
+}
 
-    /// Creates a new Db instance which uses a database in-memory, to avoid creating files when testing.
-    pub async fn new_memory() -> Result<Self> {
-        Self::new_inner(SqlitePool::connect(":memory:").await?).await
-    }
+impl std::error::Error for RowOutOfBounds {}
 
-    async fn register_random_sheetid(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-    ) -> Result<SheetId> {
-        // loop is necessary in case of duplicates. again, astronomically low chance.
-        loop {
-            let sheetid = SheetId::generate(&mut rand::thread_rng());
+/// An error signifying that the references of a new sheet's columns can't be created, e.g. because the
+/// referenced sheet doesn't exist (see [`sheet::ColumnReference`]).
+#[derive(Debug, Clone)]
+pub struct InvalidReferences(pub Vec<sheet::SchemaError>);
 
-            if sqlx::query("INSERT INTO sheets (id) VALUES (?) RETURNING id;")
-                .bind(&sheetid.0)
-                .fetch_optional(tr.as_mut())
-                .await?
-                .is_some()
-            {
-                break Ok(sheetid);
-            }
-        }
+impl std::fmt::Display for InvalidReferences {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid schema")
     }
+}
 
-    async fn build_columns_table(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-        schema: &sheet::Schema,
-    ) -> Result<()> {
-        sqlx::query(&format!(
-            "CREATE TABLE sheet_{}_columns(
-            id      INTEGER NOT NULL PRIMARY KEY,
-            name    TEXT    NOT NULL UNIQUE,
-            type    TEXT    NOT NULL
-        );",
-            &sheetid.0
-        ))
-        .execute(tr.as_mut())
-        .await?;
+impl std::error::Error for InvalidReferences {}
 
-        QueryBuilder::new(format!("INSERT INTO sheet_{}_columns (id, name, type) ", &sheetid.0))
-            .push_values(schema.columns.iter().enumerate(), |mut b, (i, col)| {
-                b.push_bind(i as i64)
-                    .push_bind(&col.name)
-                    .push_bind(col.kind.get_sql_text());
-            })
-            .build()
-            .execute(tr.as_mut())
-            .await?;
+/// An error signifying that a cell of a view sheet was written to, which only refreshing the view does (see
+/// [`sheet::view`]).
+#[derive(Debug, Clone)]
+pub struct ReadOnlyView;
 
-        Ok(())
+impl std::fmt::Display for ReadOnlyView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the sheet is a view, which can't be written to")
     }
+}
 
-    async fn build_sheet_table(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-        schema: &sheet::Schema,
-    ) -> Result<()> {
-        let mut builder = QueryBuilder::new(&format!("CREATE TABLE sheet_{} (", &sheetid.0));
+impl std::error::Error for ReadOnlyView {}
 
-        // this essentially generates a bunch of columns like this:
-        // row INTEGER NOT NULL PRIMARY KEY,
-        // col0 TYPE,
-        // col1 TYPE,
-        // col2 TYPE,
-        // ..etc
-        let mut separated = builder.separated(", ");
-        separated.push("row INTEGER NOT NULL PRIMARY KEY");
-        for (i, col) in schema.columns.iter().enumerate() {
-            separated.push(format_args!("col{} {}", i, col.kind.get_sql_text()));
-        }
-        separated.push_unseparated(");");
+/// An error signifying that a view definition doesn't fit its source, with every problem found.
+#[derive(Debug, Clone)]
+pub struct InvalidView(pub Vec<sheet::FieldError>);
 
-        builder.build().execute(tr.as_mut()).await?;
+impl std::fmt::Display for InvalidView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid view definition")
+    }
+}
 
-        Ok(())
+impl std::error::Error for InvalidView {}
+
+/// An error signifying that an editing session doesn't exist, either because it never did, or because it was
+/// already committed, aborted or has expired.
+#[derive(Debug, Clone)]
+pub struct SessionNotFound;
+
+impl std::fmt::Display for SessionNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "session doesn't exist")
     }
+}
 
-    async fn build_lookup_table(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-    ) -> Result<()> {
-        sqlx::query(&format!(
-            "CREATE TABLE sheet_{}_lookups(
-            col_id          INTEGER NOT NULL,
-            row             INTEGER NOT NULL,
-            target_col_id   INTEGER NOT NULL,
-            target_row      INTEGER NOT NULL
-        );",
-            &sheetid.0
-        ))
-        .execute(tr.as_mut())
-        .await?;
+impl std::error::Error for SessionNotFound {}
 
-        sqlx::query(&format!(
-            "CREATE UNIQUE INDEX index_lookups ON sheet_{}_lookups (col_id, row);",
-            &sheetid.0
-        ))
-        .execute(tr.as_mut())
-        .await?;
+/// An error signifying that the database contains data that doesn't make sense, e.g. an unknown column type.
+/// This is never the client's fault.
+#[derive(Debug, Clone)]
+pub struct Corrupted(pub String);
 
-        Ok(())
+impl std::fmt::Display for Corrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "corrupted database: {}", self.0)
     }
+}
 
-    /// Generates a new sheet with a unique id, according to the given schema.
-    ///
-    /// # Errors
-    /// In case the schema is invalid, or a database failure.
-    pub async fn new_sheet(&self, schema: &sheet::Schema) -> Result<SheetId> {
-        if !schema.is_valid() {
-            anyhow::bail!("Invalid schema");
-        }
+impl std::error::Error for Corrupted {}
 
-        // we need a transaction here, to make sure that a generated sheet id isn't accidentally taken by somebody
-        // else, causing a race condition. the chance of that happening is astronomically small, but not zero nonetheless.
-        let mut tr = self.pool.begin().await?;
+fn alias_mode(proxy: bool) -> sheet::AliasMode {
+    if proxy {
+        sheet::AliasMode::Proxy
+    } else {
+        sheet::AliasMode::Redirect
+    }
+}
 
-        let sheetid = Self::register_random_sheetid(&mut tr).await?;
+/// The current time in milliseconds since the unix epoch, which is how timestamps are stored.
+pub(crate) fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
 
-        // this table is necessary because it's a bad idea to name the database columns using the names that the user gave us.
-        // instead we store the names as plain strings, and we'll use the id to derive a column name.
-        // the `UNIQUE` modifier implicitly creates an index, so later looking up column ids by name will be efficient.
-        Self::build_columns_table(&mut tr, &sheetid, schema).await?;
+fn parse_kind(text: &str) -> Result<SchemaColumnKind> {
+    SchemaColumnKind::from_sql_text(text)
+        .ok_or_else(|| Corrupted(format!("unknown column type {text}")).into())
+}
 
-        // this is where we store the actual cell values, apart from lookup cells
-        Self::build_sheet_table(&mut tr, &sheetid, schema).await?;
+/// Decodes a (possibly empty) value of a column of the given kind.
+fn decode_value(
+    row: &SqliteRow,
+    index: usize,
+    kind: SchemaColumnKind,
+) -> Result<Option<CellValue>> {
+    let value = match kind {
+        SchemaColumnKind::Boolean => row
+            .try_get::<Option<bool>, _>(index)
+            .map(|x| x.map(CellValue::Boolean)),
+        SchemaColumnKind::Int | SchemaColumnKind::Autoincrement => row
+            .try_get::<Option<i64>, _>(index)
+            .map(|x| x.map(CellValue::Int)),
+        // infinities stored by older versions are read as they are, and reported as errors (see
+        // `SheetContentColumn::new`)
+        SchemaColumnKind::Double => row
+            .try_get::<Option<f64>, _>(index)
+            .map(|x| x.map(CellValue::Double)),
+        SchemaColumnKind::String | SchemaColumnKind::Url | SchemaColumnKind::Email => row
+            .try_get::<Option<String>, _>(index)
+            .map(|x| x.map(CellValue::String)),
+    };
 
-        // this is where we store only the lookup cells. a cell cannot be in both the above table and this table.
-        Self::build_lookup_table(&mut tr, &sheetid).await?;
+    // a value that doesn't match its column's type can only be the result of a corrupted database
+    value.map_err(|why| Corrupted(why.to_string()).into())
+}
 
-        tr.commit().await?;
-        Ok(sheetid)
-    }
+/// How many prepared statements every connection keeps. Statements name the tables of their sheet, so each sheet
+/// needs its own, and the default of sqlx (100) is used up by a handful of busy sheets, which then keep evicting
+/// each other's statements and preparing them again.
+const STATEMENT_CACHE_CAPACITY: usize = 2000;
 
-    async fn get_column_by_name(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-        name: &str,
-    ) -> Result<Option<(i64, SchemaColumnKind)>> {
-        Ok(sqlx::query_as::<_, (i64, String)>(&format!(
-            "SELECT id, type FROM sheet_{}_columns WHERE name = ?;",
-            sheetid.inner()
-        ))
-        .bind(name)
-        .fetch_optional(tr.as_mut())
-        .await?
-        .map(|(id, kind)| (id, SchemaColumnKind::from_sql_text(&kind).unwrap())))
+/// The most cells [`Db::insert_values_in`] writes at once. Its statements take up to 4 parameters per cell,
+/// which keeps them far below sqlite's limit of 32766.
+const INSERT_BATCH_CELLS: usize = 1000;
+
+/// Returns the id of the column a cell is in, if it can be written as part of a batch: it has to be a plain
+/// value of the column's type which can't be rejected (so no text which fails its column's checks, and no
+/// infinities or NaN), so that writing it can't fail or change the column.
+/// Values of columns with a reference are checked one by one, so they can't be batched either.
+fn batchable_column(columns: &[sheet::SchemaColumn], cell: &sheet::Cell) -> Option<i64> {
+    if cell.value.is_lookup().is_some() || check_finite(&cell.value).is_err() {
+        return None;
+    }
+    let name: String = cell.column.nfc().collect();
+    let (col_id, column) = columns
+        .iter()
+        .enumerate()
+        .find(|(_, column)| column.name == name)?;
+    if column.kind == SchemaColumnKind::Autoincrement
+        || column.references.is_some()
+        || column.kind.storage() != SchemaColumnKind::from(&cell.value)
+    {
+        return None;
     }
+    if let CellValue::String(text) = cell.value.unescaped() {
+        column.kind.check_text(&text).ok()?;
+    }
+    Some(col_id as i64)
+}
 
-    async fn detect_cycle(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-        col_id: i64,
-        row: i64,
-        mut target_col_id: i64,
-        mut target_row: i64,
-    ) -> Result<bool> {
-        if col_id == target_col_id && row == target_row {
-            return Ok(true);
+/// Rejects infinities and NaN, which sheets can't hold: sqlite stores NaN as an empty value, and JSON has no way
+/// of writing either of them.
+fn check_finite(value: &CellValue) -> Result<()> {
+    match value {
+        CellValue::Double(x) if !x.is_finite() => {
+            anyhow::bail!("doubles have to be finite, not {x}")
         }
+        _ => Ok(()),
+    }
+}
 
-        let query = format!(
-            "SELECT target_col_id, target_row FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
-            &sheetid.0
-        );
-        loop {
-            let Some((new_col_id, new_row)) = sqlx::query_as::<_, (i64, i64)>(&query)
-                .bind(target_col_id)
-                .bind(target_row)
-                .fetch_optional(tr.as_mut())
-                .await?
-            else {
-                return Ok(false);
-            };
+/// The content of one of the sheets of a [`Db::join`], laid out by row.
+struct JoinSide {
+    /// The columns which were read, in the order of the schema.
+    columns: Vec<String>,
+    /// The value of every column, for every populated row.
+    rows: BTreeMap<i64, Vec<Option<CellValue>>>,
+    /// The value of the column joined on, for every row which has one. Ints and doubles with the same value
+    /// have the same key.
+    keys: HashMap<i64, String>,
+}
 
-            if new_col_id == col_id && new_row == row {
-                return Ok(true);
-            } else {
-                target_col_id = new_col_id;
-                target_row = new_row;
+impl JoinSide {
+    fn new(
+        column_table: &[sheet::SchemaColumn],
+        mut content: sheet::SheetContent,
+        on: &str,
+    ) -> Result<Self> {
+        let on: String = on.nfc().collect();
+        if !content.columns.contains_key(&on) {
+            anyhow::bail!("unknown column to join on: {on:?}");
+        }
+
+        let columns: Vec<_> = column_table
+            .iter()
+            .filter(|column| content.columns.contains_key(&column.name))
+            .map(|column| column.name.clone())
+            .collect();
+        let mut rows = BTreeMap::new();
+        let mut keys = HashMap::new();
+        for (i, name) in columns.iter().enumerate() {
+            for cell in content.columns.remove(name).into_iter().flatten() {
+                if *name == on {
+                    if let Some(value) = &cell.value {
+                        keys.insert(cell.row, join_key(value));
+                    }
+                }
+                let row = rows
+                    .entry(cell.row)
+                    .or_insert_with(|| vec![None; columns.len()]);
+                row[i] = cell.value;
             }
         }
+
+        Ok(Self {
+            columns,
+            rows,
+            keys,
+        })
     }
+}
 
-    async fn sheet_exists(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-    ) -> Result<bool> {
-        Ok(sqlx::query_scalar::<_, i64>("SELECT EXISTS(SELECT 1 FROM sheets WHERE id = ?);")
-            .bind(&sheetid.0)
-            .fetch_one(tr.as_mut())
-            .await?
-            == 1)
+/// The key a value is joined on, which is the same for values that are equal across types, such as `1` and
+/// `1.0`.
+fn join_key(value: &CellValue) -> String {
+    match value {
+        CellValue::Boolean(x) => format!("b{x}"),
+        CellValue::Int(x) => format!("n{x}"),
+        // doubles up to 2^53 are exact, so whole ones can be written as ints
+        CellValue::Double(x) if x.fract() == 0.0 && x.abs() < 9007199254740992.0 => {
+            format!("n{}", *x as i64)
+        }
+        CellValue::Double(x) => format!("n{x}"),
+        CellValue::String(x) => format!("s{x}"),
     }
+}
 
-    pub async fn insert_cell(&self, sheetid: &SheetId, cell: &sheet::Cell) -> Result<()> {
-        let mut tr = self.pool.begin().await?;
+/// Orders two values of the same column the way sqlite does.
+fn compare(a: &CellValue, b: &CellValue) -> std::cmp::Ordering {
+    match (a, b) {
+        (CellValue::Boolean(a), CellValue::Boolean(b)) => a.cmp(b),
+        (CellValue::Int(a), CellValue::Int(b)) => a.cmp(b),
+        (CellValue::Double(a), CellValue::Double(b)) => a.total_cmp(b),
+        // sqlite compares text bytewise, and so does rust
+        (CellValue::String(a), CellValue::String(b)) => a.cmp(b),
+        _ => std::cmp::Ordering::Equal,
+    }
+}
 
-        if !Self::sheet_exists(&mut tr, sheetid).await? {
-            anyhow::bail!("sheet doesn't exist");
+/// Pushes a list of values and the closing parenthesis, for `IN (...)`.
+fn push_list<'a>(query: &mut QueryBuilder<'a, sqlx::Sqlite>, values: &BTreeSet<i64>) {
+    let mut separated = query.separated(", ");
+    for &value in values {
+        separated.push_bind(value);
+    }
+    query.push(")");
+}
+
+/// Pushes a list of `(col_id, row)` pairs and the closing parenthesis, for `(col_id, row) IN (VALUES ...)`.
+fn push_cells<'a>(
+    query: &mut QueryBuilder<'a, sqlx::Sqlite>,
+    cells: impl IntoIterator<Item = (i64, i64)>,
+) {
+    for (i, (col_id, row)) in cells.into_iter().enumerate() {
+        if i > 0 {
+            query.push(", ");
         }
+        query
+            .push("(")
+            .push_bind(col_id)
+            .push(", ")
+            .push_bind(row)
+            .push(")");
+    }
+    query.push(")");
+}
 
-        // this format is ok, since SheetId is sanitized when deserialized
-        let Some((col_id, kind)) = Self::get_column_by_name(&mut tr, sheetid, &cell.column).await?
-        else {
-            anyhow::bail!("invalid column name");
-        };
+/// A value which can be bound to a query by reference, for the parameters of filters.
+trait QueryParam: for<'q> sqlx::Encode<'q, sqlx::Sqlite> + sqlx::Type<sqlx::Sqlite> + Sync {}
 
-        if let Some(lookup) = cell.value.is_lookup() {
-            let Some((target_col_id, target_kind)) =
-                Self::get_column_by_name(&mut tr, sheetid, &lookup.target_col).await?
-            else {
-                anyhow::bail!("invalid target column name");
-            };
+impl<T: for<'q> sqlx::Encode<'q, sqlx::Sqlite> + sqlx::Type<sqlx::Sqlite> + Sync> QueryParam for T {}
 
-            if kind != target_kind {
-                anyhow::bail!("invalid target column type");
-            }
+type SqliteQuery<'q> = sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>;
 
-            if Self::detect_cycle(
-                &mut tr,
-                sheetid,
-                col_id,
-                cell.row,
-                target_col_id,
-                lookup.target_row,
-            )
+/// Binds a (possibly empty) value to the next parameter of a query.
+fn bind_value<'q>(query: SqliteQuery<'q>, value: Option<&'q CellValue>) -> SqliteQuery<'q> {
+    match value {
+        None => query.bind(None::<i64>),
+        Some(CellValue::Boolean(x)) => query.bind(*x),
+        Some(CellValue::Int(x)) => query.bind(*x),
+        Some(CellValue::Double(x)) => query.bind(*x),
+        Some(CellValue::String(x)) => query.bind(x.as_str()),
+    }
+}
+
+/// The outcome of a [`Db::maintenance`] run. Sizes are in bytes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    pub incremental: bool,
+    pub size_before: u64,
+    pub size_after: u64,
+    pub reclaimed: u64,
+}
+
+/// The outcome of a [`Db::check_consistency`] run.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    pub sheets_checked: u64,
+    pub violations: Vec<Violation>,
+    /// Whether the violations were repaired.
+    pub repaired: bool,
+}
+
+/// A broken invariant of a single cell.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub sheet_id: String,
+    pub kind: ViolationKind,
+    pub col_id: i64,
+    pub row: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// The cell has both a stored value and a lookup.
+    ValueAndLookup,
+    /// The cell is a lookup in or into a column which doesn't exist.
+    MissingColumn,
+    /// The cell's lookup chain leads back to itself.
+    Cycle,
+}
+
+/// The sqlite database holding every sheet.
+///
+/// Shared data (the `sheets` table, settings, validators, row metadata and so on) lives in fixed tables keyed
+/// by sheet id, but every sheet also gets its own tables: `sheet_<id>` for the values, with one `col<n>` column
+/// per schema column, and `sheet_<id>_columns`, `sheet_<id>_lookups` and `sheet_<id>_dependencies`. Since
+/// table names can't be bound as parameters, the queries on these tables are built with `format!`, which is
+/// safe only because [`SheetId`]s are sanitized when they're parsed.
+pub struct Db {
+    pool: SqlitePool,
+    /// Every sheet id in the `sheets` table, so that checking whether a sheet exists doesn't need a query.
+    /// This is only ever changed after the corresponding transaction has been committed.
+    known_sheets: RwLock<HashSet<String>>,
+    /// Every alias in the `redirects` table, from the alias to the id of its sheet and how it's answered. Like the
+    /// known sheets, this is kept in memory so that every request can be checked against it.
+    redirects: RwLock<HashMap<String, (String, sheet::AliasMode)>>,
+    /// Run on every cell before it's written, in order.
+    interceptors: Vec<Arc<dyn WriteInterceptor>>,
+}
+
+impl Db {
+    async fn new_inner(pool: SqlitePool) -> Result<Self> {
+        // create the initial "sheets" indexing table that we will use to easily check for column names.
+        // `IF NOT EXISTS` enables us to not worry if the database file is new or not.
+        sqlx::query(
+            "\
+                CREATE TABLE IF NOT EXISTS sheets(
+                    id      TEXT NOT NULL PRIMARY KEY
+                );",
+        )
+        .execute(&pool)
+        .await?;
+
+        Self::migrate(&pool).await?;
+        Self::with_known_sheets(pool).await
+    }
+
+    async fn with_known_sheets(pool: SqlitePool) -> Result<Self> {
+        let known_sheets = Self::load_known_sheets(&pool).await?;
+        let redirects = Self::load_redirects(&pool).await?;
+        Ok(Self {
+            pool,
+            known_sheets: RwLock::new(known_sheets),
+            redirects: RwLock::new(redirects),
+            interceptors: vec![],
+        })
+    }
+
+    /// Adds an interceptor, which sees (and may change or reject) every cell before it's written, after the
+    /// interceptors added before it.
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn WriteInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Passes a cell through every interceptor, returning it as it should be written.
+    async fn intercept(
+        interceptors: &[Arc<dyn WriteInterceptor>],
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        cell: &sheet::Cell,
+    ) -> Result<sheet::Cell> {
+        let mut cell = cell.clone();
+        for interceptor in interceptors {
+            let write = CellWrite {
+                sheet_id: sheetid,
+                cell: &mut cell,
+                conn: tr.as_mut(),
+            };
+            interceptor.intercept(write).await?;
+        }
+        Ok(cell)
+    }
+
+    async fn load_redirects(
+        pool: &SqlitePool,
+    ) -> Result<HashMap<String, (String, sheet::AliasMode)>> {
+        Ok(sqlx::query_as::<_, (String, String, bool)>("SELECT id, target, proxy FROM redirects;")
+            .fetch_all(pool)
             .await?
+            .into_iter()
+            .map(|(id, target, proxy)| (id, (target, alias_mode(proxy))))
+            .collect())
+    }
+
+    async fn load_known_sheets(pool: &SqlitePool) -> Result<HashSet<String>> {
+        Ok(sqlx::query_scalar::<_, String>("SELECT id FROM sheets;")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    /// Reloads the ids of all sheets, and the redirects between them. Sheets are only ever created through this
+    /// instance, except on replicas, whose database is written by the primary, so this is only needed there.
+    pub async fn refresh_known_sheets(&self) -> Result<()> {
+        let known_sheets = Self::load_known_sheets(&self.pool).await?;
+        let redirects = Self::load_redirects(&self.pool).await?;
+        *self.known_sheets.write().unwrap() = known_sheets;
+        *self.redirects.write().unwrap() = redirects;
+        Ok(())
+    }
+
+    /// The version [`Db::migrate`] brings databases to.
+    const SCHEMA_VERSION: i64 = 28;
+
+    /// Brings databases created by older versions up to date. The current version is tracked using
+    /// sqlite's `user_version` pragma, and every migration step is run in its own transaction.
+    async fn migrate(pool: &SqlitePool) -> Result<()> {
+        let version = sqlx::query_scalar::<_, i64>("PRAGMA user_version;")
+            .fetch_one(pool)
+            .await?;
+
+        if version < 1 {
+            // version 1 introduced the dependencies table, which we populate from the existing lookups
+            let mut tr = pool.begin().await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                Self::build_dependencies_table(&mut tr, &sheetid).await?;
+                sqlx::query(&format!(
+                    "INSERT INTO sheet_{0}_dependencies SELECT col_id, row, target_col_id, target_row FROM sheet_{0}_lookups;",
+                    sheetid.ident()
+                ))
+                .execute(tr.as_mut())
+                .await?;
+            }
+            sqlx::query("PRAGMA user_version = 1;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 2 {
+            // version 2 added the metadata used for listing sheets. existing sheets get the current time,
+            // since there's no way to know when they were actually created.
+            let mut tr = pool.begin().await?;
+            let now = now_millis();
+            for column in ["created_at", "updated_at", "cells"] {
+                sqlx::query(&format!(
+                    "ALTER TABLE sheets ADD COLUMN {column} INTEGER NOT NULL DEFAULT 0;"
+                ))
+                .execute(tr.as_mut())
+                .await?;
+            }
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                let cells = Self::count_cells(&mut tr, &sheetid).await?;
+                sqlx::query(
+                    "UPDATE sheets SET created_at = ?, updated_at = ?, cells = ? WHERE id = ?;",
+                )
+                .bind(now)
+                .bind(now)
+                .bind(cells)
+                .bind(&sheetid.0)
+                .execute(tr.as_mut())
+                .await?;
+            }
+            sqlx::query("PRAGMA user_version = 2;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 3 {
+            // version 3 added hidden columns
+            let mut tr = pool.begin().await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                sqlx::query(&format!(
+                    "ALTER TABLE sheet_{}_columns ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT FALSE;",
+                    sheetid.ident()
+                ))
+                .execute(tr.as_mut())
+                .await?;
+            }
+            sqlx::query("PRAGMA user_version = 3;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 4 {
+            // version 4 added notification rules
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE notification_rules(
+                    id          INTEGER NOT NULL PRIMARY KEY,
+                    sheet_id    TEXT    NOT NULL,
+                    rule        TEXT    NOT NULL
+                );
+                CREATE INDEX notification_rules_sheet_id ON notification_rules(sheet_id);",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 4;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 5 {
+            // version 5 added validation webhooks
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE validators(
+                    sheet_id    TEXT    NOT NULL PRIMARY KEY,
+                    url         TEXT    NOT NULL,
+                    timeout_ms  INTEGER NOT NULL,
+                    fail_open   BOOLEAN NOT NULL
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 5;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 6 {
+            // version 6 added row metadata
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE row_meta(
+                    sheet_id    TEXT    NOT NULL,
+                    row         INTEGER NOT NULL,
+                    key         TEXT    NOT NULL,
+                    value       TEXT    NOT NULL,
+                    PRIMARY KEY (sheet_id, row, key)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 6;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 7 {
+            // version 7 added column statistics, which are computed from the existing data
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE column_stats(
+                    sheet_id    TEXT    NOT NULL,
+                    col_id      INTEGER NOT NULL,
+                    count       INTEGER NOT NULL,
+                    sum         REAL,
+                    min,
+                    max,
+                    PRIMARY KEY (sheet_id, col_id)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                Self::rebuild_column_stats(&mut tr, &sheetid).await?;
+            }
+            sqlx::query("PRAGMA user_version = 7;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 8 {
+            // version 8 indexed lookups by row, for reading the last rows of a sheet
+            let mut tr = pool.begin().await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                Self::build_lookup_row_index(&mut tr, &sheetid).await?;
+            }
+            sqlx::query("PRAGMA user_version = 8;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 9 {
+            // version 9 added per-sheet settings
+            let mut tr = pool.begin().await?;
+            sqlx::query("ALTER TABLE sheets ADD COLUMN settings TEXT NOT NULL DEFAULT '{}';")
+                .execute(tr.as_mut())
+                .await?;
+            sqlx::query("PRAGMA user_version = 9;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 10 {
+            // version 10 added row keys
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE row_keys(
+                    sheet_id    TEXT    NOT NULL,
+                    row         INTEGER NOT NULL,
+                    key         TEXT    NOT NULL,
+                    PRIMARY KEY (sheet_id, row),
+                    UNIQUE (sheet_id, key)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 10;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 11 {
+            // version 11 added editing sessions
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE sessions(
+                    id          TEXT    PRIMARY KEY,
+                    sheet_id    TEXT    NOT NULL,
+                    expires_at  INTEGER NOT NULL
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query(
+                "\
+                CREATE TABLE session_cells(
+                    session_id  TEXT    NOT NULL,
+                    seq         INTEGER NOT NULL,
+                    cell        TEXT    NOT NULL,
+                    PRIMARY KEY (session_id, seq)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 11;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 12 {
+            // version 12 added versioning, for reading only what changed since a given version. cells which
+            // were written before don't have a version, and count as version 0.
+            let mut tr = pool.begin().await?;
+            sqlx::query("ALTER TABLE sheets ADD COLUMN version INTEGER NOT NULL DEFAULT 0;")
+                .execute(tr.as_mut())
+                .await?;
+            sqlx::query(
+                "\
+                CREATE TABLE cell_versions(
+                    sheet_id    TEXT    NOT NULL,
+                    col_id      INTEGER NOT NULL,
+                    row         INTEGER NOT NULL,
+                    version     INTEGER NOT NULL,
+                    PRIMARY KEY (sheet_id, col_id, row)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query(
+                "CREATE INDEX index_cell_versions_version ON cell_versions(sheet_id, version);",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 12;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 13 {
+            // version 13 added the event log. changes made before aren't in it, so the log of older sheets
+            // doesn't start at their creation.
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE events(
+                    sheet_id    TEXT    NOT NULL,
+                    seq         INTEGER NOT NULL,
+                    at          INTEGER NOT NULL,
+                    change      TEXT    NOT NULL,
+                    PRIMARY KEY (sheet_id, seq)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 13;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 14 {
+            // version 14 added traffic statistics, which are counted from then on
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE sheet_traffic(
+                    sheet_id        TEXT    NOT NULL PRIMARY KEY,
+                    reads           INTEGER NOT NULL,
+                    writes          INTEGER NOT NULL,
+                    bytes_served    INTEGER NOT NULL
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 14;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 15 {
+            // version 15 added tombstones, i.e. cells which were explicitly set to null
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE tombstones(
+                    sheet_id    TEXT    NOT NULL,
+                    col_id      INTEGER NOT NULL,
+                    row         INTEGER NOT NULL,
+                    PRIMARY KEY (sheet_id, col_id, row)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 15;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 16 {
+            // version 16 added the history of schemas. earlier changes weren't recorded, so the current schema
+            // of every sheet becomes its first version.
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE schema_versions(
+                    sheet_id    TEXT    NOT NULL,
+                    version     INTEGER NOT NULL,
+                    at          INTEGER NOT NULL,
+                    schema      TEXT    NOT NULL,
+                    PRIMARY KEY (sheet_id, version)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                Self::record_schema_version(&mut tr, &sheetid).await?;
+            }
+            sqlx::query("PRAGMA user_version = 16;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 17 {
+            // version 17 added column formats, stored as JSON
+            let mut tr = pool.begin().await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                sqlx::query(&format!(
+                    "ALTER TABLE sheet_{}_columns ADD COLUMN format TEXT;",
+                    sheetid.ident()
+                ))
+                .execute(tr.as_mut())
+                .await?;
+            }
+            sqlx::query("PRAGMA user_version = 17;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 18 {
+            // version 18 added autoincrement columns, whose last assigned values are kept here
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE sequences(
+                    sheet_id    TEXT    NOT NULL,
+                    col_id      INTEGER NOT NULL,
+                    last        INTEGER NOT NULL,
+                    PRIMARY KEY (sheet_id, col_id)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 18;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 19 {
+            // version 19 added the per-sheet NO_LOOKUP_NULLS. existing sheets keep following the server-wide one.
+            let mut tr = pool.begin().await?;
+            sqlx::query("ALTER TABLE sheets ADD COLUMN no_lookup_nulls BOOLEAN;")
+                .execute(tr.as_mut())
+                .await?;
+            sqlx::query("PRAGMA user_version = 19;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 20 {
+            // version 20 added redirects from the old ids of sheets whose id was rotated
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE redirects(
+                    id          TEXT    NOT NULL PRIMARY KEY,
+                    target      TEXT    NOT NULL
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 20;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 21 {
+            // version 21 added aliases which are answered like the sheet they lead to, rather than redirected
+            let mut tr = pool.begin().await?;
+            sqlx::query("ALTER TABLE redirects ADD COLUMN proxy BOOLEAN NOT NULL DEFAULT FALSE;")
+                .execute(tr.as_mut())
+                .await?;
+            sqlx::query("PRAGMA user_version = 21;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 22 {
+            // version 22 added the access log, which is only written if it's enabled
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE access_log(
+                    id          INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    sheet_id    TEXT    NOT NULL,
+                    reader      TEXT    NOT NULL,
+                    at          INTEGER NOT NULL,
+                    method      TEXT    NOT NULL,
+                    path        TEXT    NOT NULL,
+                    status      INTEGER NOT NULL
+                );
+                CREATE INDEX index_access_log_sheet_id ON access_log(sheet_id, id);",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 22;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 23 {
+            // version 23 added roles of principals on sheets
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE permissions(
+                    sheet_id    TEXT    NOT NULL,
+                    principal   TEXT    NOT NULL,
+                    role        TEXT    NOT NULL,
+                    PRIMARY KEY (sheet_id, principal)
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 23;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 24 {
+            // version 24 added columns of personal data
+            let mut tr = pool.begin().await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                sqlx::query(&format!(
+                    "ALTER TABLE sheet_{}_columns ADD COLUMN pii BOOLEAN NOT NULL DEFAULT FALSE;",
+                    sheetid.ident()
+                ))
+                .execute(tr.as_mut())
+                .await?;
+            }
+            sqlx::query("PRAGMA user_version = 24;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 25 {
+            // version 25 added references between columns of different sheets
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE column_references(
+                    sheet_id        TEXT    NOT NULL,
+                    col_id          INTEGER NOT NULL,
+                    target_sheet_id TEXT    NOT NULL,
+                    target_col_id   INTEGER NOT NULL,
+                    on_delete       TEXT,
+                    PRIMARY KEY (sheet_id, col_id)
+                );
+                CREATE INDEX index_column_references_target
+                    ON column_references(target_sheet_id, target_col_id);",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 25;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 26 {
+            // version 26 added view sheets
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE views(
+                    sheet_id        TEXT    NOT NULL PRIMARY KEY,
+                    source_id       TEXT    NOT NULL,
+                    definition      TEXT    NOT NULL,
+                    on_write        BOOLEAN NOT NULL,
+                    stale           BOOLEAN NOT NULL DEFAULT FALSE,
+                    refreshed_at    INTEGER NOT NULL
+                );
+                CREATE INDEX index_views_source_id ON views(source_id);",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 26;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 27 {
+            // version 27 added archived sheets
+            let mut tr = pool.begin().await?;
+            sqlx::query(
+                "\
+                CREATE TABLE archives(
+                    id          TEXT    NOT NULL PRIMARY KEY,
+                    archived_at INTEGER NOT NULL,
+                    cells       INTEGER NOT NULL,
+                    size        INTEGER NOT NULL,
+                    bundle      BLOB    NOT NULL
+                );",
+            )
+            .execute(tr.as_mut())
+            .await?;
+            sqlx::query("PRAGMA user_version = 27;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        if version < 28 {
+            // version 28 added the roots of lookup chains, for sheets which compact them
+            let mut tr = pool.begin().await?;
+            for sheetid in Self::all_sheet_ids(&mut tr).await? {
+                Self::add_lookup_roots(&mut tr, &sheetid).await?;
+            }
+            sqlx::query("PRAGMA user_version = 28;")
+                .execute(tr.as_mut())
+                .await?;
+            tr.commit().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Reclaims unused space and refreshes the query planner's statistics.
+    ///
+    /// A full run rebuilds the entire database file using `VACUUM`, which blocks all writers until it's
+    /// done. An incremental run only releases the free pages, which is much cheaper, but only has an
+    /// effect on databases using incremental auto-vacuum (which new databases do).
+    pub async fn maintenance(&self, incremental: bool) -> Result<MaintenanceReport> {
+        // everything has to happen on the same connection, otherwise in-memory databases would each
+        // measure a different database
+        let mut conn = self.pool.acquire().await?;
+
+        let size_before = Self::database_size(&mut conn).await?;
+        if incremental {
+            sqlx::query("PRAGMA incremental_vacuum;")
+                .execute(conn.as_mut())
+                .await?;
+        } else {
+            sqlx::query("VACUUM;").execute(conn.as_mut()).await?;
+        }
+        sqlx::query("ANALYZE;").execute(conn.as_mut()).await?;
+        let size_after = Self::database_size(&mut conn).await?;
+
+        Ok(MaintenanceReport {
+            incremental,
+            size_before,
+            size_after,
+            reclaimed: size_before.saturating_sub(size_after),
+        })
+    }
+
+    /// Writes a consistent copy of the whole database to `path`, replacing any file that's already there, and
+    /// returns its size in bytes. Writers only have to wait while the copy is being made, not while it's
+    /// being moved into place.
+    ///
+    /// The copy is made next to `path` first and then renamed, so that whatever picks up the file (e.g. for
+    /// shipping it to a standby) never sees a partial one.
+    pub async fn snapshot(&self, path: &std::path::Path) -> Result<u64> {
+        // in-memory databases copy into another in-memory database, rather than into a file
+        let file = sqlx::query_scalar::<_, String>(
+            "SELECT file FROM pragma_database_list WHERE name = 'main';",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if file.is_empty() {
+            anyhow::bail!("snapshots can't be taken of in-memory databases");
+        }
+
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = std::path::PathBuf::from(partial);
+
+        // `VACUUM INTO` refuses to overwrite files, and a partial file can only be left over from a failed run
+        match std::fs::remove_file(&partial) {
+            Err(why) if why.kind() != std::io::ErrorKind::NotFound => return Err(why.into()),
+            _ => {}
+        }
+
+        let target = partial
+            .to_str()
+            .with_context(|| format!("snapshot path {} isn't valid unicode", path.display()))?;
+        sqlx::query("VACUUM INTO ?;")
+            .bind(target)
+            .execute(&self.pool)
+            .await?;
+
+        std::fs::rename(&partial, path)?;
+        Ok(std::fs::metadata(path)?.len())
+    }
+
+    async fn database_size(conn: &mut sqlx::SqliteConnection) -> Result<u64> {
+        let page_count = sqlx::query_scalar::<_, i64>("PRAGMA page_count;")
+            .fetch_one(&mut *conn)
+            .await?;
+        let page_size = sqlx::query_scalar::<_, i64>("PRAGMA page_size;")
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok((page_count * page_size) as u64)
+    }
+
+    /// Checks the invariants of every sheet which writes are supposed to keep, and which may be broken after a
+    /// crash or a manual edit of the database: no cell is both a stored value and a lookup, no lookup is in or
+    /// into a column which doesn't exist, and no lookup chain is a cycle.
+    ///
+    /// If `repair` is set, the offending lookups are removed (for cycles, the lookup of a single cell in the
+    /// cycle is enough), so that stored values win over lookups. Every sheet is checked and repaired in its own
+    /// transaction.
+    pub async fn check_consistency(&self, repair: bool) -> Result<ConsistencyReport> {
+        let sheet_ids = {
+            let mut tr = self.pool.begin().await?;
+            Self::all_sheet_ids(&mut tr).await?
+        };
+
+        let mut violations = vec![];
+        for sheetid in &sheet_ids {
+            let mut tr = self.pool.begin().await?;
+            let found = Self::find_violations(&mut tr, sheetid).await?;
+            if repair && !found.is_empty() {
+                Self::repair_violations(&mut tr, sheetid, &found).await?;
+            }
+            tr.commit().await?;
+            violations.extend(found);
+        }
+
+        Ok(ConsistencyReport {
+            sheets_checked: sheet_ids.len() as u64,
+            repaired: repair && !violations.is_empty(),
+            violations,
+        })
+    }
+
+    async fn find_violations(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<Vec<Violation>> {
+        let column_table = Self::get_column_table(tr, sheetid).await?;
+        let column_name = |col_id: i64| {
+            usize::try_from(col_id)
+                .ok()
+                .and_then(|i| column_table.get(i))
+                .map(|column| column.name.as_str())
+        };
+        let violation = |kind, col_id, row, message| Violation {
+            sheet_id: sheetid.0.clone(),
+            kind,
+            col_id,
+            row,
+            message,
+        };
+
+        let lookups: HashMap<(i64, i64), (i64, i64)> =
+            sqlx::query_as::<_, (i64, i64, i64, i64)>(&format!(
+                "SELECT col_id, row, target_col_id, target_row FROM sheet_{}_lookups;",
+                sheetid.ident()
+            ))
+            .fetch_all(tr.as_mut())
+            .await?
+            .into_iter()
+            .map(|(col_id, row, target_col_id, target_row)| {
+                ((col_id, row), (target_col_id, target_row))
+            })
+            .collect();
+
+        let mut violations = vec![];
+        for col_id in 0..column_table.len() as i64 {
+            let rows = sqlx::query_scalar::<_, i64>(&format!(
+                "SELECT lookups.row FROM sheet_{0}_lookups AS lookups JOIN sheet_{0} AS sheet
+                ON sheet.row = lookups.row WHERE lookups.col_id = ? AND sheet.col{1} IS NOT NULL
+                ORDER BY lookups.row;",
+                sheetid.ident(),
+                col_id
+            ))
+            .bind(col_id)
+            .fetch_all(tr.as_mut())
+            .await?;
+            for row in rows {
+                let message = format!(
+                    "{}{row} has both a stored value and a lookup",
+                    column_name(col_id).unwrap_or_default()
+                );
+                violations.push(violation(ViolationKind::ValueAndLookup, col_id, row, message));
+            }
+        }
+
+        let mut sorted: Vec<_> = lookups.iter().collect();
+        sorted.sort();
+        for (&(col_id, row), &(target_col_id, target_row)) in &sorted {
+            let message = match (column_name(col_id), column_name(target_col_id)) {
+                (None, _) => format!("lookup in row {row} is in missing column {col_id}"),
+                (Some(name), None) => {
+                    format!(
+                        "{name}{row} looks up missing column {target_col_id} (row {target_row})"
+                    )
+                }
+                _ => continue,
+            };
+            violations.push(violation(ViolationKind::MissingColumn, col_id, row, message));
+        }
+
+        // every cell has at most one lookup, so each chain either ends or runs into a single cycle
+        let mut done = HashSet::new();
+        for &(&start, _) in &sorted {
+            let mut path = vec![];
+            let mut current = start;
+            while !done.contains(&current) {
+                if let Some(position) = path.iter().position(|&cell| cell == current) {
+                    let cycle = &path[position..];
+                    let &(col_id, row) = cycle.iter().min().unwrap();
+                    let message = format!(
+                        "{}{row} is part of a cycle of {} lookups",
+                        column_name(col_id).unwrap_or_default(),
+                        cycle.len()
+                    );
+                    violations.push(violation(ViolationKind::Cycle, col_id, row, message));
+                    break;
+                }
+                path.push(current);
+                match lookups.get(&current) {
+                    Some(&next) => current = next,
+                    None => break,
+                }
+            }
+            done.extend(path);
+        }
+
+        Ok(violations)
+    }
+
+    /// Removes the lookups of the offending cells, and brings everything derived from them up to date.
+    async fn repair_violations(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        violations: &[Violation],
+    ) -> Result<()> {
+        let version = sqlx::query_scalar::<_, i64>(
+            "UPDATE sheets SET version = version + 1 WHERE id = ? RETURNING version;",
+        )
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+
+        for violation in violations {
+            let cell = (violation.col_id, violation.row);
+            sqlx::query(&format!(
+                "DELETE FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
+                sheetid.ident()
+            ))
+            .bind(cell.0)
+            .bind(cell.1)
+            .execute(tr.as_mut())
+            .await?;
+            Self::set_dependencies(tr, sheetid, cell, &[]).await?;
+
+            // clients reading deltas have to see that the lookup is gone
+            sqlx::query(
+                "INSERT INTO cell_versions (sheet_id, col_id, row, version) VALUES (?, ?, ?, ?)
+                ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;",
+            )
+            .bind(&sheetid.0)
+            .bind(cell.0)
+            .bind(cell.1)
+            .bind(version)
+            .execute(tr.as_mut())
+            .await?;
+        }
+        // chains which went through the removed lookups (or were part of a cycle) end elsewhere now
+        Self::compact_lookups_in(tr, sheetid).await?;
+
+        let cells = Self::count_cells(tr, sheetid).await?;
+        sqlx::query("UPDATE sheets SET cells = ? WHERE id = ?;")
+            .bind(cells)
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+        Ok(())
+    }
+
+    /// Closes all of the connections, waiting for any in-flight transactions to finish first.
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    async fn all_sheet_ids(tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<Vec<SheetId>> {
+        Ok(sqlx::query_scalar::<_, String>("SELECT id FROM sheets;")
+            .fetch_all(tr.as_mut())
+            .await?
+            .into_iter()
+            .map(SheetId)
+            .collect())
+    }
+
+    /// Counts the populated cells of a sheet from scratch, including lookups.
+    async fn count_cells(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<i64> {
+        let columns = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT id FROM sheet_{}_columns;",
+            sheetid.ident()
+        ))
+        .fetch_all(tr.as_mut())
+        .await?;
+
+        let mut query = format!("SELECT (SELECT COUNT(*) FROM sheet_{}_lookups)", sheetid.ident());
+        for col_id in columns {
+            query += &format!(" + (SELECT COUNT(col{col_id}) FROM sheet_{})", sheetid.ident());
+        }
+
+        Ok(sqlx::query_scalar::<_, i64>(&query)
+            .fetch_one(tr.as_mut())
+            .await?)
+    }
+
+    /// Lists all of the sheets, sorted by the given key. Ties are broken by creation order.
+    pub async fn list_sheets(
+        &self,
+        sort: sheet::SheetSort,
+        descending: bool,
+    ) -> Result<Vec<sheet::SheetSummary>> {
+        let column = match sort {
+            sheet::SheetSort::CreatedAt => "created_at",
+            sheet::SheetSort::UpdatedAt => "updated_at",
+            sheet::SheetSort::Cells => "cells",
+        };
+        let order = if descending { "DESC" } else { "ASC" };
+
+        Ok(sqlx::query_as::<_, (String, i64, i64, i64)>(&format!(
+            "SELECT id, created_at, updated_at, cells FROM sheets ORDER BY {column} {order}, rowid {order};"
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(id, created_at, updated_at, cells)| sheet::SheetSummary {
+            id,
+            created_at,
+            updated_at,
+            cells,
+        })
+        .collect())
+    }
+
+    /// Creates a new Db instance using the given filename as the name of the sqlite database.
+    pub async fn new(filename: impl AsRef<std::path::Path>) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(filename)
+            // this is necessary so that we don't error when the file doesn't exist. we want to create it anyway
+            .create_if_missing(true)
+            // lets maintenance release free pages without rebuilding the whole file. this only applies to
+            // new databases (or after a full vacuum)
+            .auto_vacuum(SqliteAutoVacuum::Incremental)
+            .statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+
+        let pool = SqlitePool::connect_with(options).await?;
+
+        Self::new_inner(pool).await
+    }
+
+    /// Opens an existing database without ever writing to it, for replicas of a database which is written by
+    /// another instance. Since it can't be migrated, the database has to be at the version this one expects.
+    pub async fn new_read_only(filename: impl AsRef<std::path::Path>) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(filename)
+            .read_only(true)
+            .statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        let pool = SqlitePool::connect_with(options).await?;
+
+        let version = sqlx::query_scalar::<_, i64>("PRAGMA user_version;")
+            .fetch_one(&pool)
+            .await?;
+        if version != Self::SCHEMA_VERSION {
+            anyhow::bail!(
+                "the database is at version {version}, but version {} is expected",
+                Self::SCHEMA_VERSION
+            );
+        }
+
+        Self::with_known_sheets(pool).await
+    }
+
+    /// Creates a new Db instance which uses a database in-memory, to avoid creating files when testing.
+    pub async fn new_memory() -> Result<Self> {
+        let options = ":memory:"
+            .parse::<SqliteConnectOptions>()?
+            .statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        Self::new_inner(SqlitePool::connect_with(options).await?).await
+    }
+
+    /// Adds the id to the sheets table, returning `false` if it's already taken.
+    async fn register_sheetid(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<bool> {
+        // the old ids of moved sheets stay taken, so that their redirects keep working, and so do the ids of
+        // archived sheets, so that they can be rehydrated. ids are compared without case, since they end up in
+        // the names of tables, which sqlite doesn't tell apart by case, e.g. a slug and a generated id
+        Ok(sqlx::query(
+            "INSERT OR IGNORE INTO sheets (id, created_at, updated_at)
+            SELECT ?1, ?2, ?2 WHERE NOT EXISTS (SELECT 1 FROM sheets WHERE id = ?1 COLLATE NOCASE)
+            AND NOT EXISTS (SELECT 1 FROM redirects WHERE id = ?1 COLLATE NOCASE)
+            AND NOT EXISTS (SELECT 1 FROM archives WHERE id = ?1 COLLATE NOCASE) RETURNING id;",
+        )
+        .bind(&sheetid.0)
+        .bind(now_millis())
+        .fetch_optional(tr.as_mut())
+        .await?
+        .is_some())
+    }
+
+    async fn register_random_sheetid(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        scheme: IdScheme,
+    ) -> Result<SheetId> {
+        // loop is necessary in case of duplicates. again, astronomically low chance.
+        loop {
+            let sheetid = SheetId::generate(&mut rand::thread_rng(), scheme);
+
+            if Self::register_sheetid(tr, &sheetid).await? {
+                break Ok(sheetid);
+            }
+        }
+    }
+
+    /// Gives a sheet a new id generated using the given scheme, and returns it. The old id stops working, unless
+    /// `keep_redirect` is set, in which case it redirects to the new one (see [`Self::resolve_alias`]). Without a
+    /// redirect, the older aliases of the sheet are removed as well, since they would lead to the new id.
+    pub async fn rotate_id(
+        &self,
+        sheetid: &SheetId,
+        id_scheme: IdScheme,
+        keep_redirect: bool,
+    ) -> Result<SheetId> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let new_id = loop {
+            let new_id = SheetId::generate(&mut rand::thread_rng(), id_scheme);
+            // compared without case, like in [`Self::register_sheetid`]
+            let taken = sqlx::query_scalar::<_, i64>(
+                "SELECT EXISTS(SELECT 1 FROM sheets WHERE id = ?1 COLLATE NOCASE)
+                OR EXISTS(SELECT 1 FROM redirects WHERE id = ?1 COLLATE NOCASE)
+                OR EXISTS(SELECT 1 FROM archives WHERE id = ?1 COLLATE NOCASE);",
+            )
+            .bind(&new_id.0)
+            .fetch_one(tr.as_mut())
+            .await?
+                == 1;
+            if !taken {
+                break new_id;
+            }
+        };
+
+        sqlx::query("UPDATE sheets SET id = ? WHERE id = ?;")
+            .bind(&new_id.0)
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+        for table in Self::shared_tables(&mut tr).await? {
+            sqlx::query(&format!("UPDATE {table} SET sheet_id = ? WHERE sheet_id = ?;"))
+                .bind(&new_id.0)
+                .bind(&sheetid.0)
+                .execute(tr.as_mut())
+                .await?;
+        }
+        // references to the sheet and its views follow it to its new id
+        sqlx::query("UPDATE column_references SET target_sheet_id = ? WHERE target_sheet_id = ?;")
+            .bind(&new_id.0)
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+        sqlx::query("UPDATE views SET source_id = ? WHERE source_id = ?;")
+            .bind(&new_id.0)
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+
+        let (old, new) = (sheetid.ident(), new_id.ident());
+        for suffix in ["", "_columns", "_lookups", "_dependencies"] {
+            sqlx::query(&format!("ALTER TABLE sheet_{old}{suffix} RENAME TO sheet_{new}{suffix};"))
+                .execute(tr.as_mut())
+                .await?;
+        }
+        // indexes keep their names when their table is renamed, and those have to be unique per sheet too
+        for index in ["lookups", "lookups_row", "lookups_root", "dependencies"] {
+            sqlx::query(&format!("DROP INDEX index_sheet_{old}_{index};"))
+                .execute(tr.as_mut())
+                .await?;
+        }
+        sqlx::query(&format!(
+            "CREATE UNIQUE INDEX index_sheet_{new}_lookups ON sheet_{new}_lookups (col_id, row);
+            CREATE INDEX index_sheet_{new}_dependencies ON sheet_{new}_dependencies (col_id, row);"
+        ))
+        .execute(tr.as_mut())
+        .await?;
+        Self::build_lookup_row_index(&mut tr, &new_id).await?;
+        Self::build_lookup_root_index(&mut tr, &new_id).await?;
+
+        if keep_redirect {
+            sqlx::query("UPDATE redirects SET target = ?1 WHERE target = ?2;")
+                .bind(&new_id.0)
+                .bind(&sheetid.0)
+                .execute(tr.as_mut())
+                .await?;
+            sqlx::query("INSERT INTO redirects (id, target) VALUES (?, ?);")
+                .bind(&sheetid.0)
+                .bind(&new_id.0)
+                .execute(tr.as_mut())
+                .await?;
+        } else {
+            sqlx::query("DELETE FROM redirects WHERE target = ?;")
+                .bind(&sheetid.0)
+                .execute(tr.as_mut())
+                .await?;
+        }
+        tr.commit().await?;
+
+        let mut known_sheets = self.known_sheets.write().unwrap();
+        known_sheets.remove(&sheetid.0);
+        known_sheets.insert(new_id.0.clone());
+        let mut redirects = self.redirects.write().unwrap();
+        if keep_redirect {
+            for (target, _) in redirects
+                .values_mut()
+                .filter(|(target, _)| *target == sheetid.0)
+            {
+                target.clone_from(&new_id.0);
+            }
+            redirects.insert(sheetid.0.clone(), (new_id.0.clone(), sheet::AliasMode::Redirect));
+        } else {
+            redirects.retain(|_, (target, _)| *target != sheetid.0);
+        }
+
+        Ok(new_id)
+    }
+
+    /// Lists every table shared by all sheets which is keyed by sheet id, so that tables added later can't be
+    /// forgotten when a sheet is moved or archived as a whole.
+    async fn shared_tables(tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar::<_, String>(
+            "SELECT m.name FROM sqlite_master m JOIN pragma_table_info(m.name) p
+            WHERE m.type = 'table' AND p.name = 'sheet_id' ORDER BY m.name;",
+        )
+        .fetch_all(tr.as_mut())
+        .await?)
+    }
+
+    /// The tables which belong to a single sheet.
+    fn own_tables(sheetid: &SheetId) -> Vec<String> {
+        ["", "_columns", "_lookups", "_dependencies"]
+            .iter()
+            .map(|suffix| format!("sheet_{}{suffix}", sheetid.ident()))
+            .collect()
+    }
+
+    /// Moves a sheet to cold storage: everything stored about it is bundled into a single compressed row of the
+    /// archives table (see [`crate::archive`]), and its tables are dropped. The sheet stops existing until it's
+    /// brought back with [`Self::rehydrate`], but its id stays taken. Open editing sessions of the sheet are
+    /// discarded.
+    ///
+    /// # Errors
+    /// In case the sheet doesn't exist, another sheet references it or is a view of it, or a database failure.
+    pub async fn archive(&self, sheetid: &SheetId) -> Result<ArchivedSheet> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let has_dependents = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM column_references WHERE target_sheet_id = ?1 AND sheet_id != ?1)
+            OR EXISTS(SELECT 1 FROM views WHERE source_id = ?1);",
+        )
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        if has_dependents {
+            anyhow::bail!(
+                "the sheet can't be archived while other sheets reference it or are views of it"
+            );
+        }
+        // sessions would have expired by the time the sheet is rehydrated anyway
+        sqlx::query(
+            "DELETE FROM session_cells WHERE session_id IN (SELECT id FROM sessions WHERE sheet_id = ?);",
+        )
+        .bind(&sheetid.0)
+        .execute(tr.as_mut())
+        .await?;
+        sqlx::query("DELETE FROM sessions WHERE sheet_id = ?;")
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+
+        let own_tables = Self::own_tables(sheetid);
+        let mut query = QueryBuilder::new(
+            "SELECT sql FROM sqlite_master WHERE sql IS NOT NULL AND tbl_name IN (",
+        );
+        let mut names = query.separated(", ");
+        for table in &own_tables {
+            names.push_bind(table);
+        }
+        query.push(") ORDER BY type = 'index', rowid;");
+        let schema = query.build_query_scalar().fetch_all(tr.as_mut()).await?;
+
+        let shared_tables = Self::shared_tables(&mut tr).await?;
+        let mut tables = vec![Self::dump_rows(&mut tr, "sheets", Some(("id", sheetid))).await?];
+        for table in &shared_tables {
+            tables.push(Self::dump_rows(&mut tr, table, Some(("sheet_id", sheetid))).await?);
+        }
+        for table in &own_tables {
+            tables.push(Self::dump_rows(&mut tr, table, None).await?);
+        }
+        let bundle = Bundle {
+            version: Self::SCHEMA_VERSION,
+            schema,
+            tables,
+        }
+        .compress()?;
+
+        let cells = sqlx::query_scalar::<_, i64>("SELECT cells FROM sheets WHERE id = ?;")
+            .bind(&sheetid.0)
+            .fetch_one(tr.as_mut())
+            .await?;
+        for table in &shared_tables {
+            sqlx::query(&format!("DELETE FROM {table} WHERE sheet_id = ?;"))
+                .bind(&sheetid.0)
+                .execute(tr.as_mut())
+                .await?;
+        }
+        sqlx::query("DELETE FROM sheets WHERE id = ?;")
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+        for table in &own_tables {
+            sqlx::query(&format!("DROP TABLE {table};"))
+                .execute(tr.as_mut())
+                .await?;
+        }
+
+        let archived = ArchivedSheet {
+            id: sheetid.0.clone(),
+            archived_at: now_millis(),
+            cells,
+            size: bundle.len() as i64,
+        };
+        sqlx::query(
+            "INSERT INTO archives (id, archived_at, cells, size, bundle) VALUES (?, ?, ?, ?, ?);",
+        )
+        .bind(&archived.id)
+        .bind(archived.archived_at)
+        .bind(archived.cells)
+        .bind(archived.size)
+        .bind(bundle)
+        .execute(tr.as_mut())
+        .await?;
+        tr.commit().await?;
+
+        self.known_sheets.write().unwrap().remove(&sheetid.0);
+        Ok(archived)
+    }
+
+    /// Reads the rows of a table, or only those whose `column` is the given sheet's id.
+    async fn dump_rows(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        table: &str,
+        filter: Option<(&str, &SheetId)>,
+    ) -> Result<BundleTable> {
+        let columns =
+            sqlx::query_scalar::<_, String>("SELECT name FROM pragma_table_info(?) ORDER BY cid;")
+                .bind(table)
+                .fetch_all(tr.as_mut())
+                .await?;
+        let rows = match filter {
+            Some((column, sheetid)) => {
+                sqlx::query(&format!("SELECT * FROM {table} WHERE {column} = ?;"))
+                    .bind(&sheetid.0)
+                    .fetch_all(tr.as_mut())
+                    .await?
+            }
+            None => {
+                sqlx::query(&format!("SELECT * FROM {table};"))
+                    .fetch_all(tr.as_mut())
+                    .await?
+            }
+        };
+
+        let rows = rows
+            .iter()
+            .map(|row| {
+                (0..columns.len())
+                    .map(|i| {
+                        let value = row.try_get_raw(i)?;
+                        if value.is_null() {
+                            return Ok(BundleValue::Null);
+                        }
+                        // the type of the value itself, rather than the declared type of its column
+                        Ok(match value.type_info().name() {
+                            "INTEGER" => BundleValue::Integer(row.try_get(i)?),
+                            "REAL" => BundleValue::Real(row.try_get(i)?),
+                            "TEXT" => BundleValue::Text(row.try_get(i)?),
+                            other => {
+                                return Err(Corrupted(format!(
+                                    "unexpected {other} value in {table}"
+                                ))
+                                .into())
+                            }
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(BundleTable {
+            name: table.into(),
+            columns,
+            rows,
+        })
+    }
+
+    /// Lists the archived sheets, most recently archived first.
+    pub async fn archives(&self) -> Result<Vec<ArchivedSheet>> {
+        Ok(sqlx::query_as::<_, (String, i64, i64, i64)>(
+            "SELECT id, archived_at, cells, size FROM archives ORDER BY archived_at DESC, id;",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(id, archived_at, cells, size)| ArchivedSheet {
+            id,
+            archived_at,
+            cells,
+            size,
+        })
+        .collect())
+    }
+
+    /// Brings an archived sheet back, exactly as it was when it was archived. If it's a view, it becomes stale,
+    /// since its source may have changed in the meantime.
+    ///
+    /// # Errors
+    /// [`SheetNotFound`] if there's no such archived sheet, an error if a sheet it references or is a view of
+    /// doesn't exist (e.g. because it's archived itself), or a database failure.
+    pub async fn rehydrate(&self, sheetid: &SheetId) -> Result<()> {
+        let mut tr = self.pool.begin().await?;
+        let Some(compressed) =
+            sqlx::query_scalar::<_, Vec<u8>>("SELECT bundle FROM archives WHERE id = ?;")
+                .bind(&sheetid.0)
+                .fetch_optional(tr.as_mut())
+                .await?
+        else {
+            return Err(SheetNotFound.into());
+        };
+        let bundle = Bundle::decompress(&compressed).map_err(|why| Corrupted(why.to_string()))?;
+        if bundle.version > Self::SCHEMA_VERSION {
+            return Err(Corrupted(format!(
+                "the sheet was archived by a newer version (database version {})",
+                bundle.version
+            ))
+            .into());
+        }
+
+        for statement in &bundle.schema {
+            sqlx::query(statement).execute(tr.as_mut()).await?;
+        }
+        // the sheet's own tables are as old as the bundle, so they go through the migrations they missed
+        if bundle.version < 28 {
+            Self::add_lookup_roots(&mut tr, sheetid).await?;
+        }
+        for table in &bundle.tables {
+            // every row binds a value for every column. counts of requests made around the time the sheet was
+            // archived may have been written after it, and are replaced by the archived ones
+            let chunk_size = (INSERT_BATCH_CELLS / table.columns.len().max(1)).max(1);
+            for rows in table.rows.chunks(chunk_size) {
+                let mut query = QueryBuilder::new(format!(
+                    "INSERT OR REPLACE INTO {} ({}) ",
+                    table.name,
+                    table.columns.join(", ")
+                ));
+                query.push_values(rows, |mut b, row| {
+                    for value in row {
+                        match value {
+                            BundleValue::Null => b.push_bind(None::<i64>),
+                            BundleValue::Integer(x) => b.push_bind(*x),
+                            BundleValue::Real(x) => b.push_bind(*x),
+                            BundleValue::Text(x) => b.push_bind(x.clone()),
+                        };
+                    }
+                });
+                query.build().persistent(false).execute(tr.as_mut()).await?;
+            }
+        }
+        sqlx::query("DELETE FROM archives WHERE id = ?;")
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+
+        let dependencies = sqlx::query_scalar::<_, String>(
+            "SELECT target_sheet_id FROM column_references WHERE sheet_id = ?1 AND target_sheet_id != ?1
+            UNION SELECT source_id FROM views WHERE sheet_id = ?1;",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(tr.as_mut())
+        .await?;
+        if let Some(missing) = dependencies
+            .iter()
+            .find(|id| !self.sheet_exists(&SheetId((*id).clone())))
+        {
+            anyhow::bail!("the sheet depends on {missing}, which has to be rehydrated first");
+        }
+        sqlx::query("UPDATE views SET stale = TRUE WHERE sheet_id = ?;")
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+        Self::refresh_stale_views(&mut tr).await?;
+        tr.commit().await?;
+
+        self.known_sheets.write().unwrap().insert(sheetid.0.clone());
+        Ok(())
+    }
+
+    /// Returns the id of the sheet an alias leads to, and how requests for the alias are answered.
+    pub fn resolve_alias(&self, id: &str) -> Option<(String, sheet::AliasMode)> {
+        self.redirects.read().unwrap().get(id).cloned()
+    }
+
+    /// Registers another id which leads to a sheet.
+    ///
+    /// # Errors
+    /// In case the alias is already the id of a sheet or another alias ([`IdTaken`]), or a database failure.
+    pub async fn add_alias(
+        &self,
+        sheetid: &SheetId,
+        alias: &SheetId,
+        mode: sheet::AliasMode,
+    ) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        // compared without case, like in [`Self::register_sheetid`]
+        let inserted = sqlx::query(
+            "INSERT OR IGNORE INTO redirects (id, target, proxy)
+            SELECT ?1, ?2, ?3 WHERE NOT EXISTS (SELECT 1 FROM sheets WHERE id = ?1 COLLATE NOCASE)
+            AND NOT EXISTS (SELECT 1 FROM redirects WHERE id = ?1 COLLATE NOCASE)
+            AND NOT EXISTS (SELECT 1 FROM archives WHERE id = ?1 COLLATE NOCASE);",
+        )
+        .bind(&alias.0)
+        .bind(&sheetid.0)
+        .bind(mode == sheet::AliasMode::Proxy)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            == 1;
+        if !inserted {
+            return Err(IdTaken(alias.0.clone()).into());
+        }
+
+        self.redirects
+            .write()
+            .unwrap()
+            .insert(alias.0.clone(), (sheetid.0.clone(), mode));
+        Ok(())
+    }
+
+    /// Lists the aliases of a sheet, including the ones left behind by [`Self::rotate_id`].
+    pub async fn aliases(&self, sheetid: &SheetId) -> Result<Vec<sheet::SheetAlias>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        Ok(sqlx::query_as::<_, (String, bool)>(
+            "SELECT id, proxy FROM redirects WHERE target = ? ORDER BY id;",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(id, proxy)| sheet::SheetAlias {
+            id,
+            mode: alias_mode(proxy),
+        })
+        .collect())
+    }
+
+    /// Removes an alias of a sheet, returning whether it existed. The alias can then be used as a sheet id again.
+    pub async fn delete_alias(&self, sheetid: &SheetId, alias: &str) -> Result<bool> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let deleted = sqlx::query("DELETE FROM redirects WHERE id = ? AND target = ?;")
+            .bind(alias)
+            .bind(&sheetid.0)
+            .execute(&self.pool)
+            .await?
+            .rows_affected()
+            == 1;
+        if deleted {
+            self.redirects.write().unwrap().remove(alias);
+        }
+        Ok(deleted)
+    }
+
+    async fn build_columns_table(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        schema: &sheet::Schema,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "CREATE TABLE sheet_{}_columns(
+            id      INTEGER NOT NULL PRIMARY KEY,
+            name    TEXT    NOT NULL UNIQUE,
+            type    TEXT    NOT NULL,
+            hidden  BOOLEAN NOT NULL DEFAULT FALSE,
+            format  TEXT,
+            pii     BOOLEAN NOT NULL DEFAULT FALSE
+        );",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?;
+
+        let formats = schema
+            .columns
+            .iter()
+            .map(|col| col.format.as_ref().map(serde_json::to_string).transpose())
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        QueryBuilder::new(format!(
+            "INSERT INTO sheet_{}_columns (id, name, type, hidden, format, pii) ",
+            sheetid.ident()
+        ))
+        .push_values(schema.columns.iter().zip(formats).enumerate(), |mut b, (i, (col, format))| {
+            b.push_bind(i as i64)
+                .push_bind(&col.name)
+                .push_bind(col.kind.get_sql_text())
+                .push_bind(col.hidden)
+                .push_bind(format)
+                .push_bind(col.pii);
+        })
+        .build()
+        .execute(tr.as_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn build_sheet_table(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        schema: &sheet::Schema,
+    ) -> Result<()> {
+        Self::create_sheet_table(tr, &format!("sheet_{}", sheetid.ident()), &schema.columns).await
+    }
+
+    async fn create_sheet_table(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        name: &str,
+        columns: &[sheet::SchemaColumn],
+    ) -> Result<()> {
+        let mut builder = QueryBuilder::new(&format!("CREATE TABLE {name} ("));
+
+        // this essentially generates a bunch of columns like this:
+        // row INTEGER NOT NULL PRIMARY KEY,
+        // col0 TYPE,
+        // col1 TYPE,
+        // col2 TYPE,
+        // ..etc
+        let mut separated = builder.separated(", ");
+        separated.push("row INTEGER NOT NULL PRIMARY KEY");
+        for (i, col) in columns.iter().enumerate() {
+            separated.push(format_args!("col{} {}", i, col.kind.storage().get_sql_text()));
+        }
+        separated.push_unseparated(");");
+
+        builder.build().execute(tr.as_mut()).await?;
+
+        Ok(())
+    }
+
+    async fn build_lookup_table(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "CREATE TABLE sheet_{}_lookups(
+            col_id          INTEGER NOT NULL,
+            row             INTEGER NOT NULL,
+            target_col_id   INTEGER NOT NULL,
+            target_row      INTEGER NOT NULL,
+            root_col_id     INTEGER,
+            root_row        INTEGER
+        );",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?;
+
+        // index names are global to the database, so they have to be unique per sheet as well
+        sqlx::query(&format!(
+            "CREATE UNIQUE INDEX index_sheet_{0}_lookups ON sheet_{0}_lookups (col_id, row);",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?;
+
+        Self::build_lookup_row_index(tr, sheetid).await?;
+        Self::build_lookup_root_index(tr, sheetid).await
+    }
+
+    /// Adds the roots of lookup chains (see [`sheet::SheetSettings::compact_lookups`]) to a lookups table which
+    /// was created without them.
+    async fn add_lookup_roots(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        for column in ["root_col_id", "root_row"] {
+            sqlx::query(&format!(
+                "ALTER TABLE sheet_{}_lookups ADD COLUMN {column} INTEGER;",
+                sheetid.ident()
+            ))
+            .execute(tr.as_mut())
+            .await?;
+        }
+
+        Self::build_lookup_root_index(tr, sheetid).await
+    }
+
+    /// Finding every lookup whose chain ends at a cell is how compacted chains are kept up to date.
+    async fn build_lookup_root_index(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "CREATE INDEX index_sheet_{0}_lookups_root ON sheet_{0}_lookups (root_col_id, root_row);",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    /// The sheet table is already ordered by row, but the lookups table needs an index for that.
+    async fn build_lookup_row_index(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "CREATE INDEX index_sheet_{0}_lookups_row ON sheet_{0}_lookups (row);",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn build_dependencies_table(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        // unlike the lookups table, a cell may have any number of entries here (one per cell it depends on)
+        sqlx::query(&format!(
+            "CREATE TABLE sheet_{}_dependencies(
+            col_id          INTEGER NOT NULL,
+            row             INTEGER NOT NULL,
+            target_col_id   INTEGER NOT NULL,
+            target_row      INTEGER NOT NULL
+        );",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?;
+
+        sqlx::query(&format!(
+            "CREATE INDEX index_sheet_{0}_dependencies ON sheet_{0}_dependencies (col_id, row);",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Generates a new sheet according to the given schema. The sheet will have the given custom id if
+    /// there is one, or a unique id generated using the given scheme otherwise.
+    ///
+    /// # Errors
+    /// In case the schema is invalid, the custom id is taken ([`IdTaken`]), or a database failure.
+    pub async fn new_sheet(
+        &self,
+        schema: &sheet::Schema,
+        id_scheme: IdScheme,
+        custom_id: Option<SheetId>,
+        no_lookup_nulls: Option<bool>,
+    ) -> Result<SheetId> {
+        if !schema.is_valid() {
+            anyhow::bail!("Invalid schema");
+        }
+
+        // we need a transaction here, to make sure that a generated sheet id isn't accidentally taken by somebody
+        // else, causing a race condition. the chance of that happening is astronomically small, but not zero nonetheless.
+        let mut tr = self.pool.begin().await?;
+        let sheetid = self
+            .create_sheet_in(&mut tr, schema, id_scheme, custom_id, no_lookup_nulls)
+            .await?;
+        tr.commit().await?;
+        self.known_sheets.write().unwrap().insert(sheetid.0.clone());
+        Ok(sheetid)
+    }
+
+    /// Same as [`Self::new_sheet`], but also writes `cells` in order, as [`Self::insert_cells`] would. The sheet is
+    /// created and filled in a single transaction, so if any of the cells can't be written, the sheet isn't created
+    /// either.
+    ///
+    /// # Errors
+    /// Same as [`Self::new_sheet`], and same as [`Self::insert_cell`] for the first cell that can't be written, with
+    /// the offending cell added as context (e.g. `column B row 5`).
+    pub async fn new_sheet_with_cells(
+        &self,
+        schema: &sheet::Schema,
+        id_scheme: IdScheme,
+        custom_id: Option<SheetId>,
+        no_lookup_nulls: Option<bool>,
+        cells: &[sheet::Cell],
+        strict_lookups: bool,
+    ) -> Result<SheetId> {
+        if !schema.is_valid() {
+            anyhow::bail!("Invalid schema");
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let sheetid = self
+            .create_sheet_in(&mut tr, schema, id_scheme, custom_id, no_lookup_nulls)
+            .await?;
+        let context = |cell: &sheet::Cell| format!("column {} row {}", cell.column, cell.row);
+        Self::insert_cells_in(
+            &mut tr,
+            &sheetid,
+            cells,
+            strict_lookups,
+            Some(context),
+            &self.interceptors,
+        )
+        .await?;
+        tr.commit().await?;
+        self.known_sheets.write().unwrap().insert(sheetid.0.clone());
+        Ok(sheetid)
+    }
+
+    /// Creates the tables of a new sheet, which only becomes known once the transaction is committed.
+    async fn create_sheet_in(
+        &self,
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        schema: &sheet::Schema,
+        id_scheme: IdScheme,
+        custom_id: Option<SheetId>,
+        no_lookup_nulls: Option<bool>,
+    ) -> Result<SheetId> {
+        let sheetid = match custom_id {
+            Some(sheetid) => {
+                if !Self::register_sheetid(tr, &sheetid).await? {
+                    return Err(IdTaken(sheetid.0).into());
+                }
+                sheetid
+            }
+            None => Self::register_random_sheetid(tr, id_scheme).await?,
+        };
+
+        sqlx::query("UPDATE sheets SET no_lookup_nulls = ? WHERE id = ?;")
+            .bind(no_lookup_nulls)
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+
+        // this table is necessary because it's a bad idea to name the database columns using the names that the user gave us.
+        // instead we store the names as plain strings, and we'll use the id to derive a column name.
+        // the `UNIQUE` modifier implicitly creates an index, so later looking up column ids by name will be efficient.
+        Self::build_columns_table(tr, &sheetid, schema).await?;
+        self.build_references(tr, &sheetid, schema).await?;
+
+        // this is where we store the actual cell values, apart from lookup cells
+        Self::build_sheet_table(tr, &sheetid, schema).await?;
+
+        // this is where we store only the lookup cells. a cell cannot be in both the above table and this table.
+        Self::build_lookup_table(tr, &sheetid).await?;
+
+        // this is the dependency graph between cells, which is what we use to detect cycles.
+        Self::build_dependencies_table(tr, &sheetid).await?;
+
+        // these are the running aggregates of every column, which start out empty.
+        Self::rebuild_column_stats(tr, &sheetid).await?;
+
+        Self::record_schema_version(tr, &sheetid).await?;
+
+        let change = sheet::Change::Created {
+            schema: schema.clone(),
+        };
+        Self::append_event(tr, &sheetid, &change).await?;
+        Ok(sheetid)
+    }
+
+    /// Creates a view sheet of `source` (see [`sheet::view`]), which already has its content once it's created.
+    ///
+    /// # Errors
+    /// [`InvalidView`] with every problem of the definition, [`IdTaken`] if the custom id is taken, or a
+    /// database failure.
+    pub async fn new_view(
+        &self,
+        source: &SheetId,
+        definition: &ViewDefinition,
+        case_insensitive: bool,
+        id_scheme: IdScheme,
+        custom_id: Option<SheetId>,
+    ) -> Result<SheetId> {
+        if !self.sheet_exists(source) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let source_columns = Self::get_column_table(&mut tr, source).await?;
+        let schema = sheet::Schema {
+            columns: definition.validate(&source_columns).map_err(InvalidView)?,
+        };
+        if let Err(errors) = schema.validate(case_insensitive) {
+            // the names of the source's columns are valid already, so these can only be the names of aggregates
+            let errors = errors
+                .into_iter()
+                .map(|error| sheet::FieldError {
+                    path: match (&definition.group_by, error.column) {
+                        (Some(_), 0) => "group_by".into(),
+                        (Some(_), i) => format!("aggregates[{}].name", i - 1),
+                        (None, i) => format!("columns[{i}]"),
+                    },
+                    message: error.message,
+                })
+                .collect();
+            return Err(InvalidView(errors).into());
+        }
+
+        let view = self
+            .create_sheet_in(&mut tr, &schema, id_scheme, custom_id, None)
+            .await?;
+        sqlx::query(
+            "INSERT INTO views (sheet_id, source_id, definition, on_write, refreshed_at) VALUES (?, ?, ?, ?, ?);",
+        )
+        .bind(&view.0)
+        .bind(&source.0)
+        .bind(serde_json::to_string(definition)?)
+        .bind(definition.refresh == ViewRefresh::OnWrite)
+        .bind(now_millis())
+        .execute(tr.as_mut())
+        .await?;
+        Self::refresh_view_in(&mut tr, &view).await?;
+
+        tr.commit().await?;
+        self.known_sheets.write().unwrap().insert(view.0.clone());
+        Ok(view)
+    }
+
+    /// Returns the definition of a view sheet, or `None` if the sheet isn't a view.
+    pub async fn view(&self, sheetid: &SheetId) -> Result<Option<ViewInfo>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let view = sqlx::query_as::<_, (String, String, bool, i64)>(
+            "SELECT source_id, definition, stale, refreshed_at FROM views WHERE sheet_id = ?;",
+        )
+        .bind(&sheetid.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        view.map(|(source, definition, stale, refreshed_at)| {
+            Ok(ViewInfo {
+                source,
+                definition: serde_json::from_str(&definition)
+                    .map_err(|why| Corrupted(why.to_string()))?,
+                stale,
+                refreshed_at,
+            })
+        })
+        .transpose()
+    }
+
+    /// Computes the content of a view from its source again, along with the views which are refreshed on writes
+    /// and depend on it.
+    ///
+    /// # Errors
+    /// In case the sheet doesn't exist or isn't a view, or a database failure.
+    pub async fn refresh_view(&self, sheetid: &SheetId) -> Result<ViewInfo> {
+        if self.view(sheetid).await?.is_none() {
+            anyhow::bail!("the sheet isn't a view");
+        }
+
+        let mut tr = self.pool.begin().await?;
+        Self::refresh_view_in(&mut tr, sheetid).await?;
+        Self::refresh_stale_views(&mut tr).await?;
+        tr.commit().await?;
+
+        self.view(sheetid)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("the sheet isn't a view"))
+    }
+
+    /// Replaces all of the cells of a view with the ones computed from its source. Every cell of the view gets
+    /// the new version, so that reading the changes since an older version returns the whole view, and the
+    /// views of the view become stale in turn.
+    async fn refresh_view_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        view: &SheetId,
+    ) -> Result<()> {
+        let (source, definition) = sqlx::query_as::<_, (String, String)>(
+            "SELECT source_id, definition FROM views WHERE sheet_id = ?;",
+        )
+        .bind(&view.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        let source = SheetId(source);
+        let definition: ViewDefinition =
+            serde_json::from_str(&definition).map_err(|why| Corrupted(why.to_string()))?;
+
+        let source_columns = Self::get_column_table(tr, &source).await?;
+        let options = sheet::ReadOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let content =
+            Self::read_row_range(tr, &source, source_columns, Some((i64::MIN, i64::MAX)), options)
+                .await?;
+        let columns = Self::get_column_table(tr, view).await?;
+        let mut rows = definition.evaluate(&columns, &content);
+        rows.retain(|(_, values)| values.iter().any(Option::is_some));
+
+        let ident = view.ident();
+        sqlx::query(&format!("DELETE FROM sheet_{ident};"))
+            .execute(tr.as_mut())
+            .await?;
+        // every row binds its number and a value for every column
+        for chunk in rows.chunks((INSERT_BATCH_CELLS / (columns.len() + 1)).max(1)) {
+            let mut query = QueryBuilder::new(format!("INSERT INTO sheet_{ident} (row"));
+            for col_id in 0..columns.len() {
+                query.push(format_args!(", col{col_id}"));
+            }
+            query.push(") ");
+            query.push_values(chunk, |mut b, (row, values)| {
+                b.push_bind(*row);
+                for value in values {
+                    match value {
+                        Some(CellValue::Boolean(x)) => b.push_bind(*x),
+                        Some(CellValue::Int(x)) => b.push_bind(*x),
+                        Some(CellValue::Double(x)) => b.push_bind(*x),
+                        Some(CellValue::String(x)) => b.push_bind(x.clone()),
+                        None => b.push_bind(None::<i64>),
+                    };
+                }
+            });
+            query.build().persistent(false).execute(tr.as_mut()).await?;
+        }
+
+        let cells = rows
+            .iter()
+            .flat_map(|(_, values)| values)
+            .filter(|value| value.is_some())
+            .count();
+        let version = sqlx::query_scalar::<_, i64>(
+            "UPDATE sheets SET updated_at = ?, cells = ?, version = version + 1 WHERE id = ?
+            RETURNING version;",
+        )
+        .bind(now_millis())
+        .bind(cells as i64)
+        .bind(&view.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        // cells which were removed keep their entry, so that they're returned as changed to empty
+        sqlx::query("UPDATE cell_versions SET version = ? WHERE sheet_id = ?;")
+            .bind(version)
+            .bind(&view.0)
+            .execute(tr.as_mut())
+            .await?;
+        for col_id in 0..columns.len() {
+            sqlx::query(&format!(
+                "INSERT INTO cell_versions (sheet_id, col_id, row, version)
+                SELECT ?1, ?2, row, ?3 FROM sheet_{ident} WHERE col{col_id} IS NOT NULL
+                ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;"
+            ))
+            .bind(&view.0)
+            .bind(col_id as i64)
+            .bind(version)
+            .execute(tr.as_mut())
+            .await?;
+        }
+        Self::rebuild_column_stats(tr, view).await?;
+
+        sqlx::query("UPDATE views SET stale = FALSE, refreshed_at = ? WHERE sheet_id = ?;")
+            .bind(now_millis())
+            .bind(&view.0)
+            .execute(tr.as_mut())
+            .await?;
+        Self::mark_views_stale(tr, view).await
+    }
+
+    /// Marks the views of a sheet as stale, after its content changed.
+    async fn mark_views_stale(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        sqlx::query("UPDATE views SET stale = TRUE WHERE source_id = ?;")
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+        Ok(())
+    }
+
+    /// Refreshes every stale view which is refreshed on writes, including the views of views which go stale
+    /// along the way. This is done once at the end of every write, rather than along with every cell.
+    async fn refresh_stale_views(tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<()> {
+        while let Some(view) = sqlx::query_scalar::<_, String>(
+            "SELECT sheet_id FROM views WHERE stale AND on_write ORDER BY sheet_id LIMIT 1;",
+        )
+        .fetch_optional(tr.as_mut())
+        .await?
+        {
+            Self::refresh_view_in(tr, &SheetId(view)).await?;
+        }
+        Ok(())
+    }
+
+    /// Rejects writes to view sheets, whose content only changes when they're refreshed.
+    async fn check_writable(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        let is_view =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM views WHERE sheet_id = ?);")
+                .bind(&sheetid.0)
+                .fetch_one(tr.as_mut())
+                .await?;
+        if is_view {
+            return Err(ReadOnlyView.into());
+        }
+        Ok(())
+    }
+
+    /// Records the references of a new sheet's columns, after checking that the referenced columns exist and
+    /// store the same type of values.
+    ///
+    /// # Errors
+    /// [`InvalidReferences`] with every reference that can't be created, or a database failure.
+    async fn build_references(
+        &self,
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        schema: &sheet::Schema,
+    ) -> Result<()> {
+        let mut errors = vec![];
+        for (i, col) in schema.columns.iter().enumerate() {
+            let Some(reference) = &col.references else {
+                continue;
+            };
+            let mut error = |message: &str| {
+                errors.push(sheet::SchemaError {
+                    path: format!("columns[{i}].references"),
+                    column: i,
+                    name: col.name.clone(),
+                    message: message.into(),
+                })
+            };
+
+            let target = match SheetId::try_from(reference.sheet.as_str()) {
+                Ok(target) if self.sheet_exists(&target) => target,
+                _ => {
+                    error("the referenced sheet doesn't exist");
+                    continue;
+                }
+            };
+            if Self::check_writable(tr, &target).await.is_err() {
+                error("views can't be referenced, since their values change whenever they're refreshed");
+                continue;
+            }
+            let Some((target_col_id, target_kind)) =
+                Self::get_column_by_name(tr, &target, &reference.column).await?
+            else {
+                error("the referenced column doesn't exist");
+                continue;
+            };
+            if target_kind.storage() != col.kind.storage() {
+                error("the referenced column has a different type");
+                continue;
+            }
+
+            sqlx::query(
+                "INSERT INTO column_references (sheet_id, col_id, target_sheet_id, target_col_id, on_delete)
+                VALUES (?, ?, ?, ?, ?);",
+            )
+            .bind(&sheetid.0)
+            .bind(i as i64)
+            .bind(&target.0)
+            .bind(target_col_id)
+            .bind(reference.on_delete.map(|on_delete| on_delete.get_sql_text()))
+            .execute(tr.as_mut())
+            .await?;
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidReferences(errors).into())
+        }
+    }
+
+    /// Returns the column which a column references, if it has a reference.
+    async fn get_reference(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+    ) -> Result<Option<(SheetId, i64)>> {
+        Ok(sqlx::query_as::<_, (String, i64)>(
+            "SELECT target_sheet_id, target_col_id FROM column_references WHERE sheet_id = ? AND col_id = ?;",
+        )
+        .bind(&sheetid.0)
+        .bind(col_id)
+        .fetch_optional(tr.as_mut())
+        .await?
+        .map(|(target, target_col_id)| (SheetId(target), target_col_id)))
+    }
+
+    /// Checks that a plain value written to a column with a reference exists in the referenced column.
+    async fn check_reference(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        cell: &sheet::Cell,
+    ) -> Result<()> {
+        let Some((target, target_col_id)) = Self::get_reference(tr, sheetid, col_id).await? else {
+            return Ok(());
+        };
+
+        let value = cell.value.unescaped();
+        if !Self::value_exists(tr, &target, target_col_id, &value).await? {
+            let target_column = Self::get_column_name(tr, &target, target_col_id).await?;
+            return Err(ReferenceError::Dangling {
+                column: cell.column.clone(),
+                value,
+                target_sheet: target.0,
+                target_column,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks whether a value is stored in a column. Lookups which resolve to the value don't count.
+    async fn value_exists(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        value: &CellValue,
+    ) -> Result<bool> {
+        let query = format!(
+            "SELECT EXISTS(SELECT 1 FROM sheet_{} WHERE col{} = ?);",
+            sheetid.ident(),
+            col_id
+        );
+        let query = sqlx::query_scalar::<_, bool>(&query);
+        let query = match value {
+            CellValue::Boolean(x) => query.bind(*x),
+            CellValue::Double(x) => query.bind(*x),
+            CellValue::Int(x) => query.bind(*x),
+            CellValue::String(x) => query.bind(x.clone()),
+        };
+        Ok(query.fetch_one(tr.as_mut()).await?)
+    }
+
+    /// Applies the `on_delete` of every reference to a column, after `value` was removed from one of its cells.
+    /// Nothing happens while another cell of the column still holds the value.
+    ///
+    /// # Errors
+    /// [`ReferenceError::Restricted`] if a restricting reference still holds the value, or a database failure.
+    async fn enforce_references(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        value: &CellValue,
+    ) -> Result<()> {
+        let references = sqlx::query_as::<_, (String, i64, String)>(
+            "SELECT sheet_id, col_id, on_delete FROM column_references
+            WHERE target_sheet_id = ? AND target_col_id = ? AND on_delete IS NOT NULL
+            ORDER BY sheet_id, col_id;",
+        )
+        .bind(&sheetid.0)
+        .bind(col_id)
+        .fetch_all(tr.as_mut())
+        .await?;
+        if references.is_empty() || Self::value_exists(tr, sheetid, col_id, value).await? {
+            return Ok(());
+        }
+
+        for (referencing, referencing_col_id, on_delete) in references {
+            let referencing = SheetId(referencing);
+            let on_delete = sheet::OnDelete::from_sql_text(&on_delete)
+                .ok_or_else(|| Corrupted(format!("unknown on_delete {on_delete}")))?;
+            let query = format!(
+                "SELECT row FROM sheet_{} WHERE col{} = ? ORDER BY row;",
+                referencing.ident(),
+                referencing_col_id
+            );
+            let query = sqlx::query_scalar::<_, i64>(&query);
+            let query = match value {
+                CellValue::Boolean(x) => query.bind(*x),
+                CellValue::Double(x) => query.bind(*x),
+                CellValue::Int(x) => query.bind(*x),
+                CellValue::String(x) => query.bind(x.clone()),
+            };
+            let rows = query.fetch_all(tr.as_mut()).await?;
+            let Some(&first) = rows.first() else {
+                continue;
+            };
+
+            let referencing_column =
+                Self::get_column_name(tr, &referencing, referencing_col_id).await?;
+            match on_delete {
+                sheet::OnDelete::Restrict => {
+                    let column = Self::get_column_name(tr, sheetid, col_id).await?;
+                    return Err(ReferenceError::Restricted {
+                        column,
+                        value: value.clone(),
+                        referencing_sheet: referencing.0,
+                        referencing_column,
+                        referencing_row: first,
+                    }
+                    .into());
+                }
+                sheet::OnDelete::Cascade => {
+                    for row in rows {
+                        // the cascade may continue into the sheets referencing this one
+                        Box::pin(Self::insert_tombstone_in(
+                            tr,
+                            &referencing,
+                            &referencing_column,
+                            row,
+                        ))
+                        .await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_column_name(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+    ) -> Result<String> {
+        Ok(sqlx::query_scalar::<_, String>(&format!(
+            "SELECT name FROM sheet_{}_columns WHERE id = ?;",
+            sheetid.ident()
+        ))
+        .bind(col_id)
+        .fetch_one(tr.as_mut())
+        .await?)
+    }
+
+    async fn get_column_by_name(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        name: &str,
+    ) -> Result<Option<(i64, SchemaColumnKind)>> {
+        // column names are stored normalized, so the given name has to be normalized as well to match
+        sqlx::query_as::<_, (i64, String)>(&format!(
+            "SELECT id, type FROM sheet_{}_columns WHERE name = ?;",
+            sheetid.ident()
+        ))
+        .bind(name.nfc().collect::<String>())
+        .fetch_optional(tr.as_mut())
+        .await?
+        .map(|(id, kind)| Ok((id, parse_kind(&kind)?)))
+        .transpose()
+    }
+
+    /// Checks whether making `origin` depend on all of `targets` would introduce a cycle into the
+    /// dependency graph, using a depth-first search from the targets.
+    async fn detect_cycle(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        origin: (i64, i64),
+        targets: &[(i64, i64)],
+    ) -> Result<bool> {
+        let query = format!(
+            "SELECT target_col_id, target_row FROM sheet_{}_dependencies WHERE col_id = ? AND row = ?;",
+            sheetid.ident()
+        );
+
+        let mut visited = HashSet::new();
+        let mut stack = targets.to_vec();
+        while let Some(key) = stack.pop() {
+            if key == origin {
+                return Ok(true);
+            }
+
+            // cells may be reachable through more than one path, but we only need to expand them once
+            if !visited.insert(key) {
+                continue;
+            }
+
+            stack.extend(
+                sqlx::query_as::<_, (i64, i64)>(&query)
+                    .bind(key.0)
+                    .bind(key.1)
+                    .fetch_all(tr.as_mut())
+                    .await?,
+            );
+        }
+
+        Ok(false)
+    }
+
+    /// Replaces all of the outgoing edges of `origin` in the dependency graph with `targets`.
+    async fn set_dependencies(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        origin: (i64, i64),
+        targets: &[(i64, i64)],
+    ) -> Result<()> {
+        sqlx::query(&format!(
+            "DELETE FROM sheet_{}_dependencies WHERE col_id = ? AND row = ?;",
+            sheetid.ident()
+        ))
+        .bind(origin.0)
+        .bind(origin.1)
+        .execute(tr.as_mut())
+        .await?;
+
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        QueryBuilder::new(format!(
+            "INSERT INTO sheet_{}_dependencies (col_id, row, target_col_id, target_row) ",
+            sheetid.ident()
+        ))
+        .push_values(targets, |mut b, target| {
+            b.push_bind(origin.0)
+                .push_bind(origin.1)
+                .push_bind(target.0)
+                .push_bind(target.1);
+        })
+        .build()
+        .execute(tr.as_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    /// The root of the lookup chain starting at a cell (the cell at its end), if the sheet compacts its lookup
+    /// chains (see [`sheet::SheetSettings::compact_lookups`]) and the cell is a lookup.
+    async fn lookup_root(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        (col_id, row): (i64, i64),
+    ) -> Result<Option<(i64, i64)>> {
+        Ok(sqlx::query_as::<_, (i64, i64)>(&format!(
+            "SELECT root_col_id, root_row FROM sheet_{}_lookups
+            WHERE col_id = ? AND row = ? AND root_col_id IS NOT NULL;",
+            sheetid.ident()
+        ))
+        .bind(col_id)
+        .bind(row)
+        .fetch_optional(tr.as_mut())
+        .await?)
+    }
+
+    /// Brings the roots of compacted lookup chains up to date after the lookup of `cell` changed, where
+    /// `old_root` is the root of its chain before the change (if it was a lookup), and `new_root` the root
+    /// after it (if it still is one). Every chain going through the cell now ends at the new root, or at the
+    /// cell itself if it's no longer a lookup.
+    async fn relink_lookups(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        cell: (i64, i64),
+        old_root: Option<(i64, i64)>,
+        new_root: Option<(i64, i64)>,
+    ) -> Result<()> {
+        let root = new_root.unwrap_or(cell);
+        let Some(old_root) = old_root else {
+            // the cell used to end the chains going through it, so those are the ones rooted at it
+            sqlx::query(&format!(
+                "UPDATE sheet_{}_lookups SET root_col_id = ?1, root_row = ?2
+                WHERE (root_col_id = ?3 AND root_row = ?4) OR (col_id = ?3 AND row = ?4);",
+                sheetid.ident()
+            ))
+            .bind(root.0)
+            .bind(root.1)
+            .bind(cell.0)
+            .bind(cell.1)
+            .execute(tr.as_mut())
+            .await?;
+            return Ok(());
+        };
+
+        // otherwise, they are among the chains which shared its old root, and are the ones reaching the cell
+        let candidates: HashMap<(i64, i64), (i64, i64)> =
+            sqlx::query_as::<_, (i64, i64, i64, i64)>(&format!(
+                "SELECT col_id, row, target_col_id, target_row FROM sheet_{}_lookups
+                WHERE root_col_id = ? AND root_row = ?;",
+                sheetid.ident()
+            ))
+            .bind(old_root.0)
+            .bind(old_root.1)
+            .fetch_all(tr.as_mut())
+            .await?
+            .into_iter()
+            .map(|(a, b, c, d)| ((a, b), (c, d)))
+            .collect();
+        let mut reaches = HashMap::from([(cell, true)]);
+        for &start in candidates.keys() {
+            let mut path = vec![];
+            let mut current = start;
+            let reached = loop {
+                if let Some(&reached) = reaches.get(&current) {
+                    break reached;
+                }
+                match candidates.get(&current) {
+                    Some(&target) => {
+                        path.push(current);
+                        current = target;
+                    }
+                    None => break false,
+                }
+            };
+            reaches.extend(path.into_iter().map(|cell| (cell, reached)));
+        }
+
+        for (col_id, row) in reaches
+            .into_iter()
+            .filter(|(cell, reached)| *reached && candidates.contains_key(cell))
+            .map(|(cell, _)| cell)
+        {
+            sqlx::query(&format!(
+                "UPDATE sheet_{}_lookups SET root_col_id = ?, root_row = ? WHERE col_id = ? AND row = ?;",
+                sheetid.ident()
+            ))
+            .bind(root.0)
+            .bind(root.1)
+            .bind(col_id)
+            .bind(row)
+            .execute(tr.as_mut())
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the root of every lookup chain of a sheet from scratch, if the sheet compacts its lookup chains,
+    /// and forgets them otherwise.
+    async fn compact_lookups_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        if !Self::get_settings_in(tr, sheetid).await?.compact_lookups {
+            sqlx::query(&format!(
+                "UPDATE sheet_{}_lookups SET root_col_id = NULL, root_row = NULL
+                WHERE root_col_id IS NOT NULL;",
+                sheetid.ident()
+            ))
+            .execute(tr.as_mut())
+            .await?;
+            return Ok(());
+        }
+
+        // every chain is followed only until it meets one whose root is already known, so this is linear
+        let lookups = Self::get_lookups(tr.as_mut(), sheetid).await?;
+        let mut roots = HashMap::with_capacity(lookups.len());
+        for &start in lookups.keys() {
+            let mut path = vec![];
+            let mut current = start;
+            // lookup chains are guaranteed to be acyclic, so this always ends
+            let root = loop {
+                if let Some(&root) = roots.get(&current) {
+                    break root;
+                }
+                match lookups.get(&current) {
+                    Some(&target) => {
+                        path.push(current);
+                        current = target;
+                    }
+                    None => break current,
+                }
+            };
+            roots.extend(path.into_iter().map(|cell| (cell, root)));
+        }
+
+        for ((col_id, row), (root_col_id, root_row)) in roots {
+            sqlx::query(&format!(
+                "UPDATE sheet_{}_lookups SET root_col_id = ?, root_row = ? WHERE col_id = ? AND row = ?;",
+                sheetid.ident()
+            ))
+            .bind(root_col_id)
+            .bind(root_row)
+            .bind(col_id)
+            .bind(row)
+            .execute(tr.as_mut())
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    fn sheet_exists(&self, sheetid: &SheetId) -> bool {
+        self.known_sheets.read().unwrap().contains(&sheetid.0)
+    }
+
+    async fn cell_is_populated(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        row: i64,
+    ) -> Result<bool> {
+        Ok(sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT EXISTS(SELECT 1 FROM sheet_{0} WHERE row = ? AND NOT col{1} IS NULL)
+            OR EXISTS(SELECT 1 FROM sheet_{0}_lookups WHERE col_id = ? AND row = ?);",
+            sheetid.ident(),
+            col_id
+        ))
+        .bind(row)
+        .bind(col_id)
+        .bind(row)
+        .fetch_one(tr.as_mut())
+        .await?
+            == 1)
+    }
+
+    /// Sets the value of a single cell, after passing it through the interceptors (see [`Self::with_interceptor`]).
+    ///
+    /// If `strict_lookups` is set, lookup cells pointing at currently empty cells are rejected.
+    ///
+    /// Returns the key of the cell's row, if it has one (see [`sheet::SheetSettings::row_keys`]).
+    ///
+    /// # Errors
+    /// In case the sheet/column doesn't exist, the value doesn't match the column type, an interceptor rejects
+    /// the cell, or a database failure. Invalid lookups produce a [`LookupError`].
+    pub async fn insert_cell(
+        &self,
+        sheetid: &SheetId,
+        cell: &sheet::Cell,
+        strict_lookups: bool,
+    ) -> Result<Option<String>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+
+        let cell = Self::intercept(&self.interceptors, &mut tr, sheetid, cell).await?;
+        let row_key = Self::insert_cell_in(&mut tr, sheetid, &cell, strict_lookups).await?;
+        Self::refresh_stale_views(&mut tr).await?;
+        tr.commit().await?;
+        Ok(row_key)
+    }
+
+    async fn insert_cell_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        cell: &sheet::Cell,
+        strict_lookups: bool,
+    ) -> Result<Option<String>> {
+        // this format is ok, since SheetId is sanitized when deserialized
+        let Some((col_id, kind)) = Self::get_column_by_name(tr, sheetid, &cell.column).await?
+        else {
+            anyhow::bail!("invalid column name");
+        };
+        if kind == SchemaColumnKind::Autoincrement {
+            anyhow::bail!("autoincrement columns can't be written");
+        }
+        Self::check_writable(tr, sheetid).await?;
+        Self::check_row_bounds(tr, sheetid, cell.row).await?;
+
+        let was_populated = Self::cell_is_populated(tr, sheetid, col_id, cell.row).await?;
+        let old_value = Self::get_stored_value(tr, sheetid, col_id, kind, cell.row).await?;
+        let mut widened = false;
+        Self::remove_tombstone(tr, sheetid, col_id, cell.row).await?;
+
+        // the value that ends up stored in the sheet table, which lookups never are
+        let new_value = if let Some(lookup) = cell.value.is_lookup() {
+            // references are checked against plain values only
+            if Self::get_reference(tr, sheetid, col_id).await?.is_some() {
+                return Err(ReferenceError::Lookup {
+                    column: cell.column.clone(),
+                }
+                .into());
+            }
+
+            let Some((target_col_id, target_kind)) =
+                Self::get_column_by_name(tr, sheetid, &lookup.target_col).await?
+            else {
+                return Err(LookupError::UnknownTargetColumn {
+                    target_column: lookup.target_col,
+                }
+                .into());
+            };
+
+            if kind != target_kind {
+                return Err(LookupError::IncompatibleKinds {
+                    column: cell.column.clone(),
+                    column_kind: kind,
+                    target_column: lookup.target_col,
+                    target_kind,
+                }
+                .into());
+            }
+
+            if col_id == target_col_id && cell.row == lookup.target_row {
+                return Err(LookupError::SelfReference {
+                    column: cell.column.clone(),
+                    row: cell.row,
+                }
+                .into());
+            }
+
+            if strict_lookups
+                && !Self::cell_is_populated(tr, sheetid, target_col_id, lookup.target_row).await?
+            {
+                return Err(LookupError::EmptyTarget {
+                    target_column: lookup.target_col,
+                    target_row: lookup.target_row,
+                }
+                .into());
+            }
+
+            let origin = (col_id, cell.row);
+            let targets = [(target_col_id, lookup.target_row)];
+            let compact = Self::get_settings_in(tr, sheetid).await?.compact_lookups;
+            let old_root = Self::lookup_root(tr, sheetid, origin).await?;
+            let new_root = match compact {
+                true => Some(
+                    Self::lookup_root(tr, sheetid, targets[0])
+                        .await?
+                        .unwrap_or(targets[0]),
+                ),
+                false => None,
+            };
+            // with compacted chains, the target's chain can only go through the cell if it ends at the cell, or
+            // where the cell's own chain ends
+            let cycle = match new_root {
+                Some(root) if root == origin => true,
+                Some(root) if old_root != Some(root) => false,
+                _ => Self::detect_cycle(tr, sheetid, origin, &targets).await?,
+            };
+            if cycle {
+                return Err(LookupError::Cycle {
+                    column: cell.column.clone(),
+                    row: cell.row,
+                }
+                .into());
+            }
+
+            sqlx::query(&format!(
+                "UPDATE sheet_{} SET col{} = NULL WHERE row = ?;",
+                sheetid.ident(),
+                col_id
+            ))
+            .bind(cell.row)
+            .execute(&mut **tr)
+            .await?;
+
+            sqlx::query(&format!(
+                "INSERT INTO sheet_{}_lookups
+            (col_id, row, target_col_id, target_row)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(col_id, row)
+            DO UPDATE SET target_col_id = excluded.target_col_id,
+            target_row = excluded.target_row;",
+                sheetid.ident()
+            ))
+            .bind(col_id)
+            .bind(cell.row)
+            .bind(target_col_id)
+            .bind(lookup.target_row)
+            .execute(&mut **tr)
+            .await?;
+
+            Self::set_dependencies(tr, sheetid, origin, &targets).await?;
+            if compact {
+                Self::relink_lookups(tr, sheetid, origin, old_root, new_root).await?;
+            }
+            None
+        } else {
+            check_finite(&cell.value)?;
+            let value_kind = SchemaColumnKind::from(&cell.value);
+            if kind.storage() != value_kind {
+                let widen = kind == SchemaColumnKind::Int
+                    && value_kind == SchemaColumnKind::Double
+                    && Self::get_settings_in(tr, sheetid).await?.widen_types;
+                if !widen {
+                    anyhow::bail!("invalid column type");
+                }
+                Self::widen_column(tr, sheetid, col_id).await?;
+                widened = true;
+            }
+            if let CellValue::String(text) = cell.value.unescaped() {
+                kind.check_text(&text).map_err(anyhow::Error::msg)?;
+            }
+            Self::check_reference(tr, sheetid, col_id, cell).await?;
+
+            // we can't have an entry for the same cell in both tables
+            let old_root = Self::lookup_root(tr, sheetid, (col_id, cell.row)).await?;
+            sqlx::query(&format!(
+                "DELETE FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
+                sheetid.ident()
+            ))
+            .bind(col_id)
+            .bind(cell.row)
+            .execute(&mut **tr)
+            .await?;
+            Self::set_dependencies(tr, sheetid, (col_id, cell.row), &[]).await?;
+            if old_root.is_some() {
+                Self::relink_lookups(tr, sheetid, (col_id, cell.row), old_root, None).await?;
+            }
+
+            // again, the format is OK since everything is sanitized
+            let query = format!("INSERT INTO sheet_{0} (row, col{1}) VALUES(?, ?) ON CONFLICT(row) DO UPDATE SET col{1} = excluded.col{1};", sheetid.ident(), col_id);
+            let query = sqlx::query(&query).bind(cell.row);
+
+            // this is needed because they all have different types
+            let value = cell.value.unescaped();
+            let query = match &value {
+                CellValue::Boolean(x) => query.bind(x),
+                CellValue::Double(x) => query.bind(x),
+                CellValue::Int(x) => query.bind(x),
+                CellValue::String(x) => query.bind(x),
+            };
+
+            query.execute(&mut **tr).await?;
+            Some(value)
+        };
+
+        Self::update_column_stats(tr, sheetid, col_id, old_value.as_ref(), new_value.as_ref())
+            .await?;
+        if let Some(old_value) = old_value.filter(|old_value| new_value.as_ref() != Some(old_value))
+        {
+            Self::enforce_references(tr, sheetid, col_id, &old_value).await?;
+        }
+
+        let version = sqlx::query_scalar::<_, i64>(
+            "UPDATE sheets SET updated_at = ?, cells = cells + ?, version = version + 1 WHERE id = ?
+            RETURNING version;",
+        )
+        .bind(now_millis())
+        .bind(i64::from(!was_populated))
+        .bind(&sheetid.0)
+        .fetch_one(&mut **tr)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO cell_versions (sheet_id, col_id, row, version) VALUES (?, ?, ?, ?)
+            ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;",
+        )
+        .bind(&sheetid.0)
+        .bind(col_id)
+        .bind(cell.row)
+        .bind(version)
+        .execute(&mut **tr)
+        .await?;
+        if widened {
+            // every value of the column is now a double, which clients have to be told about
+            sqlx::query(&format!(
+                "INSERT INTO cell_versions (sheet_id, col_id, row, version)
+                SELECT ?1, ?2, row, ?3 FROM sheet_{0} WHERE col{1} IS NOT NULL
+                UNION SELECT ?1, ?2, row, ?3 FROM sheet_{0}_lookups WHERE col_id = ?2
+                ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;",
+                sheetid.ident(),
+                col_id
+            ))
+            .bind(&sheetid.0)
+            .bind(col_id)
+            .bind(version)
+            .execute(&mut **tr)
+            .await?;
+        }
+
+        Self::assign_sequence_numbers(tr, sheetid, cell.row, version).await?;
+
+        let change = sheet::Change::CellWritten { cell: cell.clone() };
+        Self::append_event(tr, sheetid, &change).await?;
+
+        Self::row_key(tr, sheetid, cell.row).await
+    }
+
+    /// Explicitly sets a cell to null, which unlike a cell that was never written (or one which was cleared) is
+    /// part of the sheet's content, as a cell without a value. This replaces the value or lookup of the cell,
+    /// and lookups pointing at it resolve to null.
+    ///
+    /// # Errors
+    /// In case the sheet/column doesn't exist, the value is still referenced by another sheet (see
+    /// [`sheet::OnDelete::Restrict`]), or a database failure.
+    pub async fn insert_tombstone(&self, sheetid: &SheetId, column: &str, row: i64) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        Self::insert_tombstone_in(&mut tr, sheetid, column, row).await?;
+        Self::refresh_stale_views(&mut tr).await?;
+        tr.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_tombstone_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        column: &str,
+        row: i64,
+    ) -> Result<()> {
+        let Some((col_id, kind)) = Self::get_column_by_name(tr, sheetid, column).await? else {
+            anyhow::bail!("invalid column name");
+        };
+        if kind == SchemaColumnKind::Autoincrement {
+            anyhow::bail!("autoincrement columns can't be written");
+        }
+        Self::check_writable(tr, sheetid).await?;
+        Self::check_row_bounds(tr, sheetid, row).await?;
+
+        let was_populated = Self::empty_cell_in(tr, sheetid, col_id, kind, row).await?;
+        sqlx::query("INSERT OR IGNORE INTO tombstones (sheet_id, col_id, row) VALUES (?, ?, ?);")
+            .bind(&sheetid.0)
+            .bind(col_id)
+            .bind(row)
+            .execute(tr.as_mut())
+            .await?;
+
+        let version = sqlx::query_scalar::<_, i64>(
+            "UPDATE sheets SET updated_at = ?, cells = cells - ?, version = version + 1 WHERE id = ?
+            RETURNING version;",
+        )
+        .bind(now_millis())
+        .bind(i64::from(was_populated))
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        sqlx::query(
+            "INSERT INTO cell_versions (sheet_id, col_id, row, version) VALUES (?, ?, ?, ?)
+            ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;",
+        )
+        .bind(&sheetid.0)
+        .bind(col_id)
+        .bind(row)
+        .bind(version)
+        .execute(tr.as_mut())
+        .await?;
+
+        Self::assign_sequence_numbers(tr, sheetid, row, version).await?;
+
+        let change = sheet::Change::TombstoneWritten {
+            column: column.nfc().collect(),
+            row,
+        };
+        Self::append_event(tr, sheetid, &change).await
+    }
+
+    /// Empties a cell, removing its value, lookup or tombstone, so that it's as if it had never been written.
+    /// Lookups pointing at it resolve to nothing. Clearing a cell which is already empty does nothing.
+    ///
+    /// # Errors
+    /// In case the sheet/column doesn't exist, the column is an autoincrement column, the sheet is a view, the
+    /// value is still referenced by another sheet (see [`sheet::OnDelete::Restrict`]), or a database failure.
+    pub async fn clear_cell(&self, sheetid: &SheetId, column: &str, row: i64) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        Self::clear_cell_in(&mut tr, sheetid, column, row).await?;
+        Self::refresh_stale_views(&mut tr).await?;
+        tr.commit().await?;
+        Ok(())
+    }
+
+    async fn clear_cell_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        column: &str,
+        row: i64,
+    ) -> Result<()> {
+        let Some((col_id, kind)) = Self::get_column_by_name(tr, sheetid, column).await? else {
+            anyhow::bail!("invalid column name");
+        };
+        // their values are assigned once per row, and a cleared one would never be assigned again
+        if kind == SchemaColumnKind::Autoincrement {
+            anyhow::bail!("autoincrement columns can't be cleared");
+        }
+        Self::check_writable(tr, sheetid).await?;
+
+        let had_tombstone = Self::remove_tombstone(tr, sheetid, col_id, row).await?;
+        let was_populated = Self::empty_cell_in(tr, sheetid, col_id, kind, row).await?;
+        if !had_tombstone && !was_populated {
+            return Ok(());
+        }
+
+        let version = sqlx::query_scalar::<_, i64>(
+            "UPDATE sheets SET updated_at = ?, cells = cells - ?, version = version + 1 WHERE id = ?
+            RETURNING version;",
+        )
+        .bind(now_millis())
+        .bind(i64::from(was_populated))
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        // clients reading deltas have to see that the cell is gone
+        sqlx::query(
+            "INSERT INTO cell_versions (sheet_id, col_id, row, version) VALUES (?, ?, ?, ?)
+            ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;",
+        )
+        .bind(&sheetid.0)
+        .bind(col_id)
+        .bind(row)
+        .bind(version)
+        .execute(tr.as_mut())
+        .await?;
+
+        let change = sheet::Change::CellCleared {
+            column: column.nfc().collect(),
+            row,
+        };
+        Self::append_event(tr, sheetid, &change).await
+    }
+
+    /// Removes the value or lookup of a cell, keeping the statistics, lookup chains and references to the
+    /// column up to date. Returns whether the cell was populated.
+    async fn empty_cell_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        kind: SchemaColumnKind,
+        row: i64,
+    ) -> Result<bool> {
+        let was_populated = Self::cell_is_populated(tr, sheetid, col_id, row).await?;
+        let old_value = Self::get_stored_value(tr, sheetid, col_id, kind, row).await?;
+        sqlx::query(&format!(
+            "UPDATE sheet_{} SET col{} = NULL WHERE row = ?;",
+            sheetid.ident(),
+            col_id
+        ))
+        .bind(row)
+        .execute(tr.as_mut())
+        .await?;
+        let old_root = Self::lookup_root(tr, sheetid, (col_id, row)).await?;
+        sqlx::query(&format!(
+            "DELETE FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
+            sheetid.ident()
+        ))
+        .bind(col_id)
+        .bind(row)
+        .execute(tr.as_mut())
+        .await?;
+        Self::set_dependencies(tr, sheetid, (col_id, row), &[]).await?;
+        if old_root.is_some() {
+            Self::relink_lookups(tr, sheetid, (col_id, row), old_root, None).await?;
+        }
+        Self::update_column_stats(tr, sheetid, col_id, old_value.as_ref(), None).await?;
+        if let Some(old_value) = &old_value {
+            Self::enforce_references(tr, sheetid, col_id, old_value).await?;
+        }
+        Ok(was_populated)
+    }
+
+    /// Gives a row the next value of every autoincrement column which it doesn't have a value in yet, i.e.
+    /// when it's first written to. The values are part of the sheet's version `version`, along with the write
+    /// that caused them.
+    async fn assign_sequence_numbers(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        row: i64,
+        version: i64,
+    ) -> Result<()> {
+        let col_ids = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT id FROM sheet_{}_columns WHERE type = ?;",
+            sheetid.ident()
+        ))
+        .bind(SchemaColumnKind::Autoincrement.get_sql_text())
+        .fetch_all(tr.as_mut())
+        .await?;
+
+        for col_id in col_ids {
+            if Self::cell_is_populated(tr, sheetid, col_id, row).await? {
+                continue;
+            }
+
+            let value = sqlx::query_scalar::<_, i64>(
+                "INSERT INTO sequences (sheet_id, col_id, last) VALUES (?, ?, 1)
+                ON CONFLICT(sheet_id, col_id) DO UPDATE SET last = last + 1
+                RETURNING last;",
+            )
+            .bind(&sheetid.0)
+            .bind(col_id)
+            .fetch_one(tr.as_mut())
+            .await?;
+
+            sqlx::query(&format!(
+                "INSERT INTO sheet_{0} (row, col{1}) VALUES (?, ?) ON CONFLICT(row) DO UPDATE SET col{1} = excluded.col{1};",
+                sheetid.ident(),
+                col_id
+            ))
+            .bind(row)
+            .bind(value)
+            .execute(tr.as_mut())
+            .await?;
+            Self::update_column_stats(tr, sheetid, col_id, None, Some(&CellValue::Int(value)))
+                .await?;
+
+            sqlx::query("UPDATE sheets SET cells = cells + 1 WHERE id = ?;")
+                .bind(&sheetid.0)
+                .execute(tr.as_mut())
+                .await?;
+            sqlx::query(
+                "INSERT INTO cell_versions (sheet_id, col_id, row, version) VALUES (?, ?, ?, ?)
+                ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;",
+            )
+            .bind(&sheetid.0)
+            .bind(col_id)
+            .bind(row)
+            .bind(version)
+            .execute(tr.as_mut())
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the cell had a tombstone.
+    async fn remove_tombstone(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        row: i64,
+    ) -> Result<bool> {
+        let removed =
+            sqlx::query("DELETE FROM tombstones WHERE sheet_id = ? AND col_id = ? AND row = ?;")
+                .bind(&sheetid.0)
+                .bind(col_id)
+                .bind(row)
+                .execute(tr.as_mut())
+                .await?
+                .rows_affected();
+        Ok(removed > 0)
+    }
+
+    /// Adds a change to the end of a sheet's event log, as part of the transaction which makes it.
+    async fn append_event(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        change: &sheet::Change,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO events (sheet_id, seq, at, change)
+            SELECT ?1, COALESCE(MAX(seq), 0) + 1, ?2, ?3 FROM events WHERE sheet_id = ?1;",
+        )
+        .bind(&sheetid.0)
+        .bind(now_millis())
+        .bind(serde_json::to_string(change)?)
+        .execute(tr.as_mut())
+        .await?;
+        if matches!(
+            change,
+            sheet::Change::CellWritten { .. }
+                | sheet::Change::TombstoneWritten { .. }
+                | sheet::Change::CellCleared { .. }
+        ) {
+            Self::mark_views_stale(tr, sheetid).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads up to `limit` events of a sheet's log which come after `after_seq`, in order. Along with the
+    /// events, this returns the cursor to pass as `after_seq` to get the next ones, or `None` if these were the
+    /// last.
+    pub async fn events(
+        &self,
+        sheetid: &SheetId,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<(Vec<sheet::Event>, Option<i64>)> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        // one more event than needed tells whether there are more
+        let mut events = sqlx::query_as::<_, (i64, i64, String)>(
+            "SELECT seq, at, change FROM events WHERE sheet_id = ? AND seq > ? ORDER BY seq LIMIT ?;",
+        )
+        .bind(&sheetid.0)
+        .bind(after_seq)
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(seq, at, change)| {
+            let change = serde_json::from_str(&change)
+                .map_err(|why| Corrupted(format!("invalid event {seq}: {why}")))?;
+            Ok(sheet::Event { seq, at, change })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+        let next_cursor = if events.len() as i64 > limit {
+            events.truncate(limit as usize);
+            events.last().map(|event| event.seq)
+        } else {
+            None
+        };
+        Ok((events, next_cursor))
+    }
+
+    /// Adds traffic counted since the last call to the statistics of each sheet.
+    pub async fn add_traffic(
+        &self,
+        traffic: &HashMap<String, crate::traffic::SheetTraffic>,
+    ) -> Result<()> {
+        let mut tr = self.pool.begin().await?;
+        for (sheet_id, traffic) in traffic {
+            sqlx::query(
+                "INSERT INTO sheet_traffic (sheet_id, reads, writes, bytes_served) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (sheet_id) DO UPDATE SET
+                    reads = reads + ?2, writes = writes + ?3, bytes_served = bytes_served + ?4;",
+            )
+            .bind(sheet_id)
+            .bind(traffic.reads as i64)
+            .bind(traffic.writes as i64)
+            .bind(traffic.bytes_served as i64)
+            .execute(tr.as_mut())
+            .await?;
+        }
+        tr.commit().await?;
+
+        Ok(())
+    }
+
+    /// Appends requests recorded since the last call to the access logs of their sheets.
+    pub async fn add_access_events(
+        &self,
+        events: &[(String, crate::traffic::AccessEvent)],
+    ) -> Result<()> {
+        let mut tr = self.pool.begin().await?;
+        for (sheet_id, event) in events {
+            sqlx::query(
+                "INSERT INTO access_log (sheet_id, reader, at, method, path, status)
+                VALUES (?, ?, ?, ?, ?, ?);",
+            )
+            .bind(sheet_id)
+            .bind(&event.reader)
+            .bind(event.at)
+            .bind(&event.method)
+            .bind(&event.path)
+            .bind(event.status)
+            .execute(tr.as_mut())
+            .await?;
+        }
+        tr.commit().await?;
+
+        Ok(())
+    }
+
+    /// Reads the access log of a sheet from after the entry with the given id, oldest first, along with the id to
+    /// continue from if there are more entries.
+    pub async fn access_log(
+        &self,
+        sheetid: &SheetId,
+        after_id: i64,
+        limit: i64,
+    ) -> Result<(Vec<sheet::AccessLogEntry>, Option<i64>)> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        // one more entry than needed tells whether there are more
+        let mut entries = sqlx::query_as::<_, (i64, String, i64, String, String, u16)>(
+            "SELECT id, reader, at, method, path, status FROM access_log
+            WHERE sheet_id = ? AND id > ? ORDER BY id LIMIT ?;",
+        )
+        .bind(&sheetid.0)
+        .bind(after_id)
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(id, reader, at, method, path, status)| sheet::AccessLogEntry {
+            id,
+            event: crate::traffic::AccessEvent {
+                reader,
+                at,
+                method,
+                path,
+                status,
+            },
+        })
+        .collect::<Vec<_>>();
+
+        let next_cursor = if entries.len() as i64 > limit {
+            entries.truncate(limit as usize);
+            entries.last().map(|entry| entry.id)
+        } else {
+            None
+        };
+        Ok((entries, next_cursor))
+    }
+
+    /// Returns the sheets with the most traffic, busiest first.
+    pub async fn traffic(
+        &self,
+        order: crate::traffic::TrafficOrder,
+        limit: i64,
+    ) -> Result<Vec<crate::traffic::TrafficEntry>> {
+        let order = match order {
+            crate::traffic::TrafficOrder::Requests => "reads + writes",
+            crate::traffic::TrafficOrder::BytesServed => "bytes_served",
+        };
+
+        let entries = sqlx::query_as::<_, (String, i64, i64, i64)>(&format!(
+            "SELECT sheet_id, reads, writes, bytes_served FROM sheet_traffic
+            ORDER BY {order} DESC, sheet_id LIMIT ?;"
+        ))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(sheet_id, reads, writes, bytes_served)| crate::traffic::TrafficEntry {
+            sheet_id,
+            traffic: crate::traffic::SheetTraffic {
+                reads: reads as u64,
+                writes: writes as u64,
+                bytes_served: bytes_served as u64,
+            },
+        })
+        .collect();
+
+        Ok(entries)
+    }
+
+    /// Returns the key of a row, generating one first if the sheet has row keys and the row doesn't have one.
+    async fn row_key(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        row: i64,
+    ) -> Result<Option<String>> {
+        let key = sqlx::query_scalar::<_, String>(
+            "SELECT key FROM row_keys WHERE sheet_id = ? AND row = ?;",
+        )
+        .bind(&sheetid.0)
+        .bind(row)
+        .fetch_optional(tr.as_mut())
+        .await?;
+        if key.is_some() {
+            return Ok(key);
+        }
+
+        let Some(scheme) = Self::get_settings_in(tr, sheetid).await?.row_keys else {
+            return Ok(None);
+        };
+        let key = SheetId::generate(&mut rand::thread_rng(), scheme).0;
+        sqlx::query("INSERT INTO row_keys (sheet_id, row, key) VALUES (?, ?, ?);")
+            .bind(&sheetid.0)
+            .bind(row)
+            .bind(&key)
+            .execute(tr.as_mut())
+            .await?;
+        Ok(Some(key))
+    }
+
+    /// Finds the row with the given key.
+    pub async fn row_by_key(&self, sheetid: &SheetId, key: &str) -> Result<Option<i64>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        Ok(sqlx::query_scalar::<_, i64>("SELECT row FROM row_keys WHERE sheet_id = ? AND key = ?;")
+            .bind(&sheetid.0)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// Fetches the keys of every row of a sheet, or only of an inclusive range of rows.
+    async fn get_row_keys<'e>(
+        executor: impl sqlx::SqliteExecutor<'e>,
+        sheetid: &SheetId,
+        rows: Option<(i64, i64)>,
+    ) -> Result<BTreeMap<i64, String>> {
+        let (first, last) = rows.unwrap_or((i64::MIN, i64::MAX));
+        Ok(sqlx::query_as::<_, (i64, String)>(
+            "SELECT row, key FROM row_keys WHERE sheet_id = ? AND row BETWEEN ? AND ?;",
+        )
+        .bind(&sheetid.0)
+        .bind(first)
+        .bind(last)
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .collect())
+    }
+
+    /// Opens an editing session on a sheet, which expires after `ttl` unless it's committed or aborted first.
+    pub async fn open_session(
+        &self,
+        sheetid: &SheetId,
+        ttl: std::time::Duration,
+    ) -> Result<sheet::Session> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+
+        // expired sessions are only ever cleaned up here, since they're invisible anyway
+        let now = now_millis();
+        sqlx::query(
+            "DELETE FROM session_cells WHERE session_id IN (SELECT id FROM sessions WHERE expires_at <= ?);",
+        )
+        .bind(now)
+        .execute(tr.as_mut())
+        .await?;
+        sqlx::query("DELETE FROM sessions WHERE expires_at <= ?;")
+            .bind(now)
+            .execute(tr.as_mut())
+            .await?;
+
+        let session = sheet::Session {
+            id: SheetId::generate(&mut rand::thread_rng(), IdScheme::Alphanumeric).0,
+            expires_at: now.saturating_add(ttl.as_millis() as i64),
+        };
+        sqlx::query("INSERT INTO sessions (id, sheet_id, expires_at) VALUES (?, ?, ?);")
+            .bind(&session.id)
+            .bind(&sheetid.0)
+            .bind(session.expires_at)
+            .execute(tr.as_mut())
+            .await?;
+
+        tr.commit().await?;
+        Ok(session)
+    }
+
+    /// Fails with [`SessionNotFound`] unless the session is open on this sheet.
+    async fn check_session(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        session: &str,
+    ) -> Result<()> {
+        let open = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM sessions WHERE id = ? AND sheet_id = ? AND expires_at > ?;",
+        )
+        .bind(session)
+        .bind(&sheetid.0)
+        .bind(now_millis())
+        .fetch_one(tr.as_mut())
+        .await?;
+
+        if open == 0 {
+            return Err(SessionNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Adds a write to a session. It isn't checked against the sheet until the session is committed.
+    pub async fn stage_cell(
+        &self,
+        sheetid: &SheetId,
+        session: &str,
+        cell: &sheet::Cell,
+    ) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        // staged cells are stored as JSON, which can't hold them
+        check_finite(&cell.value)?;
+        let mut tr = self.pool.begin().await?;
+        Self::check_session(&mut tr, sheetid, session).await?;
+        sqlx::query(
+            "INSERT INTO session_cells (session_id, seq, cell)
+            SELECT ?1, COALESCE(MAX(seq), -1) + 1, ?2 FROM session_cells WHERE session_id = ?1;",
+        )
+        .bind(session)
+        .bind(serde_json::to_string(cell)?)
+        .execute(tr.as_mut())
+        .await?;
+
+        tr.commit().await?;
+        Ok(())
+    }
+
+    /// Returns the writes staged in a session, in the order they were made.
+    async fn staged_cells(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        session: &str,
+    ) -> Result<Vec<sheet::Cell>> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT cell FROM session_cells WHERE session_id = ? ORDER BY seq;",
+        )
+        .bind(session)
+        .fetch_all(tr.as_mut())
+        .await?
+        .iter()
+        .map(|cell| {
+            serde_json::from_str(cell)
+                .map_err(|why| Corrupted(format!("staged cell: {why}")).into())
+        })
+        .collect()
+    }
+
+    async fn close_session(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        session: &str,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM session_cells WHERE session_id = ?;")
+            .bind(session)
+            .execute(tr.as_mut())
+            .await?;
+        sqlx::query("DELETE FROM sessions WHERE id = ?;")
+            .bind(session)
+            .execute(tr.as_mut())
+            .await?;
+        Ok(())
+    }
+
+    /// Applies every write of a session in a single transaction, and closes the session. Returns the
+    /// writes that were applied.
+    ///
+    /// # Errors
+    /// Same as [`Self::insert_cell`], for the first write that can't be applied, in which case nothing is
+    /// written and the session stays open.
+    pub async fn commit_session(
+        &self,
+        sheetid: &SheetId,
+        session: &str,
+        strict_lookups: bool,
+    ) -> Result<Vec<sheet::Cell>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        Self::check_session(&mut tr, sheetid, session).await?;
+
+        let cells = Self::staged_cells(&mut tr, session).await?;
+        Self::insert_cells_in(&mut tr, sheetid, &cells, strict_lookups, None, &self.interceptors)
+            .await?;
+        Self::close_session(&mut tr, session).await?;
+
+        tr.commit().await?;
+        Ok(cells)
+    }
+
+    /// Writes many cells at once, in order. Everything happens in a single transaction, so if any of the cells
+    /// can't be written, nothing is.
+    ///
+    /// # Errors
+    /// Same as [`Self::insert_cell`], for the first cell that can't be written.
+    pub async fn insert_cells(
+        &self,
+        sheetid: &SheetId,
+        cells: &[sheet::Cell],
+        strict_lookups: bool,
+    ) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        Self::insert_cells_in(&mut tr, sheetid, cells, strict_lookups, None, &self.interceptors)
+            .await?;
+        tr.commit().await?;
+        Ok(())
+    }
+
+    /// Same as [`Self::insert_cells`], but with `dry_run`, the transaction is always rolled back, which checks
+    /// whether the cells could be written without writing them.
+    ///
+    /// # Errors
+    /// Same as [`Self::insert_cell`], with the offending cell added as context (e.g. `rows[5].B`).
+    pub async fn import_cells(
+        &self,
+        sheetid: &SheetId,
+        cells: &[sheet::Cell],
+        strict_lookups: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let context = |cell: &sheet::Cell| format!("rows[{}].{}", cell.row, cell.column);
+        Self::insert_cells_in(
+            &mut tr,
+            sheetid,
+            cells,
+            strict_lookups,
+            Some(context),
+            &self.interceptors,
+        )
+        .await?;
+
+        if dry_run {
+            tr.rollback().await?;
+        } else {
+            tr.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes many cells in order. Plain values are written in batches of multi-row statements (see
+    /// [`Self::insert_values_in`]), which is much faster than writing them one by one. Lookups, and values which
+    /// may be rejected or widen their column, are still written one by one, in between the batches.
+    ///
+    /// If a cell can't be written, its error gets the `context` of the cell, if there is one.
+    async fn insert_cells_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        cells: &[sheet::Cell],
+        strict_lookups: bool,
+        context: Option<fn(&sheet::Cell) -> String>,
+        interceptors: &[Arc<dyn WriteInterceptor>],
+    ) -> Result<()> {
+        Self::check_writable(tr, sheetid).await?;
+        let cells: Cow<[sheet::Cell]> = if interceptors.is_empty() {
+            Cow::Borrowed(cells)
+        } else {
+            let mut intercepted = Vec::with_capacity(cells.len());
+            for cell in cells {
+                let written = Self::intercept(interceptors, tr, sheetid, cell).await;
+                intercepted.push(match context {
+                    Some(context) => written.with_context(|| context(cell))?,
+                    None => written?,
+                });
+            }
+            Cow::Owned(intercepted)
+        };
+        let mut columns = Self::get_column_table(tr, sheetid).await?;
+        let bounds = Self::get_settings_in(tr, sheetid).await?.row_bounds;
+        // overwriting a value of these may have to be restricted or cascaded
+        let referenced: HashSet<i64> = sqlx::query_scalar(
+            "SELECT target_col_id FROM column_references WHERE target_sheet_id = ? AND on_delete IS NOT NULL;",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(tr.as_mut())
+        .await?
+        .into_iter()
+        .collect();
+        let mut batch = vec![];
+        let mut batched = HashSet::new();
+        for cell in cells.iter() {
+            // cells outside of the bounds are left to insert_cell_in, which rejects them
+            let col_id = batchable_column(&columns, cell)
+                .filter(|col_id| !referenced.contains(col_id))
+                .filter(|_| bounds.map_or(true, |bounds| bounds.contains(cell.row)));
+            // a batch can't write the same cell twice, since it reads all of the old values up front
+            let is_full = batch.len() == INSERT_BATCH_CELLS;
+            if is_full || col_id.map_or(true, |col_id| batched.contains(&(col_id, cell.row))) {
+                Self::insert_values_in(tr, sheetid, &columns, &batch).await?;
+                batch.clear();
+                batched.clear();
+            }
+
+            if let Some(col_id) = col_id {
+                batched.insert((col_id, cell.row));
+                batch.push((col_id, cell));
+                continue;
+            }
+            let written = Self::insert_cell_in(tr, sheetid, cell, strict_lookups).await;
+            match context {
+                Some(context) => written.with_context(|| context(cell))?,
+                None => written?,
+            };
+            if cell.value.is_lookup().is_none() {
+                // the value may have widened its column
+                columns = Self::get_column_table(tr, sheetid).await?;
+            }
+        }
+        Self::insert_values_in(tr, sheetid, &columns, &batch).await?;
+        Self::refresh_stale_views(tr).await
+    }
+
+    /// Writes a batch of plain values, which have to be of their columns' types and must all be for different
+    /// cells (see [`batchable_column`]), along with everything else [`Self::insert_cell_in`] would do for them. Each
+    /// step is done for the whole batch at once, with as few statements as possible.
+    async fn insert_values_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        columns: &[sheet::SchemaColumn],
+        batch: &[(i64, &sheet::Cell)],
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let ident = sheetid.ident();
+        let rows: BTreeSet<i64> = batch.iter().map(|(_, cell)| cell.row).collect();
+        let col_ids: BTreeSet<i64> = batch.iter().map(|(col_id, _)| *col_id).collect();
+
+        // the statements depend on the size of the batch, so they aren't kept in the statement cache, where they
+        // would only push out the statements of single writes
+
+        // the values which are about to be overwritten, and the lookups which are about to be replaced
+        let mut query = QueryBuilder::new("SELECT row");
+        for col_id in &col_ids {
+            query.push(format_args!(", col{col_id}"));
+        }
+        query.push(format_args!(" FROM sheet_{ident} WHERE row IN ("));
+        push_list(&mut query, &rows);
+        let mut old_values = HashMap::new();
+        for row in query
+            .build()
+            .persistent(false)
+            .fetch_all(tr.as_mut())
+            .await?
+        {
+            let row_number: i64 = row.try_get(0)?;
+            for (i, &col_id) in col_ids.iter().enumerate() {
+                if let Some(value) = decode_value(&row, i + 1, columns[col_id as usize].kind)? {
+                    old_values.insert((col_id, row_number), value);
+                }
+            }
+        }
+        let mut query = QueryBuilder::new(format!(
+            "SELECT col_id, row, root_col_id, root_row FROM sheet_{ident}_lookups WHERE row IN ("
+        ));
+        push_list(&mut query, &rows);
+        let lookups: HashMap<(i64, i64), Option<(i64, i64)>> = query
+            .build_query_as()
+            .persistent(false)
+            .fetch_all(tr.as_mut())
+            .await?
+            .into_iter()
+            .filter(|lookup: &(i64, i64, Option<i64>, Option<i64>)| col_ids.contains(&lookup.0))
+            .map(|(col_id, row, root_col_id, root_row)| ((col_id, row), root_col_id.zip(root_row)))
+            .collect();
+
+        let mut query = QueryBuilder::new("DELETE FROM tombstones WHERE sheet_id = ");
+        query
+            .push_bind(&sheetid.0)
+            .push(" AND (col_id, row) IN (VALUES ");
+        push_cells(&mut query, batch.iter().map(|(col_id, cell)| (*col_id, cell.row)));
+        query.build().persistent(false).execute(tr.as_mut()).await?;
+        // we can't have an entry for the same cell in both tables
+        if !lookups.is_empty() {
+            for table in ["lookups", "dependencies"] {
+                let mut query = QueryBuilder::new(format!(
+                    "DELETE FROM sheet_{ident}_{table} WHERE (col_id, row) IN (VALUES "
+                ));
+                push_cells(&mut query, lookups.keys().copied());
+                query.build().persistent(false).execute(tr.as_mut()).await?;
+            }
+            for (&cell, &old_root) in &lookups {
+                if old_root.is_some() {
+                    Self::relink_lookups(tr, sheetid, cell, old_root, None).await?;
+                }
+            }
+        }
+
+        for &col_id in &col_ids {
+            let cells = batch.iter().filter(|(id, _)| *id == col_id);
+            let mut query =
+                QueryBuilder::new(format!("INSERT INTO sheet_{ident} (row, col{col_id}) "));
+            query.push_values(cells.clone(), |mut b, (_, cell)| {
+                b.push_bind(cell.row);
+                match cell.value.unescaped() {
+                    CellValue::Boolean(x) => b.push_bind(x),
+                    CellValue::Int(x) => b.push_bind(x),
+                    CellValue::Double(x) => b.push_bind(x),
+                    CellValue::String(x) => b.push_bind(x),
+                };
+            });
+            query.push(format_args!(
+                " ON CONFLICT(row) DO UPDATE SET col{col_id} = excluded.col{col_id};"
+            ));
+            query.build().persistent(false).execute(tr.as_mut()).await?;
+
+            let changes: Vec<_> = cells
+                .map(|(_, cell)| (old_values.get(&(col_id, cell.row)), cell.value.unescaped()))
+                .collect();
+            Self::update_column_stats_batch(tr, sheetid, col_id, &changes).await?;
+        }
+
+        let newly_populated = batch
+            .iter()
+            .filter(|(col_id, cell)| {
+                let key = (*col_id, cell.row);
+                !old_values.contains_key(&key) && !lookups.contains_key(&key)
+            })
+            .count();
+        let last_version = sqlx::query_scalar::<_, i64>(
+            "UPDATE sheets SET updated_at = ?, cells = cells + ?, version = version + ? WHERE id = ?
+            RETURNING version;",
+        )
+        .bind(now_millis())
+        .bind(newly_populated as i64)
+        .bind(batch.len() as i64)
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        // every cell is a version of its own, as if it was written by itself
+        let first_version = last_version - batch.len() as i64 + 1;
+        QueryBuilder::new("INSERT INTO cell_versions (sheet_id, col_id, row, version) ")
+            .push_values((first_version..).zip(batch), |mut b, (version, (col_id, cell))| {
+                b.push_bind(&sheetid.0)
+                    .push_bind(*col_id)
+                    .push_bind(cell.row)
+                    .push_bind(version);
+            })
+            .push(" ON CONFLICT(sheet_id, col_id, row) DO UPDATE SET version = excluded.version;")
+            .build()
+            .persistent(false)
+            .execute(tr.as_mut())
+            .await?;
+
+        if columns
+            .iter()
+            .any(|column| column.kind == SchemaColumnKind::Autoincrement)
+        {
+            for (version, (_, cell)) in (first_version..).zip(batch) {
+                Self::assign_sequence_numbers(tr, sheetid, cell.row, version).await?;
+            }
+        }
+
+        let last_seq = sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE(MAX(seq), 0) FROM events WHERE sheet_id = ?;",
+        )
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        let changes = batch
+            .iter()
+            .map(|(_, cell)| {
+                serde_json::to_string(&sheet::Change::CellWritten {
+                    cell: (*cell).clone(),
+                })
+            })
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        let at = now_millis();
+        QueryBuilder::new("INSERT INTO events (sheet_id, seq, at, change) ")
+            .push_values((last_seq + 1..).zip(changes), |mut b, (seq, change)| {
+                b.push_bind(&sheetid.0)
+                    .push_bind(seq)
+                    .push_bind(at)
+                    .push_bind(change);
+            })
+            .build()
+            .persistent(false)
+            .execute(tr.as_mut())
+            .await?;
+        Self::mark_views_stale(tr, sheetid).await?;
+
+        if let Some(scheme) = Self::get_settings_in(tr, sheetid).await?.row_keys {
+            let mut query = QueryBuilder::new("SELECT row FROM row_keys WHERE sheet_id = ");
+            query.push_bind(&sheetid.0).push(" AND row IN (");
+            push_list(&mut query, &rows);
+            let keyed: HashSet<i64> = query
+                .build_query_scalar()
+                .persistent(false)
+                .fetch_all(tr.as_mut())
+                .await?
+                .into_iter()
+                .collect();
+            let mut rng = rand::thread_rng();
+            let keys: Vec<_> = rows
+                .iter()
+                .filter(|row| !keyed.contains(row))
+                .map(|&row| (row, SheetId::generate(&mut rng, scheme).0))
+                .collect();
+            if !keys.is_empty() {
+                QueryBuilder::new("INSERT INTO row_keys (sheet_id, row, key) ")
+                    .push_values(keys, |mut b, (row, key)| {
+                        b.push_bind(&sheetid.0).push_bind(row).push_bind(key);
+                    })
+                    .build()
+                    .persistent(false)
+                    .execute(tr.as_mut())
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the statistics of a column after a batch of its cells changed from the first value of each pair
+    /// to the second, the same way as [`Self::update_column_stats`] does for a single cell.
+    async fn update_column_stats_batch(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        changes: &[(Option<&CellValue>, CellValue)],
+    ) -> Result<()> {
+        let number = |value: &CellValue| match value {
+            CellValue::Int(x) => *x as f64,
+            CellValue::Double(x) => *x,
+            _ => 0.0,
+        };
+        let changes: Vec<_> = changes
+            .iter()
+            .filter(|(old, new)| *old != Some(new))
+            .collect();
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let count: i64 = changes
+            .iter()
+            .map(|(old, _)| i64::from(old.is_none()))
+            .sum();
+        let sum: f64 = changes
+            .iter()
+            .map(|(old, new)| number(new) - old.map_or(0.0, number))
+            .sum();
+        let new_min = changes
+            .iter()
+            .map(|(_, new)| new)
+            .min_by(|a, b| compare(a, b));
+        let new_max = changes
+            .iter()
+            .map(|(_, new)| new)
+            .max_by(|a, b| compare(a, b));
+        let query = sqlx::query(
+            "UPDATE column_stats SET count = count + ?1, sum = sum + ?2,
+            min = COALESCE(MIN(min, ?3), ?3, min), max = COALESCE(MAX(max, ?4), ?4, max)
+            WHERE sheet_id = ?5 AND col_id = ?6;",
+        )
+        .bind(count)
+        .bind(sum);
+        bind_value(bind_value(query, new_min), new_max)
+            .bind(&sheetid.0)
+            .bind(col_id)
+            .execute(tr.as_mut())
+            .await?;
+
+        // the minimum is never above any old value, so only the smallest one could have been it (and likewise)
+        let old_min = changes
+            .iter()
+            .filter_map(|(old, _)| *old)
+            .min_by(|a, b| compare(a, b));
+        let old_max = changes
+            .iter()
+            .filter_map(|(old, _)| *old)
+            .max_by(|a, b| compare(a, b));
+        if old_min.is_some() {
+            let query = format!(
+                "UPDATE column_stats
+                SET min = (SELECT MIN(col{1}) FROM sheet_{0}), max = (SELECT MAX(col{1}) FROM sheet_{0})
+                WHERE sheet_id = ?1 AND col_id = ?2 AND (min = ?3 OR max = ?4);",
+                sheetid.ident(),
+                col_id
+            );
+            let query = sqlx::query(&query).bind(&sheetid.0).bind(col_id);
+            bind_value(bind_value(query, old_min), old_max)
+                .execute(tr.as_mut())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current schema of a sheet.
+    pub async fn schema(&self, sheetid: &SheetId) -> Result<sheet::Schema> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let columns = Self::get_column_table(&mut tr, sheetid).await?;
+        tr.commit().await?;
+        Ok(sheet::Schema { columns })
+    }
+
+    /// Reads the whole sheet as it would look after committing a session, without committing it.
+    ///
+    /// # Errors
+    /// Same as [`Self::commit_session`].
+    pub async fn preview_session(
+        &self,
+        sheetid: &SheetId,
+        session: &str,
+        strict_lookups: bool,
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::SheetContent> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        // the staged writes are applied for real, and then rolled back once the sheet has been read
+        let mut tr = self.pool.begin().await?;
+        Self::check_session(&mut tr, sheetid, session).await?;
+        let cells = Self::staged_cells(&mut tr, session).await?;
+        Self::insert_cells_in(&mut tr, sheetid, &cells, strict_lookups, None, &self.interceptors)
+            .await?;
+
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        let mut regular_content = Vec::with_capacity(column_table.len());
+        for (i, column) in column_table.iter().enumerate() {
+            regular_content.push(
+                Self::get_column_content(tr.as_mut(), sheetid, column.kind, i as i64, None).await?,
+            );
+        }
+        let unresolved_lookups = Self::get_lookups(tr.as_mut(), sheetid).await?;
+        let meta = if options.include_meta {
+            Some(Self::get_all_row_meta(tr.as_mut(), sheetid, None).await?)
+        } else {
+            None
+        };
+        let row_keys = if options.include_row_keys {
+            Some(Self::get_row_keys(tr.as_mut(), sheetid, None).await?)
+        } else {
+            None
+        };
+        tr.rollback().await?;
+
+        Ok(sheet::SheetContent {
+            columns: Self::resolve_sheet(
+                column_table,
+                regular_content,
+                unresolved_lookups,
+                options,
+            ),
+            meta,
+            row_keys,
+            version: None,
+        })
+    }
+
+    /// Discards a session along with all of its writes.
+    pub async fn abort_session(&self, sheetid: &SheetId, session: &str) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        Self::check_session(&mut tr, sheetid, session).await?;
+        Self::close_session(&mut tr, session).await?;
+        tr.commit().await?;
+        Ok(())
+    }
+
+    /// Lists the cells which fill a column with lookups mirroring another one: every populated row of `source` gets
+    /// `lookup("<source>", row)` in `target`. Nothing is written, so that the cells can be validated first, and
+    /// then written with [`Self::insert_cells`].
+    ///
+    /// Rows below zero are skipped, since lookups can't point at them.
+    ///
+    /// # Errors
+    /// Fails if either column doesn't exist, or if `source` can't be referred to by a lookup.
+    pub async fn mirror_cells(
+        &self,
+        sheetid: &SheetId,
+        target: &str,
+        source: &str,
+    ) -> Result<Vec<sheet::Cell>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+        if source.contains('"') {
+            anyhow::bail!("lookups can't refer to columns whose name contains a quote");
+        }
+
+        let mut tr = self.pool.begin().await?;
+        if Self::get_column_by_name(&mut tr, sheetid, target)
+            .await?
+            .is_none()
+        {
+            anyhow::bail!("invalid column name");
+        }
+        let Some((source_id, _)) = Self::get_column_by_name(&mut tr, sheetid, source).await? else {
+            return Err(LookupError::UnknownTargetColumn {
+                target_column: source.into(),
+            }
+            .into());
+        };
+
+        let rows = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT row FROM sheet_{0} WHERE col{1} IS NOT NULL AND row >= 0
+            UNION SELECT row FROM sheet_{0}_lookups WHERE col_id = ? AND row >= 0
+            ORDER BY row;",
+            sheetid.ident(),
+            source_id
+        ))
+        .bind(source_id)
+        .fetch_all(tr.as_mut())
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| sheet::Cell {
+                column: target.into(),
+                row,
+                value: CellValue::String(format!("lookup(\"{source}\", {row})")),
+            })
+            .collect())
+    }
+
+    /// Fetches the value stored directly in a cell, which is `None` for empty and lookup cells.
+    async fn get_stored_value(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        kind: SchemaColumnKind,
+        row: i64,
+    ) -> Result<Option<CellValue>> {
+        let row = sqlx::query(&format!(
+            "SELECT col{} FROM sheet_{} WHERE row = ?;",
+            col_id,
+            sheetid.ident()
+        ))
+        .bind(row)
+        .fetch_optional(tr.as_mut())
+        .await?;
+
+        match row {
+            Some(row) => decode_value(&row, 0, kind),
+            None => Ok(None),
+        }
+    }
+
+    /// Recomputes the statistics of every column of a sheet from its stored values.
+    async fn rebuild_column_stats(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM column_stats WHERE sheet_id = ?;")
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+
+        for (col_id, column) in Self::get_column_table(tr, sheetid)
+            .await?
+            .iter()
+            .enumerate()
+        {
+            // sums only make sense for numbers, and TOTAL() would happily add up strings otherwise
+            sqlx::query(&format!(
+                "INSERT INTO column_stats (sheet_id, col_id, count, sum, min, max)
+                SELECT ?, ?, COUNT(col{1}), CASE WHEN ? THEN TOTAL(col{1}) END, MIN(col{1}), MAX(col{1})
+                FROM sheet_{0};",
+                sheetid.ident(),
+                col_id
+            ))
+            .bind(&sheetid.0)
+            .bind(col_id as i64)
+            .bind(matches!(
+                column.kind,
+                SchemaColumnKind::Int | SchemaColumnKind::Double
+            ))
+            .execute(tr.as_mut())
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates the statistics of a column after the value stored in one of its cells changed from `old` to
+    /// `new`. This is constant time, except when the column's current minimum or maximum is overwritten,
+    /// which requires a scan of the column to find the next one.
+    async fn update_column_stats(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+        old: Option<&CellValue>,
+        new: Option<&CellValue>,
+    ) -> Result<()> {
+        if old == new {
+            return Ok(());
+        }
+
+        let number = |value: Option<&CellValue>| match value {
+            Some(CellValue::Int(x)) => *x as f64,
+            Some(CellValue::Double(x)) => *x,
+            _ => 0.0,
+        };
+
+        // the sum stays NULL for non-numeric columns, and MIN()/MAX() return NULL if any argument is NULL
+        let query = sqlx::query(
+            "UPDATE column_stats SET count = count + ?1, sum = sum + ?2,
+            min = COALESCE(MIN(min, ?3), ?3, min), max = COALESCE(MAX(max, ?3), ?3, max)
+            WHERE sheet_id = ?4 AND col_id = ?5;",
+        )
+        .bind(i64::from(new.is_some()) - i64::from(old.is_some()))
+        .bind(number(new) - number(old));
+        bind_value(query, new)
+            .bind(&sheetid.0)
+            .bind(col_id)
+            .execute(tr.as_mut())
+            .await?;
+
+        if old.is_some() {
+            let query = format!(
+                "UPDATE column_stats
+                SET min = (SELECT MIN(col{1}) FROM sheet_{0}), max = (SELECT MAX(col{1}) FROM sheet_{0})
+                WHERE sheet_id = ?1 AND col_id = ?2 AND (min = ?3 OR max = ?3);",
+                sheetid.ident(),
+                col_id
+            );
+            let query = sqlx::query(&query).bind(&sheetid.0).bind(col_id);
+            bind_value(query, old).execute(tr.as_mut()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Measures the lookup chains of a sheet and how many lookups point at each cell. Unlike the statistics of
+    /// columns, these aren't kept up to date on every write, so this reads every lookup of the sheet.
+    pub async fn lookup_stats(
+        &self,
+        sheetid: &SheetId,
+    ) -> Result<sheet::lookup_stats::LookupStats> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let lookups = Self::get_lookups(&self.pool, sheetid).await?;
+        Ok(sheet::lookup_stats::LookupStats::new(&lookups))
+    }
+
+    /// Returns the statistics of every column of a sheet, leaving out hidden columns unless `include_hidden`.
+    pub async fn column_stats(
+        &self,
+        sheetid: &SheetId,
+        include_hidden: bool,
+    ) -> Result<HashMap<String, sheet::ColumnStats>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let rows = sqlx::query(&format!(
+            "SELECT c.name, c.type, c.hidden, s.count, s.sum, s.min, s.max
+            FROM sheet_{}_columns c JOIN column_stats s ON s.sheet_id = ? AND s.col_id = c.id;",
+            sheetid.ident()
+        ))
+        .bind(&sheetid.0)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats = HashMap::new();
+        for row in rows {
+            let hidden: bool = row.try_get(2)?;
+            if hidden && !include_hidden {
+                continue;
+            }
+
+            let kind = parse_kind(row.try_get(1)?)?;
+            stats.insert(
+                row.try_get(0)?,
+                sheet::ColumnStats {
+                    count: row.try_get(3)?,
+                    sum: row.try_get(4)?,
+                    min: decode_value(&row, 5, kind)?,
+                    max: decode_value(&row, 6, kind)?,
+                },
+            );
+        }
+        Ok(stats)
+    }
+
+    async fn get_column_table(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<Vec<sheet::SchemaColumn>> {
+        let res = sqlx::query_as::<_, (String, String, bool, Option<String>, bool)>(&format!(
+            "SELECT name, type, hidden, format, pii FROM sheet_{}_columns ORDER BY id ASC;",
+            sheetid.ident()
+        ))
+        .fetch_all(tr.as_mut())
+        .await?;
+
+        // the referenced columns are stored by id, since their names are only unique within their own sheet
+        let targets = sqlx::query_as::<_, (i64, String, i64, Option<String>)>(
+            "SELECT col_id, target_sheet_id, target_col_id, on_delete FROM column_references WHERE sheet_id = ?;",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(tr.as_mut())
+        .await?;
+        let mut references = HashMap::new();
+        for (col_id, target, target_col_id, on_delete) in targets {
+            let column = Self::get_column_name(tr, &SheetId(target.clone()), target_col_id).await?;
+            let on_delete = on_delete
+                .map(|text| {
+                    sheet::OnDelete::from_sql_text(&text)
+                        .ok_or_else(|| Corrupted(format!("unknown on_delete {text}")))
+                })
+                .transpose()?;
+            references.insert(
+                col_id,
+                sheet::ColumnReference {
+                    sheet: target,
+                    column,
+                    on_delete,
+                },
+            );
+        }
+
+        res.into_iter()
+            .enumerate()
+            .map(|(col_id, (name, kind, hidden, format, pii))| {
+                Ok(sheet::SchemaColumn {
+                    name,
+                    kind: parse_kind(&kind)?,
+                    hidden,
+                    format: format
+                        .map(|format| serde_json::from_str(&format))
+                        .transpose()
+                        .map_err(|why| Corrupted(why.to_string()))?,
+                    pii,
+                    references: references.remove(&(col_id as i64)),
+                })
+            })
+            .collect()
+    }
+
+    /// Reads the values of a column. With a `budget`, the values are added to it one by one, and the read stops as
+    /// soon as they don't fit.
+    async fn get_column_content<'e>(
+        executor: impl sqlx::SqliteExecutor<'e>,
+        sheetid: &SheetId,
+        kind: SchemaColumnKind,
+        col_id: i64,
+        budget: Option<&Reservation<'_>>,
+    ) -> Result<HashMap<i64, Option<CellValue>>> {
+        let query = format!(
+            "SELECT row, col{0} FROM sheet_{1} WHERE NOT col{0} IS NULL;",
+            col_id,
+            sheetid.ident()
+        );
+        let mut rows = sqlx::query(&query).fetch(executor);
+
+        let mut content = HashMap::new();
+        while let Some(row) = rows.try_next().await? {
+            let id = row
+                .try_get::<i64, usize>(0)
+                .map_err(|why| Corrupted(why.to_string()))?;
+            let value = decode_value(&row, 1, kind)?;
+            if let Some(budget) = budget {
+                budget.add(cell_size(value.as_ref()))?;
+            }
+            content.insert(id, value);
+        }
+        Ok(content)
+    }
+
+    /// Finds the tombstones in the rows matching `filter`, in the same way as [`Self::read_rows_where`].
+    async fn get_tombstones<'e, P: QueryParam>(
+        executor: impl sqlx::SqliteExecutor<'e>,
+        sheetid: &SheetId,
+        filter: &str,
+        params: &[P],
+    ) -> Result<Vec<(i64, i64)>> {
+        let query =
+            format!("SELECT col_id, row FROM tombstones WHERE sheet_id = ? AND ({filter});");
+        Ok(params
+            .iter()
+            .fold(sqlx::query_as::<_, (i64, i64)>(&query).bind(&sheetid.0), |query, param| {
+                query.bind(param)
+            })
+            .fetch_all(executor)
+            .await?)
+    }
+
+    async fn get_lookups<'e>(
+        executor: impl sqlx::SqliteExecutor<'e>,
+        sheetid: &SheetId,
+    ) -> Result<HashMap<(i64, i64), (i64, i64)>> {
+        Ok(sqlx::query_as::<_, (i64, i64, i64, i64)>(&format!(
+            "SELECT col_id, row, target_col_id, target_row FROM sheet_{}_lookups;",
+            sheetid.ident()
+        ))
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .map(|(a, b, c, d)| ((a, b), (c, d)))
+        .collect())
+    }
+
+    /// Rebuilds the derived data of a sheet (currently, the dependency graph and the roots of compacted lookup
+    /// chains) from the stored lookups, then resolves the whole sheet to find out how many of them are broken.
+    pub async fn recalculate(&self, sheetid: &SheetId) -> Result<sheet::RecalculationReport> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+
+        sqlx::query(&format!("DELETE FROM sheet_{}_dependencies;", sheetid.ident()))
+            .execute(tr.as_mut())
+            .await?;
+        let recalculated = sqlx::query(&format!(
+            "INSERT INTO sheet_{0}_dependencies SELECT col_id, row, target_col_id, target_row FROM sheet_{0}_lookups;",
+            sheetid.ident()
+        ))
+        .execute(tr.as_mut())
+        .await?
+        .rows_affected();
+        Self::compact_lookups_in(&mut tr, sheetid).await?;
+
+        Self::rebuild_column_stats(&mut tr, sheetid).await?;
+
+        tr.commit().await?;
+
+        let options = sheet::ReadOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let content = self.get_sheet(sheetid, options).await?;
+        let broken = content
+            .columns
+            .values()
+            .flatten()
+            .filter(|cell| cell.error == Some(CellError::Ref))
+            .count();
+
+        Ok(sheet::RecalculationReport {
+            recalculated,
+            broken: broken as u64,
+        })
+    }
+
+    /// Checks whether the existing data of a sheet would survive switching to the given schema, without
+    /// changing anything. Columns are matched by name, and a cell is offending if its value can't be converted
+    /// to its column's new type, its column would be removed, or it's a lookup which would no longer be valid.
+    pub async fn check_schema(
+        &self,
+        sheetid: &SheetId,
+        schema: &sheet::Schema,
+    ) -> Result<sheet::SchemaCheck> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        tr.commit().await?;
+
+        // the new type of every existing column, or `None` if it would be removed
+        let new_kinds: Vec<Option<SchemaColumnKind>> = column_table
+            .iter()
+            .map(|column| {
+                let proposed = schema.columns.iter().find(|p| p.name == column.name);
+                proposed.map(|proposed| proposed.kind)
+            })
+            .collect();
+
+        let mut offending = vec![BTreeSet::new(); column_table.len()];
+        for (i, column) in column_table.iter().enumerate() {
+            if new_kinds[i] == Some(column.kind) {
+                continue;
+            }
+            let content =
+                Self::get_column_content(&self.pool, sheetid, column.kind, i as i64, None).await?;
+            for (row, value) in content {
+                let convertible = match (new_kinds[i], value) {
+                    (Some(kind), Some(value)) => value.convert(kind).is_some(),
+                    _ => false,
+                };
+                if !convertible {
+                    offending[i].insert(row);
+                }
+            }
+        }
+
+        // lookups keep working as long as both ends stay and still have the same type
+        for ((col_id, row), (target_col_id, _)) in Self::get_lookups(&self.pool, sheetid).await? {
+            let kind = new_kinds.get(col_id as usize).copied().flatten();
+            let target_kind = new_kinds.get(target_col_id as usize).copied().flatten();
+            if kind.is_none() || kind != target_kind {
+                offending[col_id as usize].insert(row);
+            }
+        }
+
+        let check = |name: String, verdict, rows: &BTreeSet<i64>| sheet::ColumnCheck {
+            name,
+            verdict,
+            compatible: rows.is_empty(),
+            offending_rows: rows
+                .iter()
+                .copied()
+                .take(sheet::SchemaCheck::MAX_OFFENDING_ROWS)
+                .collect(),
+            offending_count: rows.len(),
+        };
+
+        let mut columns = vec![];
+        for proposed in &schema.columns {
+            let existing = column_table
+                .iter()
+                .position(|column| column.name == proposed.name);
+            columns.push(match existing {
+                Some(i) if column_table[i].kind == proposed.kind => {
+                    check(proposed.name.clone(), sheet::ColumnVerdict::Unchanged, &offending[i])
+                }
+                Some(i) => {
+                    check(proposed.name.clone(), sheet::ColumnVerdict::Converted, &offending[i])
+                }
+                None => {
+                    check(proposed.name.clone(), sheet::ColumnVerdict::Added, &Default::default())
+                }
+            });
+        }
+        for (i, column) in column_table.into_iter().enumerate() {
+            if new_kinds[i].is_none() {
+                columns.push(check(column.name, sheet::ColumnVerdict::Removed, &offending[i]));
+            }
+        }
+
+        Ok(sheet::SchemaCheck {
+            compatible: columns.iter().all(|column| column.compatible),
+            columns,
+        })
+    }
+
+    /// Returns the settings of a sheet.
+    /// Returns whether reads of a sheet leave out lookups which resolve to nothing, if the sheet was created with
+    /// its own choice. Otherwise it's up to the server-wide default.
+    pub async fn no_lookup_nulls(&self, sheetid: &SheetId) -> Result<Option<bool>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        Ok(sqlx::query_scalar::<_, Option<bool>>(
+            "SELECT no_lookup_nulls FROM sheets WHERE id = ?;",
+        )
+        .bind(&sheetid.0)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Returns the role of a principal on a sheet, if it has one. Unlike most methods, this doesn't check whether
+    /// the sheet exists, since it's used before requests get to the handlers which do.
+    pub async fn role(&self, sheet_id: &str, principal: &str) -> Result<Option<sheet::Role>> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT role FROM permissions WHERE sheet_id = ? AND principal = ?;",
+        )
+        .bind(sheet_id)
+        .bind(principal)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|role| role.parse().map_err(|why: String| Corrupted(why).into()))
+        .transpose()
+    }
+
+    /// Lists the roles granted on a sheet, owners first.
+    pub async fn permissions(&self, sheetid: &SheetId) -> Result<Vec<sheet::Permission>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut permissions = sqlx::query_as::<_, (String, String)>(
+            "SELECT principal, role FROM permissions WHERE sheet_id = ? ORDER BY principal;",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(principal, role)| {
+            let role = role.parse().map_err(Corrupted)?;
+            Ok(sheet::Permission { principal, role })
+        })
+        .collect::<Result<Vec<_>>>()?;
+        permissions.sort_by_key(|permission| std::cmp::Reverse(permission.role));
+        Ok(permissions)
+    }
+
+    /// Grants a role on a sheet to a principal, or takes its role away for `None`, returning whether the
+    /// principal had a role before.
+    ///
+    /// # Errors
+    /// In case this would leave a sheet which has an owner without one, or a database failure.
+    pub async fn set_permission(
+        &self,
+        sheetid: &SheetId,
+        principal: &str,
+        role: Option<sheet::Role>,
+    ) -> Result<bool> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let existed = Self::set_permission_in(&mut tr, sheetid, principal, role).await?;
+        tr.commit().await?;
+        Ok(existed)
+    }
+
+    async fn set_permission_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        principal: &str,
+        role: Option<sheet::Role>,
+    ) -> Result<bool> {
+        let had_owner = Self::count_owners(tr, sheetid).await? > 0;
+        let existed =
+            sqlx::query("SELECT 1 FROM permissions WHERE sheet_id = ? AND principal = ?;")
+                .bind(&sheetid.0)
+                .bind(principal)
+                .fetch_optional(tr.as_mut())
+                .await?
+                .is_some();
+
+        match role {
+            Some(role) => {
+                sqlx::query(
+                    "INSERT INTO permissions (sheet_id, principal, role) VALUES (?, ?, ?)
+                    ON CONFLICT (sheet_id, principal) DO UPDATE SET role = excluded.role;",
+                )
+                .bind(&sheetid.0)
+                .bind(principal)
+                .bind(role.name())
+                .execute(tr.as_mut())
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM permissions WHERE sheet_id = ? AND principal = ?;")
+                    .bind(&sheetid.0)
+                    .bind(principal)
+                    .execute(tr.as_mut())
+                    .await?;
+            }
+        }
+
+        // nobody could manage the sheet anymore, apart from tokens which may write every sheet
+        if had_owner && Self::count_owners(tr, sheetid).await? == 0 {
+            anyhow::bail!(
+                "the last owner of a sheet can't be removed, transfer the ownership instead"
+            );
+        }
+        Ok(existed)
+    }
+
+    /// Makes a principal the only owner of a sheet. The previous owners stay on as editors.
+    pub async fn transfer_ownership(&self, sheetid: &SheetId, principal: &str) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let owners: Vec<String> = sqlx::query_scalar(
+            "SELECT principal FROM permissions WHERE sheet_id = ? AND role = 'owner';",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(tr.as_mut())
+        .await?;
+
+        // the new owner comes first, so that the sheet is never left without one
+        Self::set_permission_in(&mut tr, sheetid, principal, Some(sheet::Role::Owner)).await?;
+        for owner in owners.iter().filter(|owner| *owner != principal) {
+            Self::set_permission_in(&mut tr, sheetid, owner, Some(sheet::Role::Editor)).await?;
+        }
+        tr.commit().await?;
+        Ok(())
+    }
+
+    async fn count_owners(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<i64> {
+        Ok(sqlx::query_scalar(
+            "SELECT COUNT(*) FROM permissions WHERE sheet_id = ? AND role = 'owner';",
+        )
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?)
+    }
+
+    pub async fn get_settings(&self, sheetid: &SheetId) -> Result<sheet::SheetSettings> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let settings = Self::get_settings_in(&mut tr, sheetid).await?;
+        tr.commit().await?;
+        Ok(settings)
+    }
+
+    async fn get_settings_in(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<sheet::SheetSettings> {
+        let settings = sqlx::query_scalar::<_, String>("SELECT settings FROM sheets WHERE id = ?;")
+            .bind(&sheetid.0)
+            .fetch_one(tr.as_mut())
+            .await?;
+        serde_json::from_str(&settings).map_err(|why| Corrupted(why.to_string()).into())
+    }
+
+    /// Fails with [`RowOutOfBounds`] if the sheet doesn't allow writing to the row.
+    async fn check_row_bounds(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        row: i64,
+    ) -> Result<()> {
+        match Self::get_settings_in(tr, sheetid).await?.row_bounds {
+            Some(bounds) if !bounds.contains(row) => Err(RowOutOfBounds { row, bounds }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the settings of a sheet.
+    pub async fn set_settings(
+        &self,
+        sheetid: &SheetId,
+        settings: &sheet::SheetSettings,
+    ) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let previous = Self::get_settings_in(&mut tr, sheetid).await?;
+        sqlx::query("UPDATE sheets SET settings = ? WHERE id = ?;")
+            .bind(serde_json::to_string(settings)?)
+            .bind(&sheetid.0)
+            .execute(tr.as_mut())
+            .await?;
+
+        if settings.compact_lookups != previous.compact_lookups {
+            Self::compact_lookups_in(&mut tr, sheetid).await?;
+        }
+
+        // rows which were populated before row keys were enabled get theirs right away
+        if settings.row_keys.is_some() {
+            let columns = Self::get_column_table(&mut tr, sheetid).await?.len();
+            // a negative limit means no limit at all in sqlite
+            for row in Self::populated_rows(&mut tr, sheetid, columns, None, -1, false).await? {
+                Self::row_key(&mut tr, sheetid, row).await?;
+            }
+        }
+
+        let change = sheet::Change::SettingsChanged {
+            settings: settings.clone(),
+        };
+        Self::append_event(&mut tr, sheetid, &change).await?;
+        tr.commit().await?;
+        Ok(())
+    }
+
+    /// Changes the type of an int column to double, converting all of its values.
+    ///
+    /// # Errors
+    /// In case the column has lookups to or from other columns, or a reference to or from another sheet, which
+    /// only work between columns of the same type, the sheet has views, whose columns keep their types, or a
+    /// database failure.
+    async fn widen_column(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        col_id: i64,
+    ) -> Result<()> {
+        let crossing_lookups = sqlx::query_scalar::<_, bool>(&format!(
+            "SELECT EXISTS(SELECT 1 FROM sheet_{}_lookups WHERE (col_id = ?1) != (target_col_id = ?1));",
+            sheetid.ident()
+        ))
+        .bind(col_id)
+        .fetch_one(tr.as_mut())
+        .await?;
+        if crossing_lookups {
+            anyhow::bail!(
+                "invalid column type (the column can't be changed to double, since it has lookups to or from other columns)"
+            );
+        }
+        let referenced = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM column_references
+            WHERE (sheet_id = ?1 AND col_id = ?2) OR (target_sheet_id = ?1 AND target_col_id = ?2));",
+        )
+        .bind(&sheetid.0)
+        .bind(col_id)
+        .fetch_one(tr.as_mut())
+        .await?;
+        if referenced {
+            anyhow::bail!(
+                "invalid column type (the column can't be changed to double, since it references or is referenced by another sheet)"
+            );
+        }
+        let viewed = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM views WHERE source_id = ?);",
+        )
+        .bind(&sheetid.0)
+        .fetch_one(tr.as_mut())
+        .await?;
+        if viewed {
+            anyhow::bail!(
+                "invalid column type (the column can't be changed to double, since the sheet has views)"
+            );
+        }
+
+        // sqlite can't change the type of a column, so the whole table is copied over to a new one
+        let mut columns = Self::get_column_table(tr, sheetid).await?;
+        columns[col_id as usize].kind = SchemaColumnKind::Double;
+        let table = format!("sheet_{}", sheetid.ident());
+        let widened = format!("sheet_{}_widened", sheetid.ident());
+        Self::create_sheet_table(tr, &widened, &columns).await?;
+
+        let values = (0..columns.len() as i64)
+            .map(|i| {
+                if i == col_id {
+                    format!("CAST(col{i} AS REAL)")
+                } else {
+                    format!("col{i}")
+                }
+            })
+            .collect::<Vec<_>>();
+        let values = std::iter::once("row".to_string())
+            .chain(values)
+            .collect::<Vec<_>>()
+            .join(", ");
+        for query in [
+            format!("INSERT INTO {widened} SELECT {values} FROM {table};"),
+            format!("DROP TABLE {table};"),
+            format!("ALTER TABLE {widened} RENAME TO {table};"),
+        ] {
+            sqlx::query(&query).execute(tr.as_mut()).await?;
+        }
+
+        sqlx::query(&format!(
+            "UPDATE sheet_{}_columns SET type = ? WHERE id = ?;",
+            sheetid.ident()
+        ))
+        .bind(SchemaColumnKind::Double.get_sql_text())
+        .bind(col_id)
+        .execute(tr.as_mut())
+        .await?;
+
+        Self::record_schema_version(tr, sheetid).await?;
+
+        // the stats hold values of the old type, and the rewrite is already linear anyway
+        Self::rebuild_column_stats(tr, sheetid).await
+    }
+
+    /// Adds the current schema of a sheet to its history, as part of the transaction which changes it.
+    async fn record_schema_version(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+    ) -> Result<()> {
+        let schema = sheet::Schema {
+            columns: Self::get_column_table(tr, sheetid).await?,
+        };
+        sqlx::query(
+            "INSERT INTO schema_versions (sheet_id, version, at, schema)
+            SELECT ?1, COALESCE(MAX(version), 0) + 1, ?2, ?3 FROM schema_versions WHERE sheet_id = ?1;",
+        )
+        .bind(&sheetid.0)
+        .bind(now_millis())
+        .bind(serde_json::to_string(&schema)?)
+        .execute(tr.as_mut())
+        .await?;
+        Ok(())
+    }
+
+    /// Returns every schema a sheet has had, oldest first.
+    pub async fn schema_history(&self, sheetid: &SheetId) -> Result<Vec<sheet::SchemaVersion>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        sqlx::query_as::<_, (i64, i64, String)>(
+            "SELECT version, at, schema FROM schema_versions WHERE sheet_id = ? ORDER BY version;",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(version, at, schema)| {
+            let schema = serde_json::from_str(&schema)
+                .map_err(|why| Corrupted(format!("invalid schema version {version}: {why}")))?;
+            Ok(sheet::SchemaVersion {
+                version,
+                at,
+                schema,
+            })
+        })
+        .collect()
+    }
+
+    /// Adds a notification rule to a sheet, returning its id.
+    ///
+    /// # Errors
+    /// In case the sheet or column don't exist, or the condition can never match the column's values.
+    pub async fn add_notification_rule(
+        &self,
+        sheetid: &SheetId,
+        rule: &crate::notify::Rule,
+    ) -> Result<i64> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let Some((_, kind)) = Self::get_column_by_name(&mut tr, sheetid, &rule.column).await?
+        else {
+            anyhow::bail!("invalid column name");
+        };
+        if !rule.condition.applies_to(kind) {
+            anyhow::bail!("condition can't be applied to a column of type {kind:?}");
+        }
+
+        let id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO notification_rules (sheet_id, rule) VALUES (?, ?) RETURNING id;",
+        )
+        .bind(&sheetid.0)
+        .bind(serde_json::to_string(&crate::notify::Rule {
+            // stored normalized, so that it can be compared with the columns of written cells
+            column: rule.column.nfc().collect(),
+            ..rule.clone()
+        })?)
+        .fetch_one(tr.as_mut())
+        .await?;
+
+        tr.commit().await?;
+        Ok(id)
+    }
+
+    /// Returns all of the notification rules of a sheet, along with their ids.
+    pub async fn notification_rules(
+        &self,
+        sheetid: &SheetId,
+    ) -> Result<Vec<(i64, crate::notify::Rule)>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        sqlx::query_as::<_, (i64, String)>(
+            "SELECT id, rule FROM notification_rules WHERE sheet_id = ? ORDER BY id;",
+        )
+        .bind(&sheetid.0)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(id, rule)| {
+            let rule = serde_json::from_str(&rule).map_err(|why| Corrupted(why.to_string()))?;
+            Ok((id, rule))
+        })
+        .collect()
+    }
+
+    /// Removes a notification rule, returning `false` if the sheet had no such rule.
+    pub async fn delete_notification_rule(&self, sheetid: &SheetId, id: i64) -> Result<bool> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        Ok(sqlx::query("DELETE FROM notification_rules WHERE sheet_id = ? AND id = ?;")
+            .bind(&sheetid.0)
+            .bind(id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected()
+            == 1)
+    }
+
+    /// Registers (or replaces) the validator of a sheet, or removes it if `validator` is `None`.
+    pub async fn set_validator(
+        &self,
+        sheetid: &SheetId,
+        validator: Option<&crate::validate::Validator>,
+    ) -> Result<()> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        match validator {
+            Some(validator) => {
+                sqlx::query(
+                    "INSERT INTO validators (sheet_id, url, timeout_ms, fail_open) VALUES (?, ?, ?, ?)
+                    ON CONFLICT(sheet_id) DO UPDATE SET url = excluded.url,
+                    timeout_ms = excluded.timeout_ms, fail_open = excluded.fail_open;",
+                )
+                .bind(&sheetid.0)
+                .bind(&validator.url)
+                .bind(validator.timeout_ms as i64)
+                .bind(validator.fail_open)
+                .execute(&self.pool)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM validators WHERE sheet_id = ?;")
+                    .bind(&sheetid.0)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the validator of a sheet, if it has one.
+    pub async fn get_validator(
+        &self,
+        sheetid: &SheetId,
+    ) -> Result<Option<crate::validate::Validator>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        Ok(sqlx::query_as::<_, (String, i64, bool)>(
+            "SELECT url, timeout_ms, fail_open FROM validators WHERE sheet_id = ?;",
+        )
+        .bind(&sheetid.0)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|(url, timeout_ms, fail_open)| crate::validate::Validator {
+            url,
+            timeout_ms: timeout_ms as u64,
+            fail_open,
+        }))
+    }
+
+    /// Applies changes to the metadata of a row, returning the resulting metadata. Keys set to `null`
+    /// are removed, and all other keys are set (or replaced).
+    pub async fn update_row_meta(
+        &self,
+        sheetid: &SheetId,
+        row: i64,
+        changes: &sheet::RowMeta,
+    ) -> Result<sheet::RowMeta> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        for (key, value) in changes {
+            if value.is_null() {
+                sqlx::query("DELETE FROM row_meta WHERE sheet_id = ? AND row = ? AND key = ?;")
+                    .bind(&sheetid.0)
+                    .bind(row)
+                    .bind(key)
+                    .execute(tr.as_mut())
+                    .await?;
+            } else {
+                sqlx::query(
+                    "INSERT INTO row_meta (sheet_id, row, key, value) VALUES (?, ?, ?, ?)
+                    ON CONFLICT(sheet_id, row, key) DO UPDATE SET value = excluded.value;",
+                )
+                .bind(&sheetid.0)
+                .bind(row)
+                .bind(key)
+                .bind(value.to_string())
+                .execute(tr.as_mut())
+                .await?;
+            }
+        }
+        let change = sheet::Change::RowMetaUpdated {
+            row,
+            changes: changes.clone(),
+        };
+        Self::append_event(&mut tr, sheetid, &change).await?;
+        tr.commit().await?;
+
+        self.row_meta(sheetid, row).await
+    }
+
+    /// Returns the metadata of a single row, which is empty if none was ever set.
+    pub async fn row_meta(&self, sheetid: &SheetId, row: i64) -> Result<sheet::RowMeta> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        Ok(Self::get_all_row_meta(&self.pool, sheetid, Some((row, row)))
+            .await?
+            .remove(&row)
+            .unwrap_or_default())
+    }
+
+    /// Fetches the metadata of every row of a sheet, or only of an inclusive range of rows.
+    async fn get_all_row_meta<'e>(
+        executor: impl sqlx::SqliteExecutor<'e>,
+        sheetid: &SheetId,
+        rows: Option<(i64, i64)>,
+    ) -> Result<BTreeMap<i64, sheet::RowMeta>> {
+        let (first, last) = rows.unwrap_or((i64::MIN, i64::MAX));
+        let entries = sqlx::query_as::<_, (i64, String, String)>(
+            "SELECT row, key, value FROM row_meta WHERE sheet_id = ? AND row BETWEEN ? AND ?;",
+        )
+        .bind(&sheetid.0)
+        .bind(first)
+        .bind(last)
+        .fetch_all(executor)
+        .await?;
+
+        let mut meta = BTreeMap::<i64, sheet::RowMeta>::new();
+        for (row, key, value) in entries {
+            let value = serde_json::from_str(&value).map_err(|why| Corrupted(why.to_string()))?;
+            meta.entry(row).or_default().insert(key, value);
+        }
+        Ok(meta)
+    }
+
+    /// Reads the `n` highest-numbered populated rows of a sheet, in descending order of rows.
+    ///
+    /// Unlike [`Self::get_sheet`], this doesn't read the whole sheet: lookups in these rows are resolved by
+    /// following their chains one cell at a time.
+    pub async fn tail(
+        &self,
+        sheetid: &SheetId,
+        n: i64,
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::SheetContent> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        // a single transaction, so that the rows and the lookups they point at are consistent
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        let rows =
+            Self::populated_rows(&mut tr, sheetid, column_table.len(), None, n, true).await?;
+        let range = rows.last().map(|&first| (first, i64::MAX));
+        let mut content =
+            Self::read_row_range(&mut tr, sheetid, column_table, range, options).await?;
+        tr.commit().await?;
+
+        for cells in content.columns.values_mut() {
+            cells.reverse();
+        }
+        Ok(content)
+    }
+
+    /// Reads up to `limit` populated rows of a sheet which come after `after_row`, in ascending order of
+    /// rows. Along with the content, this returns the cursor to pass as `after_row` to get the next page,
+    /// or `None` if this was the last one.
+    ///
+    /// Since the rows are found using the row index rather than skipping over the previous pages, reading a
+    /// page takes the same time no matter how deep into the sheet it is.
+    pub async fn page(
+        &self,
+        sheetid: &SheetId,
+        after_row: Option<i64>,
+        limit: i64,
+        options: sheet::ReadOptions,
+    ) -> Result<(sheet::SheetContent, Option<i64>)> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        // one more row than needed tells whether there's a next page
+        let mut rows =
+            Self::populated_rows(&mut tr, sheetid, column_table.len(), after_row, limit + 1, false)
+                .await?;
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            rows.last().copied()
+        } else {
+            None
+        };
+
+        let range = rows.first().zip(rows.last()).map(|(&a, &b)| (a, b));
+        let mut content =
+            Self::read_row_range(&mut tr, sheetid, column_table, range, options).await?;
+        tr.commit().await?;
+        if options.include_meta {
+            content.meta = Some(match range {
+                Some(range) => Self::get_all_row_meta(&self.pool, sheetid, Some(range)).await?,
+                None => BTreeMap::new(),
+            });
+        }
+        if options.include_row_keys {
+            content.row_keys = Some(match range {
+                Some(range) => Self::get_row_keys(&self.pool, sheetid, Some(range)).await?,
+                None => BTreeMap::new(),
+            });
+        }
+
+        Ok((content, next_cursor))
+    }
+
+    /// Reads the cells of the rows from `first_row` to `last_row` (inclusive) as a grid, resolving their lookups
+    /// without reading the rest of the sheet.
+    pub async fn grid(
+        &self,
+        sheetid: &SheetId,
+        first_row: i64,
+        last_row: i64,
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::Grid> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        let range = Some((first_row, last_row));
+        let content =
+            Self::read_row_range(&mut tr, sheetid, column_table.clone(), range, options).await?;
+        tr.commit().await?;
+
+        Ok(sheet::Grid::new(&column_table, content, first_row, last_row))
+    }
+
+    /// Joins the rows of two sheets where the column `on.0` of `left` has the same value as the column `on.1` of
+    /// `right`, after resolving their lookups. Empty cells never match, and ints match doubles of the same value.
+    /// Both sheets, and the joined rows, are added to `budget` as they're read.
+    ///
+    /// # Errors
+    /// In case either sheet or column doesn't exist, [`crate::budget::BudgetExceeded`] as soon as the join stops
+    /// fitting into the budget, or a database failure.
+    pub async fn join(
+        &self,
+        left: &SheetId,
+        right: &SheetId,
+        on: (&str, &str),
+        kind: sheet::JoinKind,
+        options: sheet::ReadOptions,
+        budget: Option<&Reservation<'_>>,
+    ) -> Result<sheet::Join> {
+        if !self.sheet_exists(left) || !self.sheet_exists(right) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let left_table = Self::get_column_table(&mut tr, left).await?;
+        let right_table = Self::get_column_table(&mut tr, right).await?;
+        tr.commit().await?;
+        let left =
+            JoinSide::new(&left_table, self.get_sheet_within(left, options, budget).await?, on.0)?;
+        let right = JoinSide::new(
+            &right_table,
+            self.get_sheet_within(right, options, budget).await?,
+            on.1,
+        )?;
+
+        let mut matches = HashMap::<_, Vec<i64>>::new();
+        for (&row, key) in &right.keys {
+            matches.entry(key.as_str()).or_default().push(row);
+        }
+        for rows in matches.values_mut() {
+            rows.sort_unstable();
+        }
+
+        let empty = vec![None; right.columns.len()];
+        let mut rows = vec![];
+        for (&left_row, left_values) in &left.rows {
+            let right_rows = left
+                .keys
+                .get(&left_row)
+                .and_then(|key| matches.get(key.as_str()));
+            let pairs: Vec<_> = match right_rows {
+                Some(right_rows) => right_rows
+                    .iter()
+                    .map(|row| (Some(*row), &right.rows[row]))
+                    .collect(),
+                None if kind == sheet::JoinKind::Left => vec![(None, &empty)],
+                None => continue,
+            };
+            for (right_row, right_values) in pairs {
+                let values: Vec<_> = left_values.iter().chain(right_values).cloned().collect();
+                if let Some(budget) = budget {
+                    budget.add(values.iter().map(|value| cell_size(value.as_ref())).sum())?;
+                }
+                rows.push(sheet::JoinedRow {
+                    left_row,
+                    right_row,
+                    values,
+                });
+            }
+        }
+
+        let columns = (left.columns.iter())
+            .map(|name| format!("{}{name}", sheet::Join::LEFT_PREFIX))
+            .chain(
+                (right.columns.iter()).map(|name| format!("{}{name}", sheet::Join::RIGHT_PREFIX)),
+            )
+            .collect();
+        Ok(sheet::Join { columns, rows })
+    }
+
+    /// Reads the cells of a single row, resolving its lookups without touching the rest of the sheet.
+    pub async fn get_row(
+        &self,
+        sheetid: &SheetId,
+        row: i64,
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::RowContent> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        let content =
+            Self::read_row_range(&mut tr, sheetid, column_table, Some((row, row)), options).await?;
+        tr.commit().await?;
+
+        Ok(content
+            .into_rows()
+            .remove(&row)
+            .unwrap_or_else(|| sheet::RowContent::empty(row)))
+    }
+
+    /// Reads the cells of each of the given rows, in the order they're given. Rows which are given more than
+    /// once are only returned once.
+    pub async fn get_rows(
+        &self,
+        sheetid: &SheetId,
+        rows: &[i64],
+        options: sheet::ReadOptions,
+    ) -> Result<Vec<sheet::RowContent>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut unique = rows.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        // the rows are passed as a single json array, so that the statements are the same for any number of rows
+        // and don't have to be prepared again
+        let filter = "row IN (SELECT value FROM json_each(?))";
+        let params = [serde_json::to_string(&unique)?];
+        let content =
+            Self::read_rows_where(&mut tr, sheetid, column_table, filter, &params, options).await?;
+        tr.commit().await?;
+
+        let mut by_row = content.into_rows();
+        let mut seen = HashSet::new();
+        Ok(rows
+            .iter()
+            .filter(|&&row| seen.insert(row))
+            .map(|&row| {
+                by_row
+                    .remove(&row)
+                    .unwrap_or_else(|| sheet::RowContent::empty(row))
+            })
+            .collect())
+    }
+
+    /// Reads the cells at each of the given addresses, in the order they're given, resolving lookups without
+    /// touching the rest of the sheet. Empty cells are read as `null`, as are lookups pointing at them.
+    ///
+    /// # Errors
+    /// In case one of the columns doesn't exist, or is hidden and hidden columns aren't included.
+    pub async fn get_cells(
+        &self,
+        sheetid: &SheetId,
+        addresses: &[(String, i64)],
+        include_hidden: bool,
+    ) -> Result<Vec<sheet::AddressedCell>> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        let mut read: HashMap<(i64, i64), SheetContentColumn> = HashMap::new();
+        let mut cells = Vec::with_capacity(addresses.len());
+        for (column, row) in addresses {
+            let name: String = column.nfc().collect();
+            let Some(col_id) = column_table
+                .iter()
+                .position(|column| column.name == name && (!column.hidden || include_hidden))
+            else {
+                anyhow::bail!("invalid column name: {column:?}");
+            };
+            let col_id = col_id as i64;
+
+            let cell = match read.get(&(col_id, *row)) {
+                Some(cell) => cell.clone(),
+                None => {
+                    let target = sqlx::query_as::<_, (i64, i64)>(&format!(
+                        "SELECT target_col_id, target_row FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
+                        sheetid.ident()
+                    ))
+                    .bind(col_id)
+                    .bind(row)
+                    .fetch_optional(tr.as_mut())
+                    .await?;
+                    // lookups resolving to nothing are kept as null, since every address gets a cell
+                    let options = sheet::ReadOptions {
+                        no_lookup_nulls: false,
+                        ..Default::default()
+                    };
+                    let cell = match target {
+                        Some(target) => Self::resolve_lookup_chain(
+                            &mut tr,
+                            sheetid,
+                            &column_table,
+                            *row,
+                            target,
+                            options,
+                        )
+                        .await?
+                        .unwrap_or(SheetContentColumn {
+                            row: *row,
+                            value: None,
+                            error: None,
+                        }),
+                        None => SheetContentColumn::new(
+                            *row,
+                            Self::get_stored_value(
+                                &mut tr,
+                                sheetid,
+                                col_id,
+                                column_table[col_id as usize].kind,
+                                *row,
+                            )
+                            .await?,
+                        ),
+                    };
+                    read.insert((col_id, *row), cell.clone());
+                    cell
+                }
+            };
+            cells.push(sheet::AddressedCell {
+                column: name,
+                row: *row,
+                value: cell.value,
+                error: cell.error,
+            });
+        }
+        tr.commit().await?;
+
+        Ok(cells)
+    }
+
+    /// Finds up to `limit` populated rows, optionally only those after `after_row`, using the row indexes.
+    async fn populated_rows(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        columns: usize,
+        after_row: Option<i64>,
+        limit: i64,
+        descending: bool,
+    ) -> Result<Vec<i64>> {
+        // rows which only had their values replaced by lookups are still in the sheet table, with nulls
+        let populated = if columns == 0 {
+            "FALSE".to_string()
+        } else {
+            (0..columns)
+                .map(|i| format!("col{i} IS NOT NULL"))
+                .collect::<Vec<_>>()
+                .join(" OR ")
+        };
+
+        Ok(sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT row FROM sheet_{0} WHERE ({populated}) AND (?1 IS NULL OR row > ?1)
+            UNION SELECT row FROM sheet_{0}_lookups WHERE ?1 IS NULL OR row > ?1
+            UNION SELECT row FROM tombstones WHERE sheet_id = ?3 AND (?1 IS NULL OR row > ?1)
+            ORDER BY row {1} LIMIT ?2;",
+            sheetid.ident(),
+            if descending { "DESC" } else { "ASC" }
+        ))
+        .bind(after_row)
+        .bind(limit)
+        .bind(&sheetid.0)
+        .fetch_all(tr.as_mut())
+        .await?)
+    }
+
+    /// Reads the cells in an inclusive range of rows, in ascending order of rows. Nothing is read if there's
+    /// no range.
+    async fn read_row_range(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        column_table: Vec<sheet::SchemaColumn>,
+        range: Option<(i64, i64)>,
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::SheetContent> {
+        let (filter, rows) = match range {
+            Some((first_row, last_row)) => ("row BETWEEN ? AND ?", vec![first_row, last_row]),
+            None => ("FALSE", vec![]),
+        };
+        Self::read_rows_where(tr, sheetid, column_table, filter, &rows, options).await
+    }
+
+    /// Reads the cells of the rows matching `filter`, which is an SQL condition on `row` with a placeholder for
+    /// each of `params`, in ascending order of rows.
+    async fn read_rows_where<P: QueryParam>(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        column_table: Vec<sheet::SchemaColumn>,
+        filter: &str,
+        params: &[P],
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::SheetContent> {
+        let mut content: Vec<Vec<SheetContentColumn>> = vec![vec![]; column_table.len()];
+        let query = format!("SELECT * FROM sheet_{} WHERE {filter};", sheetid.ident());
+        let rows = params
+            .iter()
+            .fold(sqlx::query(&query), |query, param| query.bind(param))
+            .fetch_all(tr.as_mut())
+            .await?;
+        for row in rows {
+            let row_number: i64 = row.try_get("row")?;
+            for (i, column) in column_table.iter().enumerate() {
+                let value = decode_value(&row, i + 1, column.kind)?;
+                if value.is_some() {
+                    content[i].push(SheetContentColumn::new(row_number, value));
+                }
+            }
+        }
+
+        for (col_id, row) in Self::get_tombstones(tr.as_mut(), sheetid, filter, params).await? {
+            if let Some(cells) = content.get_mut(col_id as usize) {
+                cells.push(SheetContentColumn {
+                    row,
+                    value: None,
+                    error: None,
+                });
+            }
+        }
+
+        let query = format!(
+            "SELECT col_id, row, target_col_id, target_row FROM sheet_{}_lookups WHERE {filter};",
+            sheetid.ident()
+        );
+        let lookups = params
+            .iter()
+            .fold(sqlx::query_as::<_, (i64, i64, i64, i64)>(&query), |query, param| {
+                query.bind(param)
+            })
+            .fetch_all(tr.as_mut())
+            .await?;
+        for (col_id, row, target_col_id, target_row) in lookups {
+            let target = (target_col_id, target_row);
+            if let Some(cell) =
+                Self::resolve_lookup_chain(tr, sheetid, &column_table, row, target, options).await?
             {
-                anyhow::bail!("detected lookup cycle");
+                content[col_id as usize].push(cell);
             }
+        }
+
+        let columns = column_table
+            .into_iter()
+            .zip(content)
+            .filter(|(column, _)| !column.hidden || options.include_hidden)
+            .map(|(column, mut cells)| {
+                cells.sort_by_key(|cell| cell.row);
+                (column.name, cells)
+            })
+            .collect();
+
+        Ok(sheet::SheetContent {
+            columns,
+            meta: None,
+            row_keys: None,
+            version: None,
+        })
+    }
+
+    /// Resolves a single lookup in the given row by following its chain one cell at a time (or jumping straight
+    /// to its end, if the sheet compacts its lookup chains), which is cheaper than resolving every lookup of the
+    /// sheet when only a few are needed. Returns `None` if the lookup should be left out.
+    async fn resolve_lookup_chain(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        column_table: &[sheet::SchemaColumn],
+        row: i64,
+        (mut target_col_id, mut target_row): (i64, i64),
+        options: sheet::ReadOptions,
+    ) -> Result<Option<SheetContentColumn>> {
+        // lookup chains are guaranteed to be acyclic, so this always ends. compacted ones end right away
+        while let Some(next) = sqlx::query_as::<_, (i64, i64)>(&format!(
+            "SELECT COALESCE(root_col_id, target_col_id), COALESCE(root_row, target_row)
+            FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
+            sheetid.ident()
+        ))
+        .bind(target_col_id)
+        .bind(target_row)
+        .fetch_optional(tr.as_mut())
+        .await?
+        {
+            (target_col_id, target_row) = next;
+        }
+
+        let Some(target) = column_table.get(target_col_id as usize) else {
+            return Ok(Some(SheetContentColumn {
+                row,
+                value: None,
+                error: Some(CellError::Ref),
+            }));
+        };
+        let value =
+            Self::get_stored_value(tr, sheetid, target_col_id, target.kind, target_row).await?;
+        if value.is_none() && options.no_lookup_nulls {
+            return Ok(None);
+        }
+        Ok(Some(SheetContentColumn::new(row, value)))
+    }
+
+    /// Resolves a single cell step by step, recording every cell on the way.
+    ///
+    /// # Errors
+    /// In case there's no such column, or it's hidden and hidden columns aren't included.
+    pub async fn explain(
+        &self,
+        sheetid: &SheetId,
+        column: &str,
+        row: i64,
+        include_hidden: bool,
+    ) -> Result<sheet::Explanation> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        let Some((mut col_id, _)) = Self::get_column_by_name(&mut tr, sheetid, column).await?
+        else {
+            anyhow::bail!("invalid column name");
+        };
+        if column_table[col_id as usize].hidden && !include_hidden {
+            anyhow::bail!("invalid column name");
+        }
+        let name = |col_id: i64| {
+            column_table
+                .get(col_id as usize)
+                .map(|column| column.name.clone())
+        };
 
-            sqlx::query(&format!(
-                "UPDATE sheet_{} SET col{} = NULL WHERE row = ?;",
-                &sheetid.0, col_id
-            ))
-            .bind(cell.row)
-            .execute(&mut *tr)
-            .await?;
+        // lookup chains are guaranteed to be acyclic, so this always ends
+        let mut steps = vec![];
+        let mut row = row;
+        let explanation = loop {
+            let Some(column) = column_table.get(col_id as usize) else {
+                steps.push(sheet::ExplainStep {
+                    column: None,
+                    row,
+                    outcome: sheet::StepOutcome::MissingColumn,
+                });
+                break sheet::Explanation {
+                    steps,
+                    value: None,
+                    error: Some(CellError::Ref),
+                };
+            };
 
-            sqlx::query(&format!(
-                "INSERT INTO sheet_{}_lookups
-            (col_id, row, target_col_id, target_row)
-            VALUES (?, ?, ?, ?)
-            ON CONFLICT(col_id, row)
-            DO UPDATE SET target_col_id = excluded.target_col_id,
-            target_row = excluded.target_row;",
-                &sheetid.0
+            let lookup = sqlx::query_as::<_, (i64, i64)>(&format!(
+                "SELECT target_col_id, target_row FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
+                sheetid.ident()
             ))
             .bind(col_id)
-            .bind(cell.row)
-            .bind(target_col_id)
-            .bind(lookup.target_row)
-            .execute(&mut *tr)
+            .bind(row)
+            .fetch_optional(tr.as_mut())
             .await?;
-        } else {
-            if kind != SchemaColumnKind::from(&cell.value) {
-                anyhow::bail!("invalid column type");
+            if let Some((target_col_id, target_row)) = lookup {
+                steps.push(sheet::ExplainStep {
+                    column: Some(column.name.clone()),
+                    row,
+                    outcome: sheet::StepOutcome::Lookup {
+                        target_column: name(target_col_id),
+                        target_row,
+                    },
+                });
+                (col_id, row) = (target_col_id, target_row);
+                continue;
             }
 
-            // we can't have an entry for the same cell in both tables
-            sqlx::query(&format!(
-                "DELETE FROM sheet_{}_lookups WHERE col_id = ? AND row = ?;",
-                &sheetid.0
-            ))
+            let value = Self::get_stored_value(&mut tr, sheetid, col_id, column.kind, row).await?;
+            let tombstone = sqlx::query_scalar::<_, i64>(
+                "SELECT EXISTS(SELECT 1 FROM tombstones WHERE sheet_id = ? AND col_id = ? AND row = ?);",
+            )
+            .bind(&sheetid.0)
             .bind(col_id)
-            .bind(cell.row)
-            .execute(&mut *tr)
-            .await?;
+            .bind(row)
+            .fetch_one(tr.as_mut())
+            .await?
+                == 1;
+            let cell = SheetContentColumn::new(row, value);
+            let outcome = match (&cell.value, cell.error) {
+                (_, Some(error)) => sheet::StepOutcome::Error { error },
+                (Some(value), None) => sheet::StepOutcome::Value {
+                    value: value.clone(),
+                },
+                (None, None) if tombstone => sheet::StepOutcome::Null,
+                (None, None) => sheet::StepOutcome::Empty,
+            };
+            steps.push(sheet::ExplainStep {
+                column: Some(column.name.clone()),
+                row,
+                outcome,
+            });
+            break sheet::Explanation {
+                steps,
+                value: cell.value,
+                error: cell.error,
+            };
+        };
+        tr.commit().await?;
 
-            // again, the format is OK since everything is sanitized
-            let query = format!("INSERT INTO sheet_{0} (row, col{1}) VALUES(?, ?) ON CONFLICT(row) DO UPDATE SET col{1} = excluded.col{1};", sheetid.inner(), col_id);
-            let query = sqlx::query(&query).bind(cell.row);
+        Ok(explanation)
+    }
 
-            // this is needed because they all have different types
-            let query = match &cell.value {
-                CellValue::Boolean(x) => query.bind(x),
-                CellValue::Double(x) => query.bind(x),
-                CellValue::Int(x) => query.bind(x),
-                CellValue::String(x) => query.bind(x),
-            };
+    /// Reads the resolved cells of a single column, in ascending order of rows. If a `limit` is given, only up to
+    /// that many cells after `after_row` are read, and the cursor of the next page is returned as well (or
+    /// `None` if this was the last one).
+    ///
+    /// # Errors
+    /// In case there's no such column, or it's hidden and hidden columns aren't included.
+    pub async fn column_cells(
+        &self,
+        sheetid: &SheetId,
+        column: &str,
+        after_row: Option<i64>,
+        limit: Option<i64>,
+        options: sheet::ReadOptions,
+    ) -> Result<(Vec<SheetContentColumn>, Option<i64>)> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
 
-            query.execute(&mut *tr).await?;
+        let mut tr = self.pool.begin().await?;
+        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
+        let Some((col_id, kind)) = Self::get_column_by_name(&mut tr, sheetid, column).await? else {
+            anyhow::bail!("invalid column name");
+        };
+        if column_table[col_id as usize].hidden && !options.include_hidden {
+            anyhow::bail!("invalid column name");
         }
 
-        tr.commit().await?;
-        Ok(())
-    }
+        // one more row than needed tells whether there's a next page. a negative limit means no limit.
+        let mut rows = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT row FROM sheet_{0} WHERE col{1} IS NOT NULL AND (?1 IS NULL OR row > ?1)
+            UNION SELECT row FROM sheet_{0}_lookups WHERE col_id = ?3 AND (?1 IS NULL OR row > ?1)
+            UNION SELECT row FROM tombstones WHERE sheet_id = ?4 AND col_id = ?3 AND (?1 IS NULL OR row > ?1)
+            ORDER BY row LIMIT ?2;",
+            sheetid.ident(),
+            col_id
+        ))
+        .bind(after_row)
+        .bind(limit.map_or(-1, |limit| limit + 1))
+        .bind(col_id)
+        .bind(&sheetid.0)
+        .fetch_all(tr.as_mut())
+        .await?;
+        let next_cursor = match limit {
+            Some(limit) if rows.len() as i64 > limit => {
+                rows.truncate(limit as usize);
+                rows.last().copied()
+            }
+            _ => None,
+        };
+        let (Some(&first), Some(&last)) = (rows.first(), rows.last()) else {
+            return Ok((vec![], None));
+        };
 
-    async fn get_column_table(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-    ) -> Result<Vec<(String, SchemaColumnKind)>> {
-        let res = sqlx::query_as::<_, (String, String)>(&format!(
-            "SELECT name, type FROM sheet_{}_columns ORDER BY id ASC;",
-            &sheetid.0
+        let values = sqlx::query(&format!(
+            "SELECT row, col{1} FROM sheet_{0} WHERE col{1} IS NOT NULL AND row BETWEEN ? AND ?;",
+            sheetid.ident(),
+            col_id
         ))
+        .bind(first)
+        .bind(last)
         .fetch_all(tr.as_mut())
         .await?;
+        let mut cells = values
+            .iter()
+            .map(|row| Ok(SheetContentColumn::new(row.try_get(0)?, decode_value(row, 1, kind)?)))
+            .collect::<Result<Vec<_>>>()?;
 
-        Ok(res
-            .into_iter()
-            .map(|(name, kind)| (name, SchemaColumnKind::from_sql_text(&kind).unwrap()))
-            .collect())
-    }
+        let tombstones = sqlx::query_scalar::<_, i64>(
+            "SELECT row FROM tombstones WHERE sheet_id = ? AND col_id = ? AND row BETWEEN ? AND ?;",
+        )
+        .bind(&sheetid.0)
+        .bind(col_id)
+        .bind(first)
+        .bind(last)
+        .fetch_all(tr.as_mut())
+        .await?;
+        cells.extend(tombstones.into_iter().map(|row| SheetContentColumn {
+            row,
+            value: None,
+            error: None,
+        }));
 
-    async fn get_column_content(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        sheetid: &SheetId,
-        kind: SchemaColumnKind,
-        col_id: i64,
-    ) -> Result<HashMap<i64, Option<CellValue>>> {
-        let rows = sqlx::query(&format!(
-            "SELECT row, col{0} FROM sheet_{1} WHERE NOT col{0} IS NULL;",
-            col_id, &sheetid.0
+        let lookups = sqlx::query_as::<_, (i64, i64, i64)>(&format!(
+            "SELECT row, target_col_id, target_row FROM sheet_{}_lookups
+            WHERE col_id = ? AND row BETWEEN ? AND ?;",
+            sheetid.ident()
         ))
+        .bind(col_id)
+        .bind(first)
+        .bind(last)
         .fetch_all(tr.as_mut())
         .await?;
+        for (row, target_col_id, target_row) in lookups {
+            let target = (target_col_id, target_row);
+            if let Some(cell) =
+                Self::resolve_lookup_chain(&mut tr, sheetid, &column_table, row, target, options)
+                    .await?
+            {
+                cells.push(cell);
+            }
+        }
+        tr.commit().await?;
 
-        Ok(rows
-            .into_iter()
-            .map(|row| {
-                let id = row.get::<i64, usize>(0);
+        cells.sort_by_key(|cell| cell.row);
+        Ok((cells, next_cursor))
+    }
 
-                let val = match kind {
-                    SchemaColumnKind::Boolean => CellValue::Boolean(row.get::<bool, usize>(1)),
-                    SchemaColumnKind::Int => CellValue::Int(row.get::<i64, usize>(1)),
-                    SchemaColumnKind::Double => CellValue::Double(row.get::<f64, usize>(1)),
-                    SchemaColumnKind::String => CellValue::String(row.get::<String, usize>(1)),
-                };
+    pub async fn get_sheet(
+        &self,
+        sheetid: &SheetId,
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::SheetContent> {
+        self.get_sheet_within(sheetid, options, None).await
+    }
 
-                (id, Some(val))
-            })
-            .collect())
+    /// Reads every given sheet in full (resolving all of its lookups), which brings the pages they're stored in into
+    /// sqlite's and the operating system's caches. Sheets which can't be read are skipped, since a stale entry in the
+    /// list shouldn't keep the server from starting.
+    ///
+    /// Returns the number of sheets which were read.
+    pub async fn warm_up(&self, sheet_ids: &[String]) -> usize {
+        let mut warmed_up = 0;
+        for id in sheet_ids {
+            let start = std::time::Instant::now();
+            let result = match SheetId::try_from(id.as_str()) {
+                Ok(sheetid) => self
+                    .get_sheet(&sheetid, Default::default())
+                    .await
+                    .map(|_| ()),
+                Err(why) => Err(why),
+            };
+
+            match result {
+                Ok(()) => {
+                    log::info!("warmed up sheet {id} in {:?}", start.elapsed());
+                    warmed_up += 1;
+                }
+                Err(why) => log::warn!("failed to warm up sheet {id}: {why}"),
+            }
+        }
+        warmed_up
     }
 
-    async fn get_lookups(
-        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    /// Same as [`Self::get_sheet`], but every cell is added to `budget` as it's read.
+    ///
+    /// # Errors
+    /// [`crate::budget::BudgetExceeded`] as soon as the sheet stops fitting into the budget, in addition to the errors of
+    /// [`Self::get_sheet`].
+    pub async fn get_sheet_within(
+        &self,
         sheetid: &SheetId,
-    ) -> Result<HashMap<(i64, i64), (i64, i64)>> {
-        Ok(sqlx::query_as::<_, (i64, i64, i64, i64)>(&format!(
-            "SELECT col_id, row, target_col_id, target_row FROM sheet_{}_lookups;",
-            &sheetid.0
-        ))
-        .fetch_all(tr.as_mut())
-        .await?
-        .into_iter()
-        .map(|(a, b, c, d)| ((a, b), (c, d)))
-        .collect())
+        options: sheet::ReadOptions,
+        budget: Option<&Reservation<'_>>,
+    ) -> Result<sheet::SheetContent> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        let (mut tr, version) = self.begin_read(sheetid).await?;
+        let meta = match options.include_meta {
+            true => Some(Self::get_all_row_meta(tr.as_mut(), sheetid, None).await?),
+            false => None,
+        };
+        let row_keys = match options.include_row_keys {
+            true => Some(Self::get_row_keys(tr.as_mut(), sheetid, None).await?),
+            false => None,
+        };
+        let (column_table, regular_content, unresolved_lookups) =
+            Self::read_sheet(&mut tr, sheetid, budget).await?;
+        tr.commit().await?;
+        let version = options.include_version.then_some(version);
+
+        Ok(sheet::SheetContent {
+            columns: Self::resolve_sheet(
+                column_table,
+                regular_content,
+                unresolved_lookups,
+                options,
+            ),
+            meta,
+            row_keys,
+            version,
+        })
     }
 
-    pub async fn get_sheet(
+    /// Starts a read which sees a single snapshot of a sheet, along with the version of that snapshot. Sheets
+    /// which were archived or moved to another id since they were checked for are reported as missing.
+    async fn begin_read(
         &self,
         sheetid: &SheetId,
-        no_lookup_nulls: bool,
-    ) -> Result<sheet::SheetContent> {
+    ) -> Result<(sqlx::Transaction<'static, sqlx::Sqlite>, i64)> {
         let mut tr = self.pool.begin().await?;
+        // sqlite's snapshot starts with the first read, so everything read after this sees the same version
+        let version = sqlx::query_scalar::<_, i64>("SELECT version FROM sheets WHERE id = ?;")
+            .bind(&sheetid.0)
+            .fetch_optional(tr.as_mut())
+            .await?
+            .ok_or(SheetNotFound)?;
+        Ok((tr, version))
+    }
 
-        if !Self::sheet_exists(&mut tr, sheetid).await? {
-            anyhow::bail!("sheet doesn't exist");
+    /// Reads the columns, the content of every column, and the lookups of a sheet, ready to be resolved.
+    ///
+    /// Everything is read in the same transaction, since a write can change several of these tables at once,
+    /// e.g. a value turning into a lookup, and reading them apart could show such a cell twice or not at all.
+    async fn read_sheet(
+        tr: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        sheetid: &SheetId,
+        budget: Option<&Reservation<'_>>,
+    ) -> Result<(
+        Vec<sheet::SchemaColumn>,
+        Vec<HashMap<i64, Option<CellValue>>>,
+        HashMap<(i64, i64), (i64, i64)>,
+    )> {
+        let column_table = Self::get_column_table(tr, sheetid).await?;
+        let mut regular_content = Vec::with_capacity(column_table.len());
+        for (i, column) in column_table.iter().enumerate() {
+            regular_content.push(
+                Self::get_column_content(tr.as_mut(), sheetid, column.kind, i as i64, budget)
+                    .await?,
+            );
+        }
+        let unresolved_lookups = Self::get_lookups(tr.as_mut(), sheetid).await?;
+        let tombstones = Self::get_tombstones(tr.as_mut(), sheetid, "TRUE", &[] as &[i64]).await?;
+        if let Some(budget) = budget {
+            budget.add(cell_size(None) * (unresolved_lookups.len() + tombstones.len()))?;
         }
 
-        let column_table = Self::get_column_table(&mut tr, sheetid).await?;
-        let mut regular_content = {
-            let mut regular_content = vec![];
-            for (i, (_, kind)) in column_table.iter().enumerate() {
-                let column_content =
-                    Self::get_column_content(&mut tr, sheetid, *kind, i as i64).await?;
-                regular_content.push(column_content);
+        // tombstones are cells without a value, just like lookups which resolve to nothing
+        for (col_id, row) in tombstones {
+            if let Some(content) = regular_content.get_mut(col_id as usize) {
+                content.insert(row, None);
             }
-            regular_content
+        }
+
+        Ok((column_table, regular_content, unresolved_lookups))
+    }
+
+    /// Reads only the cells of a sheet which changed after the given version: the cells written since, and
+    /// every lookup whose chain passes through one of them. Changed cells which are now empty are returned as
+    /// `null`. The content always includes the current version, and never the metadata or keys of rows.
+    pub async fn get_changes(
+        &self,
+        sheetid: &SheetId,
+        since_version: i64,
+        options: sheet::ReadOptions,
+    ) -> Result<sheet::SheetContent> {
+        if !self.sheet_exists(sheetid) {
+            return Err(SheetNotFound.into());
+        }
+
+        // the changes and the content have to come from the same snapshot, or a write made in between would be
+        // left out of this response and then skipped by the next one, which asks for changes since `version`
+        let (mut tr, version) = self.begin_read(sheetid).await?;
+        let mut changed = if since_version < version {
+            sqlx::query_as::<_, (i64, i64)>(
+                "SELECT col_id, row FROM cell_versions WHERE sheet_id = ? AND version > ?;",
+            )
+            .bind(&sheetid.0)
+            .bind(since_version)
+            .fetch_all(tr.as_mut())
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>()
+        } else {
+            HashSet::new()
         };
-        let mut unresolved_lookups = Self::get_lookups(&mut tr, sheetid).await?;
-        tr.commit().await?; // we commit here to not hold up the database - we got all the data out at this point
+        if changed.is_empty() {
+            // every column is still there, just without any cells
+            let columns = Self::get_column_table(&mut tr, sheetid)
+                .await?
+                .into_iter()
+                .filter(|column| !column.hidden || options.include_hidden)
+                .map(|column| (column.name, vec![]))
+                .collect();
+            return Ok(sheet::SheetContent {
+                columns,
+                meta: None,
+                row_keys: None,
+                version: Some(version),
+            });
+        }
+
+        let (column_table, regular_content, unresolved_lookups) =
+            Self::read_sheet(&mut tr, sheetid, None).await?;
+        tr.commit().await?;
+
+        // lookups change along with their targets, however far down the chain those are
+        let mut dependents = HashMap::<_, Vec<_>>::new();
+        for (source, target) in &unresolved_lookups {
+            dependents.entry(*target).or_default().push(*source);
+        }
+        let mut queue: Vec<_> = changed.iter().copied().collect();
+        while let Some(cell) = queue.pop() {
+            for dependent in dependents.get(&cell).into_iter().flatten() {
+                if changed.insert(*dependent) {
+                    queue.push(*dependent);
+                }
+            }
+        }
+
+        let names: HashMap<_, _> = column_table
+            .iter()
+            .enumerate()
+            .map(|(i, column)| (column.name.clone(), i as i64))
+            .collect();
+        let mut columns =
+            Self::resolve_sheet(column_table, regular_content, unresolved_lookups, options);
+        for (name, cells) in &mut columns {
+            let col_id = names[name];
+            cells.retain(|cell| changed.contains(&(col_id, cell.row)));
+
+            let present: HashSet<_> = cells.iter().map(|cell| cell.row).collect();
+            let mut emptied: Vec<_> = changed
+                .iter()
+                .filter(|(id, row)| *id == col_id && !present.contains(row))
+                .map(|(_, row)| SheetContentColumn {
+                    row: *row,
+                    value: None,
+                    error: None,
+                })
+                .collect();
+            cells.append(&mut emptied);
+        }
+        columns.retain(|_, cells| !cells.is_empty());
+
+        Ok(sheet::SheetContent {
+            columns,
+            meta: None,
+            row_keys: None,
+            version: Some(version),
+        })
+    }
+
+    /// Resolves every lookup of a sheet, given the content of all of its columns and all of its lookups,
+    /// and names the columns.
+    fn resolve_sheet(
+        column_table: Vec<sheet::SchemaColumn>,
+        mut regular_content: Vec<HashMap<i64, Option<CellValue>>>,
+        mut unresolved_lookups: HashMap<(i64, i64), (i64, i64)>,
+        options: sheet::ReadOptions,
+    ) -> HashMap<String, Vec<SheetContentColumn>> {
+        // lookups whose chain ends up somewhere invalid. these are reported as errors rather than nulls.
+        let mut errors = HashMap::new();
 
         // this loop efficiently resolves all of the lookup() entries. we find continuous chains of lookup()s, and evaluate them all at once.
         while !unresolved_lookups.is_empty() {
@@ -460,11 +6493,22 @@ impl Db {
                     stack.push(current_key);
                     current_key = next_key;
                 } else {
+                    let error = match regular_content.get(current_key.0 as usize) {
+                        Some(_) => errors.get(&current_key).copied(),
+                        None => Some(CellError::Ref),
+                    };
+                    if let Some(error) = error {
+                        for key in &stack {
+                            errors.insert(*key, error);
+                        }
+                        break;
+                    }
+
                     let val = regular_content[current_key.0 as usize]
                         .get(&current_key.1)
                         .cloned()
                         .flatten();
-                    if !no_lookup_nulls {
+                    if !options.no_lookup_nulls {
                         for key in &stack {
                             regular_content[key.0 as usize].insert(key.1, val.clone());
                         }
@@ -476,24 +6520,39 @@ impl Db {
 
         let mut output = HashMap::new();
         // using .rev() because we're continously popping from regular_content (so as to not clone anything)
-        for (name, _) in column_table.into_iter().rev() {
-            let col = regular_content
-                .pop()
-                .unwrap()
+        for (i, column) in column_table.into_iter().enumerate().rev() {
+            let content = regular_content.pop().unwrap();
+            // hidden columns are only left out here, since lookups may still refer to them
+            if column.hidden && !options.include_hidden {
+                continue;
+            }
+
+            let col = content
                 .into_iter()
-                .map(|(row, value)| SheetContentColumn { row, value })
+                .map(|(row, value)| SheetContentColumn::new(row, value))
+                .chain(
+                    errors
+                        .iter()
+                        .filter(|((col_id, _), _)| *col_id == i as i64)
+                        .map(|((_, row), error)| SheetContentColumn {
+                            row: *row,
+                            value: None,
+                            error: Some(*error),
+                        }),
+                )
                 .collect();
 
-            output.insert(name, col);
+            output.insert(column.name, col);
         }
 
-        Ok(sheet::SheetContent { columns: output })
+        output
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SheetId;
+    use super::{Db, IdScheme, SheetId, ViolationKind};
+    use crate::sheet::{Cell, CellError, CellValue, ReadOptions, Schema, SheetContent};
 
     #[test]
     fn sheet_id_valid_try_from() {
@@ -505,7 +6564,7 @@ mod tests {
     #[test]
     #[should_panic]
     fn sheet_id_invalid_try_from_length() {
-        let _ = SheetId::try_from("invalidlength").unwrap();
+        let _ = SheetId::try_from("invalidLength").unwrap();
     }
 
     #[test]
@@ -513,4 +6572,520 @@ mod tests {
     fn sheet_id_invalid_try_from_content() {
         let _ = SheetId::try_from("invalid characters!zzzzz").unwrap();
     }
+
+    #[actix_web::test]
+    async fn broken_lookup_chain_is_an_error() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        // simulate a lookup whose target column has since disappeared
+        sqlx::query(&format!(
+            "INSERT INTO sheet_{}_lookups (col_id, row, target_col_id, target_row) VALUES (1, 5, 99, 4), (1, 6, 1, 5);",
+            sheetid.ident()
+        ))
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let content: SheetContent = db
+            .get_sheet(
+                &sheetid,
+                ReadOptions {
+                    no_lookup_nulls: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let mut column = content.columns["B"].clone();
+        column.sort_unstable_by_key(|x| x.row);
+        assert_eq!(column.len(), 2);
+        assert!(column
+            .iter()
+            .all(|x| x.value.is_none() && x.error == Some(CellError::Ref)));
+    }
+
+    #[actix_web::test]
+    async fn warming_up_skips_sheets_which_cant_be_read() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        let ids = [
+            "not a sheet id".to_string(),
+            "abCDefGHijklMnOPqrst1234".to_string(),
+            sheetid.inner().to_string(),
+        ];
+        assert_eq!(db.warm_up(&ids).await, 1);
+        assert_eq!(db.warm_up(&[]).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn non_finite_doubles_are_rejected_and_reported() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        let cell = |row, value| Cell {
+            column: "C".into(),
+            row,
+            value: CellValue::Double(value),
+        };
+        for value in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            assert!(db
+                .insert_cell(&sheetid, &cell(1, value), false)
+                .await
+                .is_err());
+            // batches fall back to writing the cell on its own, which rejects it as well
+            let cells = [cell(1, 1.5), cell(2, value)];
+            assert!(db.insert_cells(&sheetid, &cells, false).await.is_err());
+        }
+        db.insert_cell(&sheetid, &cell(1, 1.5), false)
+            .await
+            .unwrap();
+
+        // infinities written by older versions are kept, and read as errors, as are lookups resolving to them
+        sqlx::query(&format!(
+            "INSERT INTO sheet_{} (row, col3) VALUES (2, 9e999);",
+            sheetid.ident()
+        ))
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        let lookup = Cell {
+            column: "C".into(),
+            row: 3,
+            value: CellValue::String("lookup(\"C\", 2)".into()),
+        };
+        db.insert_cell(&sheetid, &lookup, false).await.unwrap();
+        // the sign of zero isn't kept
+        db.insert_cell(&sheetid, &cell(4, -0.0), false)
+            .await
+            .unwrap();
+
+        let content = db
+            .get_sheet(&sheetid, ReadOptions::default())
+            .await
+            .unwrap();
+        let mut cells: Vec<_> = content.columns["C"]
+            .iter()
+            .map(|x| (x.row, x.value.clone(), x.error))
+            .collect();
+        cells.sort_by_key(|x| x.0);
+        assert_eq!(
+            cells,
+            [
+                (1, Some(CellValue::Double(1.5)), None),
+                (2, None, Some(CellError::Num)),
+                (3, None, Some(CellError::Num)),
+                (4, Some(CellValue::Double(0.0)), None),
+            ]
+        );
+        assert!(matches!(cells[3].1, Some(CellValue::Double(x)) if x.is_sign_positive()));
+
+        let cells = db
+            .get_cells(&sheetid, &[("C".into(), 2), ("C".into(), 3)], false)
+            .await
+            .unwrap();
+        assert!(cells
+            .iter()
+            .all(|x| x.value.is_none() && x.error == Some(CellError::Num)));
+        let explanation = db.explain(&sheetid, "C", 3, false).await.unwrap();
+        assert_eq!(explanation.error, Some(CellError::Num));
+
+        // overwriting them works as usual
+        db.insert_cell(&sheetid, &cell(2, 2.5), false)
+            .await
+            .unwrap();
+        let (cells, _) = db
+            .column_cells(&sheetid, "C", None, None, ReadOptions::default())
+            .await
+            .unwrap();
+        assert!(cells.iter().all(|x| x.error.is_none()));
+    }
+
+    #[actix_web::test]
+    async fn consistency_violations_are_found_and_repaired() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        // B1 is both a value and a lookup, B5 looks up a missing column, and B7 and B8 look each other up
+        sqlx::query(&format!(
+            "INSERT INTO sheet_{0} (row, col1) VALUES (1, 10);
+            INSERT INTO sheet_{0}_lookups (col_id, row, target_col_id, target_row)
+            VALUES (1, 1, 2, 1), (1, 5, 99, 4), (1, 7, 1, 8), (1, 8, 1, 7), (1, 9, 1, 7);",
+            sheetid.ident()
+        ))
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let report = db.check_consistency(false).await.unwrap();
+        assert_eq!(report.sheets_checked, 1);
+        assert!(!report.repaired);
+        let found: Vec<_> = report
+            .violations
+            .iter()
+            .map(|violation| (violation.kind, violation.col_id, violation.row))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (ViolationKind::ValueAndLookup, 1, 1),
+                (ViolationKind::MissingColumn, 1, 5),
+                (ViolationKind::Cycle, 1, 7),
+            ]
+        );
+
+        let report = db.check_consistency(true).await.unwrap();
+        assert!(report.repaired);
+        let report = db.check_consistency(false).await.unwrap();
+        assert!(report.violations.is_empty());
+
+        // the stored value won, and the rest of the former cycle still resolves
+        let content = db
+            .get_sheet(&sheetid, ReadOptions::default())
+            .await
+            .unwrap();
+        let mut column = content.columns["B"].clone();
+        column.sort_unstable_by_key(|x| x.row);
+        let rows: Vec<_> = column.iter().map(|x| x.row).collect();
+        assert_eq!(rows, [1, 8, 9]);
+        assert_eq!(column[0].value, Some(crate::sheet::CellValue::Int(10)));
+    }
+
+    #[actix_web::test]
+    async fn cycle_detection_with_multiple_dependencies() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        let mut tr = db.pool.begin().await.unwrap();
+        Db::set_dependencies(&mut tr, &sheetid, (0, 1), &[(0, 2), (0, 3)])
+            .await
+            .unwrap();
+        Db::set_dependencies(&mut tr, &sheetid, (0, 3), &[(0, 4)])
+            .await
+            .unwrap();
+        Db::set_dependencies(&mut tr, &sheetid, (0, 2), &[(0, 4)])
+            .await
+            .unwrap();
+
+        assert!(Db::detect_cycle(&mut tr, &sheetid, (0, 4), &[(0, 1)])
+            .await
+            .unwrap());
+        assert!(Db::detect_cycle(&mut tr, &sheetid, (0, 3), &[(0, 5), (0, 1)])
+            .await
+            .unwrap());
+        assert!(!Db::detect_cycle(&mut tr, &sheetid, (0, 5), &[(0, 1)])
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    fn sheet_id_uuid_try_from() {
+        let mut rng = rand::thread_rng();
+        for scheme in [IdScheme::UuidV4, IdScheme::UuidV7] {
+            let sheet_id = SheetId::generate(&mut rng, scheme);
+            assert_eq!(SheetId::try_from(sheet_id.inner()).unwrap().inner(), sheet_id.inner());
+            assert!(!sheet_id.ident().contains('-'));
+        }
+
+        assert!(SheetId::try_from("67E55044-10B1-426F-9247-BB680E5FE0C8").is_err());
+    }
+
+    #[actix_web::test]
+    async fn uuid_sheets_work() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::UuidV7, None, None)
+            .await
+            .unwrap();
+        assert!(db.get_sheet(&sheetid, ReadOptions::default()).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn existing_sheets_are_known_after_reopening() {
+        let path = std::env::temp_dir().join(format!("known_sheets_{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+
+        let db = Db::new(path).await.unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+        db.close().await;
+
+        let db = Db::new(path).await.unwrap();
+        let result = db.get_sheet(&sheetid, ReadOptions::default()).await;
+        db.close().await;
+        std::fs::remove_file(path).unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn reads_see_a_single_snapshot() {
+        let path = std::env::temp_dir().join(format!("snapshot_{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+
+        // writes can only commit while a read is in progress with a write-ahead log, which is when a read could
+        // see them half-way
+        let db = Db::new(path).await.unwrap();
+        sqlx::query("PRAGMA journal_mode = WAL;")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        db.close().await;
+        let db = Db::new(path).await.unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+        // opening a connection writes to the database, so the pool has to be filled before the writes start
+        let warm_up = (0..10).map(|_| db.get_sheet(&sheetid, ReadOptions::default()));
+        futures_util::future::try_join_all(warm_up).await.unwrap();
+        let cell = |column: &str, value| Cell {
+            column: column.into(),
+            row: 1,
+            value,
+        };
+        db.insert_cell(&sheetid, &cell("B2", CellValue::Int(7)), false)
+            .await
+            .unwrap();
+
+        // B1 keeps turning from a value into a lookup and back, which changes two tables at once
+        let writes = async {
+            for i in 0..100 {
+                let value = match i % 2 {
+                    0 => CellValue::String("lookup(\"B2\", 1)".into()),
+                    _ => CellValue::Int(5),
+                };
+                db.insert_cell(&sheetid, &cell("B", value), false).await?;
+            }
+            anyhow::Ok(())
+        };
+        let reads = async {
+            let mut results = vec![];
+            for _ in 0..100 {
+                let options = ReadOptions {
+                    include_version: true,
+                    ..Default::default()
+                };
+                let content = db.get_sheet(&sheetid, options).await?;
+                let values: Vec<_> = content.columns["B"]
+                    .iter()
+                    .map(|x| x.value.clone())
+                    .collect();
+                results.push((content.version.unwrap(), values));
+            }
+            anyhow::Ok(results)
+        };
+        let (writes, reads) = futures_util::future::join(writes, reads).await;
+        db.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+
+        writes.unwrap();
+        for (version, values) in reads.unwrap() {
+            // B2 was written in version 1, and B1 turns into a lookup in every even version after it
+            let expected = match version {
+                ..=1 => vec![],
+                _ if version % 2 == 0 => vec![Some(CellValue::Int(7))],
+                _ => vec![Some(CellValue::Int(5))],
+            };
+            assert_eq!(values, expected, "version {version}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn read_only_databases_see_new_sheets() {
+        let path = std::env::temp_dir().join(format!("read_only_{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+
+        let db = Db::new(path).await.unwrap();
+        let replica = Db::new_read_only(path).await.unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        let before = replica.get_sheet(&sheetid, ReadOptions::default()).await;
+        replica.refresh_known_sheets().await.unwrap();
+        let after = replica.get_sheet(&sheetid, ReadOptions::default()).await;
+        let write = replica
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await;
+
+        replica.close().await;
+        db.close().await;
+        std::fs::remove_file(path).unwrap();
+        assert!(before.unwrap_err().is::<super::SheetNotFound>());
+        assert!(after.is_ok());
+        assert!(write.is_err());
+    }
+
+    #[actix_web::test]
+    async fn compacted_lookup_chains_stay_up_to_date() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        let cell = |row, value| Cell {
+            column: "B".into(),
+            row,
+            value,
+        };
+        let lookup =
+            |row, target: i64| cell(row, CellValue::String(format!("lookup(\"B\", {target})")));
+        let roots = || async {
+            sqlx::query_as::<_, (i64, Option<i64>)>(&format!(
+                "SELECT row, root_row FROM sheet_{}_lookups ORDER BY row;",
+                sheetid.ident()
+            ))
+            .fetch_all(&db.pool)
+            .await
+            .unwrap()
+        };
+        let value = |row| {
+            let (db, sheetid) = (&db, &sheetid);
+            async move {
+                let content = db.get_row(sheetid, row, ReadOptions::default()).await;
+                content.unwrap().columns["B"].value.clone()
+            }
+        };
+
+        // B1 -> B2 -> B3 -> B4 -> B5, and B6 -> B3
+        for row in 1..5 {
+            db.insert_cell(&sheetid, &lookup(row, row + 1), false)
+                .await
+                .unwrap();
+        }
+        db.insert_cell(&sheetid, &lookup(6, 3), false)
+            .await
+            .unwrap();
+        db.insert_cell(&sheetid, &cell(5, CellValue::Int(5)), false)
+            .await
+            .unwrap();
+        assert!(roots().await.iter().all(|(_, root)| root.is_none()));
+
+        let mut settings = db.get_settings(&sheetid).await.unwrap();
+        settings.compact_lookups = true;
+        db.set_settings(&sheetid, &settings).await.unwrap();
+        assert_eq!(roots().await, [1, 2, 3, 4, 6].map(|row| (row, Some(5))));
+        assert_eq!(value(1).await, Some(CellValue::Int(5)));
+
+        // both through the cell at the end of the chains and through one in the middle of them
+        assert!(db
+            .insert_cell(&sheetid, &lookup(5, 1), false)
+            .await
+            .is_err());
+        assert!(db
+            .insert_cell(&sheetid, &lookup(3, 6), false)
+            .await
+            .is_err());
+
+        // the chains going through B3 now end at the empty B7, until it looks up B4 in turn
+        db.insert_cell(&sheetid, &lookup(3, 7), false)
+            .await
+            .unwrap();
+        let expected = [(1, 7), (2, 7), (3, 7), (4, 5), (6, 7)];
+        assert_eq!(roots().await, expected.map(|(row, root)| (row, Some(root))));
+        db.insert_cell(&sheetid, &lookup(7, 4), false)
+            .await
+            .unwrap();
+        assert_eq!(roots().await, [1, 2, 3, 4, 6, 7].map(|row| (row, Some(5))));
+
+        // values, tombstones and batches of values cut the chains
+        db.insert_cell(&sheetid, &cell(2, CellValue::Int(2)), false)
+            .await
+            .unwrap();
+        db.insert_tombstone(&sheetid, "B", 4).await.unwrap();
+        let expected = [(1, 2), (3, 4), (6, 4), (7, 4)];
+        assert_eq!(roots().await, expected.map(|(row, root)| (row, Some(root))));
+        let cells = [cell(7, CellValue::Int(7)), cell(8, CellValue::Int(8))];
+        db.insert_cells(&sheetid, &cells, false).await.unwrap();
+        let expected = [(1, 2), (3, 7), (6, 7)];
+        assert_eq!(roots().await, expected.map(|(row, root)| (row, Some(root))));
+        assert_eq!(value(1).await, Some(CellValue::Int(2)));
+        assert_eq!(value(6).await, Some(CellValue::Int(7)));
+
+        settings.compact_lookups = false;
+        db.set_settings(&sheetid, &settings).await.unwrap();
+        assert!(roots().await.iter().all(|(_, root)| root.is_none()));
+        assert_eq!(value(6).await, Some(CellValue::Int(7)));
+    }
+
+    #[actix_web::test]
+    async fn insert_cells_spans_batches() {
+        let db = Db::new_memory().await.unwrap();
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheetid = db
+            .new_sheet(&schema, IdScheme::default(), None, None)
+            .await
+            .unwrap();
+
+        let cell = |column: &str, row, value| Cell {
+            column: column.into(),
+            row,
+            value,
+        };
+        // more cells than fit into a single batch, with a lookup and an overwritten cell in between
+        let mut cells: Vec<_> = (1..=super::INSERT_BATCH_CELLS as i64 * 2)
+            .map(|row| cell("B", row, CellValue::Int(row)))
+            .collect();
+        cells.insert(10, cell("B2", 1, CellValue::String("lookup(\"B\", 5)".into())));
+        cells.push(cell("B", 1, CellValue::Int(-1)));
+        db.insert_cells(&sheetid, &cells, false).await.unwrap();
+
+        let content = db
+            .get_sheet(&sheetid, ReadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(content.columns["B"].len(), super::INSERT_BATCH_CELLS * 2);
+        assert_eq!(content.columns["B2"][0].value, Some(CellValue::Int(5)));
+        let stats = db.column_stats(&sheetid, false).await.unwrap();
+        assert_eq!(stats["B"].min, Some(CellValue::Int(-1)));
+        assert_eq!(stats["B"].max, Some(CellValue::Int(super::INSERT_BATCH_CELLS as i64 * 2)));
+        assert!(db
+            .check_consistency(false)
+            .await
+            .unwrap()
+            .violations
+            .is_empty());
+    }
+
+    #[test]
+    fn sheet_id_slugs() {
+        assert_eq!(SheetId::from_slug("q3-budget").unwrap().ident(), "q3_budget");
+        assert!(SheetId::try_from("q3-budget").is_ok());
+        assert!(SheetId::from_slug("Q3-budget").is_err());
+        assert!(SheetId::from_slug("-q3-budget").is_err());
+        assert!(SheetId::from_slug("q3_budget").is_err());
+        assert!(SheetId::from_slug("q3").is_err());
+    }
 }
@@ -0,0 +1,121 @@
+//! Endpoints which only exist to make testing against the server easier. These are only registered when
+//! `TEST_ENDPOINTS` is set, and must never be enabled in production.
+
+use actix_web::{http::StatusCode, post, web};
+use serde::{Deserialize, Serialize};
+
+use crate::sheet::web::{create_sheet, failure, success, ErrorResponse, Responded};
+use crate::sheet::{Cell, NewSheet};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(post_seed);
+}
+
+/// A sheet creation request, along with the content to fill the sheet with.
+#[derive(Deserialize, Clone, Debug)]
+struct Seed {
+    #[serde(flatten)]
+    sheet: NewSheet,
+    #[serde(default)]
+    cells: Vec<Cell>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SeedResponse {
+    sheet_id: String,
+}
+
+/// Creates a sheet and inserts all of the given cells, in order, in the same transaction, so that a sheet is only
+/// created if all of its cells can be written. Cells may refer to each other using lookups, as long as every lookup
+/// comes after the cell it points at when `STRICT_LOOKUPS` is set.
+///
+/// The sheet is created by the request itself, so it has no validator to ask about the cells.
+#[post("/seed")]
+async fn post_seed(
+    data: web::Data<crate::AppData>,
+    seed: Option<web::Json<Seed>>,
+) -> Responded<SeedResponse> {
+    let Some(seed) = seed else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+    let Seed { sheet, cells } = seed.into_inner();
+
+    match create_sheet(&data, sheet, &cells).await {
+        Ok(sheetid) => success(SeedResponse {
+            sheet_id: sheetid.inner().into(),
+        }),
+        Err((status, error)) => failure(status, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header::ContentType, StatusCode};
+    use actix_web::test;
+
+    use crate::config::AppConfig;
+
+    const SEED: &str = r#"{
+        "id": "seeded",
+        "columns": [{"name": "A", "type": "int"}, {"name": "B", "type": "int"}],
+        "cells": [
+            {"column": "A", "row": 1, "value": 5},
+            {"column": "B", "row": 1, "value": "lookup(\"A\", 1)"}
+        ]
+    }"#;
+
+    fn seed(payload: &str) -> actix_http::Request {
+        test::TestRequest::post()
+            .uri("/test/seed")
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    }
+
+    #[actix_web::test]
+    async fn seed_creates_filled_sheet() {
+        let app = init_service!(AppConfig {
+            test_endpoints: true,
+            ..Default::default()
+        });
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, seed(SEED)).await;
+        assert_eq!(body["sheet_id"], "seeded");
+
+        let req = test::TestRequest::get().uri("/sheet/seeded").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["columns"]["B"][0]["value"], 5);
+
+        // the id is taken now
+        let resp = test::call_service(&app, seed(SEED)).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn seed_reports_bad_cells() {
+        let app = init_service!(AppConfig {
+            test_endpoints: true,
+            ..Default::default()
+        });
+
+        let payload = r#"{"id": "broken", "columns": [{"name": "A", "type": "int"}], "cells": [{"column": "A", "row": 1, "value": "nope"}]}"#;
+        let resp = test::call_service(&app, seed(payload)).await;
+        let json = assert_is_error_response!(resp);
+        assert!(json["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("column A row 1:"));
+
+        // nothing is left behind
+        let req = test::TestRequest::get().uri("/sheet/broken").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn seed_is_disabled_by_default() {
+        let app = init_service!();
+
+        let resp = test::call_service(&app, seed(SEED)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,49 @@
+//! Hooks for embedding the server as a library, which see every cell before it's written (see
+//! [`crate::db::Db::with_interceptor`]). These are meant for business rules which can't be expressed with the
+//! schema, normalizing values, or enriching them with data from elsewhere.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::Result;
+
+use crate::db::SheetId;
+use crate::sheet::Cell;
+
+/// A cell which is about to be written.
+pub struct CellWrite<'a> {
+    pub sheet_id: &'a SheetId,
+    /// The cell as it will be written, which the interceptor may change.
+    pub cell: &'a mut Cell,
+    /// The transaction the cell is going to be written in, which sees everything written before the request
+    /// (but not the other cells of the same request). Anything written through it is committed or rolled back
+    /// along with the cell, without any of the checks cells go through.
+    pub conn: &'a mut sqlx::SqliteConnection,
+}
+
+/// Something that inspects cells before they are written, and may change or reject them.
+///
+/// Interceptors run in the order they were registered, each seeing the cell as the previous one left it, and
+/// only after that is the cell checked against the schema and written. Every cell of a request is intercepted
+/// before any of them is written. Cells set to null aren't intercepted, and neither validators nor
+/// notification rules see the changes made by interceptors.
+pub trait WriteInterceptor: Send + Sync {
+    /// Returning an error rejects the whole request, and nothing it would have written is. Clients are told
+    /// about a [`WriteRejected`] with its own error code, and about other errors just like about invalid cells.
+    fn intercept<'a>(
+        &'a self,
+        write: CellWrite<'a>,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+}
+
+/// An error signifying that an interceptor refused to let a cell be written, for the given reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteRejected(pub String);
+
+impl std::fmt::Display for WriteRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the write was rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for WriteRejected {}
@@ -0,0 +1,233 @@
+//! Authentication of sheet requests with JWTs issued elsewhere (e.g. by an SSO provider). Tokens are checked
+//! against a shared secret or the keys published by the issuer (a JWKS), and their `sheets` claim lists the
+//! sheets they may read and write:
+//!
+//! ```json
+//! {"iss": "https://sso.example.com", "exp": 1700000000, "sheets": {"read": ["budget"], "write": ["*"]}}
+//! ```
+//!
+//! `*` stands for every sheet, and is needed for the endpoints which aren't about a single sheet, such as
+//! creating one. Writing implies reading.
+//!
+//! Tokens can also be granted roles on single sheets (see [`Role`]), which are kept in the database by the
+//! token's subject (`sub`), so that access can be managed by the sheet's owners rather than by the issuer.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use anyhow::{Context, Result};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppConfig;
+
+pub use crate::sheet::Role;
+
+/// The sheet endpoints which only owners may use, whatever the method, as the path after the sheet id.
+const OWNER_ENDPOINTS: &[&str] = &[
+    "/rotate-id",
+    "/aliases",
+    "/permissions",
+    "/owner",
+    "/notifications",
+    "/validator",
+    "/settings",
+    "/access-log",
+    "/archive",
+    "/export-link",
+];
+
+/// The sheet endpoints which are used with a `POST` but only read, since their requests don't fit into a query
+/// string, as the path after the sheet id.
+pub const POST_READ_ENDPOINTS: &[&str] = &["/cells:get", "/schema/check"];
+
+/// The role a request needs, given the path after the sheet id. Reading needs a viewer, writing cells needs an
+/// editor, and managing the sheet itself needs its owner.
+pub fn required_role(method: &actix_web::http::Method, path: &str) -> Role {
+    use actix_web::http::Method;
+
+    let is_owner_endpoint = OWNER_ENDPOINTS.iter().any(|endpoint| {
+        path.strip_prefix(endpoint)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    if is_owner_endpoint {
+        Role::Owner
+    } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+        || (*method == Method::POST && POST_READ_ENDPOINTS.contains(&path))
+    {
+        Role::Viewer
+    } else {
+        Role::Editor
+    }
+}
+
+/// The sheets a token grants access to.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SheetGrants {
+    pub read: Vec<String>,
+    pub write: Vec<String>,
+}
+
+/// The claims of a token which are used here. Expiry and issuer are checked while decoding.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Claims {
+    /// The principal the token was issued to, which roles are granted to.
+    #[serde(default)]
+    pub sub: Option<String>,
+    #[serde(default)]
+    pub sheets: SheetGrants,
+}
+
+impl Claims {
+    /// The role the `sheets` claim grants on the sheet, or on every sheet for `None`. Reading makes for a viewer
+    /// and writing for an editor, while writing every sheet makes for an owner of all of them.
+    pub fn role(&self, sheet_id: Option<&str>) -> Option<Role> {
+        let matches = |ids: &[String]| ids.iter().any(|id| Some(id.as_str()) == sheet_id);
+        let all = |ids: &[String]| ids.iter().any(|id| id == "*");
+
+        if all(&self.sheets.write) {
+            Some(Role::Owner)
+        } else if matches(&self.sheets.write) {
+            Some(Role::Editor)
+        } else if all(&self.sheets.read) || matches(&self.sheets.read) {
+            Some(Role::Viewer)
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks tokens against the configured issuer and keys.
+pub struct Verifier {
+    issuer: Option<String>,
+    audience: Option<String>,
+    secret: Option<DecodingKey>,
+    jwks_url: Option<String>,
+    /// The keys of the JWKS by their id, which are replaced on every refresh.
+    keys: RwLock<HashMap<String, DecodingKey>>,
+}
+
+impl Verifier {
+    /// Creates a verifier if JWT authentication is configured. The keys of a JWKS have to be fetched with
+    /// [`Self::refresh`] before any token can be verified with them.
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        if config.jwt_secret.is_none() && config.jwt_jwks_url.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+            secret: config
+                .jwt_secret
+                .as_ref()
+                .map(|secret| DecodingKey::from_secret(secret.as_bytes())),
+            jwks_url: config.jwt_jwks_url.clone(),
+            keys: RwLock::default(),
+        })
+    }
+
+    /// Fetches the current keys of the JWKS, if there is one. Keys which can't be used (e.g. because of an
+    /// unsupported algorithm) are skipped.
+    pub async fn refresh(&self, http: &reqwest::Client) -> Result<()> {
+        let Some(url) = &self.jwks_url else {
+            return Ok(());
+        };
+
+        let jwks: JwkSet = http
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("failed to fetch the JWKS from {url}"))?
+            .json()
+            .await
+            .with_context(|| format!("{url} isn't a valid JWKS"))?;
+        let keys = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let id = jwk.common.key_id.clone()?;
+                DecodingKey::from_jwk(jwk).ok().map(|key| (id, key))
+            })
+            .collect();
+
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Decodes a token, checking its signature, expiry, and (if configured) issuer and audience.
+    pub fn verify(&self, token: &str) -> Result<Claims> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let is_hmac = matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512);
+        let claims = if is_hmac {
+            let secret = self
+                .secret
+                .as_ref()
+                .context("tokens can't be signed with a secret")?;
+            jsonwebtoken::decode(token, secret, &validation)?
+        } else {
+            let id = header.kid.context("the token has no key id")?;
+            let keys = self.keys.read().unwrap();
+            let key = keys.get(&id).context("the token's key is unknown")?;
+            jsonwebtoken::decode(token, key, &validation)?
+        };
+        Ok(claims.claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::Method;
+
+    use super::{required_role, Claims, Role, SheetGrants};
+
+    #[test]
+    fn grants_make_for_roles() {
+        let claims = Claims {
+            sub: None,
+            sheets: SheetGrants {
+                read: vec!["budget".into()],
+                write: vec!["roadmap".into()],
+            },
+        };
+        assert_eq!(claims.role(Some("budget")), Some(Role::Viewer));
+        assert_eq!(claims.role(Some("roadmap")), Some(Role::Editor));
+        assert_eq!(claims.role(Some("other")), None);
+        assert_eq!(claims.role(None), None);
+
+        let everything = Claims {
+            sub: None,
+            sheets: SheetGrants {
+                read: vec![],
+                write: vec!["*".into()],
+            },
+        };
+        assert_eq!(everything.role(None), Some(Role::Owner));
+        assert_eq!(everything.role(Some("budget")), Some(Role::Owner));
+    }
+
+    #[test]
+    fn endpoints_need_roles() {
+        assert_eq!(required_role(&Method::GET, "/rows/1"), Role::Viewer);
+        assert_eq!(required_role(&Method::POST, ""), Role::Editor);
+        assert_eq!(required_role(&Method::POST, "/import"), Role::Editor);
+        assert_eq!(required_role(&Method::GET, "/permissions"), Role::Owner);
+        assert_eq!(required_role(&Method::DELETE, "/aliases/old"), Role::Owner);
+        assert_eq!(required_role(&Method::POST, "/owner"), Role::Owner);
+        assert_eq!(required_role(&Method::POST, "/export-link"), Role::Owner);
+        assert_eq!(required_role(&Method::GET, "/settingsx"), Role::Viewer);
+        assert_eq!(required_role(&Method::POST, "/cells:get"), Role::Viewer);
+        assert_eq!(required_role(&Method::POST, "/schema/check"), Role::Viewer);
+    }
+}
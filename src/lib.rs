@@ -0,0 +1,124 @@
+//! The sheet server itself. The binary in `main.rs` only deals with configuring and running it.
+
+use actix_web::web;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use config::AppConfig;
+use db::Db;
+
+#[cfg(test)]
+#[macro_use]
+mod testing;
+
+mod admin;
+pub mod archive;
+pub mod budget;
+pub mod config;
+pub mod db;
+mod fixtures;
+pub mod intercept;
+pub mod jwt;
+pub mod middleware;
+pub mod notify;
+pub mod sheet;
+pub mod signing;
+pub mod traffic;
+pub mod transform;
+pub mod validate;
+
+pub struct AppData {
+    pub db: Db,
+    pub config: AppConfig,
+    /// Whether mutating requests are currently rejected. This starts out as configured, but can be
+    /// toggled at runtime through the admin endpoints.
+    read_only: AtomicBool,
+    pub notifications: notify::Dispatcher,
+    /// Used for calling validators.
+    pub http: reqwest::Client,
+    /// The traffic of each sheet which hasn't been flushed to the database yet.
+    pub traffic: traffic::Traffic,
+    /// The signatures of recently signed requests, which mustn't be accepted again.
+    pub signatures: signing::SeenSignatures,
+    /// Checks the JWTs of sheet requests, if JWT authentication is configured.
+    pub jwt: Option<jwt::Verifier>,
+    /// The key export links are signed with.
+    pub export_link_key: Vec<u8>,
+    /// Limits the memory taken by reading whole sheets.
+    pub read_budget: budget::ReadBudget,
+    /// Change the content of sheets before it's sent.
+    pub read_transformers: transform::ReadTransformers,
+}
+
+impl AppData {
+    /// Creates the application state. This has to be called from within a tokio runtime, since it
+    /// starts the notification dispatcher.
+    pub fn new(db: Db, config: AppConfig) -> Self {
+        let notifier = Arc::new(notify::DefaultNotifier::from_config(&config));
+        Self {
+            db,
+            read_only: AtomicBool::new(config.read_only),
+            notifications: notify::Dispatcher::start(notifier),
+            http: reqwest::Client::new(),
+            traffic: traffic::Traffic::default(),
+            signatures: signing::SeenSignatures::default(),
+            jwt: jwt::Verifier::from_config(&config),
+            export_link_key: match &config.export_link_secret {
+                Some(secret) => secret.clone().into_bytes(),
+                None => rand::random::<[u8; 32]>().to_vec(),
+            },
+            read_budget: budget::ReadBudget::new(config.read_memory_budget),
+            read_transformers: transform::ReadTransformers::default(),
+            config,
+        }
+    }
+
+    /// Replaces the way notifications are delivered.
+    pub fn with_notifier(self, notifier: Arc<dyn notify::Notifier>) -> Self {
+        Self {
+            notifications: notify::Dispatcher::start(notifier),
+            ..self
+        }
+    }
+
+    /// Adds a transformer which changes the content of every sheet that's read, before it's sent.
+    pub fn with_read_transformer(
+        mut self,
+        transformer: Arc<dyn transform::ReadTransformer>,
+    ) -> Self {
+        self.read_transformers.add(None, transformer);
+        self
+    }
+
+    /// Adds a transformer which only runs on reads naming it in their [`transform::TRANSFORM_HEADER`].
+    pub fn with_named_read_transformer(
+        mut self,
+        name: impl Into<String>,
+        transformer: Arc<dyn transform::ReadTransformer>,
+    ) -> Self {
+        self.read_transformers.add(Some(name.into()), transformer);
+        self
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+}
+
+/// Registers all of the server's endpoints which are enabled by the configuration.
+pub fn routes(cfg: &mut web::ServiceConfig, config: &AppConfig) {
+    cfg.service(web::scope("/sheet").configure(sheet::web::config))
+        .service(web::scope("/published").configure(sheet::web::published_config))
+        .service(web::scope("/exports").configure(sheet::web::exports_config))
+        .service(web::scope("/admin").configure(admin::config));
+
+    if config.test_endpoints {
+        cfg.service(web::scope("/test").configure(fixtures::config));
+    }
+}
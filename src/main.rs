@@ -1,18 +1,14 @@
-use std::env;
+use actix_web::{
+    middleware::{Logger, NormalizePath},
+    web, App, HttpServer,
+};
+use anchor_test::{config::AppConfig, db::Db, middleware, routes, AppData};
+use anyhow::{Context, Result};
 
-use actix_web::{middleware, web, App, HttpServer};
-use anyhow::Result;
-use db::Db;
-
-mod db;
-mod sheet;
-
-struct AppData {
-    db: Db,
-    no_lookup_nulls: bool,
-}
-
-const DB_FILE: &str = "data.sqlite";
+/// How long it takes at most for a sheet created on the primary to become visible on a replica.
+const REPLICA_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// How often the keys of the JWKS are fetched again, which is how long it takes for rotated keys to be picked up.
+const JWKS_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
 
 #[actix_web::main]
 pub async fn main() -> Result<()> {
@@ -20,31 +16,289 @@ pub async fn main() -> Result<()> {
     // RUST_LOG environment variable.
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
 
+    let config = AppConfig::from_env()?.with_args(std::env::args().skip(1))?;
+    if config.test_endpoints {
+        log::warn!("test endpoints are enabled, this must never be used in production");
+    }
+    if config.bind_address.is_none() && config.unix_socket.is_none() {
+        anyhow::bail!("nothing to listen on: set BIND_ADDRESS and/or UNIX_SOCKET");
+    }
+
     // this is here for integration testing since we don't want to create files
-    let db = if env::var("MEMORY_DB").is_ok() {
+    let db = if config.replica {
+        if config.memory_db {
+            anyhow::bail!(
+                "replicas need a database file, so REPLICA and MEMORY_DB can't be used together"
+            );
+        }
+        let path = config.db_path();
+        log::info!("serving {} as a read-only replica", path.display());
+        if !path.is_file() {
+            anyhow::bail!(
+                "{} doesn't exist: replicas serve a copy of the primary's database, so it has to be there first",
+                path.display()
+            );
+        }
+        Db::new_read_only(&path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?
+    } else if config.memory_db {
         Db::new_memory().await?
     } else {
-        Db::new(DB_FILE).await?
+        let path = config.db_path();
+        check_writable(&config.data_dir, &path)?;
+        log::info!("using the database {}", path.display());
+        Db::new(&path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?
     };
-    let data = web::Data::new(AppData {
-        db,
-        no_lookup_nulls: env::var("NO_LOOKUP_NULLS").is_ok(),
-    });
+    db.warm_up(&config.warmup_sheets).await;
+    let data = web::Data::new(AppData::new(db, config));
+    if let Some(jwt) = &data.jwt {
+        // without the keys no token could be verified, so it's better not to start at all
+        jwt.refresh(&data.http).await?;
+    }
+
+    let config = data.config.clone();
+    let shutdown_data = data.clone();
 
-    let server = HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
+            // tokens are checked against the ids of the sheets themselves, after aliases are resolved
+            .wrap(middleware::RequireJwt)
+            // aliases are resolved right before routing, so that everything else sees the path as it was sent
+            .wrap(middleware::ResolveAliases)
+            // signatures cover the path as it was sent (but normalized), before aliases are resolved
+            .wrap(middleware::VerifySignatures)
+            // this has to be inside of the panic handler, since it relies on the app data
+            .wrap(middleware::ReadOnly)
+            // a panicking handler should still produce a proper error response
+            .wrap(middleware::CatchPanic)
+            // abort requests that take too long, so that a single pathological sheet can't hog a worker
+            .wrap(middleware::Timeout(data.config.request_timeout))
+            // this has to be inside of the path normalization, since it looks at the sheet id in the path
+            .wrap(middleware::TrackTraffic)
             // the logger middleware allows actix_web to tap into our logging library very effortlessly.
-            .wrap(middleware::Logger::default())
+            .wrap(Logger::default())
             // this will ensure that URIs always trim the trailing slash at the end, for consistency purposes
-            .wrap(middleware::NormalizePath::trim())
-            .service(web::scope("/sheet").configure(sheet::web::config))
+            .wrap(NormalizePath::trim())
+            .configure(|cfg| routes(cfg, &data.config))
     })
     // set a shutdown timeout, so that any remaining workers have some leeway
     .shutdown_timeout(10)
-    // since this is a test application after all, we use localhost:8080 for now
-    .bind("localhost:8080")?;
+    // we handle signals ourselves, see `shutdown_on_signal`
+    .disable_signals();
+
+    // these are all tunable for smaller machines - anything that isn't set keeps actix_web's default
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    if let Some(keep_alive) = config.keep_alive {
+        server = server.keep_alive(keep_alive);
+    }
+    if let Some(client_timeout) = config.client_timeout {
+        server = server.client_request_timeout(client_timeout);
+    }
+
+    if let Some(address) = config.bind_address {
+        server = server.bind(address)?;
+    }
+
+    if let Some(path) = config.unix_socket {
+        #[cfg(unix)]
+        {
+            remove_stale_socket(&path)?;
+            server = server.bind_uds(&path)?;
+        }
+
+        #[cfg(not(unix))]
+        anyhow::bail!("unix sockets are not supported on this platform ({})", path.display());
+    }
+
+    // replicas can't write, and their database is maintained by the primary
+    if config.replica {
+        actix_web::rt::spawn(refresh_replica(shutdown_data.clone()));
+    } else if let Some(interval) = config.maintenance_interval {
+        actix_web::rt::spawn(scheduled_maintenance(shutdown_data.clone(), interval));
+    }
+
+    if let Some(path) = config.snapshot_path.clone() {
+        actix_web::rt::spawn(scheduled_snapshots(
+            shutdown_data.clone(),
+            path,
+            config.snapshot_interval,
+        ));
+    }
+    if config.jwt_jwks_url.is_some() {
+        actix_web::rt::spawn(refresh_jwks(shutdown_data.clone()));
+    }
+    actix_web::rt::spawn(scheduled_traffic_flush(
+        shutdown_data.clone(),
+        config.stats_flush_interval,
+    ));
+
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        if let Err(why) = shutdown_on_signal(handle).await {
+            log::error!("failed to listen for signals: {why}");
+        }
+    });
+
+    server.await?;
+    log::info!("server stopped");
+
+    log::info!("delivering pending notifications");
+    shutdown_data.notifications.shutdown().await;
+
+    if let Err(why) = shutdown_data.traffic.flush(&shutdown_data.db).await {
+        log::error!("failed to flush traffic statistics: {why}");
+    }
+
+    log::info!("closing the database");
+    shutdown_data.db.close().await;
+
+    log::info!("shutdown complete");
+    Ok(())
+}
+
+/// Waits for SIGINT or SIGTERM, then stops the server gracefully: no new connections are accepted,
+/// and in-flight requests get to finish (up to the shutdown timeout).
+async fn shutdown_on_signal(handle: actix_web::dev::ServerHandle) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res?,
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
 
-    server.run().await?;
+    log::info!("received shutdown signal, waiting for in-flight requests");
+    handle.stop(true).await;
     Ok(())
 }
+
+/// Runs incremental maintenance every `interval`, forever.
+async fn scheduled_maintenance(data: web::Data<AppData>, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick completes immediately, and there's no point in doing maintenance right at startup
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match data.db.maintenance(true).await {
+            Ok(report) => log::info!("scheduled maintenance reclaimed {} bytes", report.reclaimed),
+            Err(why) => log::error!("scheduled maintenance failed: {why}"),
+        }
+    }
+}
+
+/// Picks up the sheets created on the primary every [`REPLICA_REFRESH_INTERVAL`], forever.
+async fn refresh_replica(data: web::Data<AppData>) {
+    let mut interval = tokio::time::interval(REPLICA_REFRESH_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Err(why) = data.db.refresh_known_sheets().await {
+            log::error!("refreshing the replica's sheets failed: {why}");
+        }
+    }
+}
+
+/// Writes a snapshot of the database to `path` every `interval`, forever. The first one is written right away,
+/// so that a standby can be set up without waiting for the interval.
+async fn scheduled_snapshots(
+    data: web::Data<AppData>,
+    path: std::path::PathBuf,
+    interval: std::time::Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        match data.db.snapshot(&path).await {
+            Ok(size) => log::info!("wrote a snapshot of {size} bytes to {}", path.display()),
+            Err(why) => log::error!("writing a snapshot to {} failed: {why}", path.display()),
+        }
+    }
+}
+
+/// Fetches the keys of the JWKS every [`JWKS_REFRESH_INTERVAL`], forever. If that fails, the old keys are kept.
+async fn refresh_jwks(data: web::Data<AppData>) {
+    let mut interval = tokio::time::interval(JWKS_REFRESH_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Some(jwt) = &data.jwt {
+            if let Err(why) = jwt.refresh(&data.http).await {
+                log::error!("refreshing the JWKS failed: {why:#}");
+            }
+        }
+    }
+}
+
+/// Adds the counted traffic to the database every `interval`, forever.
+async fn scheduled_traffic_flush(data: web::Data<AppData>, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Err(why) = data.traffic.flush(&data.db).await {
+            log::error!("failed to flush traffic statistics: {why}");
+        }
+    }
+}
+
+/// Makes sure the database can be written before opening it, since sqlite only notices on the first write, long
+/// after startup. The data directory is checked by creating a file in it, because sqlite needs to create its
+/// journal next to the database.
+fn check_writable(data_dir: &std::path::Path, path: &std::path::Path) -> Result<()> {
+    let hint = "set DATA_DIR to a writable directory, or choose another database with --db";
+    if !data_dir.is_dir() {
+        anyhow::bail!(
+            "the data directory {} doesn't exist or isn't a directory ({hint})",
+            data_dir.display()
+        );
+    }
+
+    let probe = path.with_extension(format!("write-test-{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .and_then(|_| std::fs::remove_file(&probe))
+        .with_context(|| {
+            format!("can't write to {} ({hint})", probe.parent().unwrap_or(data_dir).display())
+        })?;
+
+    if path.exists() {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .with_context(|| format!("can't write to the database {} ({hint})", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// A socket left over from a previous run would make binding fail, so we remove it first.
+/// Anything that isn't a socket is left alone, since it's most likely a configuration mistake.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
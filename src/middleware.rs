@@ -0,0 +1,801 @@
+use std::{
+    future::{ready, Future, Ready},
+    net::IpAddr,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    rc::Rc,
+    time::Duration,
+};
+
+use actix_web::{
+    body::{BodySize, EitherBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{header, Method, StatusCode, Uri},
+    web, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use futures_util::{stream, FutureExt, Stream, StreamExt};
+use serde::Serialize;
+
+use crate::sheet::AliasMode;
+use crate::traffic::AccessEvent;
+use crate::{jwt, signing};
+
+/// Whether a request is a `POST` which only reads a sheet (see [`jwt::POST_READ_ENDPOINTS`]), and so should be
+/// treated like a `GET`.
+fn is_post_read(req: &ServiceRequest) -> bool {
+    *req.method() == Method::POST
+        && req
+            .path()
+            .strip_prefix("/sheet/")
+            .and_then(|path| path.find('/').map(|i| &path[i..]))
+            .is_some_and(|rest| jwt::POST_READ_ENDPOINTS.contains(&rest))
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct TimeoutResponse {
+    error: String,
+    details: TimeoutDetails,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct TimeoutDetails {
+    timeout_ms: u128,
+}
+
+/// The error produced when a request runs out of time. actix_web renders it into a JSON 503 response.
+#[derive(Debug)]
+pub struct TimedOut(Duration);
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request timed out")
+    }
+}
+
+impl ResponseError for TimedOut {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(TimeoutResponse {
+            error: self.to_string(),
+            details: TimeoutDetails {
+                timeout_ms: self.0.as_millis(),
+            },
+        })
+    }
+}
+
+/// Aborts requests which take longer than the given duration, responding with a 503 instead.
+///
+/// Dropping the handler's future also drops any open transaction, so nothing is left half-written.
+pub struct Timeout(pub Option<Duration>);
+
+impl<S, B> Transform<S, ServiceRequest> for Timeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = TimeoutMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TimeoutMiddleware {
+            service,
+            duration: self.0,
+        }))
+    }
+}
+
+pub struct TimeoutMiddleware<S> {
+    service: S,
+    duration: Option<Duration>,
+}
+
+impl<S, B> Service<ServiceRequest> for TimeoutMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let duration = self.duration;
+        let path = req.path().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let Some(duration) = duration else {
+                return fut.await;
+            };
+
+            match tokio::time::timeout(duration, fut).await {
+                Ok(res) => res,
+                Err(_) => {
+                    log::warn!("request to {path} timed out after {duration:?}");
+                    Err(TimedOut(duration).into())
+                }
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct PanicResponse {
+    error: String,
+}
+
+/// The error produced when a handler panics. actix_web renders it into a JSON 500 response.
+#[derive(Debug)]
+pub struct Panicked;
+
+impl std::fmt::Display for Panicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "internal server error")
+    }
+}
+
+impl ResponseError for Panicked {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(PanicResponse {
+            error: self.to_string(),
+        })
+    }
+}
+
+/// Converts panics inside of handlers into regular 500 responses, so that clients always receive the
+/// usual `{"error": ...}` body instead of a dropped connection.
+pub struct CatchPanic;
+
+impl<S, B> Transform<S, ServiceRequest> for CatchPanic
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = CatchPanicMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CatchPanicMiddleware { service }))
+    }
+}
+
+pub struct CatchPanicMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for CatchPanicMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let path = req.path().to_string();
+        // the handler may panic either when creating its future or when polling it, so we catch both.
+        // nothing is shared between requests apart from the database pool, so this is unwind safe.
+        let fut = match std::panic::catch_unwind(AssertUnwindSafe(|| self.service.call(req))) {
+            Ok(fut) => fut,
+            Err(_) => {
+                log::error!("handler for {path} panicked");
+                return Box::pin(ready(Err(Panicked.into())));
+            }
+        };
+
+        Box::pin(async move {
+            match AssertUnwindSafe(fut).catch_unwind().await {
+                Ok(res) => res,
+                Err(_) => {
+                    log::error!("handler for {path} panicked");
+                    Err(Panicked.into())
+                }
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct ReadOnlyResponse {
+    error: &'static str,
+}
+
+/// Rejects all mutating requests with a 503 while the server is in read-only mode (see
+/// [`crate::AppData::read_only`]). Reads and the admin endpoints (which are needed to leave read-only
+/// mode) are always let through.
+///
+/// Replicas (see [`crate::config::AppConfig::replica`]) reject mutating requests with a 405 instead, or redirect
+/// them to the primary if it's configured.
+pub struct ReadOnly;
+
+impl<S, B> Transform<S, ServiceRequest> for ReadOnly
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = ReadOnlyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ReadOnlyMiddleware { service }))
+    }
+}
+
+pub struct ReadOnlyMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for ReadOnlyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let data = req.app_data::<web::Data<crate::AppData>>().cloned();
+        let read_only = data.as_ref().is_some_and(|data| data.is_read_only());
+        let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+            || is_post_read(&req);
+
+        // replicas can't write at all, so unlike read-only mode this includes the admin endpoints
+        if let Some(config) = data
+            .as_ref()
+            .map(|data| &data.config)
+            .filter(|config| config.replica)
+        {
+            if !is_read {
+                let resp = match &config.primary_url {
+                    // a 307 makes clients repeat the request with the same method and body
+                    Some(primary) => HttpResponse::TemporaryRedirect()
+                        .insert_header((
+                            header::LOCATION,
+                            format!("{}{}", primary.trim_end_matches('/'), req.uri()),
+                        ))
+                        .finish(),
+                    None => HttpResponse::MethodNotAllowed()
+                        .insert_header((header::ALLOW, "GET, HEAD, OPTIONS"))
+                        .json(ReadOnlyResponse {
+                            error: "this server is a read-only replica",
+                        }),
+                };
+                return Box::pin(ready(Ok(req.into_response(resp).map_into_right_body())));
+            }
+        }
+
+        if read_only && !is_read && !req.path().starts_with("/admin") {
+            let resp = HttpResponse::ServiceUnavailable().json(ReadOnlyResponse {
+                error: "the server is in read-only mode",
+            });
+            return Box::pin(ready(Ok(req.into_response(resp).map_into_right_body())));
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct JwtResponse {
+    error: String,
+}
+
+/// Rejects requests to sheets whose JWT (see [`crate::jwt`]) doesn't grant the role the endpoint needs, if JWT
+/// authentication is configured. The role comes from the token's claims, or from the roles granted to its subject
+/// on the sheet, whichever is higher. Since this runs after aliases are resolved, tokens only need to grant access
+/// to the sheets' own ids.
+///
+/// The claims of accepted tokens are added to the request's extensions, for handlers which need to know who made
+/// the request.
+pub struct RequireJwt;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireJwt
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequireJwtMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireJwtMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequireJwtMiddleware<S> {
+    // roles are looked up in the database first, so the future needs its own handle to the service
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireJwtMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let data = req
+            .app_data::<web::Data<crate::AppData>>()
+            .filter(|data| data.jwt.is_some())
+            .cloned();
+        // the sheet id, if any, and the rest of the path
+        let sheet_path = match req.path() {
+            "/sheet" => Some((None, "")),
+            path => path.strip_prefix("/sheet/").map(|path| {
+                let (id, rest) = path.split_at(path.find('/').unwrap_or(path.len()));
+                (Some(id.to_string()), rest)
+            }),
+        };
+        let (Some(data), Some((sheet_id, rest))) = (data, sheet_path) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) });
+        };
+        let required = jwt::required_role(req.method(), rest);
+
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let claims = match token.map(|token| data.jwt.as_ref().unwrap().verify(token)) {
+            Some(Ok(claims)) => claims,
+            Some(Err(why)) => {
+                let resp = HttpResponse::Unauthorized()
+                    .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                    .json(JwtResponse {
+                        error: format!("invalid token: {why}"),
+                    });
+                return Box::pin(ready(Ok(req.into_response(resp).map_into_right_body())));
+            }
+            None => {
+                let resp = HttpResponse::Unauthorized()
+                    .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                    .json(JwtResponse {
+                        error: "missing bearer token".into(),
+                    });
+                return Box::pin(ready(Ok(req.into_response(resp).map_into_right_body())));
+            }
+        };
+
+        let service = self.service.clone();
+        Box::pin(async move {
+            let mut role = claims.role(sheet_id.as_deref());
+            if let (Some(sheet_id), Some(sub)) = (&sheet_id, &claims.sub) {
+                if role < Some(required) {
+                    let granted = data
+                        .db
+                        .role(sheet_id, sub)
+                        .await
+                        .map_err(actix_web::error::ErrorInternalServerError)?;
+                    role = role.max(granted);
+                }
+            }
+
+            if role < Some(required) {
+                let resp = HttpResponse::Forbidden().json(JwtResponse {
+                    error: format!(
+                        "this needs the {} role, which the token doesn't have on this sheet",
+                        required.name()
+                    ),
+                });
+                return Ok(req.into_response(resp).map_into_right_body());
+            }
+
+            req.extensions_mut().insert(claims);
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct SignatureResponse {
+    error: String,
+}
+
+/// Rejects mutating requests to sheets which aren't signed with the configured secret (see [`crate::signing`]).
+/// The body has to be read in full to check the signature, so it's handed on to the handlers from memory.
+pub struct VerifySignatures;
+
+impl<S, B> Transform<S, ServiceRequest> for VerifySignatures
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = VerifySignaturesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(VerifySignaturesMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct VerifySignaturesMiddleware<S> {
+    // the service is called after the body has been read, so the future needs its own handle to it
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for VerifySignaturesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let data = req
+            .app_data::<web::Data<crate::AppData>>()
+            .filter(|data| data.config.signing_secret.is_some())
+            .cloned();
+        let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+            || is_post_read(&req);
+        let Some(data) = data.filter(|_| !is_read && req.path().starts_with("/sheet")) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) });
+        };
+
+        let service = self.service.clone();
+        Box::pin(async move {
+            let mut payload = req.take_payload();
+            let mut body = web::BytesMut::new();
+            while let Some(chunk) = payload.next().await {
+                body.extend_from_slice(&chunk?);
+                // nothing bigger than an import is accepted anyway
+                if body.len() > data.config.max_import_size {
+                    let resp = HttpResponse::PayloadTooLarge().json(SignatureResponse {
+                        error: "the request body is too large".into(),
+                    });
+                    return Ok(req.into_response(resp).map_into_right_body());
+                }
+            }
+            let body = body.freeze();
+
+            let headers = req.headers();
+            let timestamp = headers
+                .get(signing::TIMESTAMP_HEADER)
+                .and_then(|value| value.to_str().ok()?.parse().ok());
+            let signature = headers
+                .get(signing::SIGNATURE_HEADER)
+                .and_then(|value| value.to_str().ok());
+            let result = match (timestamp, signature) {
+                (Some(timestamp), Some(signature)) => {
+                    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+                    let request = signing::SignedRequest {
+                        timestamp,
+                        method: req.method().as_str(),
+                        path,
+                        body: &body,
+                    };
+                    let now = crate::db::now_millis() / 1000;
+                    let secret = data.config.signing_secret.as_deref().unwrap_or_default();
+                    data.signatures.verify(
+                        secret.as_bytes(),
+                        data.config.signature_window,
+                        now,
+                        &request,
+                        signature,
+                    )
+                }
+                _ => Err(signing::SignatureError::Missing),
+            };
+            if let Err(why) = result {
+                let resp = HttpResponse::Unauthorized().json(SignatureResponse {
+                    error: why.to_string(),
+                });
+                return Ok(req.into_response(resp).map_into_right_body());
+            }
+
+            let stream: Pin<Box<dyn Stream<Item = Result<web::Bytes, PayloadError>>>> =
+                Box::pin(stream::once(ready(Ok(body))));
+            req.set_payload(Payload::from(stream));
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+/// Answers requests for aliases of sheets (see [`crate::db::Db::add_alias`]), including the old ids of sheets
+/// which were moved (see [`crate::db::Db::rotate_id`]). Redirecting aliases get a 308 to the same path under the
+/// sheet's id, which makes clients repeat the request with the same method and body, and tells them to use the
+/// new URL from now on. Proxying aliases are rewritten to the sheet's id before routing instead.
+pub struct ResolveAliases;
+
+impl<S, B> Transform<S, ServiceRequest> for ResolveAliases
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = ResolveAliasesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ResolveAliasesMiddleware { service }))
+    }
+}
+
+pub struct ResolveAliasesMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for ResolveAliasesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let resolved = req.path().strip_prefix("/sheet/").and_then(|path| {
+            let (id, rest) = path.split_at(path.find('/').unwrap_or(path.len()));
+            let data = req.app_data::<web::Data<crate::AppData>>()?;
+            let (target, mode) = data.db.resolve_alias(id)?;
+            let location = match req.query_string() {
+                "" => format!("/sheet/{target}{rest}"),
+                query => format!("/sheet/{target}{rest}?{query}"),
+            };
+            Some((location, mode))
+        });
+
+        match resolved {
+            Some((location, AliasMode::Redirect)) => {
+                let resp = HttpResponse::PermanentRedirect()
+                    .insert_header((header::LOCATION, location))
+                    .finish();
+                return Box::pin(ready(Ok(req.into_response(resp).map_into_right_body())));
+            }
+            Some((location, AliasMode::Proxy)) => {
+                // the same as what NormalizePath does to the paths it rewrites
+                let mut parts = req.head().uri.clone().into_parts();
+                parts.path_and_query = location.parse().ok();
+                if let Ok(uri) = Uri::from_parts(parts) {
+                    req.match_info_mut().get_mut().update(&uri);
+                    req.head_mut().uri = uri;
+                }
+            }
+            None => {}
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+/// Who made a request, for the access log: the subject of its token if it had one, otherwise the client's address.
+/// The address is only taken from the forwarding headers of requests which came through the trusted proxy, since
+/// anyone else could set them to whatever they like.
+fn reader(req: &HttpRequest, trusted_proxy: Option<IpAddr>) -> String {
+    let sub = req
+        .extensions()
+        .get::<jwt::Claims>()
+        .and_then(|claims| claims.sub.clone());
+    if let Some(sub) = sub {
+        return sub;
+    }
+
+    match req.peer_addr().map(|addr| addr.ip()) {
+        Some(ip) if Some(ip) == trusted_proxy => req
+            .connection_info()
+            .realip_remote_addr()
+            .unwrap_or("unknown")
+            .into(),
+        Some(ip) => ip.to_string(),
+        None => "unknown".into(),
+    }
+}
+
+/// Counts the requests to every sheet and the size of their responses into [`crate::AppData::traffic`].
+///
+/// Requests which failed because the sheet doesn't exist or its id is malformed aren't counted, so that clients
+/// can't fill the statistics with made up sheets, and neither are redirects from aliases. Requests for proxying
+/// aliases are counted for the sheet they lead to. If the access log is enabled, the counted requests are added
+/// to it as well.
+pub struct TrackTraffic;
+
+impl<S, B> Transform<S, ServiceRequest> for TrackTraffic
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = TrackTrafficMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TrackTrafficMiddleware { service }))
+    }
+}
+
+pub struct TrackTrafficMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TrackTrafficMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let sheet_id = req
+            .path()
+            .strip_prefix("/sheet/")
+            .and_then(|path| path.split('/').next())
+            .filter(|id| crate::db::SheetId::try_from(*id).is_ok())
+            .map(str::to_string);
+        // replicas can't write the statistics, and the primary's are already replicated to them
+        let data = req
+            .app_data::<web::Data<crate::AppData>>()
+            .filter(|data| !data.config.replica)
+            .cloned();
+        let sheet_id =
+            sheet_id.map(|id| match data.as_ref().and_then(|data| data.db.resolve_alias(&id)) {
+                Some((target, AliasMode::Proxy)) => target,
+                _ => id,
+            });
+        let is_read = matches!(*req.method(), Method::GET | Method::HEAD) || is_post_read(&req);
+        let access = data
+            .as_ref()
+            .filter(|data| data.config.access_log)
+            .map(|_| AccessEvent {
+                // both filled in once the response is there, since the token is only checked further in
+                reader: String::new(),
+                status: 0,
+                at: crate::db::now_millis(),
+                method: req.method().to_string(),
+                path: req.path().into(),
+            });
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let status = res.status();
+            if let (Some(sheet_id), Some(data)) = (sheet_id, data) {
+                if status != StatusCode::NOT_FOUND
+                    && status != StatusCode::BAD_REQUEST
+                    && status != StatusCode::PERMANENT_REDIRECT
+                {
+                    let bytes = match res.response().body().size() {
+                        BodySize::Sized(bytes) => bytes,
+                        BodySize::None | BodySize::Stream => 0,
+                    };
+                    data.traffic.record(&sheet_id, is_read, bytes);
+                    if let Some(access) = access {
+                        let event = AccessEvent {
+                            reader: reader(res.request(), data.config.trusted_proxy),
+                            status: status.as_u16(),
+                            ..access
+                        };
+                        data.traffic.record_access(&sheet_id, event);
+                    }
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    use super::{CatchPanic, Timeout};
+
+    #[actix_web::test]
+    async fn slow_requests_time_out() {
+        let app = test::init_service(
+            App::new()
+                .wrap(Timeout(Some(Duration::from_millis(10))))
+                .route(
+                    "/slow",
+                    web::get().to(|| async {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        HttpResponse::Ok().finish()
+                    }),
+                )
+                .route("/fast", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/fast").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::get().uri("/slow").to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+        let resp = err.error_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"], "request timed out");
+        assert_eq!(json["details"]["timeout_ms"], 10);
+    }
+
+    #[actix_web::test]
+    async fn panics_become_errors() {
+        async fn panicking() -> HttpResponse {
+            panic!("oh no")
+        }
+
+        let app = test::init_service(
+            App::new()
+                .wrap(CatchPanic)
+                .route("/panic", web::get().to(panicking)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/panic").to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+        let resp = err.error_response();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"], "internal server error");
+    }
+}
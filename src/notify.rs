@@ -0,0 +1,320 @@
+//! Notification rules, which are evaluated whenever a cell is written and send a message somewhere when
+//! they match, e.g. "when any cell in column C < 0, post to this Slack webhook".
+//!
+//! Messages are delivered by a [`Notifier`] on a single background task, so that slow targets never hold
+//! up writes. Delivery is best-effort: failures are logged and not retried.
+
+use std::{cmp::Ordering, future::Future, pin::Pin, sync::Arc, sync::Mutex, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::config::AppConfig;
+use crate::sheet::{CellValue, SchemaColumnKind};
+
+/// How long a single delivery may take before it's given up on.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl CompareOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+        }
+    }
+}
+
+/// A condition on a single cell's value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub op: CompareOp,
+    pub value: CellValue,
+}
+
+impl Condition {
+    /// Checks whether the condition holds for `value`. Values of different kinds never match, except for
+    /// ints and doubles, which are compared numerically.
+    pub fn matches(&self, value: &CellValue) -> bool {
+        let ordering = match (value, &self.value) {
+            (CellValue::Int(a), CellValue::Int(b)) => a.partial_cmp(b),
+            (CellValue::Int(a), CellValue::Double(b)) => (*a as f64).partial_cmp(b),
+            (CellValue::Double(a), CellValue::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (CellValue::Double(a), CellValue::Double(b)) => a.partial_cmp(b),
+            (CellValue::Boolean(a), CellValue::Boolean(b)) => a.partial_cmp(b),
+            (CellValue::String(a), CellValue::String(b)) => a.partial_cmp(b),
+            _ => None,
+        };
+
+        let Some(ordering) = ordering else {
+            return false;
+        };
+
+        match self.op {
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+        }
+    }
+
+    /// Checks whether the condition can ever match values of a column of the given kind.
+    pub fn applies_to(&self, kind: SchemaColumnKind) -> bool {
+        let numeric = |kind| matches!(kind, SchemaColumnKind::Int | SchemaColumnKind::Double);
+        let own_kind = SchemaColumnKind::from(&self.value);
+        own_kind == kind.storage() || (numeric(own_kind) && numeric(kind))
+    }
+}
+
+/// Where a notification is delivered.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Target {
+    Slack { webhook_url: String },
+    Email { to: String },
+}
+
+/// A notification rule of a single sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub column: String,
+    pub condition: Condition,
+    pub target: Target,
+}
+
+/// A message which is ready to be delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub target: Target,
+    pub message: String,
+}
+
+impl Notification {
+    pub fn new(sheet_id: &str, row: i64, value: &CellValue, rule: &Rule) -> Self {
+        let show = |value: &CellValue| match value {
+            CellValue::Boolean(x) => x.to_string(),
+            CellValue::Int(x) => x.to_string(),
+            CellValue::Double(x) => x.to_string(),
+            CellValue::String(x) => format!("{x:?}"),
+        };
+
+        Self {
+            target: rule.target.clone(),
+            message: format!(
+                "sheet {sheet_id}: {}{row} was set to {}, which matches {} {} {}",
+                rule.column,
+                show(value),
+                rule.column,
+                rule.condition.op.symbol(),
+                show(&rule.condition.value),
+            ),
+        }
+    }
+}
+
+/// Something that can deliver notifications. This is what allows swapping out the actual delivery
+/// mechanism, e.g. in tests.
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+}
+
+/// Delivers notifications to Slack webhooks over HTTP, and to email addresses through an SMTP relay.
+pub struct DefaultNotifier {
+    http: reqwest::Client,
+    smtp: Option<SmtpConfig>,
+}
+
+#[derive(Clone, Debug)]
+struct SmtpConfig {
+    server: String,
+    from: String,
+}
+
+impl DefaultNotifier {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            smtp: config.smtp_server.clone().map(|server| SmtpConfig {
+                server,
+                from: config.smtp_from.clone(),
+            }),
+        }
+    }
+
+    async fn post_to_slack(&self, webhook_url: &str, message: &str) -> Result<()> {
+        self.http
+            .post(webhook_url)
+            .json(&serde_json::json!({ "text": message }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// A minimal SMTP client, which is enough for talking to a local relay. It supports neither TLS
+    /// nor authentication.
+    async fn send_email(&self, to: &str, message: &str) -> Result<()> {
+        let smtp = self
+            .smtp
+            .as_ref()
+            .context("SMTP_SERVER is not configured")?;
+        if to.contains(['\r', '\n']) {
+            anyhow::bail!("invalid email address");
+        }
+
+        let stream = TcpStream::connect(&smtp.server).await?;
+        let mut stream = BufReader::new(stream);
+
+        async fn expect(stream: &mut BufReader<TcpStream>, code: &str) -> Result<()> {
+            // replies may span multiple lines, all but the last of which have a dash after the code
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await?;
+                if !line.starts_with(code) {
+                    anyhow::bail!("unexpected SMTP reply: {}", line.trim_end());
+                }
+                if line.as_bytes().get(3) != Some(&b'-') {
+                    return Ok(());
+                }
+            }
+        }
+
+        async fn command(stream: &mut BufReader<TcpStream>, line: &str, code: &str) -> Result<()> {
+            stream.get_mut().write_all(line.as_bytes()).await?;
+            stream.get_mut().write_all(b"\r\n").await?;
+            expect(stream, code).await
+        }
+
+        expect(&mut stream, "220").await?;
+        command(&mut stream, "HELO localhost", "250").await?;
+        command(&mut stream, &format!("MAIL FROM:<{}>", smtp.from), "250").await?;
+        command(&mut stream, &format!("RCPT TO:<{to}>"), "250").await?;
+        command(&mut stream, "DATA", "354").await?;
+
+        // lines starting with a dot have to be escaped, so that they don't end the message early
+        let body = message.replace("\n.", "\n..");
+        let data = format!(
+            "From: <{}>\r\nTo: <{to}>\r\nSubject: sheet notification\r\n\r\n{body}\r\n.",
+            smtp.from
+        );
+        command(&mut stream, &data, "250").await?;
+        command(&mut stream, "QUIT", "221").await
+    }
+}
+
+impl Notifier for DefaultNotifier {
+    fn notify<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            match &notification.target {
+                Target::Slack { webhook_url } => {
+                    self.post_to_slack(webhook_url, &notification.message).await
+                }
+                Target::Email { to } => {
+                    tokio::time::timeout(
+                        DELIVERY_TIMEOUT,
+                        self.send_email(to, &notification.message),
+                    )
+                    .await?
+                }
+            }
+        })
+    }
+}
+
+/// Hands notifications over to a background task, which delivers them one by one.
+pub struct Dispatcher {
+    sender: Mutex<Option<mpsc::UnboundedSender<Notification>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Dispatcher {
+    /// Starts the background task. This has to be called from within a tokio runtime.
+    pub fn start(notifier: Arc<dyn Notifier>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Notification>();
+        let worker = tokio::spawn(async move {
+            while let Some(notification) = receiver.recv().await {
+                if let Err(why) = notifier.notify(&notification).await {
+                    log::warn!("failed to deliver notification: {why:#}");
+                }
+            }
+        });
+
+        Self {
+            sender: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(worker)),
+        }
+    }
+
+    /// Queues a notification for delivery. Notifications sent after [`Self::shutdown`] are dropped.
+    pub fn send(&self, notification: Notification) {
+        if let Some(sender) = &*self.sender.lock().unwrap() {
+            let _ = sender.send(notification);
+        }
+    }
+
+    /// Stops accepting notifications, and waits for the queued ones to be delivered.
+    pub async fn shutdown(&self) {
+        self.sender.lock().unwrap().take();
+        let worker = self.worker.lock().unwrap().take();
+        if let Some(worker) = worker {
+            let _ = worker.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompareOp, Condition};
+    use crate::sheet::CellValue;
+
+    #[test]
+    fn conditions_match() {
+        let below_zero = Condition {
+            op: CompareOp::Lt,
+            value: CellValue::Int(0),
+        };
+        assert!(below_zero.matches(&CellValue::Int(-1)));
+        assert!(below_zero.matches(&CellValue::Double(-0.5)));
+        assert!(!below_zero.matches(&CellValue::Int(0)));
+        assert!(!below_zero.matches(&CellValue::String("-1".into())));
+
+        let not_done = Condition {
+            op: CompareOp::Ne,
+            value: CellValue::String("done".into()),
+        };
+        assert!(not_done.matches(&CellValue::String("todo".into())));
+        assert!(!not_done.matches(&CellValue::String("done".into())));
+        assert!(!not_done.matches(&CellValue::Double(f64::NAN)));
+    }
+}
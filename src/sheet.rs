@@ -1,11 +1,19 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::OnceLock,
 };
 
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
 
+pub mod a1;
+pub mod body;
+pub mod export;
+pub mod import;
+pub mod lookup_stats;
+pub mod proto;
+pub mod view;
 pub mod web;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -14,18 +22,122 @@ pub struct Schema {
 }
 
 impl Schema {
-    /// Checks if the schema is valid, i.e. all the column names are unique
-    /// and none of them contain double quotes.
-    pub fn is_valid(&self) -> bool {
-        let mut names = HashSet::<&str>::new();
-        for col in &self.columns {
-            if col.name.contains('"') || !names.insert(&col.name) {
-                return false;
+    /// The maximum length of a column name, in characters.
+    pub const MAX_COLUMN_NAME_LENGTH: usize = 128;
+
+    /// Normalizes all of the column names to Unicode NFC, so that visually identical names are
+    /// also stored identically.
+    pub fn normalize(&mut self) {
+        for col in &mut self.columns {
+            col.name = col.name.nfc().collect();
+        }
+    }
+
+    /// Checks if the schema is valid, i.e. all the column names are non-empty, unique, no longer than
+    /// [`Self::MAX_COLUMN_NAME_LENGTH`], and contain no double quotes or control characters, every column
+    /// format suits its column (see [`ColumnFormat::validate`]), and no autoincrement column has a reference.
+    ///
+    /// If `case_insensitive` is set, names that differ only in case are considered duplicates.
+    /// Names are expected to already be normalized (see [`Self::normalize`]).
+    pub fn validate(&self, case_insensitive: bool) -> Result<(), Vec<SchemaError>> {
+        let mut errors = vec![];
+        let mut names = HashSet::<String>::new();
+        for (i, col) in self.columns.iter().enumerate() {
+            if let Some(Err(message)) = col.format.as_ref().map(|format| format.validate(col.kind))
+            {
+                errors.push(SchemaError {
+                    path: format!("columns[{i}].format"),
+                    column: i,
+                    name: col.name.clone(),
+                    message,
+                });
+            }
+
+            if col.references.is_some() && col.kind == SchemaColumnKind::Autoincrement {
+                errors.push(SchemaError {
+                    path: format!("columns[{i}].references"),
+                    column: i,
+                    name: col.name.clone(),
+                    message: "autoincrement columns can't reference another sheet".into(),
+                });
+            }
+
+            let mut error = |message: String| {
+                errors.push(SchemaError {
+                    path: format!("columns[{i}].name"),
+                    column: i,
+                    name: col.name.clone(),
+                    message,
+                })
+            };
+
+            let length = col.name.chars().count();
+            if length == 0 {
+                error("column name must not be empty".into());
+            } else if length > Self::MAX_COLUMN_NAME_LENGTH {
+                error(format!(
+                    "column name is too long ({length} characters, the maximum is {})",
+                    Self::MAX_COLUMN_NAME_LENGTH
+                ));
+            }
+
+            if col.name.contains('"') {
+                error("column name must not contain double quotes".into());
+            }
+
+            if col.name.chars().any(char::is_control) {
+                error("column name must not contain control characters".into());
+            }
+
+            let key = if case_insensitive {
+                col.name.to_lowercase()
+            } else {
+                col.name.clone()
+            };
+            if !names.insert(key) {
+                error("duplicate column name".into());
             }
         }
 
-        true
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
+
+    /// Checks if the schema is valid using the default (case sensitive) policy. See [`Self::validate`].
+    pub fn is_valid(&self) -> bool {
+        self.validate(false).is_ok()
+    }
+}
+
+/// A single problem with a single column of a [`Schema`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SchemaError {
+    pub path: String,
+    pub column: usize,
+    pub name: String,
+    pub message: String,
+}
+
+/// A request body field which couldn't be parsed, e.g. `columns[1].type`. See [`body::FromBody`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FieldError {
+    pub path: String,
+    pub message: String,
+}
+
+/// The body of a sheet creation request: a schema, and optionally a custom id for the new sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NewSheet {
+    #[serde(flatten)]
+    pub schema: Schema,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Overrides the server-wide `NO_LOOKUP_NULLS` for reads of this sheet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_lookup_nulls: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -33,6 +145,88 @@ pub struct SchemaColumn {
     pub name: String,
     #[serde(rename = "type")]
     pub kind: SchemaColumnKind,
+    /// Hidden columns are left out of reads unless they're explicitly asked for.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// How the values of a numeric column are meant to be displayed. Only the display changes, the values are
+    /// stored and returned as they are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ColumnFormat>,
+    /// Whether the column holds personal data, which anonymized exports hash or redact.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pii: bool,
+    /// A column of another sheet which every value of this column has to exist in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub references: Option<ColumnReference>,
+}
+
+/// A reference from a column to a column of another sheet, similarly to a foreign key. Values written to the
+/// referencing column have to be stored in the referenced column already.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ColumnReference {
+    /// The id of the referenced sheet.
+    pub sheet: String,
+    pub column: String,
+    /// What happens to the referencing cells when a value they hold disappears from the referenced column.
+    /// Without it, references are only checked when the referencing cells are written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_delete: Option<OnDelete>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnDelete {
+    /// Values which are still referenced can't be removed from the referenced column.
+    Restrict,
+    /// The cells referencing a removed value are set to null along with it.
+    Cascade,
+}
+
+impl OnDelete {
+    pub fn get_sql_text(&self) -> &'static str {
+        match self {
+            Self::Restrict => "restrict",
+            Self::Cascade => "cascade",
+        }
+    }
+
+    pub fn from_sql_text(text: &str) -> Option<Self> {
+        match text {
+            "restrict" => Some(Self::Restrict),
+            "cascade" => Some(Self::Cascade),
+            _ => None,
+        }
+    }
+}
+
+/// A display kind layered over the values of an `int` or `double` column.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ColumnFormat {
+    /// Fractions of one, so that `0.25` is displayed as 25%.
+    Percent,
+    /// Amounts of money in a currency, given by its ISO 4217 code, e.g. `EUR`.
+    Currency { code: String },
+}
+
+impl ColumnFormat {
+    /// Checks that the format can be used for a column of the given type.
+    pub fn validate(&self, kind: SchemaColumnKind) -> Result<(), String> {
+        if !matches!(kind, SchemaColumnKind::Int | SchemaColumnKind::Double) {
+            return Err("only int and double columns can have a format".into());
+        }
+        match self {
+            Self::Percent => Ok(()),
+            Self::Currency { code }
+                if code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase()) =>
+            {
+                Ok(())
+            }
+            Self::Currency { code } => {
+                Err(format!("currency code must be three uppercase letters, got {code:?}"))
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
@@ -42,15 +236,27 @@ pub enum SchemaColumnKind {
     Int,
     Double,
     String,
+    /// An absolute URL with a host, e.g. `https://example.com/a`, stored as a string.
+    Url,
+    /// An email address, stored as a string.
+    Email,
+    /// An int which is assigned by the server when a row is first written to, and can't be written
+    /// afterwards. The values count up from 1, separately for every column.
+    Autoincrement,
 }
 
 impl SchemaColumnKind {
+    /// The name of the kind in the columns table. The values themselves are stored with the type of
+    /// [`Self::storage`].
     pub fn get_sql_text(&self) -> &'static str {
         match self {
             Self::Boolean => "BOOLEAN",
             Self::Int => "INTEGER",
             Self::Double => "REAL",
             Self::String => "TEXT",
+            Self::Url => "URL",
+            Self::Email => "EMAIL",
+            Self::Autoincrement => "AUTOINCREMENT",
         }
     }
 
@@ -60,20 +266,58 @@ impl SchemaColumnKind {
             "INTEGER" => Some(Self::Int),
             "REAL" => Some(Self::Double),
             "TEXT" => Some(Self::String),
+            "URL" => Some(Self::Url),
+            "EMAIL" => Some(Self::Email),
+            "AUTOINCREMENT" => Some(Self::Autoincrement),
             _ => None,
         }
     }
+
+    /// The kind whose values are stored for this one. URLs and email addresses are strings, which are only
+    /// checked when they're written (see [`Self::check_text`]), and sequence numbers are ints.
+    pub fn storage(self) -> Self {
+        match self {
+            Self::Url | Self::Email => Self::String,
+            Self::Autoincrement => Self::Int,
+            kind => kind,
+        }
+    }
+
+    /// Checks the syntax of a string written to a column of this kind. Any string is fine for the other kinds.
+    pub fn check_text(self, text: &str) -> Result<(), String> {
+        let valid = match self {
+            Self::Url => url::Url::parse(text).is_ok_and(|url| url.has_host()),
+            Self::Email => EMAIL_REGEX
+                .get_or_init(|| Regex::new(r"^[^\s@]+@[^\s@.]+(\.[^\s@.]+)+$").unwrap())
+                .is_match(text),
+            _ => true,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            // the debug names are the same as the serialized ones, apart from the case
+            Err(format!("invalid {}: {text:?}", format!("{self:?}").to_lowercase()))
+        }
+    }
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Cell {
     pub column: String,
     pub row: i64,
     pub value: CellValue,
 }
 
+/// A cell which is explicitly set to null, written as a cell with a `null` value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Tombstone {
+    pub column: String,
+    pub row: i64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-#[serde(untagged)]
+#[serde(untagged, expecting = "expected a boolean, a number or a string")]
 pub enum CellValue {
     Boolean(bool),
     Int(i64),
@@ -87,7 +331,94 @@ pub struct LookupCellValue {
     pub target_row: i64,
 }
 
+/// The reasons for which a lookup cell may be rejected when written.
+///
+/// This is serialized into the `details` object of error responses, so that clients can tell
+/// exactly which part of the lookup was wrong.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum LookupError {
+    UnknownTargetColumn {
+        target_column: String,
+    },
+    IncompatibleKinds {
+        column: String,
+        column_kind: SchemaColumnKind,
+        target_column: String,
+        target_kind: SchemaColumnKind,
+    },
+    SelfReference {
+        column: String,
+        row: i64,
+    },
+    Cycle {
+        column: String,
+        row: i64,
+    },
+    EmptyTarget {
+        target_column: String,
+        target_row: i64,
+    },
+}
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownTargetColumn { .. } => write!(f, "invalid target column name"),
+            Self::IncompatibleKinds { .. } => write!(f, "invalid target column type"),
+            Self::SelfReference { .. } => write!(f, "lookup refers to itself"),
+            Self::Cycle { .. } => write!(f, "detected lookup cycle"),
+            Self::EmptyTarget { .. } => write!(f, "lookup target is empty"),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+/// The reasons for which a write may violate a [`ColumnReference`].
+///
+/// Like [`LookupError`], this is serialized into the `details` object of error responses.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ReferenceError {
+    /// The value written to a referencing column doesn't exist in the referenced column.
+    Dangling {
+        column: String,
+        value: CellValue,
+        target_sheet: String,
+        target_column: String,
+    },
+    /// Referencing columns only hold plain values, since those are what's checked.
+    Lookup { column: String },
+    /// The value removed from a referenced column is still referenced with [`OnDelete::Restrict`].
+    Restricted {
+        column: String,
+        value: CellValue,
+        referencing_sheet: String,
+        referencing_column: String,
+        referencing_row: i64,
+    },
+}
+
+impl std::fmt::Display for ReferenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dangling { .. } => write!(f, "value doesn't exist in the referenced column"),
+            Self::Lookup { .. } => write!(f, "columns with references can't hold lookups"),
+            Self::Restricted { .. } => write!(f, "value is still referenced by another sheet"),
+        }
+    }
+}
+
+impl std::error::Error for ReferenceError {}
+
 static LOOKUP_REGEX: OnceLock<Regex> = OnceLock::new();
+static EMAIL_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// A leading character which marks a string value as a literal, similarly to spreadsheet software.
+/// It is stripped before the value is stored, so `'lookup("B", 4)` is stored as `lookup("B", 4)`, but only
+/// from strings which need it (see [`CellValue::unescaped`]), so `'90s` is stored as it is.
+pub const LITERAL_ESCAPE: char = '\'';
 
 impl CellValue {
     pub fn is_lookup(&self) -> Option<LookupCellValue> {
@@ -95,12 +426,15 @@ impl CellValue {
             return None;
         };
 
+        // escaped strings are always stored literally, even if they look like a lookup
+        if s.starts_with(LITERAL_ESCAPE) {
+            return None;
+        }
+
         let re = LOOKUP_REGEX
             .get_or_init(|| Regex::new(r#"^lookup\(\s*"([^"]+)"\s*,\s*(\d+)\s*\)$"#).unwrap());
 
-        let Some((_, [col_name, row])) = re.captures(s).map(|c| c.extract()) else {
-            return None;
-        };
+        let (_, [col_name, row]) = re.captures(s).map(|c| c.extract())?;
 
         let Ok(row) = row.parse() else {
             return None;
@@ -111,6 +445,56 @@ impl CellValue {
             target_row: row,
         })
     }
+
+    /// Converts the value to one of the given kind, if that's possible without losing information.
+    pub fn convert(&self, kind: SchemaColumnKind) -> Option<Self> {
+        // doubles represent every integer up to this magnitude exactly
+        const MAX_EXACT_INT: i64 = 1 << f64::MANTISSA_DIGITS;
+
+        match (self, kind) {
+            (value, kind) if SchemaColumnKind::from(value) == kind => Some(value.clone()),
+            (Self::Int(x), SchemaColumnKind::Double) => {
+                (x.abs() <= MAX_EXACT_INT).then_some(Self::Double(*x as f64))
+            }
+            (Self::Double(x), SchemaColumnKind::Int) => {
+                let in_range = (i64::MIN as f64..i64::MAX as f64).contains(x);
+                (x.fract() == 0.0 && in_range).then_some(Self::Int(*x as i64))
+            }
+            (Self::String(s), SchemaColumnKind::Boolean) => s.parse().ok().map(Self::Boolean),
+            (Self::String(s), SchemaColumnKind::Int) => s.parse().ok().map(Self::Int),
+            (Self::String(s), SchemaColumnKind::Double) => s
+                .parse::<f64>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .map(Self::Double),
+            (Self::Boolean(x), SchemaColumnKind::String) => Some(Self::String(x.to_string())),
+            (Self::Int(x), SchemaColumnKind::String) => Some(Self::String(x.to_string())),
+            (Self::Double(x), SchemaColumnKind::String) => Some(Self::String(x.to_string())),
+            (Self::String(s), kind @ (SchemaColumnKind::Url | SchemaColumnKind::Email)) => {
+                kind.check_text(s).is_ok().then(|| self.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value as it should be stored, i.e. with the literal escape removed from strings which would
+    /// be read as a lookup (in either notation) without it. Escaped strings which start with the escape again
+    /// lose one as well, so that exports (which escape those too) can be imported again.
+    pub fn unescaped(&self) -> Self {
+        let Self::String(s) = self else {
+            return self.clone();
+        };
+        match s.strip_prefix(LITERAL_ESCAPE) {
+            Some(rest)
+                if rest.starts_with(LITERAL_ESCAPE)
+                    || a1::lookup_address(rest).is_some()
+                    || Self::String(rest.into()).is_lookup().is_some() =>
+            {
+                Self::String(rest.into())
+            }
+            _ => self.clone(),
+        }
+    }
 }
 
 impl From<&CellValue> for SchemaColumnKind {
@@ -127,12 +511,563 @@ impl From<&CellValue> for SchemaColumnKind {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SheetContent {
     pub columns: HashMap<String, Vec<SheetContentColumn>>,
+    /// The metadata of every row that has any, only present when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<BTreeMap<i64, RowMeta>>,
+    /// The key of every row that has one, only present when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row_keys: Option<BTreeMap<i64, String>>,
+    /// The version of the sheet the content is from, only present when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
 }
 
+impl SheetContent {
+    /// Orders the cells of every column by the value their row has in `column`, with rows that have no value
+    /// there coming last. Ties are broken by row number. Returns `false` if there's no such column.
+    pub fn sort_rows_by(&mut self, column: &str, descending: bool) -> bool {
+        let Some(keys) = self.columns.get(column) else {
+            return false;
+        };
+
+        let mut order: Vec<_> = keys
+            .iter()
+            .filter_map(|cell| Some((cell.row, cell.value.as_ref()?)))
+            .collect();
+        order.sort_by(|(a_row, a), (b_row, b)| {
+            // all values of a column have the same type
+            let ordering = match (a, b) {
+                (CellValue::Boolean(a), CellValue::Boolean(b)) => a.cmp(b),
+                (CellValue::Int(a), CellValue::Int(b)) => a.cmp(b),
+                (CellValue::Double(a), CellValue::Double(b)) => a.total_cmp(b),
+                (CellValue::String(a), CellValue::String(b)) => a.cmp(b),
+                _ => std::cmp::Ordering::Equal,
+            };
+            let ordering = if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            ordering.then(a_row.cmp(b_row))
+        });
+
+        let ranks: HashMap<_, _> = order
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (row, _))| (row, rank))
+            .collect();
+        for cells in self.columns.values_mut() {
+            cells.sort_by_key(|cell| {
+                (ranks.get(&cell.row).copied().unwrap_or(usize::MAX), cell.row)
+            });
+        }
+        true
+    }
+}
+
+/// Arbitrary key-value metadata attached to a row, kept apart from the row's cells.
+pub type RowMeta = serde_json::Map<String, serde_json::Value>;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SheetContentColumn {
     pub row: i64,
     pub value: Option<CellValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CellError>,
+}
+
+impl SheetContentColumn {
+    /// A cell which resolves to `value`. Doubles which aren't finite can't be sent as values, so they're
+    /// reported as [`CellError::Num`] instead.
+    pub fn new(row: i64, value: Option<CellValue>) -> Self {
+        match value {
+            Some(CellValue::Double(x)) if !x.is_finite() => Self {
+                row,
+                value: None,
+                error: Some(CellError::Num),
+            },
+            value => Self {
+                row,
+                value,
+                error: None,
+            },
+        }
+    }
+}
+
+/// The content of a single row, for sheets that model records.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RowContent {
+    pub row: i64,
+    /// The cell of every populated column in the row.
+    pub columns: HashMap<String, RowCell>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RowCell {
+    pub value: Option<CellValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CellError>,
+}
+
+/// A single cell read by its address, along with the address.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AddressedCell {
+    pub column: String,
+    pub row: i64,
+    pub value: Option<CellValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CellError>,
+}
+
+impl RowContent {
+    pub fn empty(row: i64) -> Self {
+        Self {
+            row,
+            columns: HashMap::new(),
+        }
+    }
+}
+
+impl SheetContent {
+    /// Regroups the content by row, for the rows which have any cells.
+    pub fn into_rows(self) -> BTreeMap<i64, RowContent> {
+        let mut rows = BTreeMap::new();
+        for (name, cells) in self.columns {
+            for cell in cells {
+                rows.entry(cell.row)
+                    .or_insert_with(|| RowContent::empty(cell.row))
+                    .columns
+                    .insert(
+                        name.clone(),
+                        RowCell {
+                            value: cell.value,
+                            error: cell.error,
+                        },
+                    );
+            }
+        }
+        rows
+    }
+}
+
+/// The cells of a range of rows as a dense, row-major grid, which is what plotting libraries and grid widgets
+/// work with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Grid {
+    /// The names of the columns, in the order of the schema.
+    pub columns: Vec<String>,
+    /// The number of the first row of the grid.
+    pub first_row: i64,
+    /// Every row of the range, with the value of every column in it, or `None` for empty cells.
+    pub rows: Vec<Vec<Option<CellValue>>>,
+}
+
+impl Grid {
+    /// Lays out the content of the rows from `first_row` to `last_row` (inclusive), in the order of `columns`.
+    /// Columns which aren't in the content (such as hidden ones) are left out, and cells with errors are empty.
+    pub fn new(
+        columns: &[SchemaColumn],
+        mut content: SheetContent,
+        first_row: i64,
+        last_row: i64,
+    ) -> Self {
+        let columns: Vec<_> = columns
+            .iter()
+            .filter(|column| content.columns.contains_key(&column.name))
+            .map(|column| column.name.clone())
+            .collect();
+
+        let height = (last_row - first_row + 1).max(0) as usize;
+        let mut rows = vec![vec![None; columns.len()]; height];
+        for (i, name) in columns.iter().enumerate() {
+            for cell in content.columns.remove(name).into_iter().flatten() {
+                let Ok(offset) = usize::try_from(cell.row - first_row) else {
+                    continue;
+                };
+                if let Some(row) = rows.get_mut(offset) {
+                    row[i] = cell.value;
+                }
+            }
+        }
+
+        Self {
+            columns,
+            first_row,
+            rows,
+        }
+    }
+}
+
+/// Which rows of the left sheet a [`Join`] keeps.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinKind {
+    /// Only the rows with a match in the right sheet.
+    #[default]
+    Inner,
+    /// Every populated row, with empty cells for the right sheet if there's no match.
+    Left,
+}
+
+/// The rows of two sheets joined on a column of each, for reports which combine them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Join {
+    /// The names of the columns of both sheets, in the order of their schemas, prefixed with
+    /// [`Self::LEFT_PREFIX`] or [`Self::RIGHT_PREFIX`].
+    pub columns: Vec<String>,
+    pub rows: Vec<JoinedRow>,
+}
+
+impl Join {
+    pub const LEFT_PREFIX: &'static str = "left.";
+    pub const RIGHT_PREFIX: &'static str = "right.";
+}
+
+/// A pair of rows of a [`Join`], with the value of every column of both, or `None` for empty cells.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JoinedRow {
+    pub left_row: i64,
+    /// The row of the right sheet, which is missing for rows of a left join without a match.
+    pub right_row: Option<i64>,
+    pub values: Vec<Option<CellValue>>,
+}
+
+/// Spreadsheet-style error values, which are reported in place of a value for cells that cannot
+/// be resolved (as opposed to cells which are simply empty).
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CellError {
+    /// The cell refers (possibly through a chain of lookups) to a column which doesn't exist.
+    #[serde(rename = "#REF!")]
+    Ref,
+    /// The cell holds (or refers to) a number which can't be represented, i.e. an infinity stored by an older
+    /// version, before non-finite doubles were rejected.
+    #[serde(rename = "#NUM!")]
+    Num,
+}
+
+impl std::fmt::Display for CellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ref => write!(f, "#REF!"),
+            Self::Num => write!(f, "#NUM!"),
+        }
+    }
+}
+
+/// How a single cell resolves, one visited cell at a time. Used to find out why a lookup ends up empty or
+/// broken.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Explanation {
+    pub steps: Vec<ExplainStep>,
+    /// What the cell resolves to, the same as it would be read.
+    pub value: Option<CellValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CellError>,
+}
+
+/// A cell visited while resolving another one, see [`Explanation`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExplainStep {
+    /// `None` if the column doesn't exist, which breaks the lookup that led here.
+    pub column: Option<String>,
+    pub row: i64,
+    #[serde(flatten)]
+    pub outcome: StepOutcome,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StepOutcome {
+    /// The cell is a lookup, so resolving goes on with its target.
+    Lookup {
+        target_column: Option<String>,
+        target_row: i64,
+    },
+    /// Resolving ends with a stored value.
+    Value { value: CellValue },
+    /// Resolving ends with a stored value which can't be sent, such as an infinity, see [`CellError::Num`].
+    Error { error: CellError },
+    /// Resolving ends with a cell which was explicitly set to null.
+    Null,
+    /// Resolving ends with a cell which was never written.
+    Empty,
+    /// Resolving ends with a column which doesn't exist.
+    MissingColumn,
+}
+
+/// Controls what is included when reading a sheet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct ReadOptions {
+    /// Leave out lookup cells which resolve to nothing, instead of returning them as `null`.
+    pub no_lookup_nulls: bool,
+    /// Include hidden columns.
+    pub include_hidden: bool,
+    /// Include the metadata of rows.
+    pub include_meta: bool,
+    /// Include the keys of rows.
+    pub include_row_keys: bool,
+    /// Include the version of the sheet.
+    pub include_version: bool,
+}
+
+/// Per-sheet behavior, which can be changed at any time.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SheetSettings {
+    /// Writing a double into an int column changes the column's type to double, instead of being rejected.
+    pub widen_types: bool,
+    /// Gives every populated row a stable key generated using this scheme, which can be used to address
+    /// it instead of its number.
+    pub row_keys: Option<crate::db::IdScheme>,
+    /// Makes the sheet readable by anyone under `/published`, without any credentials.
+    pub published: bool,
+    /// The rows cells may be written to. Without bounds, any row can be written.
+    pub row_bounds: Option<RowBounds>,
+    /// Keeps the cell at the end of every lookup chain next to each lookup in it, so that lookups resolve and
+    /// are checked for cycles without following their chains, at the cost of slower writes to the cells in the
+    /// middle of chains.
+    pub compact_lookups: bool,
+    /// Accepts cells addressed in A1 notation and lookups written as `lookup(B5)` (see [`a1`]) in writes.
+    /// Without it, strings of that form are stored as they are.
+    pub a1_refs: bool,
+}
+
+/// An inclusive range of rows, where either end may be left open.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RowBounds {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl RowBounds {
+    pub fn contains(&self, row: i64) -> bool {
+        self.min.map_or(true, |min| row >= min) && self.max.map_or(true, |max| row <= max)
+    }
+
+    /// Whether any row is in the bounds at all.
+    pub fn is_valid(&self) -> bool {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => min <= max,
+            _ => true,
+        }
+    }
+}
+
+impl std::fmt::Display for RowBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "{min}..={max}"),
+            (Some(min), None) => write!(f, "{min}.."),
+            (None, Some(max)) => write!(f, "..={max}"),
+            (None, None) => write!(f, ".."),
+        }
+    }
+}
+
+/// An editing session, whose writes are staged until it's committed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub id: String,
+    /// When the session is discarded unless it's committed first, in milliseconds since the unix epoch.
+    pub expires_at: i64,
+}
+
+/// An accepted change to a sheet. Replaying the changes of a sheet in order rebuilds its state.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    Created {
+        schema: Schema,
+    },
+    CellWritten {
+        cell: Cell,
+    },
+    /// A cell was explicitly set to null.
+    TombstoneWritten {
+        column: String,
+        row: i64,
+    },
+    /// A cell was emptied, as if it had never been written.
+    CellCleared {
+        column: String,
+        row: i64,
+    },
+    RowMetaUpdated {
+        row: i64,
+        changes: RowMeta,
+    },
+    SettingsChanged {
+        settings: SheetSettings,
+    },
+}
+
+/// The schema of a sheet as it was from some point on, until the next version (if any).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SchemaVersion {
+    /// Versions are numbered from 1 for every sheet.
+    pub version: i64,
+    /// When the schema started being used, in milliseconds since the unix epoch.
+    pub at: i64,
+    #[serde(flatten)]
+    pub schema: Schema,
+}
+
+/// A single entry of a sheet's event log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    /// The position of the event in the log, starting at 1 for every sheet.
+    pub seq: i64,
+    /// When the change was made, in milliseconds since the unix epoch.
+    pub at: i64,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Running aggregates over the values stored directly in a column, i.e. not counting lookups.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ColumnStats {
+    pub count: i64,
+    /// Only present for numeric columns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sum: Option<f64>,
+    pub min: Option<CellValue>,
+    pub max: Option<CellValue>,
+}
+
+/// What would happen to a column if a sheet switched to a proposed schema.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnVerdict {
+    /// The column keeps its type.
+    Unchanged,
+    /// The column doesn't exist yet.
+    Added,
+    /// The column changes its type, so its values have to be converted.
+    Converted,
+    /// The column isn't part of the proposed schema.
+    Removed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ColumnCheck {
+    pub name: String,
+    pub verdict: ColumnVerdict,
+    pub compatible: bool,
+    /// The rows whose cells would be lost or broken, up to [`SchemaCheck::MAX_OFFENDING_ROWS`] of them.
+    pub offending_rows: Vec<i64>,
+    pub offending_count: usize,
+}
+
+/// Whether the existing data of a sheet is compatible with a proposed schema.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SchemaCheck {
+    pub compatible: bool,
+    /// The columns of the proposed schema in order, followed by the removed columns.
+    pub columns: Vec<ColumnCheck>,
+}
+
+impl SchemaCheck {
+    pub const MAX_OFFENDING_ROWS: usize = 100;
+}
+
+/// The outcome of recalculating a sheet's derived values.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RecalculationReport {
+    pub recalculated: u64,
+    pub broken: u64,
+}
+
+/// What a principal may do with a sheet. Every role includes the ones before it.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can read the sheet.
+    Viewer,
+    /// Can also write cells.
+    Editor,
+    /// Can also manage the sheet itself: its settings, aliases, notifications and permissions.
+    Owner,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Owner => "owner",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Self::Viewer),
+            "editor" => Ok(Self::Editor),
+            "owner" => Ok(Self::Owner),
+            _ => Err(format!("unknown role: {s:?}")),
+        }
+    }
+}
+
+/// A role granted to a principal on a sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Permission {
+    pub principal: String,
+    pub role: Role,
+}
+
+/// An entry of a sheet's access log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccessLogEntry {
+    /// The position of the entry in the log. Ids increase over time, but are shared by all sheets.
+    pub id: i64,
+    #[serde(flatten)]
+    pub event: crate::traffic::AccessEvent,
+}
+
+/// Another id which leads to a sheet, so that links keep working after its id was rotated, or after it was merged
+/// into another sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SheetAlias {
+    pub id: String,
+    #[serde(default)]
+    pub mode: AliasMode,
+}
+
+/// How requests for an alias are answered.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AliasMode {
+    /// With a `308 Permanent Redirect` to the same path under the sheet's id.
+    #[default]
+    Redirect,
+    /// As if they were made for the sheet's id, for clients which don't follow redirects.
+    Proxy,
+}
+
+/// An entry in the sheet listing. Timestamps are in milliseconds since the unix epoch.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SheetSummary {
+    pub id: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    /// The number of populated cells, including lookups.
+    pub cells: i64,
+}
+
+/// The orders in which the sheet listing can be sorted.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SheetSort {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Cells,
 }
 
 #[cfg(test)]
@@ -146,10 +1081,16 @@ impl SheetContent {
             column.push(SheetContentColumn {
                 row: *row,
                 value: value.clone(),
+                error: None,
             })
         }
 
-        Self { columns }
+        Self {
+            columns,
+            meta: None,
+            row_keys: None,
+            version: None,
+        }
     }
 
     pub fn with_potential_empty_columns(mut self, cols: &[&str]) -> Self {
@@ -170,7 +1111,7 @@ impl SheetContent {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     pub const NO_COLUMNS_PAYLOAD: &str = r#"{ "columns": [] }"#;
@@ -214,29 +1155,72 @@ mod tests {
                 columns: vec![
                     SchemaColumn {
                         name: "A".into(),
-                        kind: SchemaColumnKind::Boolean
+                        kind: SchemaColumnKind::Boolean,
+                        hidden: false,
+                        format: None,
+                        pii: false,
+                        references: None
                     },
                     SchemaColumn {
                         name: "B".into(),
-                        kind: SchemaColumnKind::Int
+                        kind: SchemaColumnKind::Int,
+                        hidden: false,
+                        format: None,
+                        pii: false,
+                        references: None
                     },
                     SchemaColumn {
                         name: "B2".into(),
-                        kind: SchemaColumnKind::Int
+                        kind: SchemaColumnKind::Int,
+                        hidden: false,
+                        format: None,
+                        pii: false,
+                        references: None
                     },
                     SchemaColumn {
                         name: "C".into(),
-                        kind: SchemaColumnKind::Double
+                        kind: SchemaColumnKind::Double,
+                        hidden: false,
+                        format: None,
+                        pii: false,
+                        references: None
                     },
                     SchemaColumn {
                         name: "D".into(),
-                        kind: SchemaColumnKind::String
+                        kind: SchemaColumnKind::String,
+                        hidden: false,
+                        format: None,
+                        pii: false,
+                        references: None
                     }
                 ]
             }
         );
     }
 
+    #[test]
+    fn urls_and_emails_are_checked() {
+        assert!(SchemaColumnKind::Url
+            .check_text("https://example.com/a?b#c")
+            .is_ok());
+        assert!(SchemaColumnKind::Url.check_text("example.com").is_err());
+        assert!(SchemaColumnKind::Url
+            .check_text("mailto:a@example.com")
+            .is_err());
+        assert!(SchemaColumnKind::Email
+            .check_text("a.b+c@mail.example.com")
+            .is_ok());
+        assert!(SchemaColumnKind::Email.check_text("a@localhost").is_err());
+        assert!(SchemaColumnKind::Email
+            .check_text("a b@example.com")
+            .is_err());
+        assert!(SchemaColumnKind::String.check_text("anything").is_ok());
+
+        let value = CellValue::String("a@example.com".into());
+        assert_eq!(value.convert(SchemaColumnKind::Email), Some(value.clone()));
+        assert_eq!(value.convert(SchemaColumnKind::Url), None);
+    }
+
     #[test]
     fn valid_cells_deserialize() {
         assert_eq!(
@@ -297,6 +1281,52 @@ mod tests {
         assert!(!schema.is_valid());
     }
 
+    #[test]
+    fn invalid_schema_names() {
+        let mut schema: Schema = serde_json::from_str(VALID_POST_PAYLOAD).unwrap();
+        schema.columns[0].name = "".into();
+        schema.columns[1].name = "tab\there".into();
+        schema.columns[2].name = "x".repeat(Schema::MAX_COLUMN_NAME_LENGTH + 1);
+
+        let errors = schema.validate(false).unwrap_err();
+        assert_eq!(errors.iter().map(|e| e.column).collect::<Vec<_>>(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn formats_suit_their_columns() {
+        let mut schema: Schema = serde_json::from_str(VALID_POST_PAYLOAD).unwrap();
+        schema.columns[1].format = Some(ColumnFormat::Percent);
+        schema.columns[3].format = Some(ColumnFormat::Currency { code: "EUR".into() });
+        assert!(schema.is_valid());
+
+        schema.columns[3].format = Some(ColumnFormat::Currency { code: "eur".into() });
+        schema.columns[4].format = Some(ColumnFormat::Percent);
+        let errors = schema.validate(false).unwrap_err();
+        let paths: Vec<_> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["columns[3].format", "columns[4].format"]);
+    }
+
+    #[test]
+    fn schema_case_insensitive_duplicates() {
+        let mut schema: Schema = serde_json::from_str(VALID_POST_PAYLOAD).unwrap();
+        schema.columns[1].name = "a".into();
+        assert!(schema.validate(false).is_ok());
+        assert_eq!(schema.validate(true).unwrap_err()[0].column, 1);
+    }
+
+    #[test]
+    fn schema_normalization() {
+        let mut schema: Schema = serde_json::from_str(VALID_POST_PAYLOAD).unwrap();
+        // "é" as a single code point, and as "e" followed by a combining accent
+        schema.columns[0].name = "caf\u{e9}".into();
+        schema.columns[1].name = "cafe\u{301}".into();
+        assert!(schema.is_valid());
+
+        schema.normalize();
+        assert_eq!(schema.columns[1].name, "caf\u{e9}");
+        assert!(!schema.is_valid());
+    }
+
     #[test]
     fn valid_lookup() {
         let val = CellValue::String(r#"lookup("hello", 5)"#.into());
@@ -314,4 +1344,39 @@ mod tests {
         let val = CellValue::String(r#"yo"#.into());
         assert!(val.is_lookup().is_none())
     }
+
+    #[test]
+    fn escaped_lookup() {
+        let val = CellValue::String(r#"'lookup("hello", 5)"#.into());
+        assert!(val.is_lookup().is_none());
+        assert_eq!(val.unescaped(), CellValue::String(r#"lookup("hello", 5)"#.into()));
+
+        let val = CellValue::String("'lookup(B5)".into());
+        assert_eq!(val.unescaped(), CellValue::String("lookup(B5)".into()));
+        let val = CellValue::String("''90s".into());
+        assert_eq!(val.unescaped(), CellValue::String("'90s".into()));
+        // strings which wouldn't be a lookup without the quote keep it
+        for s in ["'90s", "'", r#"'lookup("hello")"#] {
+            let val = CellValue::String(s.into());
+            assert_eq!(val.unescaped(), val);
+        }
+    }
+
+    #[test]
+    fn conversions_are_lossless() {
+        use SchemaColumnKind::*;
+
+        assert_eq!(CellValue::Int(3).convert(Double), Some(CellValue::Double(3.0)));
+        assert_eq!(CellValue::Int(i64::MAX).convert(Double), None);
+        assert_eq!(CellValue::Double(-2.0).convert(Int), Some(CellValue::Int(-2)));
+        assert_eq!(CellValue::Double(2.5).convert(Int), None);
+        assert_eq!(CellValue::Double(1e300).convert(Int), None);
+        assert_eq!(CellValue::String("42".into()).convert(Int), Some(CellValue::Int(42)));
+        assert_eq!(CellValue::String("nope".into()).convert(Boolean), None);
+        assert_eq!(
+            CellValue::Boolean(true).convert(String),
+            Some(CellValue::String("true".into()))
+        );
+        assert_eq!(CellValue::Boolean(true).convert(Int), None);
+    }
 }
@@ -0,0 +1,152 @@
+//! Excel-style A1 addresses of cells, like `B5`, for clients which think of a sheet as a grid rather than as
+//! named columns. Columns are lettered in the order of the sheet's schema (including hidden columns), so `A` is
+//! its first column, `Z` its 26th and `AA` its 27th. Rows are numbered as usual.
+//!
+//! Addresses are only a way of writing cells and lookups down: they're mapped to column names as soon as a
+//! request is read, and nothing is stored or returned in A1 notation.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use super::SchemaColumn;
+
+static A1_LOOKUP_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// The address of a cell in A1 notation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct A1 {
+    /// The index of the column in the schema, from 0.
+    pub column: usize,
+    pub row: i64,
+}
+
+impl std::str::FromStr for A1 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid cell address: {s:?}, expected one like B5");
+        let digits = s.find(|c: char| c.is_ascii_digit()).ok_or_else(invalid)?;
+        let (letters, row) = s.split_at(digits);
+        if letters.is_empty()
+            || !letters.bytes().all(|b| b.is_ascii_alphabetic())
+            || !row.bytes().all(|b| b.is_ascii_digit())
+            || row.starts_with('0')
+        {
+            return Err(invalid());
+        }
+
+        let mut column: usize = 0;
+        for letter in letters.bytes() {
+            let digit = usize::from(letter.to_ascii_uppercase() - b'A') + 1;
+            column = column
+                .checked_mul(26)
+                .and_then(|column| column.checked_add(digit))
+                .ok_or_else(invalid)?;
+        }
+        Ok(Self {
+            column: column - 1,
+            row: row.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for A1 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut letters = vec![];
+        let mut column = self.column + 1;
+        while column > 0 {
+            column -= 1;
+            letters.push(b'A' + (column % 26) as u8);
+            column /= 26;
+        }
+        letters.reverse();
+        write!(f, "{}{}", String::from_utf8_lossy(&letters), self.row)
+    }
+}
+
+impl A1 {
+    /// The name of the addressed column among the columns of a sheet, if there are enough of them.
+    pub fn column_name<'a>(&self, columns: &'a [SchemaColumn]) -> Option<&'a str> {
+        columns.get(self.column).map(|column| column.name.as_str())
+    }
+}
+
+/// The address of a lookup written in A1 notation, like `lookup(B5)`, if the value is one.
+pub fn lookup_address(value: &str) -> Option<&str> {
+    let re =
+        A1_LOOKUP_REGEX.get_or_init(|| Regex::new(r"^lookup\(\s*([A-Za-z]+[0-9]+)\s*\)$").unwrap());
+    re.captures(value)
+        .and_then(|captures| captures.get(1))
+        .map(|address| address.as_str())
+}
+
+/// Rewrites a lookup in A1 notation into the usual form, e.g. `lookup(B5)` into `lookup("Price", 5)`.
+pub fn resolve_lookup(value: &str, columns: &[SchemaColumn]) -> Option<Result<String, String>> {
+    let address = lookup_address(value)?;
+    Some(address.parse::<A1>().and_then(|a1| {
+        let letters = address.trim_end_matches(|c: char| c.is_ascii_digit());
+        match a1.column_name(columns) {
+            // names can't be quoted inside of lookups
+            Some(name) if name.contains('"') => {
+                Err(format!("column {letters} can't be looked up, since its name contains a quote"))
+            }
+            Some(name) => Ok(format!("lookup(\"{name}\", {})", a1.row)),
+            None => Err(format!("the sheet has no column {letters}")),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_lookup, A1};
+    use crate::sheet::Schema;
+
+    #[test]
+    fn addresses_round_trip() {
+        for (address, column, row) in [
+            ("A1", 0, 1),
+            ("b5", 1, 5),
+            ("Z10", 25, 10),
+            ("AA3", 26, 3),
+            ("AZ1", 51, 1),
+            ("BA2", 52, 2),
+            ("ZZ7", 701, 7),
+            ("AAA1", 702, 1),
+        ] {
+            let a1: A1 = address.parse().unwrap();
+            assert_eq!(a1, A1 { column, row }, "{address}");
+            assert_eq!(a1.to_string(), address.to_uppercase());
+        }
+        for invalid in ["", "A", "5", "A0", "A05", "5A", "A-1", "Ä1", "A1B", "A 1"] {
+            assert!(invalid.parse::<A1>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn lookups_are_resolved_by_schema_order() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"columns": [
+                {"name": "Item", "type": "string"},
+                {"name": "Price", "type": "double"},
+                {"name": "\"Net\"", "type": "double"}
+            ]}"#,
+        )
+        .unwrap();
+        let resolved = resolve_lookup("lookup( b3 )", &schema.columns)
+            .unwrap()
+            .unwrap();
+        assert_eq!(resolved, r#"lookup("Price", 3)"#);
+        assert!(crate::sheet::CellValue::String(resolved)
+            .is_lookup()
+            .is_some());
+        assert!(resolve_lookup("lookup(C3)", &schema.columns)
+            .unwrap()
+            .is_err());
+        assert!(resolve_lookup("lookup(D3)", &schema.columns)
+            .unwrap()
+            .is_err());
+        assert!(resolve_lookup(r#"lookup("Item", 3)"#, &schema.columns).is_none());
+        assert!(resolve_lookup("'lookup(A1)", &schema.columns).is_none());
+    }
+}
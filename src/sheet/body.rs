@@ -0,0 +1,137 @@
+//! Parsing of request bodies which reports the problem along with the path of the offending field, e.g.
+//! `columns[1].type`, rather than only an opaque message.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::view::{NewView, ViewDefinition};
+use super::{Cell, FieldError, NewSheet, Schema, Tombstone};
+
+/// A request body which can be parsed from arbitrary JSON.
+pub trait FromBody: Sized {
+    fn from_body(value: Value) -> Result<Self, Vec<FieldError>>;
+}
+
+/// Deserializes a value, keeping track of where in it the first error happened.
+fn deserialize<T: DeserializeOwned>(value: &Value) -> Result<T, Vec<FieldError>> {
+    serde_path_to_error::deserialize(value).map_err(|why| {
+        // the root of the value is written as `.`
+        let mut path = match why.path().to_string() {
+            path if path == "." => String::new(),
+            path => path,
+        };
+        let message = why.into_inner().to_string();
+        // missing fields are reported at the object they're missing from, rather than at the field itself
+        let missing = message
+            .strip_prefix("missing field `")
+            .and_then(|rest| rest.strip_suffix('`'));
+        if let Some(name) = missing {
+            path = match path.is_empty() {
+                true => name.into(),
+                false => format!("{path}.{name}"),
+            };
+        }
+        vec![FieldError { path, message }]
+    })
+}
+
+// flattened fields are buffered before they're deserialized, which loses track of the path. so the flattened part
+// is deserialized on its own first, which finds the errors in it along with their paths.
+
+impl FromBody for NewSheet {
+    fn from_body(value: Value) -> Result<Self, Vec<FieldError>> {
+        deserialize::<Schema>(&value)?;
+        deserialize(&value)
+    }
+}
+
+impl FromBody for Schema {
+    fn from_body(value: Value) -> Result<Self, Vec<FieldError>> {
+        deserialize(&value)
+    }
+}
+
+impl FromBody for NewView {
+    fn from_body(value: Value) -> Result<Self, Vec<FieldError>> {
+        deserialize::<ViewDefinition>(&value)?;
+        deserialize(&value)
+    }
+}
+
+impl FromBody for Cell {
+    fn from_body(value: Value) -> Result<Self, Vec<FieldError>> {
+        deserialize(&value)
+    }
+}
+
+impl FromBody for Tombstone {
+    fn from_body(value: Value) -> Result<Self, Vec<FieldError>> {
+        let tombstone = deserialize(&value)?;
+        if !value.get("value").is_some_and(Value::is_null) {
+            return Err(vec![FieldError {
+                path: "value".into(),
+                message: "expected null".into(),
+            }]);
+        }
+        Ok(tombstone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::FromBody;
+    use crate::sheet::{Cell, CellValue, NewSheet, Tombstone};
+
+    fn error<T: FromBody + std::fmt::Debug>(value: serde_json::Value) -> (String, String) {
+        let mut errors = T::from_body(value).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let error = errors.remove(0);
+        (error.path, error.message)
+    }
+
+    #[test]
+    fn new_sheet_errors_have_paths() {
+        let (path, message) = error::<NewSheet>(json!({
+            "columns": [{"name": "A", "type": "int"}, {"name": "B", "type": "intt"}]
+        }));
+        assert_eq!(path, "columns[1].type");
+        assert!(message.contains("intt"), "{message}");
+
+        assert_eq!(error::<NewSheet>(json!({"columns": [{"type": "int"}]})).0, "columns[0].name");
+        assert_eq!(error::<NewSheet>(json!({"id": 5, "columns": []})).0, "id");
+        assert_eq!(error::<NewSheet>(json!({})).0, "columns");
+        assert_eq!(error::<NewSheet>(json!([])).0, "");
+    }
+
+    #[test]
+    fn valid_bodies_parse() {
+        let payload = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        let sheet = NewSheet::from_body(payload).unwrap();
+        assert_eq!(sheet.schema.columns.len(), 5);
+        assert_eq!(sheet.id, None);
+
+        let cell = Cell::from_body(json!({"column": "A", "row": 3, "value": 1.5})).unwrap();
+        assert_eq!(cell.value, CellValue::Double(1.5));
+    }
+
+    #[test]
+    fn cell_errors_have_paths() {
+        assert_eq!(error::<Cell>(json!({"column": 1, "row": 1, "value": 1})).0, "column");
+        assert_eq!(error::<Cell>(json!({"column": "A", "row": "a", "value": 1})).0, "row");
+        assert_eq!(
+            error::<Cell>(json!({"column": "A", "row": 1, "value": [1]})),
+            ("value".into(), "expected a boolean, a number or a string".into())
+        );
+    }
+
+    #[test]
+    fn tombstones_need_a_null_value() {
+        let tombstone =
+            Tombstone::from_body(json!({"column": "A", "row": 2, "value": null})).unwrap();
+        assert_eq!(tombstone.row, 2);
+        assert_eq!(error::<Tombstone>(json!({"column": "A", "row": 2.5, "value": null})).0, "row");
+        assert_eq!(error::<Tombstone>(json!({"column": "A", "row": 2, "value": 1})).0, "value");
+    }
+}
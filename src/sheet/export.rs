@@ -0,0 +1,268 @@
+//! Exports of whole sheets to CSV, laid out the same way imports are (see [`super::import`]), so that an
+//! exported file can be imported again. Lookups are exported as the values they resolve to.
+//!
+//! Published sheets can also be rendered as an HTML table, which isn't meant to be imported.
+//!
+//! Numbers can be written the way a locale writes them (see [`Locale`]), for systems which expect that. Such
+//! exports can't be imported again, since imports only understand the usual decimal point.
+//!
+//! Exports can be anonymized (see [`anonymize`]), so that the structure and the other data of a sheet can be
+//! shared without the personal data in its `pii` columns.
+
+use std::collections::HashMap;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::import::CsvDialect;
+use super::{CellValue, SchemaColumn, SheetContent, LITERAL_ESCAPE};
+
+/// The most records an export may have. Since every row up to the last one gets a record, a single cell far down
+/// a sheet would otherwise make for an enormous file.
+pub const MAX_EXPORT_ROWS: i64 = 1_000_000;
+
+/// Writes the content of a sheet as CSV, with a column for each of `columns` in their order, and a record for
+/// every row from 1 up to the last populated one. Cells in rows below 1 can't be imported, so they're left out.
+///
+/// # Errors
+/// In case the last populated row is above [`MAX_EXPORT_ROWS`].
+pub fn write_csv(
+    columns: &[SchemaColumn],
+    content: &SheetContent,
+    dialect: CsvDialect,
+    locale: Locale,
+) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .from_writer(vec![]);
+    if dialect.has_header {
+        writer.write_record(columns.iter().map(|column| column.name.as_str()))?;
+    }
+    for record in records(columns, content, |value| format_value(value, locale))? {
+        writer.write_record(record)?;
+    }
+
+    let data = String::from_utf8(writer.into_inner()?)?;
+    Ok(dialect.encoding.encode(data))
+}
+
+/// Writes the content of a sheet as a standalone HTML page holding a single table, laid out like
+/// [`write_csv`], for viewing a sheet in a browser.
+///
+/// # Errors
+/// In case the last populated row is above [`MAX_EXPORT_ROWS`].
+pub fn write_html(
+    title: &str,
+    columns: &[SchemaColumn],
+    content: &SheetContent,
+    locale: Locale,
+) -> anyhow::Result<String> {
+    use std::fmt::Write;
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<table>\n<tr><th></th>",
+        escape_html(title)
+    );
+    for column in columns {
+        write!(html, "<th>{}</th>", escape_html(&column.name))?;
+    }
+    html.push_str("</tr>\n");
+    // nothing is imported from here, so strings are shown as they are
+    let display = |value: &CellValue| match value {
+        CellValue::String(x) => x.clone(),
+        value => format_value(value, locale),
+    };
+    for (row, record) in (1..).zip(records(columns, content, display)?) {
+        write!(html, "<tr><th>{row}</th>")?;
+        for field in record {
+            write!(html, "<td>{}</td>", escape_html(&field))?;
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+/// The fields of every row from 1 up to the last populated one, in the order of `columns`.
+fn records(
+    columns: &[SchemaColumn],
+    content: &SheetContent,
+    format: impl Fn(&CellValue) -> String,
+) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut fields: HashMap<i64, Vec<String>> = HashMap::new();
+    for (i, column) in columns.iter().enumerate() {
+        for cell in content.columns.get(&column.name).into_iter().flatten() {
+            if cell.row < 1 {
+                continue;
+            }
+            let field = match (&cell.value, cell.error) {
+                (_, Some(error)) => error.to_string(),
+                (Some(value), None) => format(value),
+                (None, None) => continue,
+            };
+            fields
+                .entry(cell.row)
+                .or_insert_with(|| vec![String::new(); columns.len()])[i] = field;
+        }
+    }
+
+    let last_row = fields.keys().copied().max().unwrap_or(0);
+    if last_row > MAX_EXPORT_ROWS {
+        anyhow::bail!("only sheets of up to {MAX_EXPORT_ROWS} rows can be exported");
+    }
+    Ok((1..=last_row)
+        .map(|row| {
+            fields
+                .remove(&row)
+                .unwrap_or_else(|| vec![String::new(); columns.len()])
+        })
+        .collect())
+}
+
+/// How numbers are written in an export, according to a locale given as a language tag like `de-DE`. Thousands
+/// aren't grouped, since most systems reading the exports don't expect it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Locale {
+    pub decimal_separator: char,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+        }
+    }
+}
+
+/// The languages which write a decimal comma, unless their region is among [`DECIMAL_POINT_REGIONS`].
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "is", "it", "lt",
+    "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+/// The languages which write a decimal point, unless their region is among [`DECIMAL_COMMA_REGIONS`].
+const DECIMAL_POINT_LANGUAGES: &[&str] =
+    &["en", "ga", "he", "hi", "ja", "ko", "ms", "mt", "th", "zh"];
+const DECIMAL_POINT_REGIONS: &[(&str, &str)] = &[
+    ("de", "CH"),
+    ("de", "LI"),
+    ("it", "CH"),
+    ("es", "MX"),
+    ("es", "US"),
+];
+const DECIMAL_COMMA_REGIONS: &[(&str, &str)] = &[("en", "ZA")];
+
+impl std::str::FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid locale: {s:?}, expected a language tag like de-DE");
+        let mut subtags = s.split(['-', '_']);
+        let language = subtags.next().unwrap_or_default().to_ascii_lowercase();
+        let region = subtags.next().map(str::to_ascii_uppercase);
+        let valid_region = region.as_deref().map_or(true, |region| {
+            (region.len() == 2 && region.bytes().all(|b| b.is_ascii_alphabetic()))
+                || (region.len() == 3 && region.bytes().all(|b| b.is_ascii_digit()))
+        });
+        if !(2..=3).contains(&language.len())
+            || !language.bytes().all(|b| b.is_ascii_alphabetic())
+            || !valid_region
+            || subtags.next().is_some()
+        {
+            return Err(invalid());
+        }
+
+        let in_region = |regions: &[(&str, &str)]| {
+            regions
+                .iter()
+                .any(|&(l, r)| l == language && Some(r) == region.as_deref())
+        };
+        let decimal_separator = if DECIMAL_COMMA_LANGUAGES.contains(&language.as_str()) {
+            if in_region(DECIMAL_POINT_REGIONS) {
+                '.'
+            } else {
+                ','
+            }
+        } else if DECIMAL_POINT_LANGUAGES.contains(&language.as_str()) {
+            if in_region(DECIMAL_COMMA_REGIONS) {
+                ','
+            } else {
+                '.'
+            }
+        } else {
+            return Err(format!("unsupported locale: {s:?}"));
+        };
+        Ok(Self { decimal_separator })
+    }
+}
+
+/// How the values of `pii` columns are left out of an anonymized export.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Anonymization {
+    /// Values are replaced with hashes, so that equal values stay equal within the export.
+    Hash,
+    /// Values are left out entirely.
+    Redact,
+}
+
+impl std::str::FromStr for Anonymization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(Self::Hash),
+            "redact" => Ok(Self::Redact),
+            _ => Err(format!("unknown anonymization: {s:?}, expected hash or redact")),
+        }
+    }
+}
+
+/// Replaces the values of the `pii` columns among `columns`. The hashes are keyed with a secret of their own for
+/// every export, so they can't be reversed by hashing guesses, nor be matched up across exports.
+pub fn anonymize(
+    columns: &[SchemaColumn],
+    content: &mut SheetContent,
+    anonymization: Anonymization,
+) {
+    let key: [u8; 32] = rand::random();
+    for column in columns.iter().filter(|column| column.pii) {
+        let Some(cells) = content.columns.get_mut(&column.name) else {
+            continue;
+        };
+        match anonymization {
+            Anonymization::Redact => cells.retain(|cell| cell.error.is_some()),
+            Anonymization::Hash => {
+                for value in cells.iter_mut().filter_map(|cell| cell.value.as_mut()) {
+                    let mut mac = Hmac::<Sha256>::new_from_slice(&key).unwrap();
+                    mac.update(format_value(value, Locale::default()).as_bytes());
+                    // 64 bits are plenty to tell the values of a single export apart
+                    let hash = hex::encode(&mac.finalize().into_bytes()[..8]);
+                    *value = CellValue::String(hash);
+                }
+            }
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn format_value(value: &CellValue, locale: Locale) -> String {
+    match value {
+        CellValue::Boolean(x) => x.to_string(),
+        CellValue::Int(x) => x.to_string(),
+        CellValue::Double(x) if locale.decimal_separator == '.' => x.to_string(),
+        CellValue::Double(x) => x
+            .to_string()
+            .replace('.', &locale.decimal_separator.to_string()),
+        // strings which would be imported as lookups, or lose their leading quote, have to be escaped
+        CellValue::String(x) if value.is_lookup().is_some() || x.starts_with(LITERAL_ESCAPE) => {
+            format!("{LITERAL_ESCAPE}{x}")
+        }
+        CellValue::String(x) => x.clone(),
+    }
+}
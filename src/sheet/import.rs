@@ -0,0 +1,320 @@
+//! Imports of whole tables from CSV. The first record is a header naming the column of every field (unless
+//! the file has no header, see [`CsvDialect::has_header`]), and every record after it fills the row of the same
+//! number, so the first one after the header is row 1.
+//!
+//! Parsing reports every problem along with its place in the file, using the same kind of paths as request
+//! bodies: `header[2]` for the third header field, and `rows[5].B` for the field of column `B` in row 5.
+
+use std::collections::HashSet;
+
+use unicode_normalization::UnicodeNormalization;
+
+use super::{Cell, CellValue, FieldError, SchemaColumn, SchemaColumnKind};
+
+/// How a CSV file is laid out, for files which don't use the usual commas and double quotes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// Without a header, the fields of every record are in the order of the sheet's columns.
+    pub has_header: bool,
+    pub encoding: Encoding,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            has_header: true,
+            encoding: Encoding::Utf8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1, whose characters are exactly the first 256 of unicode.
+    Latin1,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Self::Latin1),
+            _ => Err(format!("unknown encoding: {s:?}")),
+        }
+    }
+}
+
+impl Encoding {
+    /// Decodes a file into UTF-8, which is what the CSV parser works with. A byte order mark is dropped.
+    fn decode(self, data: &[u8]) -> std::borrow::Cow<'_, [u8]> {
+        match self {
+            Self::Utf8 => data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data).into(),
+            Self::Latin1 => data
+                .iter()
+                .map(|&byte| char::from(byte))
+                .collect::<String>()
+                .into_bytes()
+                .into(),
+        }
+    }
+
+    /// Encodes UTF-8 text. Characters which don't exist in the encoding are replaced with `?`.
+    pub fn encode(self, text: String) -> Vec<u8> {
+        match self {
+            Self::Utf8 => text.into_bytes(),
+            Self::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+
+    /// The name of the encoding as a `Content-Type` charset.
+    pub fn charset(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Latin1 => "iso-8859-1",
+        }
+    }
+}
+
+/// A parsed import. It's only ready to be written if there are no errors, otherwise the cells are those of the
+/// fields which could be parsed.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Import {
+    /// The number of records after the header, including empty ones.
+    pub rows: u64,
+    pub cells: Vec<Cell>,
+    pub errors: Vec<FieldError>,
+}
+
+/// Parses a CSV file against the columns of a sheet. Empty fields are left out rather than written, and fields
+/// that look like lookups are kept as they are, to be checked when they're written. Fields of autoincrement
+/// columns are skipped, since the server assigns those values itself.
+pub fn parse_csv(data: &[u8], columns: &[SchemaColumn], dialect: CsvDialect) -> Import {
+    let mut errors = vec![];
+    let mut error = |path: String, message: &str| {
+        errors.push(FieldError {
+            path,
+            message: message.into(),
+        })
+    };
+
+    let data = dialect.encoding.decode(data);
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .from_reader(data.as_ref());
+    let mut records = reader.records();
+
+    if !dialect.has_header {
+        let header = columns
+            .iter()
+            .map(|column| {
+                (column.kind != SchemaColumnKind::Autoincrement)
+                    .then(|| (column.name.clone(), column.kind))
+            })
+            .collect();
+        return parse_records(records, header, errors);
+    }
+
+    let header = match records.next() {
+        Some(Ok(header)) => header,
+        Some(Err(why)) => {
+            error("header".into(), &why.to_string());
+            return Import {
+                errors,
+                ..Default::default()
+            };
+        }
+        None => {
+            error("header".into(), "missing header");
+            return Import {
+                errors,
+                ..Default::default()
+            };
+        }
+    };
+
+    // every header field is checked, even after the first broken one
+    let mut seen = HashSet::new();
+    let header: Vec<_> = header
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let name: String = name.nfc().collect();
+            let column = columns.iter().find(|column| column.name == name);
+            if column.is_none() {
+                error(format!("header[{i}]"), "unknown column");
+            } else if !seen.insert(name.clone()) {
+                error(format!("header[{i}]"), "duplicate column");
+            }
+            column
+                .filter(|column| column.kind != SchemaColumnKind::Autoincrement)
+                .map(|column| (name, column.kind))
+        })
+        .collect();
+
+    parse_records(records, header, errors)
+}
+
+/// Parses the records after the header, whose fields are in the columns of `header` (or skipped for `None`).
+fn parse_records(
+    records: csv::StringRecordsIter<&[u8]>,
+    header: Vec<Option<(String, SchemaColumnKind)>>,
+    mut errors: Vec<FieldError>,
+) -> Import {
+    let mut error = |path: String, message: &str| {
+        errors.push(FieldError {
+            path,
+            message: message.into(),
+        })
+    };
+
+    let mut rows = 0;
+    let mut cells = vec![];
+    for (row, record) in (1..).zip(records) {
+        rows += 1;
+        let record = match record {
+            Ok(record) => record,
+            Err(why) => {
+                error(format!("rows[{row}]"), &why.to_string());
+                continue;
+            }
+        };
+
+        for (field, column) in record.iter().zip(&header) {
+            let Some((name, kind)) = column else {
+                continue;
+            };
+            if field.is_empty() {
+                continue;
+            }
+
+            match parse_field(field, *kind) {
+                Some(value) => cells.push(Cell {
+                    column: name.clone(),
+                    row,
+                    value,
+                }),
+                None => error(
+                    format!("rows[{row}].{name}"),
+                    // the debug names are the same as the serialized ones, apart from the case
+                    &format!("expected a value of type {}", format!("{kind:?}").to_lowercase()),
+                ),
+            }
+        }
+    }
+
+    Import {
+        rows,
+        cells,
+        errors,
+    }
+}
+
+fn parse_field(field: &str, kind: SchemaColumnKind) -> Option<CellValue> {
+    let value = CellValue::String(field.into());
+    if value.is_lookup().is_some() {
+        return Some(value);
+    }
+
+    match kind {
+        SchemaColumnKind::Boolean => field.parse().ok().map(CellValue::Boolean),
+        SchemaColumnKind::Int => field.parse().ok().map(CellValue::Int),
+        // sqlite can't store NaN, and JSON has no infinities
+        SchemaColumnKind::Double => field
+            .parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite())
+            .map(CellValue::Double),
+        SchemaColumnKind::String => Some(value),
+        // autoincrement columns are skipped while parsing the header
+        SchemaColumnKind::Autoincrement => None,
+        SchemaColumnKind::Url | SchemaColumnKind::Email => {
+            let valid = match value.unescaped() {
+                CellValue::String(text) => kind.check_text(&text).is_ok(),
+                _ => false,
+            };
+            valid.then_some(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_csv, CsvDialect, Encoding};
+    use crate::sheet::{CellValue, Schema};
+
+    fn columns() -> Vec<crate::sheet::SchemaColumn> {
+        let schema: Schema = serde_json::from_str(crate::sheet::tests::VALID_POST_PAYLOAD).unwrap();
+        schema.columns
+    }
+
+    #[test]
+    fn fields_are_parsed_by_column_type() {
+        let import = parse_csv(
+            b"B,A\n1,true\n,false\n\"lookup(\"\"B\"\", 1)\",",
+            &columns(),
+            CsvDialect::default(),
+        );
+        assert!(import.errors.is_empty());
+        assert_eq!(import.rows, 3);
+        let cells: Vec<_> = import
+            .cells
+            .iter()
+            .map(|cell| (cell.column.as_str(), cell.row, cell.value.clone()))
+            .collect();
+        assert_eq!(
+            cells,
+            [
+                ("B", 1, CellValue::Int(1)),
+                ("A", 1, CellValue::Boolean(true)),
+                ("A", 2, CellValue::Boolean(false)),
+                ("B", 3, CellValue::String("lookup(\"B\", 1)".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_have_paths() {
+        let import = parse_csv(b"B,Z,B\n1.5,1,2\n2,3\n", &columns(), CsvDialect::default());
+        let paths: Vec<_> = import
+            .errors
+            .iter()
+            .map(|error| error.path.as_str())
+            .collect();
+        assert_eq!(paths, ["header[1]", "header[2]", "rows[1].B", "rows[2]"]);
+        assert_eq!(import.rows, 2);
+
+        let import = parse_csv(b"", &columns(), CsvDialect::default());
+        assert_eq!(import.errors[0].path, "header");
+    }
+
+    #[test]
+    fn dialects_are_respected() {
+        let dialect = CsvDialect {
+            delimiter: b';',
+            quote: b'\'',
+            has_header: false,
+            encoding: Encoding::Latin1,
+        };
+        // A;B;B2;C;D, where D is a string column
+        let import = parse_csv(b"true;1;;1.5;'caf\xE9;x'", &columns(), dialect);
+        assert!(import.errors.is_empty(), "{:?}", import.errors);
+        let values: Vec<_> = import.cells.iter().map(|cell| cell.value.clone()).collect();
+        assert_eq!(values[0], CellValue::Boolean(true));
+        assert_eq!(values.last().unwrap(), &CellValue::String("café;x".into()));
+
+        let import = parse_csv(b"\xEF\xBB\xBFB\n1\n", &columns(), CsvDialect::default());
+        assert!(import.errors.is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+//! Histograms describing the shape of a sheet's lookups (see [`crate::db::Db::lookup_stats`]), to find sheets
+//! whose lookups approach the patterns which make resolving them slow: very long chains, and cells which a lot of
+//! lookups point at.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The shape of the lookups of a sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct LookupStats {
+    /// The number of lookup cells.
+    pub lookups: u64,
+    /// The number of lookups followed to resolve each lookup cell, which is 1 for one pointing at a value.
+    pub chain_lengths: Histogram,
+    /// The number of lookups pointing directly at each cell that any lookup points at.
+    pub fan_in: Histogram,
+}
+
+/// Counts of values in buckets whose bounds are powers of two, up to the one holding the largest value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct Histogram {
+    pub buckets: Vec<Bucket>,
+    /// The largest value, or 0 if there are none.
+    pub max: u64,
+}
+
+/// The values greater than the previous bucket's `up_to`, up to and including this one's.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Bucket {
+    pub up_to: u64,
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Builds the histogram of values which are all at least 1.
+    pub fn new(values: impl IntoIterator<Item = u64>) -> Self {
+        let mut counts: Vec<u64> = vec![];
+        let mut max = 0;
+        for value in values {
+            let bucket = value.max(1).next_power_of_two().trailing_zeros() as usize;
+            if counts.len() <= bucket {
+                counts.resize(bucket + 1, 0);
+            }
+            counts[bucket] += 1;
+            max = max.max(value);
+        }
+
+        let buckets = counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| Bucket {
+                up_to: 1 << i,
+                count,
+            })
+            .collect();
+        Self { buckets, max }
+    }
+}
+
+impl LookupStats {
+    /// Measures the lookups of a sheet, given as the target of every lookup cell. Chains are followed the same
+    /// way as when resolving the whole sheet, so this takes linear time.
+    pub fn new(lookups: &HashMap<(i64, i64), (i64, i64)>) -> Self {
+        let mut lengths: HashMap<(i64, i64), u64> = HashMap::with_capacity(lookups.len());
+        for &start in lookups.keys() {
+            // follow the chain until a lookup whose length is already known, or the end of the chain
+            let mut path = vec![];
+            let mut current = start;
+            // lookup chains are guaranteed to be acyclic, so this always ends
+            let mut length = loop {
+                if let Some(&length) = lengths.get(&current) {
+                    break length;
+                }
+                match lookups.get(&current) {
+                    Some(&target) => {
+                        path.push(current);
+                        current = target;
+                    }
+                    None => break 0,
+                }
+            };
+            for cell in path.into_iter().rev() {
+                length += 1;
+                lengths.insert(cell, length);
+            }
+        }
+
+        let mut fan_in: HashMap<(i64, i64), u64> = HashMap::new();
+        for &target in lookups.values() {
+            *fan_in.entry(target).or_default() += 1;
+        }
+
+        Self {
+            lookups: lookups.len() as u64,
+            chain_lengths: Histogram::new(lengths.into_values()),
+            fan_in: Histogram::new(fan_in.into_values()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{Bucket, Histogram, LookupStats};
+
+    #[test]
+    fn histograms_have_power_of_two_buckets() {
+        let histogram = Histogram::new([1, 3, 4, 4, 9]);
+        let counts: Vec<_> = histogram
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.up_to, bucket.count))
+            .collect();
+        assert_eq!(counts, [(1, 1), (2, 0), (4, 3), (8, 0), (16, 1)]);
+        assert_eq!(histogram.max, 9);
+        assert_eq!(Histogram::new([]), Histogram::default());
+    }
+
+    #[test]
+    fn chains_and_fan_in_are_measured() {
+        // B1 -> B2 -> B3 -> B4, with B5 and B6 pointing at B3 as well, and C1 -> B4
+        let lookups: HashMap<_, _> = [
+            ((1, 1), (1, 2)),
+            ((1, 2), (1, 3)),
+            ((1, 3), (1, 4)),
+            ((1, 5), (1, 3)),
+            ((1, 6), (1, 3)),
+            ((2, 1), (1, 4)),
+        ]
+        .into_iter()
+        .collect();
+        let stats = LookupStats::new(&lookups);
+        assert_eq!(stats.lookups, 6);
+
+        // lengths: B3 and C1 1, B2, B5 and B6 2, B1 3
+        let bucket = |up_to, count| Bucket { up_to, count };
+        assert_eq!(stats.chain_lengths.buckets, [bucket(1, 2), bucket(2, 3), bucket(4, 1)]);
+        assert_eq!(stats.chain_lengths.max, 3);
+        // targets: B2 once, B4 twice, B3 three times
+        assert_eq!(stats.fan_in.buckets, [bucket(1, 1), bucket(2, 1), bucket(4, 1)]);
+        assert_eq!(stats.fan_in.max, 3);
+    }
+}
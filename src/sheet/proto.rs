@@ -0,0 +1,356 @@
+//! Protobuf encoding of the main request and response bodies, for bandwidth-sensitive clients. The messages are
+//! described in `proto/sheet.proto`, and are written out by hand here so that building doesn't need `protoc`.
+//!
+//! Requests are decoded as protobuf when their `Content-Type` says so, and are then turned into the JSON they
+//! stand for, so that they're parsed and validated the same way. Successful responses are encoded as protobuf
+//! when the `Accept` header allows it, while error responses are always JSON.
+
+use std::{collections::HashMap, marker::PhantomData};
+
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+
+pub const CONTENT_TYPE: &str = "application/protobuf";
+/// Also accepted, since some clients still use the older name.
+const LEGACY_CONTENT_TYPE: &str = "application/x-protobuf";
+
+fn is_protobuf(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    essence.eq_ignore_ascii_case(CONTENT_TYPE) || essence.eq_ignore_ascii_case(LEGACY_CONTENT_TYPE)
+}
+
+/// Whether the body of a request is protobuf.
+pub fn sends_protobuf(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(is_protobuf)
+}
+
+/// Whether the client wants a protobuf response.
+pub fn accepts_protobuf(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(is_protobuf)
+}
+
+/// A response which can be encoded as protobuf.
+pub trait Encode {
+    fn encode_protobuf(&self) -> Vec<u8>;
+}
+
+/// A request message, which stands for some JSON body.
+pub trait IntoJson {
+    fn into_json(self) -> serde_json::Value;
+}
+
+/// A JSON request body, which may also be sent as the protobuf message `M`. Either way, it ends up as JSON.
+pub struct Body<M>(pub serde_json::Value, PhantomData<M>);
+
+impl<M> FromRequest for Body<M>
+where
+    M: prost::Message + Default + IntoJson + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if sends_protobuf(req) {
+            let bytes = web::Bytes::from_request(req, payload);
+            Box::pin(async move {
+                let message = M::decode(bytes.await?).map_err(actix_web::error::ErrorBadRequest)?;
+                Ok(Self(message.into_json(), PhantomData))
+            })
+        } else {
+            let json = web::Json::<serde_json::Value>::from_request(req, payload);
+            Box::pin(async move { Ok(Self(json.await?.into_inner(), PhantomData)) })
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NewSheet {
+    #[prost(message, repeated, tag = "1")]
+    pub columns: Vec<SchemaColumn>,
+    #[prost(string, optional, tag = "2")]
+    pub id: Option<String>,
+    #[prost(bool, optional, tag = "3")]
+    pub no_lookup_nulls: Option<bool>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SchemaColumn {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(enumeration = "ColumnType", tag = "2")]
+    pub r#type: i32,
+    #[prost(bool, tag = "3")]
+    pub hidden: bool,
+    #[prost(message, optional, tag = "4")]
+    pub format: Option<ColumnFormat>,
+    #[prost(bool, tag = "5")]
+    pub pii: bool,
+    #[prost(message, optional, tag = "6")]
+    pub references: Option<ColumnReference>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ColumnFormat {
+    #[prost(string, tag = "1")]
+    pub kind: String,
+    #[prost(string, optional, tag = "2")]
+    pub code: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ColumnReference {
+    #[prost(string, tag = "1")]
+    pub sheet: String,
+    #[prost(string, tag = "2")]
+    pub column: String,
+    #[prost(string, optional, tag = "3")]
+    pub on_delete: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ColumnType {
+    Boolean = 0,
+    Int = 1,
+    Double = 2,
+    String = 3,
+    Url = 4,
+    Email = 5,
+    Autoincrement = 6,
+}
+
+impl From<ColumnType> for super::SchemaColumnKind {
+    fn from(kind: ColumnType) -> Self {
+        match kind {
+            ColumnType::Boolean => Self::Boolean,
+            ColumnType::Int => Self::Int,
+            ColumnType::Double => Self::Double,
+            ColumnType::String => Self::String,
+            ColumnType::Url => Self::Url,
+            ColumnType::Email => Self::Email,
+            ColumnType::Autoincrement => Self::Autoincrement,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Value {
+    #[prost(oneof = "value::Kind", tags = "1, 2, 3, 4")]
+    pub kind: Option<value::Kind>,
+}
+
+pub mod value {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Kind {
+        #[prost(bool, tag = "1")]
+        Boolean(bool),
+        #[prost(int64, tag = "2")]
+        Int(i64),
+        #[prost(double, tag = "3")]
+        Double(f64),
+        #[prost(string, tag = "4")]
+        String(String),
+    }
+}
+
+impl From<&super::CellValue> for Value {
+    fn from(value: &super::CellValue) -> Self {
+        let kind = match value {
+            super::CellValue::Boolean(x) => value::Kind::Boolean(*x),
+            super::CellValue::Int(x) => value::Kind::Int(*x),
+            super::CellValue::Double(x) => value::Kind::Double(*x),
+            super::CellValue::String(x) => value::Kind::String(x.clone()),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Cell {
+    #[prost(string, tag = "1")]
+    pub column: String,
+    #[prost(int64, optional, tag = "2")]
+    pub row: Option<i64>,
+    #[prost(string, optional, tag = "3")]
+    pub row_key: Option<String>,
+    #[prost(message, optional, tag = "4")]
+    pub value: Option<Value>,
+    #[prost(string, optional, tag = "5")]
+    pub r#ref: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PostResponse {
+    #[prost(string, tag = "1")]
+    pub sheet_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PostSheetIdResponse {
+    #[prost(string, optional, tag = "1")]
+    pub row_key: Option<String>,
+    #[prost(bool, tag = "2")]
+    pub staged: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SheetContent {
+    #[prost(map = "string, message", tag = "1")]
+    pub columns: HashMap<String, Column>,
+    #[prost(map = "int64, string", tag = "2")]
+    pub row_keys: HashMap<i64, String>,
+    /// The metadata of every row, encoded as JSON objects.
+    #[prost(map = "int64, string", tag = "3")]
+    pub meta: HashMap<i64, String>,
+    #[prost(int64, optional, tag = "4")]
+    pub version: Option<i64>,
+    #[prost(int64, optional, tag = "5")]
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Column {
+    #[prost(message, repeated, tag = "1")]
+    pub cells: Vec<ColumnCell>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ColumnCell {
+    #[prost(int64, tag = "1")]
+    pub row: i64,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<Value>,
+    #[prost(string, optional, tag = "3")]
+    pub error: Option<String>,
+}
+
+impl From<&super::SheetContent> for SheetContent {
+    fn from(content: &super::SheetContent) -> Self {
+        let columns = content
+            .columns
+            .iter()
+            .map(|(name, cells)| {
+                let cells = cells
+                    .iter()
+                    .map(|cell| ColumnCell {
+                        row: cell.row,
+                        value: cell.value.as_ref().map(Value::from),
+                        error: cell.error.and_then(|error| {
+                            serde_json::to_value(error)
+                                .ok()
+                                .and_then(|error| error.as_str().map(String::from))
+                        }),
+                    })
+                    .collect();
+                (name.clone(), Column { cells })
+            })
+            .collect();
+
+        Self {
+            columns,
+            row_keys: content
+                .row_keys
+                .iter()
+                .flatten()
+                .map(|(row, key)| (*row, key.clone()))
+                .collect(),
+            meta: content
+                .meta
+                .iter()
+                .flatten()
+                .map(|(row, meta)| (*row, serde_json::Value::from(meta.clone()).to_string()))
+                .collect(),
+            version: content.version,
+            next_cursor: None,
+        }
+    }
+}
+
+impl IntoJson for Value {
+    fn into_json(self) -> serde_json::Value {
+        match self.kind {
+            Some(value::Kind::Boolean(x)) => x.into(),
+            Some(value::Kind::Int(x)) => x.into(),
+            Some(value::Kind::Double(x)) if x.is_finite() => x.into(),
+            // these can't be JSON numbers, and null would clear the cell, so they're passed on as something
+            // which is reported like any other bad value
+            Some(value::Kind::Double(x)) => serde_json::json!({ "double": x.to_string() }),
+            Some(value::Kind::String(x)) => x.into(),
+            None => serde_json::Value::Null,
+        }
+    }
+}
+
+impl IntoJson for NewSheet {
+    fn into_json(self) -> serde_json::Value {
+        let columns: Vec<_> = self
+            .columns
+            .into_iter()
+            .map(|column| {
+                // unknown types are passed on as they are, so that they're reported like any other bad type
+                let kind = match ColumnType::try_from(column.r#type) {
+                    Ok(kind) => serde_json::to_value(super::SchemaColumnKind::from(kind))
+                        .unwrap_or_default(),
+                    Err(_) => column.r#type.into(),
+                };
+                let mut body = serde_json::json!({
+                    "name": column.name, "type": kind, "hidden": column.hidden, "pii": column.pii,
+                });
+                if let Some(format) = column.format {
+                    body["format"] = serde_json::json!({ "kind": format.kind });
+                    if let Some(code) = format.code {
+                        body["format"]["code"] = code.into();
+                    }
+                }
+                if let Some(references) = column.references {
+                    body["references"] = serde_json::json!({
+                        "sheet": references.sheet, "column": references.column,
+                    });
+                    if let Some(on_delete) = references.on_delete {
+                        body["references"]["on_delete"] = on_delete.into();
+                    }
+                }
+                body
+            })
+            .collect();
+
+        let mut body = serde_json::json!({ "columns": columns });
+        if let Some(id) = self.id {
+            body["id"] = id.into();
+        }
+        if let Some(no_lookup_nulls) = self.no_lookup_nulls {
+            body["no_lookup_nulls"] = no_lookup_nulls.into();
+        }
+        body
+    }
+}
+
+impl IntoJson for Cell {
+    fn into_json(self) -> serde_json::Value {
+        let mut body = serde_json::json!({});
+        // the column is left empty when the cell is addressed by its ref instead
+        if self.r#ref.is_none() || !self.column.is_empty() {
+            body["column"] = self.column.into();
+        }
+        if let Some(reference) = self.r#ref {
+            body["ref"] = reference.into();
+        }
+        if let Some(row) = self.row {
+            body["row"] = row.into();
+        }
+        if let Some(row_key) = self.row_key {
+            body["row_key"] = row_key.into();
+        }
+        if let Some(value) = self.value {
+            body["value"] = value.into_json();
+        }
+        body
+    }
+}
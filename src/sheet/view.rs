@@ -0,0 +1,567 @@
+//! View sheets, whose content is derived from another sheet (their source) rather than written to them. A view
+//! either keeps the rows of its source which match a filter, with some of their columns, or groups them by
+//! the value of a column and aggregates the other columns of every group.
+//!
+//! Views are materialized: their content is stored like that of any other sheet, so every endpoint which reads
+//! sheets works with them as well, and it's computed again whenever the view is refreshed.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use super::{CellValue, FieldError, SchemaColumn, SchemaColumnKind, SheetContent};
+
+/// What a view contains, and when it's refreshed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewDefinition {
+    /// The columns of the source to keep, in order. Without it, every column which isn't hidden is kept.
+    /// Grouped views can't have it, since their columns are given by `group_by` and `aggregates`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub columns: Option<Vec<String>>,
+    /// The conditions a row of the source has to meet to be part of the view.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filter: Vec<ViewFilter>,
+    /// Groups the rows by the value of this column, so that the view has a row for every value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_by: Option<String>,
+    /// The columns of a grouped view, next to the one grouped by.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aggregates: Vec<ViewAggregate>,
+    #[serde(default)]
+    pub refresh: ViewRefresh,
+}
+
+/// A condition on the value of a column of the source.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewFilter {
+    pub column: String,
+    pub op: FilterOp,
+    /// The value to compare with, which `empty` and `not_empty` don't have.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<CellValue>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Empty,
+    NotEmpty,
+}
+
+/// A column of a grouped view, which aggregates a column of the source over every group.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewAggregate {
+    /// The name of the column in the view.
+    pub name: String,
+    pub function: AggregateFunction,
+    /// The column of the source to aggregate. `count` counts the rows of the group without it, and the
+    /// non-empty values of the column with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// When a view is refreshed.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewRefresh {
+    /// Along with every write to the source, as part of the same transaction.
+    #[default]
+    OnWrite,
+    /// Only when asked to, with `POST /sheet/:sheetid/refresh`.
+    Manual,
+}
+
+/// A view sheet as returned by `GET /sheet/:sheetid/view`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewInfo {
+    /// The id of the source sheet.
+    pub source: String,
+    #[serde(flatten)]
+    pub definition: ViewDefinition,
+    /// Whether the source was written to since the view was last refreshed.
+    pub stale: bool,
+    /// When the view was last refreshed, in milliseconds since the unix epoch.
+    pub refreshed_at: i64,
+}
+
+/// The body of a view creation request: the definition, and optionally a custom id for the view.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewView {
+    #[serde(flatten)]
+    pub definition: ViewDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+impl ViewDefinition {
+    /// Normalizes all of the column names to Unicode NFC, like the names of a schema (see
+    /// [`super::Schema::normalize`]).
+    pub fn normalize(&mut self) {
+        let normalize = |name: &mut String| *name = name.nfc().collect();
+        self.columns.iter_mut().flatten().for_each(normalize);
+        self.filter
+            .iter_mut()
+            .for_each(|filter| normalize(&mut filter.column));
+        self.group_by.iter_mut().for_each(normalize);
+        for aggregate in &mut self.aggregates {
+            normalize(&mut aggregate.name);
+            aggregate.column.iter_mut().for_each(normalize);
+        }
+    }
+
+    /// Checks the definition against the columns of the source, and returns the columns of the view. Every
+    /// problem is reported along with the path of the offending field, e.g. `filter[1].value`.
+    pub fn validate(&self, source: &[SchemaColumn]) -> Result<Vec<SchemaColumn>, Vec<FieldError>> {
+        let mut errors = vec![];
+        let mut error = |path: String, message: &str| {
+            errors.push(FieldError {
+                path,
+                message: message.into(),
+            })
+        };
+        let find = |name: &str| source.iter().find(|column| column.name == name);
+
+        for (i, filter) in self.filter.iter().enumerate() {
+            let Some(column) = find(&filter.column) else {
+                error(format!("filter[{i}].column"), "unknown column");
+                continue;
+            };
+            match (&filter.value, filter.op) {
+                (Some(_), FilterOp::Empty | FilterOp::NotEmpty) => {
+                    error(format!("filter[{i}].value"), "empty and not_empty don't take a value")
+                }
+                (None, FilterOp::Empty | FilterOp::NotEmpty) => {}
+                (None, _) => error(format!("filter[{i}].value"), "missing field"),
+                (Some(value), _) if !comparable(column.kind, value) => error(
+                    format!("filter[{i}].value"),
+                    "the value can't be compared with the values of the column",
+                ),
+                (Some(_), _) => {}
+            }
+        }
+
+        let Some(group_by) = &self.group_by else {
+            if !self.aggregates.is_empty() {
+                error("aggregates".into(), "only grouped views can have aggregates");
+            }
+            let columns: Vec<_> = match &self.columns {
+                Some(names) => names
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, name)| {
+                        let column = find(name);
+                        if column.is_none() {
+                            error(format!("columns[{i}]"), "unknown column");
+                        }
+                        column
+                    })
+                    .collect(),
+                None => source.iter().filter(|column| !column.hidden).collect(),
+            };
+            if self
+                .columns
+                .as_ref()
+                .is_some_and(|names| names.iter().collect::<BTreeSet<_>>().len() != names.len())
+            {
+                error("columns".into(), "duplicate column name");
+            }
+            // unknown columns were already reported
+            if columns.is_empty() && self.columns.as_ref().map_or(true, Vec::is_empty) {
+                error("columns".into(), "a view needs at least one column");
+            }
+
+            return if errors.is_empty() {
+                Ok(columns.into_iter().map(view_column).collect())
+            } else {
+                Err(errors)
+            };
+        };
+
+        if self.columns.is_some() {
+            error("columns".into(), "grouped views can't have columns, only aggregates");
+        }
+        let mut columns = vec![];
+        match find(group_by) {
+            Some(column) => columns.push(view_column(column)),
+            None => error("group_by".into(), "unknown column"),
+        }
+        for (i, aggregate) in self.aggregates.iter().enumerate() {
+            if aggregate.name.is_empty() {
+                error(format!("aggregates[{i}].name"), "column name must not be empty");
+            } else if aggregate.name == *group_by
+                || self.aggregates[..i]
+                    .iter()
+                    .any(|other| other.name == aggregate.name)
+            {
+                error(format!("aggregates[{i}].name"), "duplicate column name");
+            }
+
+            let column = aggregate.column.as_deref().map(find);
+            match (aggregate.function, column) {
+                (_, Some(None)) => error(format!("aggregates[{i}].column"), "unknown column"),
+                (AggregateFunction::Count, _) => {}
+                (_, None) => error(format!("aggregates[{i}].column"), "missing field"),
+                (_, Some(Some(column)))
+                    if !matches!(
+                        column.kind.storage(),
+                        SchemaColumnKind::Int | SchemaColumnKind::Double
+                    ) =>
+                {
+                    error(
+                        format!("aggregates[{i}].column"),
+                        "only int and double columns can be aggregated",
+                    )
+                }
+                _ => {}
+            }
+            columns.push(SchemaColumn {
+                name: aggregate.name.clone(),
+                kind: match aggregate.function {
+                    AggregateFunction::Count => SchemaColumnKind::Int,
+                    _ => SchemaColumnKind::Double,
+                },
+                hidden: false,
+                format: None,
+                pii: false,
+                references: None,
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(columns)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Computes the rows of the view from the content of its source, which has to include hidden columns. Every
+    /// row has a value for every column of the view, in the order [`Self::validate`] returned them.
+    ///
+    /// Filtered views keep the row numbers of their source, while grouped views have a row for every value of
+    /// the column grouped by, numbered from 1 in ascending order of the values. Rows with an empty value there
+    /// aren't part of any group.
+    pub fn evaluate(
+        &self,
+        columns: &[SchemaColumn],
+        source: &SheetContent,
+    ) -> Vec<(i64, Vec<Option<CellValue>>)> {
+        let values: HashMap<&str, HashMap<i64, &CellValue>> = source
+            .columns
+            .iter()
+            .map(|(name, cells)| {
+                let cells = cells
+                    .iter()
+                    .filter_map(|cell| Some((cell.row, cell.value.as_ref()?)))
+                    .collect();
+                (name.as_str(), cells)
+            })
+            .collect();
+        let value = |column: &str, row: i64| values.get(column).and_then(|cells| cells.get(&row));
+
+        let rows: BTreeSet<i64> = source
+            .columns
+            .values()
+            .flatten()
+            .map(|cell| cell.row)
+            .collect();
+        let rows = rows.into_iter().filter(|&row| {
+            self.filter
+                .iter()
+                .all(|filter| filter.matches(value(&filter.column, row).copied()))
+        });
+
+        let Some(group_by) = &self.group_by else {
+            return rows
+                .map(|row| {
+                    let values = columns
+                        .iter()
+                        .map(|column| value(&column.name, row).map(|&value| value.clone()))
+                        .collect();
+                    (row, values)
+                })
+                .collect();
+        };
+
+        let mut groups: Vec<(&CellValue, Vec<i64>)> = vec![];
+        let mut index = HashMap::new();
+        for row in rows {
+            let Some(&key) = value(group_by, row) else {
+                continue;
+            };
+            let i = *index
+                .entry(serde_json::to_string(key).unwrap_or_default())
+                .or_insert_with(|| {
+                    groups.push((key, vec![]));
+                    groups.len() - 1
+                });
+            groups[i].1.push(row);
+        }
+        groups.sort_by(|(a, _), (b, _)| compare(a, b).unwrap_or(Ordering::Equal));
+
+        groups
+            .into_iter()
+            .zip(1..)
+            .map(|((key, rows), number)| {
+                let aggregates = self.aggregates.iter().map(|aggregate| {
+                    let values = rows.iter().filter_map(|&row| {
+                        value(aggregate.column.as_deref()?, row).and_then(|value| match value {
+                            CellValue::Int(x) => Some(*x as f64),
+                            CellValue::Double(x) => Some(*x),
+                            _ => None,
+                        })
+                    });
+                    aggregate
+                        .function
+                        .apply(aggregate.column.is_none(), rows.len(), values)
+                });
+                let values = std::iter::once(Some(key.clone()))
+                    .chain(aggregates)
+                    .collect();
+                (number, values)
+            })
+            .collect()
+    }
+}
+
+impl ViewFilter {
+    fn matches(&self, value: Option<&CellValue>) -> bool {
+        let (Some(value), Some(other)) = (value, &self.value) else {
+            return match self.op {
+                FilterOp::Empty => value.is_none(),
+                FilterOp::NotEmpty => value.is_some(),
+                _ => false,
+            };
+        };
+        let Some(ordering) = compare(value, other) else {
+            return false;
+        };
+        match self.op {
+            FilterOp::Eq => ordering.is_eq(),
+            FilterOp::Ne => ordering.is_ne(),
+            FilterOp::Lt => ordering.is_lt(),
+            FilterOp::Le => ordering.is_le(),
+            FilterOp::Gt => ordering.is_gt(),
+            FilterOp::Ge => ordering.is_ge(),
+            FilterOp::Empty => false,
+            FilterOp::NotEmpty => true,
+        }
+    }
+}
+
+impl AggregateFunction {
+    /// Aggregates the numeric values of a group of `rows` rows. Without any values, the result is empty, apart
+    /// from counts, which are 0. So is a result which overflows, since sheets can't hold infinities.
+    fn apply(
+        self,
+        count_rows: bool,
+        rows: usize,
+        values: impl Iterator<Item = f64>,
+    ) -> Option<CellValue> {
+        let values: Vec<f64> = values.collect();
+        if self == Self::Count {
+            let count = if count_rows { rows } else { values.len() };
+            return Some(CellValue::Int(count as i64));
+        }
+        if values.is_empty() {
+            return None;
+        }
+
+        let result = match self {
+            Self::Sum => values.iter().sum(),
+            Self::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Self::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Self::Count => unreachable!(),
+        };
+        result.is_finite().then_some(CellValue::Double(result))
+    }
+}
+
+/// The column of a view which holds the values of a column of its source.
+fn view_column(column: &SchemaColumn) -> SchemaColumn {
+    SchemaColumn {
+        name: column.name.clone(),
+        // the values are copied from the source, so they don't need to be assigned
+        kind: match column.kind {
+            SchemaColumnKind::Autoincrement => SchemaColumnKind::Int,
+            kind => kind,
+        },
+        hidden: false,
+        format: column.format.clone(),
+        pii: column.pii,
+        references: None,
+    }
+}
+
+/// Whether a value can be compared with the values of a column of the given kind. Ints and doubles can be
+/// compared with each other.
+fn comparable(kind: SchemaColumnKind, value: &CellValue) -> bool {
+    let numeric = |kind| matches!(kind, SchemaColumnKind::Int | SchemaColumnKind::Double);
+    let value_kind = SchemaColumnKind::from(value);
+    kind.storage() == value_kind || (numeric(kind.storage()) && numeric(value_kind))
+}
+
+fn compare(a: &CellValue, b: &CellValue) -> Option<Ordering> {
+    match (a, b) {
+        (CellValue::Boolean(a), CellValue::Boolean(b)) => Some(a.cmp(b)),
+        (CellValue::Int(a), CellValue::Int(b)) => Some(a.cmp(b)),
+        (CellValue::Int(a), CellValue::Double(b)) => (*a as f64).partial_cmp(b),
+        (CellValue::Double(a), CellValue::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (CellValue::Double(a), CellValue::Double(b)) => a.partial_cmp(b),
+        (CellValue::String(a), CellValue::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::ViewDefinition;
+    use crate::sheet::{CellValue, Schema, SheetContent, SheetContentColumn};
+
+    fn source() -> (Schema, SheetContent) {
+        let schema: Schema = serde_json::from_value(json!({"columns": [
+            {"name": "team", "type": "string"},
+            {"name": "points", "type": "int"},
+            {"name": "secret", "type": "string", "hidden": true},
+        ]}))
+        .unwrap();
+        let column = |cells: &[(i64, CellValue)]| {
+            cells
+                .iter()
+                .map(|(row, value)| SheetContentColumn {
+                    row: *row,
+                    value: Some(value.clone()),
+                    error: None,
+                })
+                .collect()
+        };
+        let string = |x: &str| CellValue::String(x.into());
+        let content = SheetContent {
+            columns: HashMap::from([
+                ("team".into(), column(&[(1, string("b")), (2, string("a")), (3, string("b"))])),
+                (
+                    "points".into(),
+                    column(&[
+                        (1, CellValue::Int(3)),
+                        (3, CellValue::Int(4)),
+                        (4, CellValue::Int(9)),
+                    ]),
+                ),
+                ("secret".into(), column(&[(2, string("x"))])),
+            ]),
+            meta: None,
+            row_keys: None,
+            version: None,
+        };
+        (schema, content)
+    }
+
+    #[test]
+    fn filtered_views_keep_rows() {
+        let (schema, content) = source();
+        let view: ViewDefinition = serde_json::from_value(json!({
+            "filter": [{"column": "points", "op": "ge", "value": 3.5}]
+        }))
+        .unwrap();
+        let columns = view.validate(&schema.columns).unwrap();
+        assert_eq!(
+            columns
+                .iter()
+                .map(|column| column.name.as_str())
+                .collect::<Vec<_>>(),
+            ["team", "points"]
+        );
+        assert_eq!(
+            view.evaluate(&columns, &content),
+            [
+                (3, vec![Some(CellValue::String("b".into())), Some(CellValue::Int(4))]),
+                (4, vec![None, Some(CellValue::Int(9))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn grouped_views_aggregate() {
+        let (schema, content) = source();
+        let view: ViewDefinition = serde_json::from_value(json!({
+            "group_by": "team",
+            "aggregates": [
+                {"name": "rows", "function": "count"},
+                {"name": "total", "function": "sum", "column": "points"},
+            ]
+        }))
+        .unwrap();
+        let columns = view.validate(&schema.columns).unwrap();
+        assert_eq!(
+            view.evaluate(&columns, &content),
+            [
+                (
+                    1,
+                    vec![
+                        Some(CellValue::String("a".into())),
+                        Some(CellValue::Int(1)),
+                        None
+                    ]
+                ),
+                (
+                    2,
+                    vec![
+                        Some(CellValue::String("b".into())),
+                        Some(CellValue::Int(2)),
+                        Some(CellValue::Double(7.0))
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_definitions_have_paths() {
+        let (schema, _) = source();
+        let view: ViewDefinition = serde_json::from_value(json!({
+            "columns": ["team"],
+            "filter": [{"column": "nope", "op": "eq", "value": 1}, {"column": "team", "op": "lt", "value": 1}],
+            "group_by": "team",
+            "aggregates": [{"name": "team", "function": "sum", "column": "team"}]
+        }))
+        .unwrap();
+        let paths: Vec<_> = view
+            .validate(&schema.columns)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.path)
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "filter[0].column",
+                "filter[1].value",
+                "columns",
+                "aggregates[0].name",
+                "aggregates[0].column"
+            ]
+        );
+    }
+}
@@ -1,113 +1,3352 @@
-use actix_web::{get, http::StatusCode, post, web, Responder};
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{
+    body::BoxBody, delete, get, http::StatusCode, post, put, web, Either, HttpRequest,
+    HttpResponse, Responder,
+};
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
 
-use crate::db::SheetId;
+use super::body::FromBody;
+use super::proto::{self, Encode};
+use crate::budget::{BudgetExceeded, Reservation};
+use crate::db::{
+    Corrupted, IdTaken, InvalidReferences, InvalidView, ReadOnlyView, RowOutOfBounds,
+    SessionNotFound, SheetId, SheetNotFound,
+};
+use crate::intercept::WriteRejected;
+use crate::notify::{Notification, Rule};
+use crate::transform::{TransformFailed, UnknownTransformer, TRANSFORM_HEADER};
+use crate::validate::{Rejection, Validator};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(post).service(post_sheetid).service(get_sheetid);
+    cfg.service(post)
+        .service(get)
+        .service(post_sheetid)
+        .service(delete_cell)
+        .service(get_sheetid)
+        .service(post_recalculate)
+        .service(post_rotate_id)
+        .service(post_archive)
+        .service(post_alias)
+        .service(get_aliases)
+        .service(delete_alias)
+        .service(get_permissions)
+        .service(put_permission)
+        .service(delete_permission)
+        .service(post_owner)
+        .service(post_notification)
+        .service(get_notifications)
+        .service(delete_notification)
+        .service(put_validator)
+        .service(get_validator)
+        .service(delete_validator)
+        .service(post_row_meta)
+        .service(get_row_meta)
+        .service(get_stats)
+        .service(get_lookup_stats)
+        .service(get_tail)
+        .service(get_column)
+        .service(get_row)
+        .service(get_rows)
+        .service(post_get_cells)
+        .service(get_grid)
+        .service(get_join)
+        .service(post_view)
+        .service(get_view)
+        .service(post_refresh)
+        .service(get_events)
+        .service(get_access_log)
+        .service(get_explain)
+        .service(get_schema)
+        .service(post_schema_check)
+        .service(get_schema_history)
+        .service(get_settings)
+        .service(put_settings)
+        .service(post_mirror)
+        .service(post_import)
+        .service(get_export)
+        .service(post_export_link)
+        .service(post_session)
+        .service(post_session_commit)
+        .service(post_session_abort)
+        .service(get_session_preview);
+}
+
+/// The read-only views of published sheets, which are served without any credentials.
+pub fn published_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_published)
+        .service(get_published_csv)
+        .service(get_published_html);
+}
+
+/// Database corruption and failing read transformers are never the client's fault, but everything else is.
+pub(crate) fn error_status(why: &anyhow::Error) -> StatusCode {
+    if why.is::<Corrupted>() || why.is::<TransformFailed>() {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else if let Some(exceeded) = why.downcast_ref::<BudgetExceeded>() {
+        match exceeded {
+            BudgetExceeded::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            BudgetExceeded::Busy => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    } else if why.is::<SheetNotFound>() || why.is::<SessionNotFound>() {
+        StatusCode::NOT_FOUND
+    } else if let Some(super::ReferenceError::Restricted { .. }) = why.downcast_ref() {
+        // the request is fine, it's the referencing sheet that's in the way
+        StatusCode::CONFLICT
+    } else {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+/// The `details` of an error response, which explain what exactly was wrong with the request.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum ErrorDetails {
+    Body(Vec<super::FieldError>),
+    Schema(Vec<super::SchemaError>),
+    Lookup(super::LookupError),
+    // boxed, since it's much larger than the others
+    Reference(Box<super::ReferenceError>),
+}
+
+/// Machine-readable error codes. Unlike the error messages, these are stable and safe to match on.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    InvalidSheetId,
+    SheetNotFound,
+    InvalidBody,
+    InvalidQuery,
+    RejectedByValidator,
+    ValidatorUnavailable,
+    SessionNotFound,
+    SheetTooLarge,
+    Busy,
+    RowOutOfBounds,
+    ReferenceViolation,
+    ReadOnlyView,
+    WriteRejected,
+    UnknownTransformer,
+}
+
+/// The body of every error response.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ErrorResponse {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<ErrorDetails>,
+}
+
+impl ErrorResponse {
+    fn new(error: impl ToString) -> Self {
+        Self {
+            error: error.to_string(),
+            code: None,
+            details: None,
+        }
+    }
+
+    fn invalid_sheet_id() -> Self {
+        Self {
+            code: Some(ErrorCode::InvalidSheetId),
+            ..Self::new("invalid sheetid")
+        }
+    }
+
+    fn invalid_query(error: impl ToString) -> Self {
+        Self {
+            code: Some(ErrorCode::InvalidQuery),
+            ..Self::new(error)
+        }
+    }
+
+    pub(crate) fn invalid_body(details: Option<Vec<super::FieldError>>) -> Self {
+        Self {
+            code: Some(ErrorCode::InvalidBody),
+            details: details.map(ErrorDetails::Body),
+            ..Self::new("invalid request body")
+        }
+    }
+
+    /// Describes an error returned by the database.
+    fn from_db(why: &anyhow::Error) -> Self {
+        Self {
+            code: if why.is::<SheetNotFound>() {
+                Some(ErrorCode::SheetNotFound)
+            } else if why.is::<SessionNotFound>() {
+                Some(ErrorCode::SessionNotFound)
+            } else if why.is::<RowOutOfBounds>() {
+                Some(ErrorCode::RowOutOfBounds)
+            } else if why.is::<super::ReferenceError>() {
+                Some(ErrorCode::ReferenceViolation)
+            } else if why.is::<ReadOnlyView>() {
+                Some(ErrorCode::ReadOnlyView)
+            } else if why.is::<WriteRejected>() {
+                Some(ErrorCode::WriteRejected)
+            } else if why.is::<UnknownTransformer>() {
+                Some(ErrorCode::UnknownTransformer)
+            } else {
+                why.downcast_ref::<BudgetExceeded>()
+                    .map(|exceeded| match exceeded {
+                        BudgetExceeded::TooLarge => ErrorCode::SheetTooLarge,
+                        BudgetExceeded::Busy => ErrorCode::Busy,
+                    })
+            },
+            details: why
+                .downcast_ref::<super::LookupError>()
+                .cloned()
+                .map(ErrorDetails::Lookup)
+                .or_else(|| {
+                    why.downcast_ref::<super::ReferenceError>()
+                        .cloned()
+                        .map(|error| ErrorDetails::Reference(Box::new(error)))
+                }),
+            ..Self::new(why)
+        }
+    }
 }
 
+/// Every handler's response is either some kind of success, or an [`ErrorResponse`].
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(untagged)]
-enum PostResponse {
-    Success { sheet_id: String },
+enum Response<T> {
+    Success(T),
+    Failure(ErrorResponse),
+}
+
+/// A handler's response, which is sent as JSON unless the handler supports protobuf and the client asked for it.
+pub(crate) struct Responded<T> {
+    status: StatusCode,
+    body: Response<T>,
+    /// Encodes a successful response as protobuf, see [`Self::negotiated`].
+    protobuf: Option<fn(&T) -> Vec<u8>>,
+}
+
+impl<T: Encode> Responded<T> {
+    /// Allows sending the response as protobuf. Errors are always sent as JSON.
+    fn negotiated(self) -> Self {
+        Self {
+            protobuf: Some(T::encode_protobuf),
+            ..self
+        }
+    }
+}
+
+impl<T: Serialize> Responder for Responded<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        if let (Some(encode), Response::Success(value)) = (self.protobuf, &self.body) {
+            if proto::accepts_protobuf(req) {
+                return HttpResponse::build(self.status)
+                    .content_type(proto::CONTENT_TYPE)
+                    .body(encode(value));
+            }
+        }
+
+        web::Json(self.body)
+            .customize()
+            .with_status(self.status)
+            .respond_to(req)
+            .map_into_boxed_body()
+    }
+}
+
+pub(crate) fn success<T: Serialize>(value: T) -> Responded<T> {
+    Responded {
+        status: StatusCode::OK,
+        body: Response::Success(value),
+        protobuf: None,
+    }
+}
+
+pub(crate) fn failure<T: Serialize>(status: StatusCode, error: ErrorResponse) -> Responded<T> {
+    Responded {
+        status,
+        body: Response::Failure(error),
+        protobuf: None,
+    }
+}
+
+/// Parses a request body, pointing out every field that's wrong with it.
+fn parse_body<T: FromBody>(body: Option<web::Json<serde_json::Value>>) -> Result<T, ErrorResponse> {
+    let Some(body) = body else {
+        return Err(ErrorResponse::invalid_body(None));
+    };
+
+    T::from_body(body.into_inner()).map_err(|errors| ErrorResponse::invalid_body(Some(errors)))
+}
 
-    Failure { error: String },
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PostResponse {
+    sheet_id: String,
+}
+
+impl Encode for PostResponse {
+    fn encode_protobuf(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(&proto::PostResponse {
+            sheet_id: self.sheet_id.clone(),
+        })
+    }
 }
 
 #[post("")]
 async fn post(
     data: web::Data<crate::AppData>,
-    schema: Option<web::Json<super::Schema>>,
-) -> impl Responder {
-    if let Some(schema) = schema {
-        match data.db.new_sheet(&schema).await {
-            Ok(sheet_id) => {
-                return web::Json(PostResponse::Success {
-                    sheet_id: sheet_id.inner().into(),
-                })
-                .customize()
+    body: Option<proto::Body<proto::NewSheet>>,
+    claims: Option<web::ReqData<crate::jwt::Claims>>,
+) -> Responded<PostResponse> {
+    let new_sheet = match parse_body(body.map(|body| web::Json(body.0))) {
+        Ok(new_sheet) => new_sheet,
+        Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+    };
+
+    match create_sheet(&data, new_sheet, &[]).await {
+        Ok(sheet_id) => {
+            // whoever creates a sheet owns it
+            if let Some(sub) = claims.as_ref().and_then(|claims| claims.sub.as_deref()) {
+                let owner = Some(super::Role::Owner);
+                if let Err(why) = data.db.set_permission(&sheet_id, sub, owner).await {
+                    log::warn!("failed to make {sub} the owner of {}: {why}", sheet_id.inner());
+                }
+            }
+            success(PostResponse {
+                sheet_id: sheet_id.inner().into(),
+            })
+            .negotiated()
+        }
+        Err((status, error)) => failure(status, error),
+    }
+}
+
+/// Creates a sheet as asked for by a creation request, and fills it with `cells` in the same transaction. This is
+/// shared by `POST /sheet` and the seed endpoint of [`crate::fixtures`], so that both check new sheets the same way.
+pub(crate) async fn create_sheet(
+    data: &crate::AppData,
+    new_sheet: super::NewSheet,
+    cells: &[super::Cell],
+) -> Result<SheetId, (StatusCode, ErrorResponse)> {
+    let super::NewSheet {
+        mut schema,
+        id,
+        no_lookup_nulls,
+    } = new_sheet;
+    let invalid_schema = |errors| ErrorResponse {
+        details: Some(ErrorDetails::Schema(errors)),
+        ..ErrorResponse::new("invalid schema")
+    };
+
+    let custom_id = match id.as_deref().map(SheetId::from_slug).transpose() {
+        Ok(custom_id) => custom_id,
+        Err(why) => return Err((StatusCode::BAD_REQUEST, ErrorResponse::new(why))),
+    };
+
+    schema.normalize();
+    if let Err(errors) = schema.validate(data.config.case_insensitive_columns) {
+        return Err((StatusCode::BAD_REQUEST, invalid_schema(errors)));
+    }
+
+    let result = data
+        .db
+        .new_sheet_with_cells(
+            &schema,
+            data.config.id_scheme,
+            custom_id,
+            no_lookup_nulls,
+            cells,
+            data.config.strict_lookups,
+        )
+        .await;
+    match result {
+        Ok(sheet_id) => Ok(sheet_id),
+        Err(why) if why.is::<IdTaken>() => {
+            Err((StatusCode::CONFLICT, ErrorResponse::from_db(&why)))
+        }
+        Err(why) => match why.downcast::<InvalidReferences>() {
+            Ok(InvalidReferences(errors)) => Err((StatusCode::BAD_REQUEST, invalid_schema(errors))),
+            // the message includes the offending cell, which is added as context
+            Err(why) if !cells.is_empty() && error_status(&why) == StatusCode::BAD_REQUEST => {
+                Err((
+                    StatusCode::BAD_REQUEST,
+                    ErrorResponse {
+                        error: format!("{why:#}"),
+                        ..ErrorResponse::from_db(&why)
+                    },
+                ))
             }
             Err(why) => {
                 log::warn!("error when servicing post: {why}");
+                Err((error_status(&why), ErrorResponse::new("invalid schema")))
             }
-        }
+        },
     }
+}
 
-    web::Json(PostResponse::Failure {
-        error: "invalid schema".into(),
-    })
-    .customize()
-    .with_status(StatusCode::BAD_REQUEST)
+#[derive(Deserialize, Clone, Debug, Default)]
+struct ListQuery {
+    #[serde(default)]
+    sort: super::SheetSort,
+    #[serde(default)]
+    order: SortOrder,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(untagged)]
-enum PostSheetIdResponse {
-    Success {},
+struct ListResponse {
+    sheets: Vec<super::SheetSummary>,
+}
+
+#[get("")]
+async fn get(
+    data: web::Data<crate::AppData>,
+    query: Result<web::Query<ListQuery>, actix_web::Error>,
+) -> Responded<ListResponse> {
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    match data
+        .db
+        .list_sheets(query.sort, query.order == SortOrder::Desc)
+        .await
+    {
+        Ok(sheets) => success(ListResponse { sheets }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PostSheetIdResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    row_key: Option<String>,
+    /// Whether the write was only staged in a session, rather than applied.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    staged: bool,
+}
+
+impl Encode for PostSheetIdResponse {
+    fn encode_protobuf(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(&proto::PostSheetIdResponse {
+            row_key: self.row_key.clone(),
+            staged: self.staged,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct PostSheetIdQuery {
+    /// Stages the write in this session instead of applying it.
+    session: Option<String>,
+    #[serde(flatten)]
+    cell: CellParams,
+}
+
+/// A cell given as form fields or query parameters, for clients which can't easily build JSON. Since every
+/// parameter is a string, the type of the value is given by `type`, or guessed if there isn't one.
+#[derive(Deserialize, Clone, Debug, Default)]
+struct CellParams {
+    #[serde(rename = "ref")]
+    reference: Option<String>,
+    column: Option<String>,
+    row: Option<String>,
+    row_key: Option<String>,
+    value: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+impl CellParams {
+    fn is_empty(&self) -> bool {
+        self.reference.is_none()
+            && self.column.is_none()
+            && self.row.is_none()
+            && self.row_key.is_none()
+            && self.value.is_none()
+            && self.kind.is_none()
+    }
+
+    /// Converts the parameters into the JSON body they stand for, so that they're parsed the same way.
+    fn into_body(self) -> Result<serde_json::Value, Vec<super::FieldError>> {
+        use serde_json::Value;
+
+        let mut errors = vec![];
+        let mut error = |path: &str, message: &str| {
+            errors.push(super::FieldError {
+                path: path.into(),
+                message: message.into(),
+            })
+        };
+
+        let mut body = serde_json::Map::new();
+        if let Some(reference) = self.reference {
+            body.insert("ref".into(), reference.into());
+        }
+        if let Some(column) = self.column {
+            body.insert("column".into(), column.into());
+        }
+        if let Some(row_key) = self.row_key {
+            body.insert("row_key".into(), row_key.into());
+        }
+        if let Some(row) = self.row {
+            match row.parse::<i64>() {
+                Ok(row) => {
+                    body.insert("row".into(), row.into());
+                }
+                Err(_) => error("row", "expected an integer"),
+            }
+        }
+
+        let kind = match self.kind {
+            Some(kind) => match serde_json::from_value(Value::String(kind)) {
+                Ok(kind) => Some(kind),
+                Err(why) => {
+                    error("type", &why.to_string());
+                    None
+                }
+            },
+            None => None,
+        };
+        let double = |value: &str| {
+            value
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+        };
+        if let Some(value) = self.value {
+            let parsed = match kind {
+                Some(super::SchemaColumnKind::Boolean) => {
+                    value.parse::<bool>().ok().map(Value::Bool)
+                }
+                Some(super::SchemaColumnKind::Int | super::SchemaColumnKind::Autoincrement) => {
+                    value.parse::<i64>().ok().map(Value::from)
+                }
+                Some(super::SchemaColumnKind::Double) => double(&value),
+                Some(
+                    super::SchemaColumnKind::String
+                    | super::SchemaColumnKind::Url
+                    | super::SchemaColumnKind::Email,
+                ) => Some(Value::String(value.clone())),
+                None => Some(
+                    value
+                        .parse::<bool>()
+                        .map(Value::Bool)
+                        .or_else(|_| value.parse::<i64>().map(Value::from))
+                        .ok()
+                        .or_else(|| double(&value))
+                        .unwrap_or_else(|| Value::String(value.clone())),
+                ),
+            };
+            match parsed {
+                Some(parsed) => {
+                    body.insert("value".into(), parsed);
+                }
+                None => error("value", "doesn't match the given type"),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Value::Object(body))
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Cells of sheets with the `a1_refs` setting may be addressed in A1 notation (see [`super::a1`]), and lookups
+/// written that way, in which case they're replaced by the names of the columns before the body is parsed any
+/// further.
+async fn resolve_a1(
+    data: &crate::AppData,
+    sheetid: &SheetId,
+    body: &mut Option<serde_json::Value>,
+) -> Result<(), (StatusCode, ErrorResponse)> {
+    let Some(object) = body.as_mut().and_then(|body| body.as_object_mut()) else {
+        return Ok(());
+    };
+    let reference = object.remove("ref");
+    let lookup = object
+        .get("value")
+        .and_then(|value| value.as_str())
+        .is_some_and(|value| super::a1::lookup_address(value).is_some());
+    if reference.is_none() && !lookup {
+        return Ok(());
+    }
+
+    let field_error = |path: &str, message: &str| {
+        let error = super::FieldError {
+            path: path.into(),
+            message: message.into(),
+        };
+        (StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(Some(vec![error])))
+    };
+    match data.db.get_settings(sheetid).await {
+        Ok(settings) if settings.a1_refs => {}
+        // strings which look like lookups in A1 notation are just strings for sheets which didn't opt in
+        Ok(_) if reference.is_none() => return Ok(()),
+        Ok(_) => return Err(field_error("ref", "A1 refs aren't enabled for this sheet")),
+        Err(why) => return Err((error_status(&why), ErrorResponse::from_db(&why))),
+    }
+    let columns = match data.db.schema(sheetid).await {
+        Ok(schema) => schema.columns,
+        Err(why) => return Err((error_status(&why), ErrorResponse::from_db(&why))),
+    };
+
+    if let Some(reference) = reference {
+        let Some(reference) = reference.as_str() else {
+            return Err(field_error("ref", "expected a string"));
+        };
+        if ["column", "row", "row_key"]
+            .iter()
+            .any(|field| object.contains_key(*field))
+        {
+            return Err(field_error("ref", "ref can't be given along with column, row or row_key"));
+        }
+        let a1: super::a1::A1 = reference
+            .parse()
+            .map_err(|why: String| field_error("ref", &why))?;
+        let Some(column) = a1.column_name(&columns) else {
+            return Err(field_error("ref", "the sheet doesn't have this many columns"));
+        };
+        object.insert("column".into(), column.into());
+        object.insert("row".into(), a1.row.into());
+    }
+
+    if let Some(serde_json::Value::String(value)) = object.get_mut("value") {
+        match super::a1::resolve_lookup(value, &columns) {
+            Some(Ok(lookup)) => *value = lookup,
+            Some(Err(why)) => return Err(field_error("value", &why)),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Cells may address their row by its key instead of its number, in which case the key is replaced by the
+/// number before the body is parsed any further.
+async fn resolve_row_key(
+    data: &crate::AppData,
+    sheetid: &SheetId,
+    body: &mut Option<serde_json::Value>,
+) -> Result<(), (StatusCode, ErrorResponse)> {
+    let Some(object) = body.as_mut().and_then(|body| body.as_object_mut()) else {
+        return Ok(());
+    };
+    let Some(key) = object.remove("row_key") else {
+        return Ok(());
+    };
+
+    let field_error = |message: &str| {
+        let error = super::FieldError {
+            path: "row_key".into(),
+            message: message.into(),
+        };
+        (StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(Some(vec![error])))
+    };
+    let Some(key) = key.as_str() else {
+        return Err(field_error("expected a string"));
+    };
+    if object.contains_key("row") {
+        return Err(field_error("only one of row and row_key may be given"));
+    }
 
-    Failure { error: String },
+    match data.db.row_by_key(sheetid, key).await {
+        Ok(Some(row)) => {
+            object.insert("row".into(), row.into());
+            Ok(())
+        }
+        Ok(None) => Err(field_error("no row has this key")),
+        Err(why) => Err((error_status(&why), ErrorResponse::from_db(&why))),
+    }
 }
 
 #[post("/{sheetid}")]
 async fn post_sheetid(
     data: web::Data<crate::AppData>,
     sheetid: Option<web::Path<SheetId>>,
-    cell: Option<web::Json<super::Cell>>,
-) -> impl Responder {
+    query: Result<web::Query<PostSheetIdQuery>, actix_web::Error>,
+    body: Option<Either<proto::Body<proto::Cell>, web::Form<CellParams>>>,
+) -> Responded<PostSheetIdResponse> {
     let Some(sheetid) = sheetid else {
-        return web::Json(PostSheetIdResponse::Failure {
-            error: "invalid sheetid".into(),
-        })
-        .customize()
-        .with_status(StatusCode::BAD_REQUEST);
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
     };
 
-    let Some(cell) = cell else {
-        return web::Json(PostSheetIdResponse::Failure {
-            error: "invalid request body".into(),
-        })
-        .customize()
-        .with_status(StatusCode::BAD_REQUEST);
+    // the cell may also be given as a form, or as query parameters if there's no body at all
+    let params = match body {
+        Some(Either::Left(body)) => Ok(Some(body.0)),
+        Some(Either::Right(form)) => form.into_inner().into_body().map(Some),
+        None if !query.cell.is_empty() => query.cell.into_body().map(Some),
+        None => Ok(None),
+    };
+    let mut body = match params {
+        Ok(body) => body,
+        Err(errors) => {
+            return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(Some(errors)))
+        }
     };
 
-    match data.db.insert_cell(&sheetid, &cell).await {
-        Ok(()) => web::Json(PostSheetIdResponse::Success {}).customize(),
-        Err(why) => web::Json(PostSheetIdResponse::Failure {
-            error: why.to_string(),
-        })
-        .customize()
-        .with_status(StatusCode::BAD_REQUEST),
+    if let Err((status, error)) = resolve_a1(&data, &sheetid, &mut body).await {
+        return failure(status, error);
+    }
+    if let Err((status, error)) = resolve_row_key(&data, &sheetid, &mut body).await {
+        return failure(status, error);
+    }
+
+    // a null value sets the cell to null, rather than leaving it unwritten. validators are only asked about
+    // values, and tombstones can't be staged.
+    if body
+        .as_ref()
+        .is_some_and(|body| body.get("value").is_some_and(serde_json::Value::is_null))
+    {
+        if query.session.is_some() {
+            return failure(
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::invalid_query("null values can't be written in a session"),
+            );
+        }
+        let tombstone = match parse_body::<super::Tombstone>(body.map(web::Json)) {
+            Ok(tombstone) => tombstone,
+            Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+        };
+        return match data
+            .db
+            .insert_tombstone(&sheetid, &tombstone.column, tombstone.row)
+            .await
+        {
+            Ok(()) => success(PostSheetIdResponse {
+                row_key: None,
+                staged: false,
+            })
+            .negotiated(),
+            Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    }
+
+    let cell = match parse_body::<super::Cell>(body.map(web::Json)) {
+        Ok(cell) => cell,
+        Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+    };
+
+    // the validator is asked before anything is written, so that no transaction is held open meanwhile
+    if let Err((status, error)) =
+        check_validator(&data, &sheetid, std::slice::from_ref(&cell), None).await
+    {
+        return failure(status, error);
+    }
+
+    if let Some(session) = query.session {
+        return match data.db.stage_cell(&sheetid, &session, &cell).await {
+            Ok(()) => success(PostSheetIdResponse {
+                row_key: None,
+                staged: true,
+            })
+            .negotiated(),
+            Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    }
+
+    match data
+        .db
+        .insert_cell(&sheetid, &cell, data.config.strict_lookups)
+        .await
+    {
+        Ok(row_key) => {
+            notify_matching_rules(&data, &sheetid, std::slice::from_ref(&cell)).await;
+            success(PostSheetIdResponse {
+                row_key,
+                staged: false,
+            })
+            .negotiated()
+        }
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// Asks the sheet's validator, if it has one, about every cell that's about to be written, and stops at the first
+/// one it doesn't accept. Every handler that writes cells goes through this before writing anything.
+///
+/// If a cell is declined, its `context` (e.g. `rows[5].B`) is added to the error, if there is one.
+async fn check_validator(
+    data: &crate::AppData,
+    sheetid: &SheetId,
+    cells: &[super::Cell],
+    context: Option<fn(&super::Cell) -> String>,
+) -> Result<(), (StatusCode, ErrorResponse)> {
+    let validator = match data.db.get_validator(sheetid).await {
+        Ok(Some(validator)) => validator,
+        Ok(None) => return Ok(()),
+        Err(why) => return Err((error_status(&why), ErrorResponse::from_db(&why))),
+    };
+
+    for cell in cells {
+        let Err(rejection) = validator.check(&data.http, sheetid.inner(), cell).await else {
+            continue;
+        };
+        let (status, code) = match rejection {
+            Rejection::Declined { .. } => (StatusCode::BAD_REQUEST, ErrorCode::RejectedByValidator),
+            Rejection::Unavailable(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, ErrorCode::ValidatorUnavailable)
+            }
+        };
+        let error = match context {
+            Some(context) => format!("{}: {rejection}", context(cell)),
+            None => rejection.to_string(),
+        };
+        return Err((
+            status,
+            ErrorResponse {
+                code: Some(code),
+                ..ErrorResponse::new(error)
+            },
+        ));
+    }
+    Ok(())
+}
+
+/// Queues a notification for every rule that matches one of the freshly written cells. Only plain values are
+/// checked - cells which merely change because of a lookup don't trigger anything.
+async fn notify_matching_rules(data: &crate::AppData, sheetid: &SheetId, cells: &[super::Cell]) {
+    if cells.iter().all(|cell| cell.value.is_lookup().is_some()) {
+        return;
+    }
+
+    let rules = match data.db.notification_rules(sheetid).await {
+        Ok(rules) => rules,
+        Err(why) => {
+            log::warn!("failed to get notification rules for {}: {why}", sheetid.inner());
+            return;
+        }
+    };
+
+    if rules.is_empty() {
+        return;
+    }
+    for cell in cells.iter().filter(|cell| cell.value.is_lookup().is_none()) {
+        let column: String = cell.column.nfc().collect();
+        let value = cell.value.unescaped();
+        for (_, rule) in &rules {
+            if rule.column == column && rule.condition.matches(&value) {
+                data.notifications
+                    .send(Notification::new(sheetid.inner(), cell.row, &value, rule));
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct DeleteCellQuery {
+    column: String,
+    row: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct DeleteCellResponse {}
+
+/// Empties a cell, as if it had never been written. Unlike writing null, this leaves no tombstone behind.
+#[delete("/{sheetid}/cell")]
+async fn delete_cell(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<DeleteCellQuery>, actix_web::Error>,
+) -> Responded<DeleteCellResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    match data.db.clear_cell(&sheetid, &query.column, query.row).await {
+        Ok(()) => success(DeleteCellResponse {}),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// Query flags are enabled by being present, e.g. `?include_hidden` or `?include_hidden=1`, unless they're
+/// explicitly set to `0` or `false`.
+fn query_flag(value: Option<&str>) -> bool {
+    value.is_some_and(|value| !matches!(value, "0" | "false"))
+}
+
+/// Whether lookups which resolve to nothing are left out of a read of a sheet: the `no_lookup_nulls` query
+/// parameter if it's given, otherwise the sheet's own setting, otherwise the server-wide `NO_LOOKUP_NULLS`.
+async fn no_lookup_nulls(
+    data: &crate::AppData,
+    sheetid: &SheetId,
+    query: Option<&str>,
+) -> anyhow::Result<bool> {
+    match query {
+        Some(value) => Ok(query_flag(Some(value))),
+        None => Ok(data
+            .db
+            .no_lookup_nulls(sheetid)
+            .await?
+            .unwrap_or(data.config.no_lookup_nulls)),
+    }
+}
+
+/// Builds the options of a whole-sheet read from the `include_hidden` and `include` query parameters.
+fn read_options(
+    no_lookup_nulls: bool,
+    include_hidden: Option<&str>,
+    include: Option<&str>,
+) -> Result<super::ReadOptions, ErrorResponse> {
+    let (mut include_meta, mut include_row_keys, mut include_version) = (false, false, false);
+    for extra in include.iter().flat_map(|include| include.split(',')) {
+        match extra {
+            "meta" => include_meta = true,
+            "row_keys" => include_row_keys = true,
+            "version" => include_version = true,
+            _ => {
+                return Err(ErrorResponse::invalid_query(format_args!(
+                    "unknown include: {extra:?}"
+                )))
+            }
+        }
     }
+
+    Ok(super::ReadOptions {
+        no_lookup_nulls,
+        include_hidden: query_flag(include_hidden),
+        include_meta,
+        include_row_keys,
+        include_version,
+    })
 }
 
+#[derive(Deserialize, Clone, Debug, Default)]
+struct GetSheetIdQuery {
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+    /// A comma-separated list of extra data to include, e.g. `?include=meta`.
+    include: Option<String>,
+    /// Giving either of these reads a single page of rows instead of the whole sheet.
+    after_row: Option<i64>,
+    limit: Option<i64>,
+    /// Only reads the cells which changed after this version.
+    since_version: Option<i64>,
+    /// Orders the rows by the values of this column.
+    order_by: Option<String>,
+    #[serde(default)]
+    direction: SortOrder,
+}
+
+/// The number of rows in a page when no limit is given.
+const DEFAULT_PAGE_ROWS: i64 = 100;
+/// The most rows that can be read at once without reading the whole sheet.
+const MAX_PAGE_ROWS: i64 = 1000;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(untagged)]
 enum GetSheetIdResponse {
-    Success(super::SheetContent),
-    Failure { error: String },
+    Page {
+        #[serde(flatten)]
+        content: super::SheetContent,
+        next_cursor: Option<i64>,
+    },
+    Full(super::SheetContent),
+}
+
+impl Encode for GetSheetIdResponse {
+    fn encode_protobuf(&self) -> Vec<u8> {
+        let message = match self {
+            Self::Page {
+                content,
+                next_cursor,
+            } => proto::SheetContent {
+                next_cursor: *next_cursor,
+                ..content.into()
+            },
+            Self::Full(content) => content.into(),
+        };
+        prost::Message::encode_to_vec(&message)
+    }
 }
 
+/// Reads a sheet. A sheet which doesn't exist is a 404 (`sheet_not_found`), while an existing one always has every
+/// one of its (visible) columns in the response, with an empty list for columns without cells. That way clients
+/// can tell a missing sheet from an empty one, and an empty column from one that doesn't exist.
 #[get("/{sheetid}")]
 async fn get_sheetid(
     data: web::Data<crate::AppData>,
+    req: HttpRequest,
     sheetid: Option<web::Path<SheetId>>,
-) -> impl Responder {
+    query: Result<web::Query<GetSheetIdQuery>, actix_web::Error>,
+) -> Responded<GetSheetIdResponse> {
     let Some(sheetid) = sheetid else {
-        return web::Json(GetSheetIdResponse::Failure {
-            error: "invalid sheetid".into(),
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    let no_lookup_nulls =
+        match no_lookup_nulls(&data, &sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    let options = match read_options(
+        no_lookup_nulls,
+        query.include_hidden.as_deref(),
+        query.include.as_deref(),
+    ) {
+        Ok(options) => options,
+        Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+    };
+
+    let paged = query.after_row.is_some() || query.limit.is_some();
+    if query.order_by.is_some() && (paged || query.since_version.is_some()) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query("order_by can't be combined with paging or since_version"),
+        );
+    }
+
+    if let Some(since_version) = query.since_version {
+        if paged {
+            return failure(
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::invalid_query("since_version can't be combined with paging"),
+            );
+        }
+        if options.include_meta || options.include_row_keys {
+            return failure(
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::invalid_query("since_version can only include the version"),
+            );
+        }
+        let content = data.db.get_changes(&sheetid, since_version, options).await;
+        return match content.and_then(|mut content| {
+            transform(&data, &req, &sheetid, &mut content).map(|()| content)
+        }) {
+            Ok(content) => success(GetSheetIdResponse::Full(content)).negotiated(),
+            Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    }
+
+    if !paged {
+        // the memory stays reserved until the content has been sorted and handed over to be sent
+        let budget = data.read_budget.reserve();
+        let mut content = match data
+            .db
+            .get_sheet_within(&sheetid, options, Some(&budget))
+            .await
+        {
+            Ok(content) => content,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+        // sorting happens after the lookups are resolved, so it sees the same values as the client
+        if let Some(column) = query.order_by {
+            let column: String = column.nfc().collect();
+            if !content.sort_rows_by(&column, query.direction == SortOrder::Desc) {
+                return failure(
+                    StatusCode::BAD_REQUEST,
+                    ErrorResponse::invalid_query(format_args!(
+                        "unknown column to order by: {column:?}"
+                    )),
+                );
+            }
+        }
+        if let Err(why) = transform(&data, &req, &sheetid, &mut content) {
+            return failure(error_status(&why), ErrorResponse::from_db(&why));
+        }
+        return success(GetSheetIdResponse::Full(content)).negotiated();
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_ROWS);
+    if !(1..=MAX_PAGE_ROWS).contains(&limit) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!(
+                "limit must be between 1 and {MAX_PAGE_ROWS}"
+            )),
+        );
+    }
+    let page = data
+        .db
+        .page(&sheetid, query.after_row, limit, options)
+        .await;
+    match page.and_then(|(mut content, next_cursor)| {
+        transform(&data, &req, &sheetid, &mut content).map(|()| (content, next_cursor))
+    }) {
+        Ok((content, next_cursor)) => success(GetSheetIdResponse::Page {
+            content,
+            next_cursor,
         })
-        .customize()
-        .with_status(StatusCode::BAD_REQUEST);
+        .negotiated(),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[post("/{sheetid}/recalculate")]
+async fn post_recalculate(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<super::RecalculationReport> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.recalculate(&sheetid).await {
+        Ok(report) => success(report),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct RotateIdQuery {
+    redirect: Option<String>,
+}
+
+/// Moves a sheet to a fresh id, for when its URL has leaked. The old id stops working, unless `redirect` is set,
+/// in which case requests for it are redirected to the new one.
+#[post("/{sheetid}/rotate-id")]
+async fn post_rotate_id(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<RotateIdQuery>, actix_web::Error>,
+) -> Responded<PostResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(&why)),
     };
 
-    match data.db.get_sheet(&sheetid, data.no_lookup_nulls).await {
-        Ok(content) => web::Json(GetSheetIdResponse::Success(content)).customize(),
-        Err(why) => web::Json(GetSheetIdResponse::Failure {
-            error: why.to_string(),
+    let keep_redirect = query_flag(query.redirect.as_deref());
+    match data
+        .db
+        .rotate_id(&sheetid, data.config.id_scheme, keep_redirect)
+        .await
+    {
+        Ok(sheet_id) => success(PostResponse {
+            sheet_id: sheet_id.inner().into(),
         })
-        .customize()
-        .with_status(StatusCode::BAD_REQUEST),
+        .negotiated(),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// Moves a sheet to cold storage, from where it can be rehydrated through `POST /admin/archives/:id/rehydrate`.
+#[post("/{sheetid}/archive")]
+async fn post_archive(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<crate::archive::ArchivedSheet> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.archive(&sheetid).await {
+        Ok(archived) => success(archived),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[post("/{sheetid}/aliases")]
+async fn post_alias(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    alias: Option<web::Json<super::SheetAlias>>,
+) -> Responded<super::SheetAlias> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(alias) = alias else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+    let alias = alias.into_inner();
+    let id = match SheetId::try_from(alias.id.as_str()) {
+        Ok(id) => id,
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::new(why)),
+    };
+
+    match data.db.add_alias(&sheetid, &id, alias.mode).await {
+        Ok(()) => success(alias),
+        Err(why) if why.is::<IdTaken>() => {
+            failure(StatusCode::CONFLICT, ErrorResponse::from_db(&why))
+        }
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct GetAliasesResponse {
+    aliases: Vec<super::SheetAlias>,
+}
+
+#[get("/{sheetid}/aliases")]
+async fn get_aliases(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<GetAliasesResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.aliases(&sheetid).await {
+        Ok(aliases) => success(GetAliasesResponse { aliases }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct DeleteAliasResponse {}
+
+#[delete("/{sheetid}/aliases/{alias}")]
+async fn delete_alias(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, String)>>,
+) -> Responded<DeleteAliasResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let (sheetid, alias) = path.into_inner();
+
+    match data.db.delete_alias(&sheetid, &alias).await {
+        Ok(true) => success(DeleteAliasResponse {}),
+        Ok(false) => failure(StatusCode::NOT_FOUND, ErrorResponse::new("no such alias")),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct GetPermissionsResponse {
+    permissions: Vec<super::Permission>,
+}
+
+#[get("/{sheetid}/permissions")]
+async fn get_permissions(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<GetPermissionsResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.permissions(&sheetid).await {
+        Ok(permissions) => success(GetPermissionsResponse { permissions }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct PermissionRequest {
+    role: super::Role,
+}
+
+#[put("/{sheetid}/permissions/{principal}")]
+async fn put_permission(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, String)>>,
+    body: Option<web::Json<PermissionRequest>>,
+) -> Responded<super::Permission> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(body) = body else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+    let (sheetid, principal) = path.into_inner();
+
+    match data
+        .db
+        .set_permission(&sheetid, &principal, Some(body.role))
+        .await
+    {
+        Ok(_) => success(super::Permission {
+            principal,
+            role: body.role,
+        }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct DeletePermissionResponse {}
+
+#[delete("/{sheetid}/permissions/{principal}")]
+async fn delete_permission(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, String)>>,
+) -> Responded<DeletePermissionResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let (sheetid, principal) = path.into_inner();
+
+    match data.db.set_permission(&sheetid, &principal, None).await {
+        Ok(true) => success(DeletePermissionResponse {}),
+        Ok(false) => failure(StatusCode::NOT_FOUND, ErrorResponse::new("no such permission")),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct OwnerRequest {
+    principal: String,
+}
+
+#[post("/{sheetid}/owner")]
+async fn post_owner(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    body: Option<web::Json<OwnerRequest>>,
+    claims: Option<web::ReqData<crate::jwt::Claims>>,
+) -> Responded<GetPermissionsResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(body) = body else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+
+    // handing a sheet to nobody would leave it without an owner, and handing it to oneself would demote the
+    // other owners without anyone meaning to
+    let caller = claims.as_ref().and_then(|claims| claims.sub.as_deref());
+    let message = if body.principal.trim().is_empty() {
+        Some("the new owner can't be empty")
+    } else if caller == Some(body.principal.as_str()) {
+        Some("the new owner can't be the caller")
+    } else {
+        None
+    };
+    if let Some(message) = message {
+        let error = super::FieldError {
+            path: "principal".into(),
+            message: message.into(),
+        };
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(Some(vec![error])));
+    }
+
+    if let Err(why) = data.db.transfer_ownership(&sheetid, &body.principal).await {
+        return failure(error_status(&why), ErrorResponse::from_db(&why));
+    }
+    match data.db.permissions(&sheetid).await {
+        Ok(permissions) => success(GetPermissionsResponse { permissions }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PostNotificationResponse {
+    id: i64,
+}
+
+#[post("/{sheetid}/notifications")]
+async fn post_notification(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    rule: Option<web::Json<Rule>>,
+) -> Responded<PostNotificationResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(rule) = rule else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+
+    match data.db.add_notification_rule(&sheetid, &rule).await {
+        Ok(id) => success(PostNotificationResponse { id }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct NotificationRule {
+    id: i64,
+    #[serde(flatten)]
+    rule: Rule,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct GetNotificationsResponse {
+    rules: Vec<NotificationRule>,
+}
+
+#[get("/{sheetid}/notifications")]
+async fn get_notifications(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<GetNotificationsResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.notification_rules(&sheetid).await {
+        Ok(rules) => success(GetNotificationsResponse {
+            rules: rules
+                .into_iter()
+                .map(|(id, rule)| NotificationRule { id, rule })
+                .collect(),
+        }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct DeleteNotificationResponse {}
+
+#[delete("/{sheetid}/notifications/{id}")]
+async fn delete_notification(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, i64)>>,
+) -> Responded<DeleteNotificationResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let (sheetid, id) = path.into_inner();
+
+    match data.db.delete_notification_rule(&sheetid, id).await {
+        Ok(true) => success(DeleteNotificationResponse {}),
+        Ok(false) => {
+            failure(StatusCode::NOT_FOUND, ErrorResponse::new("no such notification rule"))
+        }
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ValidatorResponse {
+    validator: Option<Validator>,
+}
+
+#[put("/{sheetid}/validator")]
+async fn put_validator(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    validator: Option<web::Json<Validator>>,
+) -> Responded<ValidatorResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(validator) = validator else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+
+    let validator = validator.into_inner();
+    if !validator.url.starts_with("http://") && !validator.url.starts_with("https://") {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::new("validator url must be http or https"),
+        );
+    }
+    if validator.timeout_ms == 0 || validator.timeout_ms > crate::validate::MAX_TIMEOUT_MS {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::new(format_args!(
+                "validator timeout must be between 1 and {} ms",
+                crate::validate::MAX_TIMEOUT_MS
+            )),
+        );
+    }
+
+    match data.db.set_validator(&sheetid, Some(&validator)).await {
+        Ok(()) => success(ValidatorResponse {
+            validator: Some(validator),
+        }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[get("/{sheetid}/validator")]
+async fn get_validator(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<ValidatorResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.get_validator(&sheetid).await {
+        Ok(validator) => success(ValidatorResponse { validator }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[delete("/{sheetid}/validator")]
+async fn delete_validator(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<ValidatorResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.set_validator(&sheetid, None).await {
+        Ok(()) => success(ValidatorResponse { validator: None }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RowMetaResponse {
+    row: i64,
+    meta: super::RowMeta,
+}
+
+#[post("/{sheetid}/row/{row}/meta")]
+async fn post_row_meta(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, i64)>>,
+    changes: Option<web::Json<super::RowMeta>>,
+) -> Responded<RowMetaResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(changes) = changes else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+    let (sheetid, row) = path.into_inner();
+
+    match data.db.update_row_meta(&sheetid, row, &changes).await {
+        Ok(meta) => success(RowMetaResponse { row, meta }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[get("/{sheetid}/row/{row}/meta")]
+async fn get_row_meta(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, i64)>>,
+) -> Responded<RowMetaResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let (sheetid, row) = path.into_inner();
+
+    match data.db.row_meta(&sheetid, row).await {
+        Ok(meta) => success(RowMetaResponse { row, meta }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct StatsResponse {
+    columns: HashMap<String, super::ColumnStats>,
+}
+
+#[get("/{sheetid}/stats")]
+async fn get_stats(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: web::Query<GetSheetIdQuery>,
+) -> Responded<StatsResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    let include_hidden = query_flag(query.include_hidden.as_deref());
+    match data.db.column_stats(&sheetid, include_hidden).await {
+        Ok(columns) => success(StatsResponse { columns }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[get("/{sheetid}/stats/lookups")]
+async fn get_lookup_stats(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<super::lookup_stats::LookupStats> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.lookup_stats(&sheetid).await {
+        Ok(stats) => success(stats),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// The number of rows returned by the tail endpoint when none is given.
+const DEFAULT_TAIL_ROWS: i64 = 50;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct TailQuery {
+    n: Option<i64>,
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+}
+
+#[get("/{sheetid}/tail")]
+async fn get_tail(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<TailQuery>, actix_web::Error>,
+) -> Responded<super::SheetContent> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    let n = query.n.unwrap_or(DEFAULT_TAIL_ROWS);
+    if !(1..=MAX_PAGE_ROWS).contains(&n) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!("n must be between 1 and {MAX_PAGE_ROWS}")),
+        );
+    }
+
+    let no_lookup_nulls =
+        match no_lookup_nulls(&data, &sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    let options = super::ReadOptions {
+        no_lookup_nulls,
+        include_hidden: query_flag(query.include_hidden.as_deref()),
+        ..Default::default()
+    };
+    let content = data.db.tail(&sheetid, n, options).await;
+    match content
+        .and_then(|mut content| transform(&data, &req, &sheetid, &mut content).map(|()| content))
+    {
+        Ok(content) => success(content),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct GetColumnQuery {
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+    /// Giving either of these reads a single page of cells instead of the whole column.
+    after_row: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum GetColumnResponse {
+    Page {
+        cells: Vec<super::SheetContentColumn>,
+        next_cursor: Option<i64>,
+    },
+    Full {
+        cells: Vec<super::SheetContentColumn>,
+    },
+}
+
+/// Reads the resolved cells of a single column, for clients which have no use for the rest of the sheet.
+#[get("/{sheetid}/columns/{column}")]
+async fn get_column(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    path: Option<web::Path<(SheetId, String)>>,
+    query: Result<web::Query<GetColumnQuery>, actix_web::Error>,
+) -> Responded<GetColumnResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let (sheetid, column) = path.into_inner();
+
+    let paged = query.after_row.is_some() || query.limit.is_some();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_ROWS);
+    if paged && !(1..=MAX_PAGE_ROWS).contains(&limit) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!(
+                "limit must be between 1 and {MAX_PAGE_ROWS}"
+            )),
+        );
+    }
+
+    let no_lookup_nulls =
+        match no_lookup_nulls(&data, &sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    let options = super::ReadOptions {
+        no_lookup_nulls,
+        include_hidden: query_flag(query.include_hidden.as_deref()),
+        ..Default::default()
+    };
+    let result = data
+        .db
+        .column_cells(&sheetid, &column, query.after_row, paged.then_some(limit), options)
+        .await;
+    let result = result.and_then(|(mut cells, next_cursor)| {
+        let transformed = cells.iter_mut().map(|cell| TransformedCell {
+            column: &column,
+            row: cell.row,
+            value: &mut cell.value,
+            error: Some(&mut cell.error),
+        });
+        transform_cells(&data, &req, &sheetid, transformed).map(|()| (cells, next_cursor))
+    });
+    match result {
+        Ok((cells, next_cursor)) if paged => {
+            success(GetColumnResponse::Page { cells, next_cursor })
+        }
+        Ok((cells, _)) => success(GetColumnResponse::Full { cells }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct GetRowQuery {
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+}
+
+/// Reads the cells of a single row, which is how sheets that model records are usually read.
+#[get("/{sheetid}/rows/{row}")]
+async fn get_row(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    path: Option<web::Path<(SheetId, i64)>>,
+    query: Result<web::Query<GetRowQuery>, actix_web::Error>,
+) -> Responded<super::RowContent> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let (sheetid, row) = path.into_inner();
+
+    let no_lookup_nulls =
+        match no_lookup_nulls(&data, &sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    let options = super::ReadOptions {
+        no_lookup_nulls,
+        include_hidden: query_flag(query.include_hidden.as_deref()),
+        ..Default::default()
+    };
+    let content = data.db.get_row(&sheetid, row, options).await;
+    match content.and_then(|mut content| {
+        transform_cells(&data, &req, &sheetid, row_cells(&mut content)).map(|()| content)
+    }) {
+        Ok(content) => success(content),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct GetRowsQuery {
+    /// A comma-separated list of rows, e.g. `?ids=1,5,9`.
+    ids: String,
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct GetRowsResponse {
+    rows: Vec<super::RowContent>,
+}
+
+/// Reads the cells of several rows at once, for clients which keep track of a working set of records.
+#[get("/{sheetid}/rows")]
+async fn get_rows(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<GetRowsQuery>, actix_web::Error>,
+) -> Responded<GetRowsResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    let rows = match query
+        .ids
+        .split(',')
+        .map(|id| id.trim().parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(rows) => rows,
+        Err(why) => {
+            return failure(
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::invalid_query(format_args!("invalid row in ids: {why}")),
+            )
+        }
+    };
+    if rows.len() as i64 > MAX_PAGE_ROWS {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!(
+                "at most {MAX_PAGE_ROWS} rows can be read at once"
+            )),
+        );
+    }
+
+    let no_lookup_nulls =
+        match no_lookup_nulls(&data, &sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    let options = super::ReadOptions {
+        no_lookup_nulls,
+        include_hidden: query_flag(query.include_hidden.as_deref()),
+        ..Default::default()
+    };
+    let rows = data.db.get_rows(&sheetid, &rows, options).await;
+    match rows.and_then(|mut rows| {
+        let transformed = rows.iter_mut().flat_map(row_cells);
+        transform_cells(&data, &req, &sheetid, transformed).map(|()| rows)
+    }) {
+        Ok(rows) => success(GetRowsResponse { rows }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// The most cells which can be read at once by their addresses.
+const MAX_ADDRESSED_CELLS: usize = 1000;
+
+/// The address of a cell, either by its column's name or in A1 notation (see [`super::a1`]).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum CellAddress {
+    Named {
+        column: String,
+        row: i64,
+    },
+    A1 {
+        #[serde(rename = "ref")]
+        reference: String,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct GetCellsRequest {
+    cells: Vec<CellAddress>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct GetCellsQuery {
+    include_hidden: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct GetCellsResponse {
+    cells: Vec<super::AddressedCell>,
+}
+
+/// Reads a scattered set of cells by their addresses, for clients which watch a few key cells of a large sheet.
+/// This only reads, but takes a `POST`, since the addresses may not fit into a query string.
+#[post("/{sheetid}/cells:get")]
+async fn post_get_cells(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<GetCellsQuery>, actix_web::Error>,
+    body: Option<web::Json<GetCellsRequest>>,
+) -> Responded<GetCellsResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let Some(body) = body else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+    let field_error = |path: String, message: String| {
+        let error = super::FieldError { path, message };
+        failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(Some(vec![error])))
+    };
+    if body.cells.len() > MAX_ADDRESSED_CELLS {
+        return field_error(
+            "cells".into(),
+            format!("at most {MAX_ADDRESSED_CELLS} cells can be read at once"),
+        );
+    }
+
+    // the schema is only needed to map addresses in A1 notation to the names of columns
+    let columns = if body
+        .cells
+        .iter()
+        .any(|address| matches!(address, CellAddress::A1 { .. }))
+    {
+        match data.db.schema(&sheetid).await {
+            Ok(schema) => schema.columns,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        }
+    } else {
+        vec![]
+    };
+    let mut addresses = Vec::with_capacity(body.cells.len());
+    for (i, address) in body.into_inner().cells.into_iter().enumerate() {
+        match address {
+            CellAddress::Named { column, row } => addresses.push((column, row)),
+            CellAddress::A1 { reference } => {
+                let a1 = match reference.parse::<super::a1::A1>() {
+                    Ok(a1) => a1,
+                    Err(why) => return field_error(format!("cells[{i}].ref"), why),
+                };
+                let Some(column) = a1.column_name(&columns) else {
+                    return field_error(
+                        format!("cells[{i}].ref"),
+                        "the sheet doesn't have this many columns".into(),
+                    );
+                };
+                addresses.push((column.to_owned(), a1.row));
+            }
+        }
+    }
+
+    let include_hidden = query_flag(query.include_hidden.as_deref());
+    let cells = data
+        .db
+        .get_cells(&sheetid, &addresses, include_hidden)
+        .await;
+    match cells.and_then(|mut cells| {
+        let transformed = cells.iter_mut().map(|cell| TransformedCell {
+            column: &cell.column,
+            row: cell.row,
+            value: &mut cell.value,
+            error: Some(&mut cell.error),
+        });
+        transform_cells(&data, &req, &sheetid, transformed).map(|()| cells)
+    }) {
+        Ok(cells) => success(GetCellsResponse { cells }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// The most rows a grid can have.
+const MAX_GRID_ROWS: i64 = 10_000;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct GridQuery {
+    /// An inclusive range of rows, e.g. `?rows=1-100`.
+    rows: String,
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+}
+
+/// Parses an inclusive range of rows such as `1-100` (or `-5--1`, for negative rows).
+fn parse_row_range(range: &str) -> Option<(i64, i64)> {
+    // the first character may be the sign of the first row
+    let split = range.get(1..)?.find('-')? + 1;
+    let first = range[..split].trim().parse().ok()?;
+    let last = range[split + 1..].trim().parse().ok()?;
+    Some((first, last))
+}
+
+/// Reads a range of rows as a dense grid, with a value (or `null`) for every column of every row.
+#[get("/{sheetid}/grid")]
+async fn get_grid(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<GridQuery>, actix_web::Error>,
+) -> Responded<super::Grid> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    let Some((first_row, last_row)) = parse_row_range(&query.rows) else {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query("rows must be a range such as 1-100"),
+        );
+    };
+    if first_row > last_row || last_row.saturating_sub(first_row) >= MAX_GRID_ROWS {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!(
+                "rows must be an ascending range of at most {MAX_GRID_ROWS} rows"
+            )),
+        );
+    }
+
+    let no_lookup_nulls =
+        match no_lookup_nulls(&data, &sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    let options = super::ReadOptions {
+        no_lookup_nulls,
+        include_hidden: query_flag(query.include_hidden.as_deref()),
+        ..Default::default()
+    };
+    let grid = data.db.grid(&sheetid, first_row, last_row, options).await;
+    match grid.and_then(|mut grid| {
+        let columns = &grid.columns;
+        let transformed = (grid.first_row..)
+            .zip(&mut grid.rows)
+            .flat_map(|(row, values)| {
+                columns
+                    .iter()
+                    .zip(values)
+                    .map(move |(column, value)| TransformedCell {
+                        column,
+                        row,
+                        value,
+                        error: None,
+                    })
+            });
+        transform_cells(&data, &req, &sheetid, transformed).map(|()| grid)
+    }) {
+        Ok(grid) => success(grid),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct JoinQuery {
+    /// The id of the sheet to join with.
+    with: String,
+    left_on: String,
+    right_on: String,
+    #[serde(default)]
+    how: super::JoinKind,
+    include_hidden: Option<String>,
+}
+
+/// Checks whether the request may read a sheet other than the one in its path, which [`crate::middleware::RequireJwt`]
+/// only checked for that one. Without JWT authentication, every sheet may be read.
+async fn may_read(
+    data: &crate::AppData,
+    claims: Option<&crate::jwt::Claims>,
+    sheetid: &SheetId,
+) -> anyhow::Result<bool> {
+    if data.jwt.is_none() {
+        return Ok(true);
+    }
+    let Some(claims) = claims else {
+        return Ok(false);
+    };
+    if claims.role(Some(sheetid.inner())).is_some() {
+        return Ok(true);
+    }
+    match &claims.sub {
+        Some(sub) => Ok(data.db.role(sheetid.inner(), sub).await?.is_some()),
+        None => Ok(false),
+    }
+}
+
+/// Joins the rows of the sheet with the rows of another one, matching them on a column of each.
+#[get("/{sheetid}/join")]
+async fn get_join(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<JoinQuery>, actix_web::Error>,
+    claims: Option<web::ReqData<crate::jwt::Claims>>,
+) -> Responded<super::Join> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let with = match SheetId::try_from(query.with.as_str()) {
+        Ok(with) => with,
+        Err(why) => {
+            return failure(
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::invalid_query(format_args!("invalid sheet id to join with: {why}")),
+            )
+        }
+    };
+
+    match may_read(&data, claims.as_deref(), &with).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return failure(
+                StatusCode::FORBIDDEN,
+                ErrorResponse::new("the token doesn't grant access to the sheet to join with"),
+            )
+        }
+        Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+
+    let options = super::ReadOptions {
+        include_hidden: query_flag(query.include_hidden.as_deref()),
+        ..Default::default()
+    };
+    // the memory stays reserved until the joined rows have been handed over to be sent
+    let budget = data.read_budget.reserve();
+    let on = (query.left_on.as_str(), query.right_on.as_str());
+    let join = data
+        .db
+        .join(&sheetid, &with, on, query.how, options, Some(&budget))
+        .await;
+    match join.and_then(|mut join| {
+        // each side is transformed as a read of its own sheet
+        for (side, left) in [(&*sheetid, true), (&with, false)] {
+            let prefix = match left {
+                true => super::Join::LEFT_PREFIX,
+                false => super::Join::RIGHT_PREFIX,
+            };
+            let columns = &join.columns;
+            let transformed = join.rows.iter_mut().flat_map(|joined| {
+                let row = match left {
+                    true => Some(joined.left_row),
+                    false => joined.right_row,
+                };
+                columns
+                    .iter()
+                    .zip(&mut joined.values)
+                    .filter_map(move |(column, value)| {
+                        Some(TransformedCell {
+                            column: column.strip_prefix(prefix)?,
+                            row: row?,
+                            value,
+                            error: None,
+                        })
+                    })
+            });
+            transform_cells(&data, &req, side, transformed)?;
+        }
+        Ok(join)
+    }) {
+        Ok(join) => success(join),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// Creates a view of the sheet, which is a sheet of its own. Whoever creates it owns it, like with `POST /sheet`.
+#[post("/{sheetid}/views")]
+async fn post_view(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    body: Option<web::Json<serde_json::Value>>,
+    claims: Option<web::ReqData<crate::jwt::Claims>>,
+) -> Responded<PostResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let super::view::NewView { mut definition, id } = match parse_body(body) {
+        Ok(new_view) => new_view,
+        Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+    };
+    let custom_id = match id.as_deref().map(SheetId::from_slug).transpose() {
+        Ok(custom_id) => custom_id,
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::new(why)),
+    };
+
+    definition.normalize();
+    match data
+        .db
+        .new_view(
+            &sheetid,
+            &definition,
+            data.config.case_insensitive_columns,
+            data.config.id_scheme,
+            custom_id,
+        )
+        .await
+    {
+        Ok(view) => {
+            if let Some(sub) = claims.as_ref().and_then(|claims| claims.sub.as_deref()) {
+                let owner = Some(super::Role::Owner);
+                if let Err(why) = data.db.set_permission(&view, sub, owner).await {
+                    log::warn!("failed to make {sub} the owner of {}: {why}", view.inner());
+                }
+            }
+            success(PostResponse {
+                sheet_id: view.inner().into(),
+            })
+        }
+        Err(why) if why.is::<IdTaken>() => {
+            failure(StatusCode::CONFLICT, ErrorResponse::from_db(&why))
+        }
+        Err(why) => match why.downcast::<InvalidView>() {
+            Ok(InvalidView(errors)) => {
+                failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(Some(errors)))
+            }
+            Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+        },
+    }
+}
+
+#[get("/{sheetid}/view")]
+async fn get_view(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<super::view::ViewInfo> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.view(&sheetid).await {
+        Ok(Some(view)) => success(view),
+        Ok(None) => failure(StatusCode::NOT_FOUND, ErrorResponse::new("the sheet isn't a view")),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// Computes the content of a view from its source again, which is how views with `"refresh": "manual"` are
+/// brought up to date.
+#[post("/{sheetid}/refresh")]
+async fn post_refresh(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<super::view::ViewInfo> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.refresh_view(&sheetid).await {
+        Ok(view) => success(view),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct EventsQuery {
+    #[serde(default)]
+    after_seq: i64,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct EventsResponse {
+    events: Vec<super::Event>,
+    next_cursor: Option<i64>,
+}
+
+/// Reads the event log of a sheet, for replaying its changes elsewhere.
+#[get("/{sheetid}/events")]
+async fn get_events(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<EventsQuery>, actix_web::Error>,
+) -> Responded<EventsResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_ROWS);
+    if !(1..=MAX_PAGE_ROWS).contains(&limit) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!(
+                "limit must be between 1 and {MAX_PAGE_ROWS}"
+            )),
+        );
+    }
+
+    match data.db.events(&sheetid, query.after_seq, limit).await {
+        Ok((events, next_cursor)) => success(EventsResponse {
+            events,
+            next_cursor,
+        }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct AccessLogQuery {
+    #[serde(default)]
+    after_id: i64,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct AccessLogResponse {
+    entries: Vec<super::AccessLogEntry>,
+    next_cursor: Option<i64>,
+}
+
+/// Reads the access log of a sheet, for finding out who has looked at it.
+#[get("/{sheetid}/access-log")]
+async fn get_access_log(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<AccessLogQuery>, actix_web::Error>,
+) -> Responded<AccessLogResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    if !data.config.access_log {
+        return failure(StatusCode::NOT_FOUND, ErrorResponse::new("the access log is disabled"));
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_ROWS);
+    if !(1..=MAX_PAGE_ROWS).contains(&limit) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!(
+                "limit must be between 1 and {MAX_PAGE_ROWS}"
+            )),
+        );
+    }
+
+    // the log should include everything up to this request, rather than up to the last scheduled flush
+    let result = match data.traffic.flush(&data.db).await {
+        Ok(()) => data.db.access_log(&sheetid, query.after_id, limit).await,
+        Err(why) => Err(why),
+    };
+    match result {
+        Ok((entries, next_cursor)) => success(AccessLogResponse {
+            entries,
+            next_cursor,
+        }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct ExplainQuery {
+    column: String,
+    row: i64,
+    include_hidden: Option<String>,
+}
+
+/// Shows how a single cell resolves, one lookup at a time, for finding out why it's empty or broken.
+#[get("/{sheetid}/explain")]
+async fn get_explain(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<ExplainQuery>, actix_web::Error>,
+) -> Responded<super::Explanation> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let include_hidden = query_flag(query.include_hidden.as_deref());
+
+    match data
+        .db
+        .explain(&sheetid, &query.column, query.row, include_hidden)
+        .await
+        .and_then(|mut explanation| {
+            transform_explanation(&data, &req, &sheetid, &query, &mut explanation)?;
+            Ok(explanation)
+        }) {
+        Ok(explanation) => success(explanation),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct SchemaQuery {
+    include_hidden: Option<String>,
+}
+
+/// Returns the current schema of a sheet, including the formats of its columns.
+#[get("/{sheetid}/schema")]
+async fn get_schema(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<SchemaQuery>, actix_web::Error>,
+) -> Responded<super::Schema> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+
+    match data.db.schema(&sheetid).await {
+        Ok(mut schema) => {
+            if !query_flag(query.include_hidden.as_deref()) {
+                schema.columns.retain(|column| !column.hidden);
+            }
+            success(schema)
+        }
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[post("/{sheetid}/schema/check")]
+async fn post_schema_check(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    body: Option<web::Json<serde_json::Value>>,
+) -> Responded<super::SchemaCheck> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let mut schema: super::Schema = match parse_body(body) {
+        Ok(schema) => schema,
+        Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+    };
+
+    schema.normalize();
+    if let Err(errors) = schema.validate(data.config.case_insensitive_columns) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                details: Some(ErrorDetails::Schema(errors)),
+                ..ErrorResponse::new("invalid schema")
+            },
+        );
+    }
+
+    match data.db.check_schema(&sheetid, &schema).await {
+        Ok(check) => success(check),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct SchemaHistoryQuery {
+    include_hidden: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SchemaHistoryResponse {
+    versions: Vec<super::SchemaVersion>,
+}
+
+/// Lists every schema a sheet has had, so that older exports can be read with the schema they were made with.
+#[get("/{sheetid}/schema/history")]
+async fn get_schema_history(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<SchemaHistoryQuery>, actix_web::Error>,
+) -> Responded<SchemaHistoryResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let include_hidden = query_flag(query.include_hidden.as_deref());
+
+    match data.db.schema_history(&sheetid).await {
+        Ok(mut versions) => {
+            if !include_hidden {
+                for version in &mut versions {
+                    version.schema.columns.retain(|column| !column.hidden);
+                }
+            }
+            success(SchemaHistoryResponse { versions })
+        }
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[get("/{sheetid}/settings")]
+async fn get_settings(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<super::SheetSettings> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data.db.get_settings(&sheetid).await {
+        Ok(settings) => success(settings),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[put("/{sheetid}/settings")]
+async fn put_settings(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    settings: Option<web::Json<super::SheetSettings>>,
+) -> Responded<super::SheetSettings> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(settings) = settings else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+    if settings.row_bounds.is_some_and(|bounds| !bounds.is_valid()) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_body(Some(vec![super::FieldError {
+                path: "row_bounds".into(),
+                message: "min can't be greater than max".into(),
+            }])),
+        );
+    }
+
+    match data.db.set_settings(&sheetid, &settings).await {
+        Ok(()) => success(settings.into_inner()),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct MirrorRequest {
+    source: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct MirrorResponse {
+    written: u64,
+}
+
+#[post("/{sheetid}/column/{column}/mirror")]
+async fn post_mirror(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, String)>>,
+    body: Option<web::Json<MirrorRequest>>,
+) -> Responded<MirrorResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let Some(body) = body else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+    };
+    let (sheetid, column) = path.into_inner();
+
+    let cells = match data.db.mirror_cells(&sheetid, &column, &body.source).await {
+        Ok(cells) => cells,
+        Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+    };
+    if let Err((status, error)) = check_validator(&data, &sheetid, &cells, None).await {
+        return failure(status, error);
+    }
+
+    match data.db.insert_cells(&sheetid, &cells, false).await {
+        Ok(()) => success(MirrorResponse {
+            written: cells.len() as u64,
+        }),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+/// The layout of a CSV file, shared by imports and exports.
+#[derive(Deserialize, Clone, Debug, Default)]
+struct CsvQuery {
+    delimiter: Option<String>,
+    quote: Option<String>,
+    has_header: Option<String>,
+    encoding: Option<String>,
+}
+
+impl CsvQuery {
+    fn dialect(&self) -> Result<super::import::CsvDialect, ErrorResponse> {
+        let default = super::import::CsvDialect::default();
+        let single_byte = |name: &str, value: &Option<String>, default: u8| match value.as_deref() {
+            None => Ok(default),
+            Some(value) if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+            Some(_) => Err(ErrorResponse::invalid_query(format_args!(
+                "{name} must be a single ASCII character"
+            ))),
+        };
+
+        Ok(super::import::CsvDialect {
+            delimiter: single_byte("delimiter", &self.delimiter, default.delimiter)?,
+            quote: single_byte("quote", &self.quote, default.quote)?,
+            has_header: match &self.has_header {
+                Some(value) => query_flag(Some(value)),
+                None => default.has_header,
+            },
+            encoding: match &self.encoding {
+                Some(encoding) => encoding.parse().map_err(ErrorResponse::invalid_query)?,
+                None => default.encoding,
+            },
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct ImportQuery {
+    dry_run: Option<String>,
+    #[serde(flatten)]
+    csv: CsvQuery,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ImportResponse {
+    dry_run: bool,
+    rows: u64,
+    cells: usize,
+    /// Only dry runs report errors this way, real imports fail instead.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<super::FieldError>,
+}
+
+/// Fills a sheet from a CSV file, or only checks the file with `?dry_run`.
+#[post("/{sheetid}/import")]
+async fn post_import(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<ImportQuery>, actix_web::Error>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Responded<ImportResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let dry_run = query_flag(query.dry_run.as_deref());
+    let dialect = match query.csv.dialect() {
+        Ok(dialect) => dialect,
+        Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+    };
+
+    // large files are usually compressed, in which case the limit applies to their decompressed size
+    let mut payload = actix_web::dev::Decompress::from_headers(payload.into_inner(), req.headers());
+    let mut file = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let Ok(chunk) = chunk else {
+            return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(None));
+        };
+        if file.len() + chunk.len() > data.config.max_import_size {
+            return failure(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorResponse {
+                    error: format!(
+                        "imports can't be larger than {} bytes",
+                        data.config.max_import_size
+                    ),
+                    ..ErrorResponse::invalid_body(None)
+                },
+            );
+        }
+        file.extend_from_slice(&chunk);
+    }
+
+    let schema = match data.db.schema(&sheetid).await {
+        Ok(schema) => schema,
+        Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+    };
+    let mut import = super::import::parse_csv(&file, &schema.columns, dialect);
+    if !dry_run && !import.errors.is_empty() {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_body(Some(import.errors)));
+    }
+
+    // a dry run only gets this far when the whole file parses, since the cells are pointless to check otherwise
+    if import.errors.is_empty() {
+        let context = |cell: &super::Cell| format!("rows[{}].{}", cell.row, cell.column);
+        if let Err((status, error)) =
+            check_validator(&data, &sheetid, &import.cells, Some(context)).await
+        {
+            return failure(status, error);
+        }
+    }
+    let result = if import.errors.is_empty() {
+        data.db
+            .import_cells(&sheetid, &import.cells, data.config.strict_lookups, dry_run)
+            .await
+    } else {
+        Ok(())
+    };
+    match result {
+        Ok(()) => {
+            if !dry_run {
+                notify_matching_rules(&data, &sheetid, &import.cells).await;
+            }
+            success(ImportResponse {
+                dry_run,
+                rows: import.rows,
+                cells: import.cells.len(),
+                errors: import.errors,
+            })
+        }
+        // cells which can't be written have their path added as context, unlike failures of the database itself
+        Err(why) if dry_run && error_status(&why) == StatusCode::BAD_REQUEST => {
+            let Some(path) = why.downcast_ref::<String>() else {
+                return failure(error_status(&why), ErrorResponse::from_db(&why));
+            };
+            import.errors.push(super::FieldError {
+                path: path.clone(),
+                message: why.root_cause().to_string(),
+            });
+            success(ImportResponse {
+                dry_run,
+                rows: import.rows,
+                cells: import.cells.len(),
+                errors: import.errors,
+            })
+        }
+        // the message includes the offending cell, which is added as context
+        Err(why) => failure(
+            error_status(&why),
+            ErrorResponse {
+                error: format!("{why:#}"),
+                ..ErrorResponse::from_db(&why)
+            },
+        ),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct ExportQuery {
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+    anonymize: Option<String>,
+    #[serde(flatten)]
+    csv: CsvQuery,
+    #[serde(flatten)]
+    locale: LocaleQuery,
+}
+
+impl ExportQuery {
+    /// The layout of the file, which is separated by semicolons by default for locales with a decimal comma.
+    fn dialect(&self) -> Result<(super::import::CsvDialect, super::export::Locale), ErrorResponse> {
+        let mut dialect = self.csv.dialect()?;
+        let locale = self.locale.locale()?;
+        if self.csv.delimiter.is_none() && locale.decimal_separator == ',' {
+            dialect.delimiter = b';';
+        }
+        Ok((dialect, locale))
+    }
+
+    fn anonymization(&self) -> Result<Option<super::export::Anonymization>, ErrorResponse> {
+        self.anonymize
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(ErrorResponse::invalid_query)
+    }
+}
+
+/// The locale numbers are written in by exports, which don't use one unless they're asked to.
+#[derive(Deserialize, Clone, Debug, Default)]
+struct LocaleQuery {
+    locale: Option<String>,
+}
+
+impl LocaleQuery {
+    fn locale(&self) -> Result<super::export::Locale, ErrorResponse> {
+        match &self.locale {
+            Some(locale) => locale.parse().map_err(ErrorResponse::invalid_query),
+            None => Ok(super::export::Locale::default()),
+        }
+    }
+}
+
+/// Exports a whole sheet as CSV, in the same layout imports use.
+#[get("/{sheetid}/export")]
+async fn get_export(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    sheetid: Option<web::Path<SheetId>>,
+    query: Result<web::Query<ExportQuery>, actix_web::Error>,
+) -> Either<HttpResponse, Responded<()>> {
+    let Some(sheetid) = sheetid else {
+        return Either::Right(failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id()));
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => {
+            return Either::Right(failure(
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::invalid_query(why),
+            ))
+        }
+    };
+
+    export(&data, &req, &sheetid, &query).await
+}
+
+async fn export(
+    data: &crate::AppData,
+    req: &HttpRequest,
+    sheetid: &SheetId,
+    query: &ExportQuery,
+) -> Either<HttpResponse, Responded<()>> {
+    let (dialect, locale) = match query.dialect() {
+        Ok(dialect) => dialect,
+        Err(error) => return Either::Right(failure(StatusCode::BAD_REQUEST, error)),
+    };
+    let anonymization = match query.anonymization() {
+        Ok(anonymization) => anonymization,
+        Err(error) => return Either::Right(failure(StatusCode::BAD_REQUEST, error)),
+    };
+
+    let no_lookup_nulls =
+        match no_lookup_nulls(data, sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => {
+                return Either::Right(failure(error_status(&why), ErrorResponse::from_db(&why)))
+            }
+        };
+    let options = super::ReadOptions {
+        no_lookup_nulls,
+        include_hidden: query_flag(query.include_hidden.as_deref()),
+        ..Default::default()
+    };
+    // the memory stays reserved until the file has been written
+    let budget = data.read_budget.reserve();
+    let result = futures_util::try_join!(
+        data.db.schema(sheetid),
+        data.db.get_sheet_within(sheetid, options, Some(&budget))
+    );
+    let file = result.and_then(|(schema, mut content)| {
+        transform(data, req, sheetid, &mut content)?;
+        let columns: Vec<_> = schema
+            .columns
+            .into_iter()
+            .filter(|column| content.columns.contains_key(&column.name))
+            .collect();
+        if let Some(anonymization) = anonymization {
+            super::export::anonymize(&columns, &mut content, anonymization);
+        }
+        super::export::write_csv(&columns, &content, dialect, locale)
+    });
+
+    match file {
+        Ok(file) => Either::Left(
+            HttpResponse::Ok()
+                .content_type(format!("text/csv; charset={}", dialect.encoding.charset()))
+                .body(file),
+        ),
+        Err(why) => Either::Right(failure(error_status(&why), ErrorResponse::from_db(&why))),
+    }
+}
+
+/// The longest an export link may be valid for, in seconds.
+const MAX_EXPORT_LINK_TTL: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Deserialize, Clone, Debug)]
+struct ExportLinkQuery {
+    ttl: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ExportLinkResponse {
+    url: String,
+    expires_at: i64,
+}
+
+/// Leaves the parameters named in `skip` out of a query string, keeping the others exactly as they were sent.
+fn query_without(query: &str, skip: &[&str]) -> String {
+    query
+        .split('&')
+        .filter(|param| {
+            let name = param.split('=').next().unwrap_or(param);
+            !param.is_empty() && !skip.contains(&name)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Creates a link for downloading the export of a sheet without any credentials, until it expires. Every query
+/// parameter apart from `ttl` is an option of the export, as for `GET /sheet/{sheetid}/export`.
+#[post("/{sheetid}/export-link")]
+async fn post_export_link(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    req: HttpRequest,
+    query: Result<web::Query<ExportLinkQuery>, actix_web::Error>,
+) -> Responded<ExportLinkResponse> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let ttl = query.ttl.unwrap_or(60 * 60);
+    if !(1..=MAX_EXPORT_LINK_TTL).contains(&ttl) {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            ErrorResponse::invalid_query(format_args!(
+                "ttl must be between 1 and {MAX_EXPORT_LINK_TTL} seconds"
+            )),
+        );
+    }
+
+    // the options are checked now, rather than when the link is used
+    let options = query_without(req.query_string(), &["ttl"]);
+    match web::Query::<ExportQuery>::from_query(&options) {
+        Ok(export) => {
+            if let Err(error) = export.dialect().and(export.anonymization()) {
+                return failure(StatusCode::BAD_REQUEST, error);
+            }
+        }
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    }
+    if let Err(why) = data.db.schema(&sheetid).await {
+        return failure(error_status(&why), ErrorResponse::from_db(&why));
+    }
+
+    let expires_at = crate::db::now_millis() + ttl as i64 * 1000;
+    let link = crate::signing::ExportLink {
+        sheet_id: sheetid.inner(),
+        query: &options,
+        expires: expires_at,
+    };
+    let signature = link.sign(&data.export_link_key);
+    let separator = if options.is_empty() { "" } else { "&" };
+    success(ExportLinkResponse {
+        url: format!(
+            "/exports/{}?{options}{separator}expires={expires_at}&signature={signature}",
+            sheetid.inner()
+        ),
+        expires_at,
+    })
+}
+
+/// Export links, which are served without any credentials apart from their signatures.
+pub fn exports_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_linked_export);
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct LinkedExportQuery {
+    expires: i64,
+    signature: String,
+    #[serde(flatten)]
+    export: ExportQuery,
+}
+
+#[get("/{sheetid}")]
+async fn get_linked_export(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+    req: HttpRequest,
+    query: Result<web::Query<LinkedExportQuery>, actix_web::Error>,
+) -> Either<HttpResponse, Responded<()>> {
+    let Some(sheetid) = sheetid else {
+        return Either::Right(failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id()));
+    };
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => {
+            return Either::Right(failure(
+                StatusCode::BAD_REQUEST,
+                ErrorResponse::invalid_query(why),
+            ))
+        }
+    };
+
+    let options = query_without(req.query_string(), &["expires", "signature"]);
+    let link = crate::signing::ExportLink {
+        sheet_id: sheetid.inner(),
+        query: &options,
+        expires: query.expires,
+    };
+    let now = crate::db::now_millis();
+    if let Err(why) = link.verify(&data.export_link_key, now, &query.signature) {
+        return Either::Right(failure(StatusCode::FORBIDDEN, ErrorResponse::new(why)));
+    }
+
+    export(&data, &req, &sheetid, &query.export).await
+}
+
+/// Applies the read transformers to content which is about to be sent (see [`crate::transform`]).
+fn transform(
+    data: &crate::AppData,
+    req: &HttpRequest,
+    sheetid: &SheetId,
+    content: &mut super::SheetContent,
+) -> anyhow::Result<()> {
+    let selected =
+        match req.headers().get(TRANSFORM_HEADER) {
+            Some(value) => Some(value.to_str().map_err(|_| {
+                UnknownTransformer(String::from_utf8_lossy(value.as_bytes()).into())
+            })?),
+            None => None,
+        };
+    data.read_transformers.apply(selected, sheetid, content)
+}
+
+/// A cell which is sent in some other shape than a [`super::SheetContent`], see [`transform_cells`].
+struct TransformedCell<'a> {
+    column: &'a str,
+    row: i64,
+    value: &'a mut Option<super::CellValue>,
+    /// `None` for shapes which can't tell errors apart from empty cells.
+    error: Option<&'a mut Option<super::CellError>>,
+}
+
+/// Applies the read transformers to cells which are sent in some other shape than a [`super::SheetContent`], by
+/// putting them into one and taking them out of it again. Cells which a transformer removes come out empty, and
+/// cells which it adds are left out.
+fn transform_cells<'a>(
+    data: &crate::AppData,
+    req: &HttpRequest,
+    sheetid: &SheetId,
+    cells: impl IntoIterator<Item = TransformedCell<'a>>,
+) -> anyhow::Result<()> {
+    // nothing would change, so the cells don't have to be copied around
+    if data.read_transformers.is_empty() && !req.headers().contains_key(TRANSFORM_HEADER) {
+        return Ok(());
+    }
+
+    let mut cells: Vec<_> = cells.into_iter().collect();
+    // the same cell may be sent more than once, e.g. as part of several rows of a join
+    let mut columns = HashMap::<String, BTreeMap<i64, super::SheetContentColumn>>::new();
+    for cell in &cells {
+        let content = super::SheetContentColumn {
+            row: cell.row,
+            value: cell.value.clone(),
+            error: cell.error.as_deref().copied().flatten(),
+        };
+        columns
+            .entry(cell.column.into())
+            .or_default()
+            .insert(cell.row, content);
+    }
+    let mut content = super::SheetContent {
+        columns: columns
+            .into_iter()
+            .map(|(name, cells)| (name, cells.into_values().collect()))
+            .collect(),
+        meta: None,
+        row_keys: None,
+        version: None,
+    };
+    transform(data, req, sheetid, &mut content)?;
+
+    let transformed: HashMap<_, HashMap<_, _>> = content
+        .columns
+        .into_iter()
+        .map(|(name, cells)| (name, cells.into_iter().map(|cell| (cell.row, cell)).collect()))
+        .collect();
+    for cell in &mut cells {
+        let result = transformed
+            .get(cell.column)
+            .and_then(|cells| cells.get(&cell.row));
+        *cell.value = result.and_then(|result| result.value.clone());
+        if let Some(error) = &mut cell.error {
+            **error = result.and_then(|result| result.error);
+        }
+    }
+    Ok(())
+}
+
+/// The cells of a row, to be transformed with [`transform_cells`].
+fn row_cells(content: &mut super::RowContent) -> impl Iterator<Item = TransformedCell<'_>> {
+    let row = content.row;
+    content
+        .columns
+        .iter_mut()
+        .map(move |(column, cell)| TransformedCell {
+            column,
+            row,
+            value: &mut cell.value,
+            error: Some(&mut cell.error),
+        })
+}
+
+/// Transforms both the cell which was explained and the stored value that resolving it ended with, which may be
+/// in another column.
+fn transform_explanation(
+    data: &crate::AppData,
+    req: &HttpRequest,
+    sheetid: &SheetId,
+    query: &ExplainQuery,
+    explanation: &mut super::Explanation,
+) -> anyhow::Result<()> {
+    let mut last_value = None;
+    let mut last_error = None;
+    let mut last = None;
+    if let Some(step) = explanation.steps.last_mut() {
+        if let Some(column) = &step.column {
+            match &step.outcome {
+                super::StepOutcome::Value { value } => last_value = Some(value.clone()),
+                super::StepOutcome::Error { error } => last_error = Some(*error),
+                _ => {}
+            }
+            if last_value.is_some() || last_error.is_some() {
+                last = Some((column.clone(), step.row));
+            }
+        }
+    }
+
+    let mut cells = vec![TransformedCell {
+        column: &query.column,
+        row: query.row,
+        value: &mut explanation.value,
+        error: Some(&mut explanation.error),
+    }];
+    if let Some((column, row)) = &last {
+        cells.push(TransformedCell {
+            column,
+            row: *row,
+            value: &mut last_value,
+            error: Some(&mut last_error),
+        });
+    }
+    transform_cells(data, req, sheetid, cells)?;
+
+    if last.is_some() {
+        let step = explanation
+            .steps
+            .last_mut()
+            .expect("the last step was transformed");
+        step.outcome = match (last_value, last_error) {
+            (Some(value), _) => super::StepOutcome::Value { value },
+            (None, Some(error)) => super::StepOutcome::Error { error },
+            (None, None) => super::StepOutcome::Null,
+        };
+    }
+    Ok(())
+}
+
+/// Reads a published sheet as anyone would see it. Sheets which aren't published look the same as those which
+/// don't exist, and aliases are followed, so that published links keep working after an id rotation.
+/// Reads a published sheet, reserving its memory from `budget`, which has to be kept until the response is built.
+async fn published_sheet(
+    data: &crate::AppData,
+    req: &HttpRequest,
+    id: &str,
+    budget: &Reservation<'_>,
+) -> anyhow::Result<(SheetId, Vec<super::SchemaColumn>, super::SheetContent)> {
+    let target = data.db.resolve_alias(id).map(|(target, _)| target);
+    let sheetid = SheetId::try_from(target.as_deref().unwrap_or(id)).map_err(|_| SheetNotFound)?;
+    if !data.db.get_settings(&sheetid).await?.published {
+        return Err(SheetNotFound.into());
+    }
+
+    let options = super::ReadOptions {
+        no_lookup_nulls: no_lookup_nulls(data, &sheetid, None).await?,
+        ..Default::default()
+    };
+    let (schema, mut content) = futures_util::try_join!(
+        data.db.schema(&sheetid),
+        data.db.get_sheet_within(&sheetid, options, Some(budget))
+    )?;
+    transform(data, req, &sheetid, &mut content)?;
+    // hidden columns aren't part of the content
+    let columns = schema
+        .columns
+        .into_iter()
+        .filter(|column| content.columns.contains_key(&column.name))
+        .collect();
+    Ok((sheetid, columns, content))
+}
+
+#[get("/{sheetid}")]
+async fn get_published(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    id: web::Path<String>,
+) -> Responded<GetSheetIdResponse> {
+    let budget = data.read_budget.reserve();
+    match published_sheet(&data, &req, &id, &budget).await {
+        Ok((_, _, content)) => success(GetSheetIdResponse::Full(content)).negotiated(),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[get("/{sheetid}/csv")]
+async fn get_published_csv(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    query: Result<web::Query<LocaleQuery>, actix_web::Error>,
+) -> Either<HttpResponse, Responded<()>> {
+    // laid out like an export with the default options
+    let dialect = query
+        .map_err(ErrorResponse::invalid_query)
+        .and_then(|query| {
+            let locale = query.into_inner();
+            ExportQuery {
+                locale,
+                ..Default::default()
+            }
+            .dialect()
+        });
+    let (dialect, locale) = match dialect {
+        Ok(dialect) => dialect,
+        Err(error) => return Either::Right(failure(StatusCode::BAD_REQUEST, error)),
+    };
+    let budget = data.read_budget.reserve();
+    let file =
+        published_sheet(&data, &req, &id, &budget)
+            .await
+            .and_then(|(_, columns, content)| {
+                super::export::write_csv(&columns, &content, dialect, locale)
+            });
+
+    match file {
+        Ok(file) => Either::Left(
+            HttpResponse::Ok()
+                .content_type(format!("text/csv; charset={}", dialect.encoding.charset()))
+                .body(file),
+        ),
+        Err(why) => Either::Right(failure(error_status(&why), ErrorResponse::from_db(&why))),
+    }
+}
+
+#[get("/{sheetid}/html")]
+async fn get_published_html(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    query: Result<web::Query<LocaleQuery>, actix_web::Error>,
+) -> Either<HttpResponse, Responded<()>> {
+    let locale = match query
+        .map_err(ErrorResponse::invalid_query)
+        .and_then(|query| query.locale())
+    {
+        Ok(locale) => locale,
+        Err(error) => return Either::Right(failure(StatusCode::BAD_REQUEST, error)),
+    };
+    let budget = data.read_budget.reserve();
+    let page =
+        published_sheet(&data, &req, &id, &budget)
+            .await
+            .and_then(|(sheetid, columns, content)| {
+                super::export::write_html(sheetid.inner(), &columns, &content, locale)
+            });
+
+    match page {
+        Ok(page) => Either::Left(
+            HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .body(page),
+        ),
+        Err(why) => Either::Right(failure(error_status(&why), ErrorResponse::from_db(&why))),
+    }
+}
+
+#[post("/{sheetid}/session")]
+async fn post_session(
+    data: web::Data<crate::AppData>,
+    sheetid: Option<web::Path<SheetId>>,
+) -> Responded<super::Session> {
+    let Some(sheetid) = sheetid else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+
+    match data
+        .db
+        .open_session(&sheetid, data.config.session_ttl)
+        .await
+    {
+        Ok(session) => success(session),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CommitResponse {
+    committed: usize,
+}
+
+#[post("/{sheetid}/session/{session}/commit")]
+async fn post_session_commit(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, String)>>,
+) -> Responded<CommitResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let (sheetid, session) = path.into_inner();
+
+    match data
+        .db
+        .commit_session(&sheetid, &session, data.config.strict_lookups)
+        .await
+    {
+        Ok(cells) => {
+            notify_matching_rules(&data, &sheetid, &cells).await;
+            success(CommitResponse {
+                committed: cells.len(),
+            })
+        }
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct AbortResponse {}
+
+#[post("/{sheetid}/session/{session}/abort")]
+async fn post_session_abort(
+    data: web::Data<crate::AppData>,
+    path: Option<web::Path<(SheetId, String)>>,
+) -> Responded<AbortResponse> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let (sheetid, session) = path.into_inner();
+
+    match data.db.abort_session(&sheetid, &session).await {
+        Ok(()) => success(AbortResponse {}),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+struct PreviewQuery {
+    include_hidden: Option<String>,
+    no_lookup_nulls: Option<String>,
+    include: Option<String>,
+}
+
+#[get("/{sheetid}/session/{session}/preview")]
+async fn get_session_preview(
+    data: web::Data<crate::AppData>,
+    req: HttpRequest,
+    path: Option<web::Path<(SheetId, String)>>,
+    query: Result<web::Query<PreviewQuery>, actix_web::Error>,
+) -> Responded<super::SheetContent> {
+    let Some(path) = path else {
+        return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_sheet_id());
+    };
+    let (sheetid, session) = path.into_inner();
+    let query = match query {
+        Ok(query) => query.into_inner(),
+        Err(why) => return failure(StatusCode::BAD_REQUEST, ErrorResponse::invalid_query(why)),
+    };
+    let no_lookup_nulls =
+        match no_lookup_nulls(&data, &sheetid, query.no_lookup_nulls.as_deref()).await {
+            Ok(no_lookup_nulls) => no_lookup_nulls,
+            Err(why) => return failure(error_status(&why), ErrorResponse::from_db(&why)),
+        };
+    let options = match read_options(
+        no_lookup_nulls,
+        query.include_hidden.as_deref(),
+        query.include.as_deref(),
+    ) {
+        Ok(options) => options,
+        Err(error) => return failure(StatusCode::BAD_REQUEST, error),
+    };
+
+    let content = data
+        .db
+        .preview_session(&sheetid, &session, data.config.strict_lookups, options)
+        .await;
+    match content
+        .and_then(|mut content| transform(&data, &req, &sheetid, &mut content).map(|()| content))
+    {
+        Ok(content) => success(content),
+        Err(why) => failure(error_status(&why), ErrorResponse::from_db(&why)),
     }
 }
 
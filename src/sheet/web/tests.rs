@@ -1,36 +1,11 @@
 use actix_web::http::header::ContentType;
 use actix_web::test;
 
+use crate::config::AppConfig;
 use crate::sheet::tests::VALID_POST_PAYLOAD;
 use crate::sheet::{CellValue, SheetContent};
 
-use super::PostResponse;
-
-// this is a macro because frankly writing the return type would be a hassle
-macro_rules! init_service {
-    ($lookup_nulls:expr) => {{
-        let _ = ::env_logger::builder()
-            .is_test(true)
-            .filter_level(::log::LevelFilter::max())
-            .try_init();
-        let db = crate::db::Db::new_memory().await.unwrap();
-        let data = ::actix_web::web::Data::new(crate::AppData {
-            db,
-            no_lookup_nulls: $lookup_nulls,
-        });
-        ::actix_web::test::init_service(
-            ::actix_web::App::new()
-                .app_data(data)
-                .wrap(::actix_web::middleware::NormalizePath::trim())
-                .service(::actix_web::web::scope("/sheet").configure(super::config)),
-        )
-        .await
-    }};
-
-    () => {
-        init_service!(false)
-    };
-}
+use super::{ErrorResponse, PostResponse, Response};
 
 #[actix_web::test]
 async fn test_post_success_simple() {
@@ -63,27 +38,6 @@ async fn test_post_success() {
     assert_eq!(resp.as_object().unwrap().keys().collect::<Vec<_>>(), ["sheet_id"])
 }
 
-macro_rules! assert_is_error_response {
-    ($resp:expr) => {{
-        ::std::assert!($resp.status().is_client_error());
-
-        let body = ::actix_web::body::to_bytes($resp.into_body())
-            .await
-            .unwrap();
-        ::std::dbg!(&body);
-        let json: ::serde_json::Value = ::serde_json::from_slice(&body).unwrap();
-        ::std::assert!(json.is_object());
-        ::std::assert_eq!(
-            json.as_object()
-                .unwrap()
-                .keys()
-                .collect::<::std::vec::Vec<_>>(),
-            ["error"]
-        );
-        ::std::assert!(json["error"].is_string());
-    }};
-}
-
 #[actix_web::test]
 async fn test_post_no_payload() {
     let app = init_service!();
@@ -138,6 +92,112 @@ async fn test_post_schema_with_duplicates() {
     assert_is_error_response!(resp);
 }
 
+#[actix_web::test]
+async fn test_post_schema_details() {
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [{"name": "A", "type": "string"}, {"name": "A\n", "type": "boolean"}]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"][0]["column"], 1);
+    assert_eq!(json["details"][0]["path"], "columns[1].name");
+    assert_eq!(json["details"][0]["message"], "column name must not contain control characters");
+}
+
+#[actix_web::test]
+async fn test_post_body_details() {
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [{"name": "A", "type": "int"}, {"name": "B", "type": "intt"}]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"][0]["path"], "columns[1].type");
+
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{"column": "A", "value": [true]}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    // only the first problem of a malformed body is reported
+    assert_eq!(json["details"].as_array().unwrap().len(), 1, "{json}");
+    assert_eq!(json["details"][0]["path"], "value");
+}
+
+#[actix_web::test]
+async fn test_post_custom_id() {
+    let app = init_service!();
+
+    let payload = r#"{"id": "q3-budget", "columns": [{"name": "A", "type": "string"}]}"#;
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(payload)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(resp["sheet_id"], "q3-budget");
+
+    let req = test::TestRequest::post()
+        .uri("/sheet/q3-budget")
+        .set_payload(r#"{ "column": "A", "row": 1, "value": "hi" }"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // the same id can't be used twice
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(payload)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::CONFLICT);
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(r#"{"id": "Not A Slug", "columns": []}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_is_error_response!(resp);
+}
+
+#[actix_web::test]
+async fn test_custom_id_differing_in_case() {
+    let app = init_service!();
+
+    // sheets get tables named after their ids, which sqlite doesn't tell apart by case
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    let payload = format!(
+        r#"{{"id": "{}", "columns": [{{"name": "A", "type": "string"}}]}}"#,
+        sheet_id.to_lowercase()
+    );
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(payload)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::CONFLICT);
+}
+
 async fn get_standard_sheet<S, B>(app: &S) -> anyhow::Result<String>
 where
     S: actix_web::dev::Service<
@@ -153,13 +213,22 @@ where
         .insert_header(ContentType::json())
         .to_request();
 
-    let resp: PostResponse = test::call_and_read_body_json(&app, req).await;
+    let resp: Response<PostResponse> = test::call_and_read_body_json(&app, req).await;
     match resp {
-        PostResponse::Success { sheet_id } => Ok(sheet_id),
-        PostResponse::Failure { error } => anyhow::bail!("Error: {error:#?}"),
+        Response::Success(PostResponse { sheet_id }) => Ok(sheet_id),
+        Response::Failure(ErrorResponse { error, .. }) => anyhow::bail!("Error: {error:#?}"),
     }
 }
 
+#[actix_web::test]
+async fn test_post_multiple_sheets() {
+    let app = init_service!();
+
+    let first = get_standard_sheet(&app).await.expect("valid sheet failed");
+    let second = get_standard_sheet(&app).await.expect("valid sheet failed");
+    assert_ne!(first, second);
+}
+
 #[actix_web::test]
 async fn test_post_sheetid_simple() {
     let app = init_service!();
@@ -597,7 +666,10 @@ async fn test_get_sheetid_with_lookup_nulls() {
 
 #[actix_web::test]
 async fn test_get_sheetid_without_lookup_nulls() {
-    let app = init_service!(true);
+    let app = init_service!(AppConfig {
+        no_lookup_nulls: true,
+        ..Default::default()
+    });
 
     let sheet_id = get_standard_sheet(&app).await.expect("valid sheet failed");
 
@@ -641,3 +713,4602 @@ async fn test_get_sheetid_without_lookup_nulls() {
 
     assert_eq!(resp, should_be);
 }
+
+#[actix_web::test]
+async fn test_get_sheetid_escaped_lookup() {
+    let app = init_service!();
+
+    let sheet_id = get_standard_sheet(&app).await.expect("valid sheet failed");
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{ "column": "D", "row": 1, "value": "'lookup(\"A\", 2)" }"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let resp: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let resp = resp.with_sorted_columns();
+
+    let should_be = SheetContent::build_with_triples(&[(
+        "D",
+        1,
+        Some(CellValue::String(r#"lookup("A", 2)"#.into())),
+    )])
+    .with_potential_empty_columns(&["A", "B", "B2", "C"])
+    .with_sorted_columns();
+
+    assert_eq!(resp, should_be);
+}
+
+#[actix_web::test]
+async fn test_post_sheetid_lookup_error_details() {
+    let app = init_service!();
+
+    let sheet_id = get_standard_sheet(&app).await.expect("valid sheet failed");
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{ "column": "B", "row": 5, "value": "lookup(\"A\", 5)" }"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(
+        json["details"],
+        serde_json::json!({
+            "reason": "incompatible_kinds",
+            "column": "B",
+            "column_kind": "int",
+            "target_column": "A",
+            "target_kind": "boolean",
+        })
+    );
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{ "column": "B", "row": 5, "value": "lookup(\"Z\", 5)" }"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"]["reason"], "unknown_target_column");
+}
+
+#[actix_web::test]
+async fn test_post_sheetid_strict_lookups() {
+    let app = init_service!(AppConfig {
+        strict_lookups: true,
+        ..Default::default()
+    });
+
+    let sheet_id = get_standard_sheet(&app).await.expect("valid sheet failed");
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{ "column": "B", "row": 5, "value": "lookup(\"B\", 4)" }"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"]["reason"], "empty_target");
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{ "column": "B", "row": 4, "value": 42 }"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    test::call_service(&app, req).await;
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{ "column": "B", "row": 5, "value": "lookup(\"B\", 4)" }"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_post_recalculate() {
+    let app = init_service!();
+
+    let sheet_id = get_standard_sheet(&app).await.expect("valid sheet failed");
+
+    for payload in [
+        r#"{ "column": "B", "row": 5, "value": "lookup(\"B\", 4)" }"#,
+        r#"{ "column": "B", "row": 4, "value": "lookup(\"B2\", 3)" }"#,
+        r#"{ "column": "B2", "row": 3, "value": 1 }"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/recalculate"))
+        .to_request();
+    let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(resp, serde_json::json!({ "recalculated": 2, "broken": 0 }));
+}
+
+#[actix_web::test]
+async fn test_post_recalculate_invalid_sheet() {
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet/abCDefGHijklMnOPqrst1234/recalculate")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_is_error_response!(resp);
+}
+
+#[actix_web::test]
+async fn test_error_codes() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    let cell = r#"{"column": "B", "row": 1, "value": 5}"#;
+
+    let cases = [
+        ("/sheet/Not-Valid", cell, "invalid_sheet_id"),
+        ("/sheet/aaaaaaaaaaaaaaaaaaaaaaaa", cell, "sheet_not_found"),
+        (&format!("/sheet/{sheet_id}"), r#"{"column": "B"}"#, "invalid_body"),
+    ];
+    for (uri, body, code) in cases {
+        let req = test::TestRequest::post()
+            .uri(uri)
+            .set_payload(body)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], code);
+    }
+
+    // reads report the same codes as writes
+    let req = test::TestRequest::get()
+        .uri("/sheet/aaaaaaaaaaaaaaaaaaaaaaaa")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "sheet_not_found");
+}
+
+#[actix_web::test]
+async fn test_missing_and_empty_sheets() {
+    let app = init_service!();
+
+    for uri in [
+        "/sheet/aaaaaaaaaaaaaaaaaaaaaaaa",
+        "/sheet/missing-sheet/rows/1",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "sheet_not_found");
+    }
+
+    // every visible column is there, even though none of them has cells
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    for query in ["", "?limit=10", "?since_version=0", "?order_by=B"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}{query}"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let columns = body["columns"].as_object().unwrap();
+        let mut names: Vec<_> = columns.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["A", "B", "B2", "C", "D"], "{query}");
+        assert!(columns
+            .values()
+            .all(|cells| cells.as_array().unwrap().is_empty()));
+    }
+}
+
+#[actix_web::test]
+async fn test_list_sheets() {
+    let app = init_service!();
+
+    let mut ids = vec![];
+    for _ in 0..3 {
+        ids.push(get_standard_sheet(&app).await.unwrap());
+    }
+    // timestamps have millisecond precision, so make sure the update is strictly the latest
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    for row in 0..2 {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{}", ids[1]))
+            .set_payload(format!(r#"{{"column": "B", "row": {row}, "value": 5}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let list = |query: &'static str| test::TestRequest::get().uri(query).to_request();
+
+    let json: serde_json::Value = test::call_and_read_body_json(&app, list("/sheet")).await;
+    let listed: Vec<_> = json["sheets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| &s["id"])
+        .collect();
+    assert_eq!(listed, ids.iter().collect::<Vec<_>>());
+    assert_eq!(json["sheets"][1]["cells"], 2);
+
+    for query in [
+        "/sheet?sort=cells&order=desc",
+        "/sheet?sort=updated_at&order=desc",
+    ] {
+        let json: serde_json::Value = test::call_and_read_body_json(&app, list(query)).await;
+        assert_eq!(json["sheets"][0]["id"], ids[1].as_str());
+    }
+
+    let resp = test::call_service(&app, list("/sheet?sort=size")).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "invalid_query");
+}
+
+#[actix_web::test]
+async fn test_hidden_columns() {
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [{"name": "A", "type": "int"}, {"name": "internal", "type": "int", "hidden": true}]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let sheet_id = resp["sheet_id"].as_str().unwrap();
+
+    for (column, value) in [("internal", "7"), ("A", r#""lookup(\"internal\", 1)""#)] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "{column}", "row": 1, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert!(!content.columns.contains_key("internal"));
+    // lookups into hidden columns still resolve
+    assert_eq!(content.columns["A"][0].value, Some(CellValue::Int(7)));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?include_hidden=1"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert!(content.columns.contains_key("internal"));
+}
+
+#[derive(Default)]
+struct RecordingNotifier(std::sync::Mutex<Vec<crate::notify::Notification>>);
+
+impl crate::notify::Notifier for RecordingNotifier {
+    fn notify<'a>(
+        &'a self,
+        notification: &'a crate::notify::Notification,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = anyhow::Result<()>> + Send + 'a>> {
+        self.0.lock().unwrap().push(notification.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[actix_web::test]
+async fn test_notification_rules() {
+    let notifier = std::sync::Arc::new(RecordingNotifier::default());
+    let db = crate::db::Db::new_memory().await.unwrap();
+    let app = init_service!(data: crate::AppData::new(db, AppConfig::default())
+        .with_notifier(notifier.clone()));
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let rule = r#"{
+        "column": "B",
+        "condition": {"op": "lt", "value": 0},
+        "target": {"kind": "slack", "webhook_url": "http://localhost/hook"}
+    }"#;
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/notifications"))
+        .set_payload(rule)
+        .insert_header(ContentType::json())
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let rule_id = json["id"].as_i64().unwrap();
+
+    // a condition which can never match the column is rejected
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/notifications"))
+        .set_payload(rule.replace(r#""value": 0"#, r#""value": "zero""#))
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_is_error_response!(resp);
+
+    for value in [5, -3] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "B", "row": 2, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    // delivery happens in the background
+    for _ in 0..100 {
+        if !notifier.0.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let sent = notifier.0.lock().unwrap().clone();
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].message.contains("B2 was set to -3"));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/notifications"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["rules"][0]["id"], rule_id);
+    assert_eq!(json["rules"][0]["target"]["kind"], "slack");
+
+    let req = test::TestRequest::delete()
+        .uri(&format!("/sheet/{sheet_id}/notifications/{rule_id}"))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::delete()
+        .uri(&format!("/sheet/{sheet_id}/notifications/{rule_id}"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+}
+
+/// Starts a validator which answers every request with the given status and body, and returns its url.
+fn fake_validator(status: &'static str, body: &'static str) -> String {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+            let mut request = vec![0; content_length];
+            let _ = reader.read_exact(&mut request);
+            let _ = write!(
+                reader.get_mut(),
+                "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        }
+    });
+    url
+}
+
+/// Upper-cases the strings of column D, and rejects negative ints.
+struct ShoutingInterceptor;
+
+impl crate::intercept::WriteInterceptor for ShoutingInterceptor {
+    fn intercept<'a>(
+        &'a self,
+        write: crate::intercept::CellWrite<'a>,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = anyhow::Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            match &write.cell.value {
+                CellValue::String(text) if write.cell.column == "D" => {
+                    write.cell.value = CellValue::String(text.trim().to_uppercase());
+                }
+                CellValue::Int(x) if *x < 0 => {
+                    return Err(crate::intercept::WriteRejected(format!("{x} is negative")).into());
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+}
+
+#[actix_web::test]
+async fn test_write_interceptors() {
+    let db = crate::db::Db::new_memory()
+        .await
+        .unwrap()
+        .with_interceptor(std::sync::Arc::new(ShoutingInterceptor));
+    let app = init_service!(data: crate::AppData::new(db, AppConfig::default()));
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let post = |uri: String, payload: &str| {
+        test::TestRequest::post()
+            .uri(&uri)
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let get = || async {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .to_request();
+        let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+        content
+    };
+
+    let uri = format!("/sheet/{sheet_id}");
+    let resp = test::call_service(
+        &app,
+        post(uri.clone(), r#"{"column": "D", "row": 1, "value": " hi "}"#),
+    )
+    .await;
+    assert!(resp.status().is_success());
+    let resp =
+        test::call_service(&app, post(uri, r#"{"column": "B", "row": 1, "value": -5}"#)).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "write_rejected");
+    assert_eq!(json["error"], "the write was rejected: -5 is negative");
+
+    let content = get().await;
+    assert_eq!(content.columns["D"][0].value, Some(CellValue::String("HI".into())));
+    assert!(content.columns["B"].is_empty());
+
+    // every cell of a session is intercepted when it's committed, so a single rejected one fails them all
+    let session: serde_json::Value =
+        test::call_and_read_body_json(&app, post(format!("/sheet/{sheet_id}/session"), "")).await;
+    let session = session["id"].as_str().unwrap().to_owned();
+    for payload in [
+        r#"{"column": "D", "row": 2, "value": "there"}"#,
+        r#"{"column": "B", "row": 2, "value": -1}"#,
+    ] {
+        let resp =
+            test::call_service(&app, post(format!("/sheet/{sheet_id}?session={session}"), payload))
+                .await;
+        assert!(resp.status().is_success());
+    }
+    let resp =
+        test::call_service(&app, post(format!("/sheet/{sheet_id}/session/{session}/commit"), ""))
+            .await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "write_rejected");
+    assert_eq!(get().await.columns["D"].len(), 1);
+}
+
+struct MaskingTransformer;
+
+impl crate::transform::ReadTransformer for MaskingTransformer {
+    fn transform(&self, _: &crate::db::SheetId, content: &mut SheetContent) -> anyhow::Result<()> {
+        for cell in content.columns.get_mut("D").into_iter().flatten() {
+            cell.value = Some(CellValue::String("***".into()));
+        }
+        Ok(())
+    }
+}
+
+struct ScalingTransformer(i64);
+
+impl crate::transform::ReadTransformer for ScalingTransformer {
+    fn transform(&self, _: &crate::db::SheetId, content: &mut SheetContent) -> anyhow::Result<()> {
+        for cell in content.columns.get_mut("B").into_iter().flatten() {
+            if let Some(CellValue::Int(x)) = &mut cell.value {
+                *x = x
+                    .checked_mul(self.0)
+                    .ok_or_else(|| anyhow::anyhow!("{x} overflowed"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[actix_web::test]
+async fn test_read_transformers() {
+    let db = crate::db::Db::new_memory().await.unwrap();
+    let data = crate::AppData::new(db, AppConfig::default())
+        .with_read_transformer(std::sync::Arc::new(MaskingTransformer))
+        .with_named_read_transformer("thousands", std::sync::Arc::new(ScalingTransformer(1000)))
+        .with_named_read_transformer("overflow", std::sync::Arc::new(ScalingTransformer(i64::MAX)));
+    let app = init_service!(data: data);
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 3}"#,
+        r#"{"column": "D", "row": 1, "value": "secret"}"#,
+        r#"{"column": "D", "row": 2, "value": "lookup(\"D\", 1)"}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let get = |uri: String, transform: Option<&str>| {
+        let mut req = test::TestRequest::get().uri(&uri);
+        if let Some(transform) = transform {
+            req = req.insert_header((crate::transform::TRANSFORM_HEADER, transform));
+        }
+        req.to_request()
+    };
+
+    let content: SheetContent =
+        test::call_and_read_body_json(&app, get(format!("/sheet/{sheet_id}"), None)).await;
+    assert_eq!(content.columns["B"][0].value, Some(CellValue::Int(3)));
+    assert_eq!(content.columns["D"][0].value, Some(CellValue::String("***".into())));
+
+    // named transformers run in the order they're asked for, and may be asked for more than once
+    let content: SheetContent = test::call_and_read_body_json(
+        &app,
+        get(format!("/sheet/{sheet_id}"), Some("thousands, thousands")),
+    )
+    .await;
+    assert_eq!(content.columns["B"][0].value, Some(CellValue::Int(3_000_000)));
+    assert_eq!(content.columns["D"][0].value, Some(CellValue::String("***".into())));
+
+    let resp =
+        test::call_service(&app, get(format!("/sheet/{sheet_id}/export"), Some("thousands"))).await;
+    assert!(resp.status().is_success());
+    let body = test::read_body(resp).await;
+    let csv = std::str::from_utf8(&body).unwrap();
+    assert!(csv.contains("3000") && csv.contains("***") && !csv.contains("secret"));
+
+    let resp =
+        test::call_service(&app, get(format!("/sheet/{sheet_id}"), Some("thousands,nope"))).await;
+    assert_eq!(resp.status(), 400);
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "unknown_transformer");
+
+    let resp =
+        test::call_service(&app, get(format!("/sheet/{sheet_id}/tail"), Some("overflow"))).await;
+    assert_eq!(resp.status(), 500);
+
+    // every other read of the cells is transformed the same way
+    for uri in [
+        format!("/sheet/{sheet_id}/columns/D"),
+        format!("/sheet/{sheet_id}/columns/B"),
+        format!("/sheet/{sheet_id}/rows/1"),
+        format!("/sheet/{sheet_id}/rows?ids=1,2"),
+        format!("/sheet/{sheet_id}/grid?rows=1-2"),
+        format!("/sheet/{sheet_id}/join?with={sheet_id}&left_on=B&right_on=B"),
+        format!("/sheet/{sheet_id}/explain?column=D&row=2"),
+    ] {
+        let resp = test::call_service(&app, get(uri.clone(), Some("thousands"))).await;
+        assert!(resp.status().is_success(), "{uri}");
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(
+            !body.contains("secret") && !body.contains(":3,") && !body.contains(":3}"),
+            "{uri}: {body}"
+        );
+    }
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/cells:get"))
+        .set_payload(r#"{"cells": [{"column": "D", "row": 2}, {"column": "D", "row": 1}]}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["cells"][0]["value"], "***");
+    assert_eq!(json["cells"][1]["value"], "***");
+
+    let resp =
+        test::call_service(&app, get(format!("/sheet/{sheet_id}/rows/1"), Some("overflow"))).await;
+    assert_eq!(resp.status(), 500);
+}
+
+#[actix_web::test]
+async fn test_validators() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let set_validator = |payload: String| {
+        test::TestRequest::put()
+            .uri(&format!("/sheet/{sheet_id}/validator"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let set_cell = || {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(r#"{"column": "B", "row": 1, "value": 3}"#)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    let url = fake_validator("422 Unprocessable Entity", r#"{"reason": "too small"}"#);
+    let resp = test::call_service(&app, set_validator(format!(r#"{{"url": "{url}"}}"#))).await;
+    assert!(resp.status().is_success());
+    let resp = test::call_service(&app, set_cell()).await;
+    assert_eq!(resp.status(), 400);
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "rejected_by_validator");
+    assert!(json["error"].as_str().unwrap().contains("too small"));
+
+    // every other way of writing cells asks the validator as well
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import"))
+        .set_payload("B,B2\n1,2\n")
+        .insert_header(("Content-Type", "text/csv"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "rejected_by_validator");
+    assert!(json["error"].as_str().unwrap().starts_with("rows[1].B: "));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert!(content.columns.values().all(Vec::is_empty));
+
+    let url = fake_validator("204 No Content", "");
+    test::call_service(&app, set_validator(format!(r#"{{"url": "{url}"}}"#))).await;
+    assert!(test::call_service(&app, set_cell())
+        .await
+        .status()
+        .is_success());
+
+    // nothing listens on the port of a dropped listener
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", closed.local_addr().unwrap());
+    drop(closed);
+
+    test::call_service(&app, set_validator(format!(r#"{{"url": "{url}"}}"#))).await;
+    let resp = test::call_service(&app, set_cell()).await;
+    assert_eq!(resp.status(), 503);
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(json["code"], "validator_unavailable");
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/column/B2/mirror"))
+        .set_payload(r#"{"source": "B"}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    let json: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(json["code"], "validator_unavailable");
+
+    let payload = format!(r#"{{"url": "{url}", "fail_open": true}}"#);
+    test::call_service(&app, set_validator(payload)).await;
+    assert!(test::call_service(&app, set_cell())
+        .await
+        .status()
+        .is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/validator"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["validator"]["fail_open"], true);
+    assert_eq!(json["validator"]["timeout_ms"], 2000);
+
+    let resp = test::call_service(&app, set_validator(r#"{"url": "ftp://x"}"#.into())).await;
+    assert_is_error_response!(resp);
+
+    let req = test::TestRequest::delete()
+        .uri(&format!("/sheet/{sheet_id}/validator"))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/validator"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(json["validator"].is_null());
+}
+
+#[actix_web::test]
+async fn test_row_meta() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/row/3/meta"))
+        .set_payload(r#"{"reviewed": true, "flag": "red"}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["meta"]["flag"], "red");
+
+    // changes are merged, and null removes a key
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/row/3/meta"))
+        .set_payload(r#"{"flag": null, "owner": {"name": "sam"}}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["meta"], serde_json::json!({"reviewed": true, "owner": {"name": "sam"}}));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/row/4/meta"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["meta"], serde_json::json!({}));
+
+    // metadata is only part of the sheet when asked for
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(json.get("meta").is_none());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?include=meta"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["meta"]["3"]["reviewed"], true);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?include=everything"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "invalid_query");
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/row/3/meta"))
+        .set_payload("[1, 2]")
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_is_error_response!(resp);
+}
+
+#[actix_web::test]
+async fn test_column_stats() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let cells = [
+        ("B", 1, "4"),
+        ("B", 2, "-2"),
+        ("B", 3, "10"),
+        ("B", 4, r#""lookup(\"B\", 3)""#),
+        // overwriting the maximum has to find the next one
+        ("B", 3, "7"),
+        ("D", 1, r#""pear""#),
+        ("D", 2, r#""apple""#),
+        // replacing a value with a lookup removes it from the stats
+        ("B", 2, r#""lookup(\"B\", 1)""#),
+    ];
+    for (column, row, value) in cells {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "{column}", "row": {row}, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/stats"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        json["columns"]["B"],
+        serde_json::json!({"count": 2, "sum": 11.0, "min": 4, "max": 7})
+    );
+    assert_eq!(
+        json["columns"]["D"],
+        serde_json::json!({"count": 2, "min": "apple", "max": "pear"})
+    );
+    assert_eq!(
+        json["columns"]["C"],
+        serde_json::json!({"count": 0, "sum": 0.0, "min": null, "max": null})
+    );
+
+    // rebuilding the stats from scratch gives the same result
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/recalculate"))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/stats"))
+        .to_request();
+    let rebuilt: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json, rebuilt);
+}
+
+#[actix_web::test]
+async fn test_lookup_stats() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/stats/lookups"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "lookups": 0,
+            "chain_lengths": {"buckets": [], "max": 0},
+            "fan_in": {"buckets": [], "max": 0}
+        })
+    );
+
+    // B4 -> B3 -> B2 -> B1, and B5 and B6 -> B1
+    let cells = [
+        ("B", 1, "1"),
+        ("B", 2, r#""lookup(\"B\", 1)""#),
+        ("B", 3, r#""lookup(\"B\", 2)""#),
+        ("B", 4, r#""lookup(\"B\", 3)""#),
+        ("B", 5, r#""lookup(\"B\", 1)""#),
+        ("B", 6, r#""lookup(\"B\", 1)""#),
+    ];
+    for (column, row, value) in cells {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "{column}", "row": {row}, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/stats/lookups"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "lookups": 5,
+            "chain_lengths": {
+                "buckets": [{"up_to": 1, "count": 3}, {"up_to": 2, "count": 1}, {"up_to": 4, "count": 1}],
+                "max": 3
+            },
+            "fan_in": {
+                "buckets": [{"up_to": 1, "count": 2}, {"up_to": 2, "count": 0}, {"up_to": 4, "count": 1}],
+                "max": 3
+            }
+        })
+    );
+}
+
+#[actix_web::test]
+async fn test_tail() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let cells = [
+        ("B", 1, "1"),
+        ("B", 2, "2"),
+        ("D", 3, r#""three""#),
+        ("B", 4, r#""lookup(\"B2\", 9)""#),
+        ("B", 5, r#""lookup(\"B\", 4)""#),
+        ("B2", 9, "9"),
+        // a lookup to an empty cell still counts as a populated row
+        ("C", 10, "1.5"),
+        ("C", 10, r#""lookup(\"C\", 11)""#),
+    ];
+    for (column, row, value) in cells {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "{column}", "row": {row}, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/tail?n=3"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let rows = |column: &str| -> Vec<(i64, Option<CellValue>)> {
+        content.columns[column]
+            .iter()
+            .map(|cell| (cell.row, cell.value.clone()))
+            .collect()
+    };
+    assert_eq!(rows("B"), [(5, Some(CellValue::Int(9)))]);
+    assert_eq!(rows("B2"), [(9, Some(CellValue::Int(9)))]);
+    assert_eq!(rows("C"), [(10, None)]);
+    assert!(rows("D").is_empty());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/tail"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let rows: Vec<i64> = content.columns["B"].iter().map(|cell| cell.row).collect();
+    assert_eq!(rows, [5, 4, 2, 1]);
+
+    for query in ["n=0", "n=lots"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/tail?{query}"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_query");
+    }
+}
+
+#[actix_web::test]
+async fn test_cursor_pagination() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for row in [2, 3, 5, 8, 13] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "B", "row": {row}, "value": {row}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{"column": "B2", "row": 21, "value": "lookup(\"B\", 2)"}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let mut cursor = None;
+    let mut pages = vec![];
+    loop {
+        let uri = match cursor {
+            Some(cursor) => format!("/sheet/{sheet_id}?limit=2&after_row={cursor}"),
+            None => format!("/sheet/{sheet_id}?limit=2"),
+        };
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let rows: Vec<i64> = ["B", "B2"]
+            .iter()
+            .flat_map(|column| json["columns"][column].as_array().unwrap())
+            .map(|cell| cell["row"].as_i64().unwrap())
+            .collect();
+        pages.push(rows);
+
+        cursor = json["next_cursor"].as_i64();
+        if cursor.is_none() {
+            assert!(json["next_cursor"].is_null());
+            assert_eq!(json["columns"]["B2"][0]["value"], 2);
+            break;
+        }
+    }
+    assert_eq!(pages, [vec![2, 3], vec![5, 8], vec![13, 21]]);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?after_row=21"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["columns"]["B"], serde_json::json!([]));
+    assert!(json["next_cursor"].is_null());
+
+    // reading the whole sheet doesn't mention cursors at all
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(json.get("next_cursor").is_none());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?limit=0"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "invalid_query");
+}
+
+#[actix_web::test]
+async fn test_schema_check() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let cells = [
+        ("B", 1, "1"),
+        ("B", 2, r#""lookup(\"B2\", 1)""#),
+        ("B2", 1, "5"),
+        ("C", 1, "1.5"),
+        ("C", 2, "2.0"),
+        ("D", 1, r#""12""#),
+    ];
+    for (column, row, value) in cells {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "{column}", "row": {row}, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let check = |schema: &'static str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}/schema/check"))
+            .set_payload(schema)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    let json: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        check(
+            r#"{"columns": [
+                {"name": "A", "type": "boolean"},
+                {"name": "B", "type": "double"},
+                {"name": "C", "type": "int"},
+                {"name": "D", "type": "int"},
+                {"name": "E", "type": "string"}
+            ]}"#,
+        ),
+    )
+    .await;
+    assert_eq!(json["compatible"], false);
+    let verdicts: Vec<_> = json["columns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|column| {
+            (
+                column["name"].as_str().unwrap(),
+                column["verdict"].as_str().unwrap(),
+                column["offending_rows"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        verdicts,
+        [
+            ("A", "unchanged", serde_json::json!([])),
+            // the lookup points at B2, which stays an int
+            ("B", "converted", serde_json::json!([2])),
+            ("C", "converted", serde_json::json!([1])),
+            ("D", "converted", serde_json::json!([])),
+            ("E", "added", serde_json::json!([])),
+            ("B2", "removed", serde_json::json!([1])),
+        ]
+    );
+
+    let json: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        check(
+            r#"{"columns": [
+                {"name": "A", "type": "boolean"},
+                {"name": "B", "type": "int"},
+                {"name": "B2", "type": "int"},
+                {"name": "C", "type": "string"},
+                {"name": "D", "type": "string"}
+            ]}"#,
+        ),
+    )
+    .await;
+    assert_eq!(json["compatible"], true);
+
+    let resp = test::call_service(&app, check(r#"{"columns": [{"name": "A"}]}"#)).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"][0]["path"], "columns[0].type");
+}
+
+#[actix_web::test]
+async fn test_type_widening() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let set_cell = |column: &str, row: i64, value: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "{column}", "row": {row}, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    assert!(test::call_service(&app, set_cell("B", 1, "3"))
+        .await
+        .status()
+        .is_success());
+    let resp = test::call_service(&app, set_cell("B", 2, "2.5")).await;
+    assert_is_error_response!(resp);
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .set_payload(r#"{"widen_types": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        json,
+        serde_json::json!({"widen_types": true, "row_keys": null, "published": false, "row_bounds": null, "compact_lookups": false, "a1_refs": false})
+    );
+
+    // a column with lookups to other columns keeps its type
+    assert!(test::call_service(&app, set_cell("B2", 1, r#""lookup(\"B\", 1)""#))
+        .await
+        .status()
+        .is_success());
+    let resp = test::call_service(&app, set_cell("B2", 2, "0.5")).await;
+    assert_is_error_response!(resp);
+
+    assert!(test::call_service(&app, set_cell("B", 1, r#""lookup(\"B\", 5)""#))
+        .await
+        .status()
+        .is_success());
+    assert!(test::call_service(&app, set_cell("B", 5, "4"))
+        .await
+        .status()
+        .is_success());
+    assert!(test::call_service(&app, set_cell("B2", 1, "7"))
+        .await
+        .status()
+        .is_success());
+    assert!(test::call_service(&app, set_cell("B", 2, "2.5"))
+        .await
+        .status()
+        .is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let mut column = content.columns["B"].clone();
+    column.sort_by_key(|cell| cell.row);
+    let values: Vec<_> = column.into_iter().map(|cell| cell.value).collect();
+    assert_eq!(
+        values,
+        [
+            Some(CellValue::Double(4.0)),
+            Some(CellValue::Double(2.5)),
+            Some(CellValue::Double(4.0))
+        ]
+    );
+
+    // the column only takes doubles from now on
+    let resp = test::call_service(&app, set_cell("B", 3, "1")).await;
+    assert_is_error_response!(resp);
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/stats"))
+        .to_request();
+    let json: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(json["columns"]["B"]["max"], 4.0);
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .set_payload(r#"{"widen": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_is_error_response!(resp);
+}
+
+#[actix_web::test]
+async fn test_mirror_column() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let cells = [
+        ("B", 1, "1"),
+        ("B", 4, "4"),
+        ("B", 6, r#""lookup(\"B\", 1)""#),
+        ("B2", 4, "40"),
+        ("B2", 9, "90"),
+    ];
+    for (column, row, value) in cells {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "{column}", "row": {row}, "value": {value}}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let mirror = |column: &str, source: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}/column/{column}/mirror"))
+            .set_payload(format!(r#"{{"source": "{source}"}}"#))
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    let json: serde_json::Value = test::call_and_read_body_json(&app, mirror("B2", "B")).await;
+    assert_eq!(json["written"], 3);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let mut column = content.columns["B2"].clone();
+    column.sort_by_key(|cell| cell.row);
+    let cells: Vec<_> = column
+        .into_iter()
+        .map(|cell| (cell.row, cell.value))
+        .collect();
+    assert_eq!(
+        cells,
+        [
+            (1, Some(CellValue::Int(1))),
+            (4, Some(CellValue::Int(4))),
+            (6, Some(CellValue::Int(1))),
+            (9, Some(CellValue::Int(90))),
+        ]
+    );
+
+    // mirroring back would create cycles, so nothing is written
+    let resp = test::call_service(&app, mirror("B", "B2")).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"]["reason"], "cycle");
+
+    let resp = test::call_service(&app, mirror("D", "B")).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"]["reason"], "incompatible_kinds");
+}
+
+#[actix_web::test]
+async fn test_row_keys() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let set_cell = |payload: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    // rows don't have keys by default
+    let json: serde_json::Value =
+        test::call_and_read_body_json(&app, set_cell(r#"{"column": "B", "row": 1, "value": 1}"#))
+            .await;
+    assert_eq!(json, serde_json::json!({}));
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .set_payload(r#"{"row_keys": "uuidv7"}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let json: serde_json::Value =
+        test::call_and_read_body_json(&app, set_cell(r#"{"column": "B", "row": 2, "value": 2}"#))
+            .await;
+    let key = json["row_key"].as_str().unwrap().to_owned();
+    assert_eq!(key.len(), 36);
+
+    let payload = format!(r#"{{"column": "B2", "row_key": "{key}", "value": 20}}"#);
+    let json: serde_json::Value = test::call_and_read_body_json(&app, set_cell(&payload)).await;
+    assert_eq!(json["row_key"], key.as_str());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?include=row_keys"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let row_keys = content.row_keys.unwrap();
+    // the row populated before keys were enabled got one as well
+    assert_eq!(row_keys.len(), 2);
+    assert_eq!(row_keys[&2], key);
+    assert_eq!(content.columns["B2"][0].value, Some(CellValue::Int(20)));
+
+    for payload in [
+        r#"{"column": "B", "row_key": "nope", "value": 1}"#.to_owned(),
+        format!(r#"{{"column": "B", "row": 2, "row_key": "{key}", "value": 1}}"#),
+    ] {
+        let resp = test::call_service(&app, set_cell(&payload)).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["details"][0]["path"], "row_key");
+    }
+}
+
+#[actix_web::test]
+async fn test_sessions() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let post = |uri: String, payload: &str| {
+        test::TestRequest::post()
+            .uri(&uri)
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let get_b = || async {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .to_request();
+        let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+        content.columns["B"].clone()
+    };
+
+    let session: serde_json::Value =
+        test::call_and_read_body_json(&app, post(format!("/sheet/{sheet_id}/session"), "")).await;
+    let session = session["id"].as_str().unwrap().to_owned();
+    let staged_uri = format!("/sheet/{sheet_id}?session={session}");
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 5}"#,
+        r#"{"column": "B", "row": 2, "value": "lookup(\"B\", 1)"}"#,
+    ] {
+        let json: serde_json::Value =
+            test::call_and_read_body_json(&app, post(staged_uri.clone(), payload)).await;
+        assert_eq!(json, serde_json::json!({"staged": true}));
+    }
+    // nothing is visible before the commit
+    assert!(get_b().await.is_empty());
+
+    let json: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        post(format!("/sheet/{sheet_id}/session/{session}/commit"), ""),
+    )
+    .await;
+    assert_eq!(json["committed"], 2);
+    let column = get_b().await;
+    assert_eq!(column.len(), 2);
+    assert_eq!(column[1].value, Some(CellValue::Int(5)));
+
+    // committed sessions are gone
+    let resp =
+        test::call_service(&app, post(staged_uri, r#"{"column": "B", "row": 3, "value": 1}"#))
+            .await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "session_not_found");
+
+    // an aborted session discards its writes, and a failing commit writes nothing
+    for end in ["abort", "commit"] {
+        let session: serde_json::Value =
+            test::call_and_read_body_json(&app, post(format!("/sheet/{sheet_id}/session"), ""))
+                .await;
+        let session = session["id"].as_str().unwrap().to_owned();
+        for payload in [
+            r#"{"column": "B", "row": 3, "value": 3}"#,
+            r#"{"column": "B", "row": 4, "value": "lookup(\"B\", 4)"}"#,
+        ] {
+            let req = post(format!("/sheet/{sheet_id}?session={session}"), payload);
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+
+        let req = post(format!("/sheet/{sheet_id}/session/{session}/{end}"), "");
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().is_success(), end == "abort");
+        assert_eq!(get_b().await.len(), 2);
+    }
+}
+
+#[actix_web::test]
+async fn test_session_preview() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let post = |uri: String, payload: &str| {
+        test::TestRequest::post()
+            .uri(&uri)
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let app = &app;
+    let read = |uri: String| async move {
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let content: SheetContent = test::call_and_read_body_json(app, req).await;
+        let mut column = content.columns["B"].clone();
+        column.sort_by_key(|cell| cell.row);
+        column
+            .into_iter()
+            .map(|cell| (cell.row, cell.value))
+            .collect::<Vec<_>>()
+    };
+
+    let req = post(format!("/sheet/{sheet_id}"), r#"{"column": "B", "row": 1, "value": 5}"#);
+    assert!(test::call_service(app, req).await.status().is_success());
+
+    let session: serde_json::Value =
+        test::call_and_read_body_json(app, post(format!("/sheet/{sheet_id}/session"), "")).await;
+    let session = session["id"].as_str().unwrap().to_owned();
+    for payload in [
+        r#"{"column": "B", "row": 2, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "B", "row": 1, "value": 7}"#,
+    ] {
+        let req = post(format!("/sheet/{sheet_id}?session={session}"), payload);
+        assert!(test::call_service(app, req).await.status().is_success());
+    }
+
+    // the preview resolves lookups against the staged writes, but nothing is visible outside of it
+    let preview_uri = format!("/sheet/{sheet_id}/session/{session}/preview");
+    let staged = vec![(1, Some(CellValue::Int(7))), (2, Some(CellValue::Int(7)))];
+    assert_eq!(read(preview_uri.clone()).await, staged);
+    assert_eq!(read(format!("/sheet/{sheet_id}")).await, [(1, Some(CellValue::Int(5)))]);
+    // previewing doesn't use up the session
+    assert_eq!(read(preview_uri.clone()).await, staged);
+
+    // a write that would make the commit fail makes the preview fail the same way
+    let req = post(
+        format!("/sheet/{sheet_id}?session={session}"),
+        r#"{"column": "B", "row": 1, "value": "lookup(\"B\", 2)"}"#,
+    );
+    assert!(test::call_service(app, req).await.status().is_success());
+    let req = test::TestRequest::get().uri(&preview_uri).to_request();
+    let resp = test::call_service(app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"]["reason"], "cycle");
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/session/nope/preview"))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "session_not_found");
+}
+
+#[actix_web::test]
+async fn test_delta_reads() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let set_cell = |payload: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let app = &app;
+    let read = |query: &str| {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}?{query}"))
+            .to_request();
+        async move {
+            let content: SheetContent = test::call_and_read_body_json(app, req).await;
+            content.with_sorted_columns()
+        }
+    };
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 1}"#,
+        r#"{"column": "B", "row": 2, "value": 2}"#,
+        r#"{"column": "B", "row": 4, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "D", "row": 1, "value": "x"}"#,
+    ] {
+        assert!(test::call_service(app, set_cell(payload))
+            .await
+            .status()
+            .is_success());
+    }
+    let version = read("include=version").await.version.unwrap();
+    assert_eq!(read("since_version=0").await.version, Some(version));
+
+    // nothing changed yet, but the columns are still there
+    let content = read(&format!("since_version={version}")).await;
+    assert_eq!(content.columns.len(), 5);
+    assert!(content.columns.values().all(Vec::is_empty));
+
+    // lookups are included along with the cells they point at
+    let req = set_cell(r#"{"column": "B", "row": 1, "value": 5}"#);
+    assert!(test::call_service(app, req).await.status().is_success());
+    let content = read(&format!("since_version={version}")).await;
+    assert_eq!(content.version, Some(version + 1));
+    let mut expected = SheetContent::build_with_triples(&[
+        ("B", 1, Some(CellValue::Int(5))),
+        ("B", 4, Some(CellValue::Int(5))),
+    ]);
+    expected.version = Some(version + 1);
+    assert_eq!(content, expected);
+
+    // widening a column changes all of its cells
+    let req = test::TestRequest::put()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .set_payload(r#"{"widen_types": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(app, req).await.status().is_success());
+    let req = set_cell(r#"{"column": "B", "row": 3, "value": 1.5}"#);
+    assert!(test::call_service(app, req).await.status().is_success());
+    let content = read(&format!("since_version={}", version + 1)).await;
+    assert_eq!(content.columns.len(), 1);
+    assert_eq!(content.columns["B"].len(), 4);
+    assert_eq!(content.columns["B"][0].value, Some(CellValue::Double(5.0)));
+
+    for query in ["since_version=1&limit=5", "since_version=1&include=meta"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}?{query}"))
+            .to_request();
+        let resp = test::call_service(app, req).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_query");
+    }
+}
+
+#[actix_web::test]
+async fn test_cells_from_params() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let with_query = |query: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}?{query}"))
+            .to_request()
+    };
+    let with_form = |form: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(form.to_owned())
+            .insert_header(ContentType::form_url_encoded())
+            .to_request()
+    };
+
+    for req in [
+        with_query("column=B&row=5&value=42&type=int"),
+        with_query("column=A&row=1&value=true"),
+        with_query("column=D&row=1&value=12&type=string"),
+        with_query("column=D&row=2&value=hello%20there"),
+        with_form("column=C&row=1&value=3&type=double"),
+        with_form("column=B2&row=1&value=lookup(%22B%22%2C5)"),
+    ] {
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let content = content.with_sorted_columns();
+    let expected = SheetContent::build_with_triples(&[
+        ("A", 1, Some(CellValue::Boolean(true))),
+        ("B", 5, Some(CellValue::Int(42))),
+        ("B2", 1, Some(CellValue::Int(42))),
+        ("C", 1, Some(CellValue::Double(3.0))),
+        ("D", 1, Some(CellValue::String("12".into()))),
+        ("D", 2, Some(CellValue::String("hello there".into()))),
+    ]);
+    assert_eq!(content, expected);
+
+    for (req, path) in [
+        (with_query("column=B&row=5&value=4.5&type=int"), "value"),
+        (with_query("column=B&row=five&value=4"), "row"),
+        (with_form("column=B&row=5&value=4&type=integer"), "type"),
+        (with_query("column=B&row=5"), "value"),
+    ] {
+        let resp = test::call_service(&app, req).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_body");
+        assert_eq!(json["details"][0]["path"], path);
+    }
+}
+
+#[actix_web::test]
+async fn test_protobuf() {
+    use prost::Message;
+
+    use crate::sheet::proto;
+
+    let app = init_service!();
+
+    let protobuf = |uri: &str, body: Vec<u8>| {
+        test::TestRequest::post()
+            .uri(uri)
+            .set_payload(body)
+            .insert_header(("Content-Type", proto::CONTENT_TYPE))
+            .insert_header(("Accept", proto::CONTENT_TYPE))
+            .to_request()
+    };
+
+    let new_sheet = proto::NewSheet {
+        columns: vec![
+            proto::SchemaColumn {
+                name: "A".into(),
+                r#type: proto::ColumnType::Int as i32,
+                hidden: false,
+                format: None,
+                pii: false,
+                references: None,
+            },
+            proto::SchemaColumn {
+                name: "B".into(),
+                r#type: proto::ColumnType::String as i32,
+                hidden: false,
+                format: None,
+                pii: false,
+                references: None,
+            },
+        ],
+        id: None,
+        no_lookup_nulls: None,
+    };
+    let resp = test::call_service(&app, protobuf("/sheet", new_sheet.encode_to_vec())).await;
+    assert!(resp.status().is_success());
+    assert_eq!(resp.headers().get("content-type").unwrap(), proto::CONTENT_TYPE);
+    let body = test::read_body(resp).await;
+    let sheet_id = proto::PostResponse::decode(body).unwrap().sheet_id;
+
+    let uri = format!("/sheet/{sheet_id}");
+    for (column, value) in [
+        ("A", proto::value::Kind::Int(5)),
+        ("B", proto::value::Kind::String("five".into())),
+    ] {
+        let cell = proto::Cell {
+            column: column.into(),
+            row: Some(1),
+            row_key: None,
+            value: Some(proto::Value { kind: Some(value) }),
+            r#ref: None,
+        };
+        let resp = test::call_service(&app, protobuf(&uri, cell.encode_to_vec())).await;
+        assert!(resp.status().is_success());
+        let body = test::read_body(resp).await;
+        assert_eq!(
+            proto::PostSheetIdResponse::decode(body).unwrap(),
+            proto::PostSheetIdResponse::default()
+        );
+    }
+
+    // errors are still JSON
+    let cell = proto::Cell {
+        column: "A".into(),
+        row: Some(2),
+        row_key: None,
+        value: Some(proto::Value {
+            kind: Some(proto::value::Kind::String("five".into())),
+        }),
+        r#ref: None,
+    };
+    let resp = test::call_service(&app, protobuf(&uri, cell.encode_to_vec())).await;
+    assert_is_error_response!(resp);
+
+    let req = test::TestRequest::get()
+        .uri(&uri)
+        .insert_header(("Accept", "application/x-protobuf"))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let content = proto::SheetContent::decode(body).unwrap();
+    assert_eq!(content.columns.len(), 2);
+    assert_eq!(
+        content.columns["A"].cells,
+        [proto::ColumnCell {
+            row: 1,
+            value: Some(proto::Value {
+                kind: Some(proto::value::Kind::Int(5))
+            }),
+            error: None,
+        }]
+    );
+
+    // cells can be addressed by their ref instead, once the sheet allows it
+    let req = test::TestRequest::put()
+        .uri(&format!("{uri}/settings"))
+        .set_payload(r#"{"a1_refs": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let cell = proto::Cell {
+        value: Some(proto::Value {
+            kind: Some(proto::value::Kind::Int(6)),
+        }),
+        r#ref: Some("A2".into()),
+        ..Default::default()
+    };
+    let resp = test::call_service(&app, protobuf(&uri, cell.encode_to_vec())).await;
+    assert!(resp.status().is_success());
+
+    // without asking for protobuf, everything stays JSON
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(content.columns["B"][0].value, Some(CellValue::String("five".into())));
+    let cell = content.columns["A"].iter().find(|cell| cell.row == 2);
+    assert_eq!(cell.unwrap().value, Some(CellValue::Int(6)));
+}
+
+#[actix_web::test]
+async fn test_non_finite_doubles() {
+    use prost::Message;
+
+    use crate::sheet::proto;
+
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    let uri = format!("/sheet/{sheet_id}");
+
+    let req = test::TestRequest::post()
+        .uri(&uri)
+        .set_payload(r#"{"column": "C", "row": 1, "value": -0.0}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // protobuf can carry them, but they're rejected instead of clearing the cell like a null would
+    for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        let cell = proto::Cell {
+            column: "C".into(),
+            row: Some(1),
+            value: Some(proto::Value {
+                kind: Some(proto::value::Kind::Double(value)),
+            }),
+            ..Default::default()
+        };
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .set_payload(cell.encode_to_vec())
+            .insert_header(("Content-Type", proto::CONTENT_TYPE))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["details"][0]["path"], "value");
+    }
+
+    for query in [
+        "value=inf&type=double",
+        "value=NaN&type=double",
+        "value=inf",
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("{uri}?column=C&row=1&{query}"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_is_error_response!(resp);
+    }
+
+    // negative zero is read back as zero
+    let req = test::TestRequest::get()
+        .uri(&format!("{uri}/rows/1"))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["columns"]["C"]["value"].as_f64().unwrap().to_bits(), 0.0f64.to_bits());
+}
+
+#[actix_web::test]
+async fn test_a1_refs() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    let post = |payload: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    // sheets have to opt in, so that strings which happen to look like lookups in A1 notation stay strings
+    let resp =
+        test::call_service(&app, post(r#"{"column": "D", "row": 5, "value": "lookup(C1)"}"#)).await;
+    assert!(resp.status().is_success());
+    let resp = test::call_service(&app, post(r#"{"ref": "C1", "value": 5}"#)).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["details"][0]["path"], "ref");
+    let req = test::TestRequest::put()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .set_payload(r#"{"a1_refs": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // letters follow the order of the schema, not the names of the columns: C is the third column, B2
+    for payload in [
+        r#"{"ref": "C1", "value": 5}"#,
+        r#"{"ref": "b2", "value": "lookup(c1)"}"#,
+        r#"{"column": "B", "row": 3, "value": "lookup(C1)"}"#,
+        r#"{"ref": "E1", "value": "'lookup(A1)"}"#,
+    ] {
+        let resp = test::call_service(&app, post(payload)).await;
+        assert!(resp.status().is_success(), "{payload}");
+    }
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}?ref=A4&value=true"))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(content.columns["B2"][0].value, Some(CellValue::Int(5)));
+    let mut rows: Vec<_> = content.columns["B"]
+        .iter()
+        .map(|cell| (cell.row, cell.value.clone()))
+        .collect();
+    rows.sort_by_key(|(row, _)| *row);
+    assert_eq!(rows, [(2, Some(CellValue::Int(5))), (3, Some(CellValue::Int(5)))]);
+    let mut rows: Vec<_> = content.columns["D"]
+        .iter()
+        .map(|cell| (cell.row, cell.value.clone()))
+        .collect();
+    rows.sort_by_key(|(row, _)| *row);
+    assert_eq!(
+        rows,
+        [
+            (1, Some(CellValue::String("lookup(A1)".into()))),
+            (5, Some(CellValue::String("lookup(C1)".into())))
+        ]
+    );
+    assert_eq!(content.columns["A"][0].value, Some(CellValue::Boolean(true)));
+
+    for (payload, path) in [
+        (r#"{"ref": "F1", "value": 1}"#, "ref"),
+        (r#"{"ref": "B0", "value": 1}"#, "ref"),
+        (r#"{"ref": 5, "value": 1}"#, "ref"),
+        (r#"{"ref": "B1", "row": 1, "value": 1}"#, "ref"),
+        (r#"{"ref": "B1", "value": "lookup(F1)"}"#, "value"),
+    ] {
+        let resp = test::call_service(&app, post(payload)).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_body", "{payload}");
+        assert_eq!(json["details"][0]["path"], path, "{payload}");
+    }
+}
+
+#[actix_web::test]
+async fn test_get_cells() {
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+    let token = |grants: serde_json::Value| {
+        let exp = crate::db::now_millis() / 1000 + 60;
+        let claims = serde_json::json!({
+            "iss": "https://sso.example.com", "exp": exp, "sheets": grants,
+        });
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap()
+    };
+    let (writer, reader) =
+        (token(serde_json::json!({"write": ["*"]})), token(serde_json::json!({"read": ["*"]})));
+    let request = |req: test::TestRequest, token: &str, payload: &str| {
+        req.insert_header(("Authorization", format!("Bearer {token}")))
+            .insert_header(ContentType::json())
+            .set_payload(payload.to_owned())
+            .to_request()
+    };
+
+    let req = test::TestRequest::post().uri("/sheet");
+    let resp: PostResponse = test::call_and_read_body_json(
+        &app,
+        request(
+            req,
+            &writer,
+            r#"{"columns": [
+                {"name": "Name", "type": "string"},
+                {"name": "Score", "type": "int"},
+                {"name": "Secret", "type": "int", "hidden": true}
+            ]}"#,
+        ),
+    )
+    .await;
+    let sheet_id = resp.sheet_id;
+    for payload in [
+        r#"{"column": "Name", "row": 1, "value": "Ada"}"#,
+        r#"{"column": "Score", "row": 1, "value": 10}"#,
+        r#"{"column": "Score", "row": 1000000, "value": "lookup(\"Score\", 1)"}"#,
+        r#"{"column": "Score", "row": 5, "value": "lookup(\"Score\", 7)"}"#,
+        r#"{"column": "Secret", "row": 1, "value": 42}"#,
+    ] {
+        let req = test::TestRequest::post().uri(&format!("/sheet/{sheet_id}"));
+        assert!(test::call_service(&app, request(req, &writer, payload))
+            .await
+            .status()
+            .is_success());
+    }
+
+    // reading cells only needs read access, even though it's a POST
+    let uri = format!("/sheet/{sheet_id}/cells:get");
+    let req = test::TestRequest::post().uri(&uri);
+    let resp = test::call_service(
+        &app,
+        request(
+            req,
+            &reader,
+            r#"{"cells": [
+                {"column": "Score", "row": 1000000},
+                {"ref": "A1"},
+                {"column": "Score", "row": 5},
+                {"column": "Name", "row": 2},
+                {"column": "Score", "row": 1000000}
+            ]}"#,
+        ),
+    )
+    .await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        body,
+        serde_json::json!({"cells": [
+            {"column": "Score", "row": 1000000, "value": 10},
+            {"column": "Name", "row": 1, "value": "Ada"},
+            {"column": "Score", "row": 5, "value": null},
+            {"column": "Name", "row": 2, "value": null},
+            {"column": "Score", "row": 1000000, "value": 10},
+        ]})
+    );
+    let req = test::TestRequest::post().uri(&format!("/sheet/{sheet_id}"));
+    let resp = test::call_service(
+        &app,
+        request(req, &reader, r#"{"column": "Score", "row": 2, "value": 1}"#),
+    )
+    .await;
+    assert_eq!(resp.status(), 403);
+
+    // hidden columns can only be read when asked for, like anywhere else
+    let hidden = r#"{"cells": [{"column": "Secret", "row": 1}]}"#;
+    let req = test::TestRequest::post().uri(&uri);
+    let resp = test::call_service(&app, request(req, &reader, hidden)).await;
+    assert_is_error_response!(resp);
+    let req = test::TestRequest::post().uri(&format!("{uri}?include_hidden"));
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, request(req, &reader, hidden)).await;
+    assert_eq!(body["cells"][0]["value"], 42);
+
+    for (payload, path) in [
+        (r#"{"cells": [{"ref": "D1"}]}"#.to_owned(), Some("cells[0].ref")),
+        (r#"{"cells": [{"column": "Name"}]}"#.to_owned(), None),
+        (format!(r#"{{"cells": [{}]}}"#, vec![r#"{"ref": "A1"}"#; 1001].join(",")), Some("cells")),
+    ] {
+        let req = test::TestRequest::post().uri(&uri);
+        let resp = test::call_service(&app, request(req, &reader, &payload)).await;
+        assert_eq!(resp.status(), 400);
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_body");
+        if let Some(path) = path {
+            assert_eq!(json["details"][0]["path"], path);
+        }
+    }
+}
+
+#[actix_web::test]
+async fn test_order_by() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 30}"#,
+        r#"{"column": "B", "row": 2, "value": 10}"#,
+        r#"{"column": "B", "row": 3, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "D", "row": 1, "value": "a"}"#,
+        r#"{"column": "D", "row": 2, "value": "b"}"#,
+        r#"{"column": "D", "row": 4, "value": "c"}"#,
+        r#"{"column": "D", "row": 5, "value": "d"}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let rows = |content: &SheetContent, column: &str| {
+        content.columns[column]
+            .iter()
+            .map(|cell| cell.row)
+            .collect::<Vec<_>>()
+    };
+
+    // the lookup in row 3 sorts by the value it resolves to, and rows without a B come last
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?order_by=B"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(rows(&content, "B"), [2, 1, 3]);
+    assert_eq!(rows(&content, "D"), [2, 1, 4, 5]);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?order_by=B&direction=desc"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(rows(&content, "B"), [1, 3, 2]);
+    assert_eq!(rows(&content, "D"), [1, 2, 4, 5]);
+
+    for query in [
+        "order_by=Z",
+        "order_by=B&direction=up",
+        "order_by=B&limit=10",
+        "order_by=B&since_version=0",
+    ] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}?{query}"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_query");
+    }
+}
+
+#[actix_web::test]
+async fn test_get_column() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 30}"#,
+        r#"{"column": "B", "row": 2, "value": 10}"#,
+        r#"{"column": "B2", "row": 1, "value": 5}"#,
+        r#"{"column": "B", "row": 4, "value": "lookup(\"B2\", 1)"}"#,
+        r#"{"column": "B", "row": 7, "value": 20}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/columns/B"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body,
+        serde_json::json!({"cells": [
+            {"row": 1, "value": 30},
+            {"row": 2, "value": 10},
+            {"row": 4, "value": 5},
+            {"row": 7, "value": 20},
+        ]})
+    );
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/columns/B?limit=2&after_row=1"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "cells": [{"row": 2, "value": 10}, {"row": 4, "value": 5}],
+            "next_cursor": 4,
+        })
+    );
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/columns/B?limit=2&after_row=4"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["cells"].as_array().unwrap().len(), 1);
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/columns/Z"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_is_error_response!(resp);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/columns/B?limit=0"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "invalid_query");
+}
+
+#[actix_web::test]
+async fn test_get_row() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "A", "row": 3, "value": true}"#,
+        r#"{"column": "B", "row": 1, "value": 30}"#,
+        r#"{"column": "B", "row": 3, "value": 10}"#,
+        r#"{"column": "B2", "row": 3, "value": "lookup(\"B\", 1)"}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/rows/3"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body,
+        serde_json::json!({"row": 3, "columns": {
+            "A": {"value": true},
+            "B": {"value": 10},
+            "B2": {"value": 30},
+        }})
+    );
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/rows/2"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body, serde_json::json!({"row": 2, "columns": {}}));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/rows/x"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_is_error_response!(resp);
+}
+
+#[actix_web::test]
+async fn test_get_rows() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 30}"#,
+        r#"{"column": "B", "row": 5, "value": 10}"#,
+        r#"{"column": "B2", "row": 9, "value": "lookup(\"B\", 5)"}"#,
+        r#"{"column": "B", "row": 200, "value": 7}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/rows?ids=9,5,3,9"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body,
+        serde_json::json!({"rows": [
+            {"row": 9, "columns": {"B2": {"value": 10}}},
+            {"row": 5, "columns": {"B": {"value": 10}}},
+            {"row": 3, "columns": {}},
+        ]})
+    );
+
+    for query in ["ids=1,x", "ids=", ""] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/rows?{query}"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_query");
+    }
+}
+
+#[actix_web::test]
+async fn test_events() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 30}"#,
+        r#"{"column": "B2", "row": 1, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "B", "row": 1, "value": "nope"}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/row/1/meta"))
+        .set_payload(r#"{"reviewed": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/events?limit=2"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events[0]["seq"], 1);
+    assert_eq!(events[0]["kind"], "created");
+    assert_eq!(events[0]["schema"]["columns"][0]["name"], "A");
+    assert_eq!(events[1]["kind"], "cell_written");
+    assert_eq!(events[1]["cell"], serde_json::json!({"column": "B", "row": 1, "value": 30}));
+    assert_eq!(body["next_cursor"], 2);
+
+    // the rejected write isn't logged
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/events?after_seq=2"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["cell"]["value"], "lookup(\"B\", 1)");
+    assert_eq!(events[1]["kind"], "row_meta_updated");
+    assert_eq!(events[1]["changes"], serde_json::json!({"reviewed": true}));
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+}
+
+#[actix_web::test]
+async fn test_import() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    let import = |query: &str, csv: &'static str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}/import{query}"))
+            .set_payload(csv)
+            .insert_header(("Content-Type", "text/csv"))
+            .to_request()
+    };
+
+    // problems are reported without writing anything, including those only found by writing
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, import("?dry_run=true", "B,B2,Z\nx,1,\n2,3,\n")).await;
+    assert_eq!(body["dry_run"], true);
+    assert_eq!(body["rows"], 2);
+    let paths: Vec<_> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["header[2]", "rows[1].B"]);
+
+    let body: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        import("?dry_run", "B,B2\n1,\"lookup(\"\"D\"\", 1)\"\n"),
+    )
+    .await;
+    assert_eq!(body["errors"][0]["path"], "rows[1].B2");
+
+    let resp = test::call_service(&app, import("", "B,B2,Z\nx,1,\n")).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "invalid_body");
+    assert_eq!(json["details"].as_array().unwrap().len(), 2);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert!(content.columns.values().all(Vec::is_empty));
+
+    let body: serde_json::Value = test::call_and_read_body_json(
+        &app,
+        import("", "B,B2,A\n1,\"lookup(\"\"B\"\", 2)\",true\n2,,\n"),
+    )
+    .await;
+    assert_eq!(body, serde_json::json!({"dry_run": false, "rows": 2, "cells": 4}));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/rows/1"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["B2"]["value"], 2);
+    assert_eq!(body["columns"]["A"]["value"], true);
+}
+
+#[actix_web::test]
+async fn test_csv_dialects() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import?delimiter=;&encoding=latin-1"))
+        .set_payload(&b"B;D;C\n1;caf\xE9;\n;\"a;b\";2.5\n"[..])
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["cells"], 4);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/export"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv; charset=utf-8");
+    let file = test::read_body(resp).await;
+    assert_eq!(&file[..], "A,B,B2,C,D\n,1,,,café\n,,,2.5,a;b\n".as_bytes());
+
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/sheet/{sheet_id}/export?delimiter=;&quote='&has_header=false&encoding=latin1"
+        ))
+        .to_request();
+    let file = test::call_and_read_body(&app, req).await;
+    assert_eq!(&file[..], &b";1;;;caf\xE9\n;;;2.5;'a;b'\n"[..]);
+
+    for query in ["delimiter=ab", "encoding=utf-16"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/export?{query}"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_query");
+    }
+}
+
+#[actix_web::test]
+async fn test_compressed_import() {
+    use std::io::Write;
+
+    let app = init_service!(AppConfig {
+        max_import_size: 100,
+        ..Default::default()
+    });
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+    let gzip = |data: &[u8]| {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    };
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import"))
+        .insert_header(("Content-Encoding", "gzip"))
+        .set_payload(gzip(b"B,D\n1,a\n2,b\n"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["cells"], 4);
+
+    // the limit applies to the decompressed file, which is much larger here
+    let file = format!("B\n{}", "1\n".repeat(100));
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import"))
+        .insert_header(("Content-Encoding", "gzip"))
+        .set_payload(gzip(file.as_bytes()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import"))
+        .insert_header(("Content-Encoding", "gzip"))
+        .set_payload("B\n1\n")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "invalid_body");
+}
+
+#[actix_web::test]
+async fn test_tombstones() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 30}"#,
+        r#"{"column": "B2", "row": 1, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "B", "row": 1, "value": null}"#,
+        r#"{"column": "D", "row": 3, "value": null}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    // tombstones are null, while cells that were never written are left out
+    for query in ["", "?limit=10"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}{query}"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["columns"]["B"], serde_json::json!([{"row": 1, "value": null}]));
+        assert_eq!(body["columns"]["B2"], serde_json::json!([{"row": 1, "value": null}]));
+        assert_eq!(body["columns"]["D"], serde_json::json!([{"row": 3, "value": null}]));
+        assert_eq!(body["columns"]["A"], serde_json::json!([]));
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/columns/D"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["cells"], serde_json::json!([{"row": 3, "value": null}]));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/events?after_seq=3"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["events"][0]["kind"], "tombstone_written");
+    assert_eq!(body["events"][0]["column"], "B");
+
+    // writing a value replaces the tombstone
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{"column": "B", "row": 1, "value": 5}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/rows/1"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["B"]["value"], 5);
+    assert_eq!(body["columns"]["B2"]["value"], 5);
+
+    for (payload, query) in [
+        (r#"{"column": "Z", "row": 1, "value": null}"#, ""),
+        (r#"{"column": "B", "row": 1.5, "value": null}"#, ""),
+        (r#"{"column": "B", "row": 1, "value": null}"#, "?session=abc"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}{query}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_is_error_response!(resp);
+    }
+}
+
+#[actix_web::test]
+async fn test_delete_cell() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 1, "value": 30}"#,
+        r#"{"column": "B2", "row": 1, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "B2", "row": 2, "value": 7}"#,
+        r#"{"column": "D", "row": 3, "value": null}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?include=version"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    let version = content.version.unwrap();
+
+    let delete = |query: &str| {
+        test::TestRequest::delete()
+            .uri(&format!("/sheet/{sheet_id}/cell?{query}"))
+            .to_request()
+    };
+    // a value, a lookup, a tombstone, and a cell that was never written
+    for query in [
+        "column=B&row=1",
+        "column=B2&row=2",
+        "column=D&row=3",
+        "column=A&row=9",
+    ] {
+        let resp = test::call_service(&app, delete(query)).await;
+        assert!(resp.status().is_success(), "{query}");
+    }
+
+    // nothing is left behind, and the lookup at the cleared cell resolves to nothing
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["B"], serde_json::json!([]));
+    assert_eq!(body["columns"]["B2"], serde_json::json!([{"row": 1, "value": null}]));
+    assert_eq!(body["columns"]["D"], serde_json::json!([]));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}?since_version={version}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(content.version, Some(version + 3));
+    for column in ["B", "D"] {
+        assert!(content.columns[column]
+            .iter()
+            .all(|cell| cell.value.is_none()));
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/stats"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["B"]["count"], 0);
+    assert_eq!(body["columns"]["B2"]["count"], 0);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/events?after_seq=5"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 3);
+    assert!(events.iter().all(|event| event["kind"] == "cell_cleared"));
+    assert_eq!((&events[0]["column"], &events[0]["row"]), (&"B".into(), &1.into()));
+
+    for query in ["column=Z&row=1", "column=B", "row=1"] {
+        let resp = test::call_service(&app, delete(query)).await;
+        assert_eq!(resp.status(), 400, "{query}");
+    }
+    let req = test::TestRequest::delete()
+        .uri("/sheet/abCDefGHijklMnOPqrst1234/cell?column=B&row=1")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+}
+
+#[actix_web::test]
+async fn test_schema_history() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .set_payload(r#"{"widen_types": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{"column": "B", "row": 1, "value": 1.5}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/schema/history"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let versions = body["versions"].as_array().unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0]["version"], 1);
+    assert_eq!(versions[0]["columns"][1], serde_json::json!({"name": "B", "type": "int"}));
+    assert_eq!(versions[1]["version"], 2);
+    assert_eq!(versions[1]["columns"][1], serde_json::json!({"name": "B", "type": "double"}));
+    assert!(versions[0]["at"].as_i64().unwrap() <= versions[1]["at"].as_i64().unwrap());
+
+    let req = test::TestRequest::get()
+        .uri("/sheet/aaaaaaaaaaaaaaaaaaaaaaaa/schema/history")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_column_formats() {
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [
+                {"name": "rate", "type": "double", "format": {"kind": "percent"}},
+                {"name": "price", "type": "int", "format": {"kind": "currency", "code": "USD"}},
+                {"name": "note", "type": "string"}
+            ]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: Response<PostResponse> = test::call_and_read_body_json(&app, req).await;
+    let Response::Success(PostResponse { sheet_id }) = resp else {
+        panic!("sheet creation failed: {resp:?}");
+    };
+
+    // the values are stored as they are
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .set_payload(r#"{"column": "rate", "row": 1, "value": 0.25}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/schema"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body["columns"],
+        serde_json::json!([
+            {"name": "rate", "type": "double", "format": {"kind": "percent"}},
+            {"name": "price", "type": "int", "format": {"kind": "currency", "code": "USD"}},
+            {"name": "note", "type": "string"}
+        ])
+    );
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["rate"][0]["value"], 0.25);
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [{"name": "note", "type": "string", "format": {"kind": "percent"}}]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["details"][0]["path"], "columns[0].format");
+}
+
+#[actix_web::test]
+async fn test_url_and_email_columns() {
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [{"name": "site", "type": "url"}, {"name": "contact", "type": "email"}]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: Response<PostResponse> = test::call_and_read_body_json(&app, req).await;
+    let Response::Success(PostResponse { sheet_id }) = resp else {
+        panic!("sheet creation failed: {resp:?}");
+    };
+
+    let write = |body: &'static str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(body)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    for body in [
+        r#"{"column": "site", "row": 1, "value": "https://example.com/about"}"#,
+        r#"{"column": "contact", "row": 1, "value": "team@example.com"}"#,
+        r#"{"column": "contact", "row": 2, "value": "lookup(\"contact\", 1)"}"#,
+    ] {
+        let resp = test::call_service(&app, write(body)).await;
+        assert!(resp.status().is_success(), "{body}");
+    }
+    for body in [
+        r#"{"column": "site", "row": 2, "value": "example.com"}"#,
+        r#"{"column": "contact", "row": 3, "value": "not an address"}"#,
+        r#"{"column": "site", "row": 3, "value": 5}"#,
+        r#"{"column": "site", "row": 4, "value": "lookup(\"contact\", 1)"}"#,
+    ] {
+        let resp = test::call_service(&app, write(body)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{body}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["site"][0]["value"], "https://example.com/about");
+    assert_eq!(body["columns"]["contact"].as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn test_autoincrement_columns() {
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [{"name": "n", "type": "autoincrement"}, {"name": "name", "type": "string"}]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: Response<PostResponse> = test::call_and_read_body_json(&app, req).await;
+    let Response::Success(PostResponse { sheet_id }) = resp else {
+        panic!("sheet creation failed: {resp:?}");
+    };
+
+    let write = |body: &'static str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(body)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    for body in [
+        r#"{"column": "name", "row": 3, "value": "first"}"#,
+        r#"{"column": "name", "row": 1, "value": "second"}"#,
+        r#"{"column": "name", "row": 3, "value": "first again"}"#,
+        r#"{"column": "name", "row": 7, "value": null}"#,
+    ] {
+        let resp = test::call_service(&app, write(body)).await;
+        assert!(resp.status().is_success(), "{body}");
+    }
+    for body in [
+        r#"{"column": "n", "row": 3, "value": 10}"#,
+        r#"{"column": "n", "row": 4, "value": "lookup(\"n\", 3)"}"#,
+        r#"{"column": "n", "row": 3, "value": null}"#,
+    ] {
+        let resp = test::call_service(&app, write(body)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{body}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let mut numbers: Vec<_> = body["columns"]["n"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cell| (cell["row"].as_i64().unwrap(), cell["value"].as_i64().unwrap()))
+        .collect();
+    numbers.sort();
+    assert_eq!(numbers, [(1, 2), (3, 1), (7, 3)]);
+}
+
+#[actix_web::test]
+async fn test_explain() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for body in [
+        r#"{"column": "B", "row": 1, "value": 5}"#,
+        r#"{"column": "B2", "row": 1, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "B", "row": 2, "value": "lookup(\"B2\", 1)"}"#,
+        r#"{"column": "B2", "row": 3, "value": "lookup(\"B\", 9)"}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(body)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success(), "{body}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/explain?column=B&row=2"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "steps": [
+                {"column": "B", "row": 2, "kind": "lookup", "target_column": "B2", "target_row": 1},
+                {"column": "B2", "row": 1, "kind": "lookup", "target_column": "B", "target_row": 1},
+                {"column": "B", "row": 1, "kind": "value", "value": 5}
+            ],
+            "value": 5
+        })
+    );
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/explain?column=B2&row=3"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["steps"][1]["kind"], "empty");
+    assert_eq!(body["value"], serde_json::Value::Null);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/explain?column=B"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_per_sheet_lookup_nulls() {
+    let app = init_service!();
+
+    let mut sheets = vec![];
+    for body in [
+        r#"{"columns": [{"name": "A", "type": "int"}], "no_lookup_nulls": true}"#,
+        r#"{"columns": [{"name": "A", "type": "int"}]}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(body)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp: Response<PostResponse> = test::call_and_read_body_json(&app, req).await;
+        let Response::Success(PostResponse { sheet_id }) = resp else {
+            panic!("sheet creation failed: {resp:?}");
+        };
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(r#"{"column": "A", "row": 1, "value": "lookup(\"A\", 2)"}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        sheets.push(sheet_id);
+    }
+
+    let cells = |sheet_id: &str, query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}{query}"))
+            .to_request()
+    };
+    for (sheet_id, query, expected) in [
+        (&sheets[0], "", 0),
+        (&sheets[0], "?no_lookup_nulls=0", 1),
+        (&sheets[1], "", 1),
+        (&sheets[1], "?no_lookup_nulls=1", 0),
+    ] {
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, cells(sheet_id, query)).await;
+        assert_eq!(body["columns"]["A"].as_array().unwrap().len(), expected, "{query}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{}/rows/1", sheets[0]))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"], serde_json::json!({}));
+}
+
+#[actix_web::test]
+async fn test_rotate_id() {
+    use actix_web::http::{header, StatusCode};
+
+    let app = init_service!();
+
+    let new_sheet = || {
+        test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(
+                r#"{"id": "leaked-budget", "columns": [{"name": "A", "type": "int"}, {"name": "B", "type": "int"}]}"#,
+            )
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    assert!(test::call_service(&app, new_sheet())
+        .await
+        .status()
+        .is_success());
+
+    let post_cell = |sheet_id: &str, body: &'static str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(body)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let req = post_cell("leaked-budget", r#"{"column": "A", "row": 1, "value": 5}"#);
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let rotate = |sheet_id: &str, query: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}/rotate-id{query}"))
+            .to_request()
+    };
+    let resp: Response<PostResponse> =
+        test::call_and_read_body_json(&app, rotate("leaked-budget", "?redirect=1")).await;
+    let Response::Success(PostResponse { sheet_id: new_id }) = resp else {
+        panic!("rotation failed: {resp:?}");
+    };
+    assert_ne!(new_id, "leaked-budget");
+
+    let req = test::TestRequest::get()
+        .uri("/sheet/leaked-budget/rows/1?no_lookup_nulls=1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        resp.headers().get(header::LOCATION).unwrap(),
+        &format!("/sheet/{new_id}/rows/1?no_lookup_nulls=1")
+    );
+    // the old id stays taken while it redirects
+    let resp = test::call_service(&app, new_sheet()).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    // the lookup tables have to work under their new names
+    let req = post_cell(&new_id, r#"{"column": "B", "row": 1, "value": "lookup(\"A\", 1)"}"#);
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{new_id}/rows/1"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["B"]["value"], 5, "{body}");
+
+    let resp: Response<PostResponse> =
+        test::call_and_read_body_json(&app, rotate(&new_id, "")).await;
+    let Response::Success(PostResponse {
+        sheet_id: newest_id,
+    }) = resp
+    else {
+        panic!("rotation failed: {resp:?}");
+    };
+    // without a redirect, neither of the old ids lead anywhere
+    for sheet_id in [new_id.as_str(), "leaked-budget"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    }
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{newest_id}/rows/1"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["A"]["value"], 5, "{body}");
+}
+
+#[actix_web::test]
+async fn test_aliases() {
+    use actix_web::http::{header, StatusCode};
+
+    let app = init_service!();
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(r#"{"id": "budget", "columns": [{"name": "A", "type": "int"}]}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let add_alias = |body: &'static str| {
+        test::TestRequest::post()
+            .uri("/sheet/budget/aliases")
+            .set_payload(body)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let req = add_alias(r#"{"id": "old-budget"}"#);
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = add_alias(r#"{"id": "budget-2023", "mode": "proxy"}"#);
+    assert!(test::call_service(&app, req).await.status().is_success());
+    // neither sheet ids nor other aliases can become aliases
+    for body in [r#"{"id": "budget"}"#, r#"{"id": "old-budget"}"#] {
+        let resp = test::call_service(&app, add_alias(body)).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT, "{body}");
+    }
+    let resp = test::call_service(&app, add_alias(r#"{"id": "No Slug"}"#)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    // nor can ids which only differ from those by case, since they would share their tables
+    let generated = get_standard_sheet(&app).await.unwrap();
+    let req = add_alias(r#"{"id": "Abcdefghijklmnopqrstuvwx"}"#);
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let swapped: String = generated
+        .chars()
+        .map(|x| match x.is_ascii_lowercase() {
+            true => x.to_ascii_uppercase(),
+            false => x.to_ascii_lowercase(),
+        })
+        .collect();
+    for id in [swapped.as_str(), "aBCDEFGHIJKLMNOPQRSTUVWX"] {
+        let req = test::TestRequest::post()
+            .uri("/sheet/budget/aliases")
+            .set_payload(format!(r#"{{"id": "{id}"}}"#))
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT, "{id}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/sheet/budget/aliases")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body,
+        serde_json::json!({"aliases": [
+            {"id": "Abcdefghijklmnopqrstuvwx", "mode": "redirect"},
+            {"id": "budget-2023", "mode": "proxy"},
+            {"id": "old-budget", "mode": "redirect"},
+        ]})
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/sheet/old-budget")
+        .set_payload(r#"{"column": "A", "row": 1, "value": 5}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(resp.headers().get(header::LOCATION).unwrap(), "/sheet/budget");
+
+    // proxied requests are answered as if they were made for the sheet itself
+    let req = test::TestRequest::post()
+        .uri("/sheet/budget-2023")
+        .set_payload(r#"{"column": "A", "row": 1, "value": 5}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::get()
+        .uri("/sheet/budget-2023/rows/1")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["A"]["value"], 5, "{body}");
+
+    let delete = || {
+        test::TestRequest::delete()
+            .uri("/sheet/budget/aliases/old-budget")
+            .to_request()
+    };
+    assert!(test::call_service(&app, delete())
+        .await
+        .status()
+        .is_success());
+    assert_eq!(test::call_service(&app, delete()).await.status(), StatusCode::NOT_FOUND);
+    let req = test::TestRequest::get()
+        .uri("/sheet/old-budget")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_access_log() {
+    let app = init_service!(AppConfig {
+        access_log: true,
+        ..Default::default()
+    });
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(VALID_POST_PAYLOAD)
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: Response<PostResponse> = test::call_and_read_body_json(&app, req).await;
+    let Response::Success(PostResponse { sheet_id }) = resp else {
+        panic!("sheet creation failed: {resp:?}");
+    };
+
+    for row in 1..=3 {
+        let req = test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/rows/{row}?include_hidden=1"))
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            // without a trusted proxy, the forwarding headers could come from anyone
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    // requests for sheets which don't exist aren't logged
+    let req = test::TestRequest::get()
+        .uri("/sheet/nothing-here")
+        .to_request();
+    assert!(!test::call_service(&app, req).await.status().is_success());
+
+    let read_log = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/access-log{query}"))
+            .to_request()
+    };
+    let body: serde_json::Value = test::call_and_read_body_json(&app, read_log("?limit=2")).await;
+    let entries = body["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2, "{body}");
+    assert_eq!(entries[0]["reader"], "10.0.0.1");
+    assert_eq!(entries[0]["method"], "GET");
+    assert_eq!(entries[0]["path"], format!("/sheet/{sheet_id}/rows/1"));
+    assert_eq!(entries[0]["status"], 200);
+
+    // the first read of the log is logged as well
+    let cursor = &body["next_cursor"];
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, read_log(&format!("?after_id={cursor}"))).await;
+    let paths: Vec<_> = body["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["path"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            format!("/sheet/{sheet_id}/rows/3"),
+            format!("/sheet/{sheet_id}/access-log")
+        ]
+    );
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+    let app = init_service!();
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/access-log"))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        actix_web::http::StatusCode::NOT_FOUND
+    );
+}
+
+#[actix_web::test]
+async fn test_access_log_readers() {
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        access_log: true,
+        trusted_proxy: Some("10.0.0.1".parse().unwrap()),
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+    let token = |sub: Option<&str>| {
+        let exp = crate::db::now_millis() / 1000 + 60;
+        let claims = serde_json::json!({
+            "iss": "https://sso.example.com",
+            "exp": exp,
+            "sub": sub,
+            "sheets": {"write": ["*"]},
+        });
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"hunter2"),
+        )
+        .unwrap();
+        format!("Bearer {token}")
+    };
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(r#"{"id": "budget", "columns": [{"name": "A", "type": "int"}]}"#)
+        .insert_header(ContentType::json())
+        .insert_header(("Authorization", token(Some("alice"))))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let read = |sub: Option<&str>, peer: &str| {
+        test::TestRequest::get()
+            .uri("/sheet/budget")
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .insert_header(("Authorization", token(sub)))
+            .to_request()
+    };
+    for req in [
+        read(Some("bob"), "10.0.0.2:4000"),
+        // the forwarding headers count for requests through the proxy
+        read(None, "10.0.0.1:4000"),
+        read(None, "10.0.0.2:4000"),
+    ] {
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/sheet/budget/access-log")
+        .insert_header(("Authorization", token(Some("alice"))))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let readers: Vec<_> = body["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["reader"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(readers, ["bob", "203.0.113.7", "10.0.0.2"], "{body}");
+}
+
+#[actix_web::test]
+async fn test_signed_writes() {
+    use crate::signing::{SignedRequest, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+    let app = init_service!(AppConfig {
+        signing_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+
+    let signed_as = |uri: &str, path: &str, body: &str, secret: &[u8]| {
+        let timestamp = crate::db::now_millis() / 1000;
+        let signature = SignedRequest {
+            timestamp,
+            method: "POST",
+            path,
+            body: body.as_bytes(),
+        }
+        .sign(secret);
+        test::TestRequest::post()
+            .uri(uri)
+            .set_payload(body.to_string())
+            .insert_header(ContentType::json())
+            .insert_header((TIMESTAMP_HEADER, timestamp.to_string()))
+            .insert_header((SIGNATURE_HEADER, signature))
+    };
+    let signed = |path: &str, body: &str, secret: &[u8]| signed_as(path, path, body, secret);
+
+    let req = signed("/sheet", VALID_POST_PAYLOAD, b"hunter2").to_request();
+    let resp: Response<PostResponse> = test::call_and_read_body_json(&app, req).await;
+    let Response::Success(PostResponse { sheet_id }) = resp else {
+        panic!("sheet creation failed: {resp:?}");
+    };
+
+    let path = format!("/sheet/{sheet_id}");
+    let body = r#"{"column": "A", "row": 1, "value": true}"#;
+    let req = test::TestRequest::post()
+        .uri(&path)
+        .set_payload(body)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        actix_web::http::StatusCode::UNAUTHORIZED
+    );
+    let req = signed(&path, body, b"hunter3").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        actix_web::http::StatusCode::UNAUTHORIZED
+    );
+
+    let req = signed(&path, body, b"hunter2");
+    let replay = signed(&path, body, b"hunter2");
+    assert!(test::call_service(&app, req.to_request())
+        .await
+        .status()
+        .is_success());
+    let resp = test::call_service(&app, replay.to_request()).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "the signature was already used");
+
+    // paths are signed after they're normalized
+    let body = r#"{"column": "A", "row": 2, "value": true}"#;
+    let req = signed(&format!("{path}/"), body, b"hunter2").to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        actix_web::http::StatusCode::UNAUTHORIZED
+    );
+    let req = signed_as(&format!("{path}/"), &path, body, b"hunter2").to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // reads don't need to be signed
+    let req = test::TestRequest::get().uri(&path).to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_jwt_claims() {
+    use actix_web::http::StatusCode;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+
+    let token = |issuer: &str, exp: i64, sheets: serde_json::Value| {
+        let claims = serde_json::json!({"iss": issuer, "exp": exp, "sheets": sheets});
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap()
+    };
+    let exp = crate::db::now_millis() / 1000 + 60;
+    let admin = token("https://sso.example.com", exp, serde_json::json!({"write": ["*"]}));
+    let reader = token("https://sso.example.com", exp, serde_json::json!({"read": ["budget"]}));
+
+    let new_sheet = || {
+        test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(r#"{"id": "budget", "columns": [{"name": "A", "type": "int"}]}"#)
+            .insert_header(ContentType::json())
+    };
+    let resp = test::call_service(&app, new_sheet().to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    // a token which may only read a single sheet can't create new ones
+    let req = new_sheet()
+        .insert_header(("Authorization", format!("Bearer {reader}")))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = new_sheet()
+        .insert_header(("Authorization", format!("Bearer {admin}")))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let write = |token: &str| {
+        test::TestRequest::post()
+            .uri("/sheet/budget")
+            .set_payload(r#"{"column": "A", "row": 1, "value": 5}"#)
+            .insert_header(ContentType::json())
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+    let read = |token: &str| {
+        test::TestRequest::get()
+            .uri("/sheet/budget")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+    assert!(test::call_service(&app, write(&admin))
+        .await
+        .status()
+        .is_success());
+    assert_eq!(test::call_service(&app, write(&reader)).await.status(), StatusCode::FORBIDDEN);
+    assert!(test::call_service(&app, read(&reader))
+        .await
+        .status()
+        .is_success());
+
+    let other_sheet =
+        token("https://sso.example.com", exp, serde_json::json!({"write": ["roadmap"]}));
+    assert_eq!(test::call_service(&app, read(&other_sheet)).await.status(), StatusCode::FORBIDDEN);
+    for invalid in [
+        token("https://evil.example.com", exp, serde_json::json!({"write": ["*"]})),
+        token("https://sso.example.com", exp - 3600, serde_json::json!({"write": ["*"]})),
+        "not a token".into(),
+    ] {
+        let resp = test::call_service(&app, read(&invalid)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{invalid}");
+    }
+}
+
+#[actix_web::test]
+async fn test_roles() {
+    use actix_web::http::StatusCode;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+
+    let token = |sub: &str, sheets: serde_json::Value| {
+        let exp = crate::db::now_millis() / 1000 + 60;
+        let claims = serde_json::json!({
+            "iss": "https://sso.example.com", "exp": exp, "sub": sub, "sheets": sheets,
+        });
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap()
+    };
+    let creator = token("alice", serde_json::json!({"write": ["*"]}));
+    let alice = token("alice", serde_json::json!({}));
+    let bob = token("bob", serde_json::json!({}));
+    let request = |req: test::TestRequest, token: &str| {
+        req.insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+
+    // whoever creates a sheet becomes its owner
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(r#"{"id": "budget", "columns": [{"name": "A", "type": "int"}]}"#)
+        .insert_header(ContentType::json());
+    assert!(test::call_service(&app, request(req, &creator))
+        .await
+        .status()
+        .is_success());
+
+    let req = test::TestRequest::get().uri("/sheet/budget");
+    assert_eq!(test::call_service(&app, request(req, &bob)).await.status(), StatusCode::FORBIDDEN);
+
+    let grant = |role: &str| {
+        test::TestRequest::put()
+            .uri("/sheet/budget/permissions/bob")
+            .set_payload(format!(r#"{{"role": "{role}"}}"#))
+            .insert_header(ContentType::json())
+    };
+    let resp = test::call_service(&app, request(grant("viewer"), &alice)).await;
+    assert!(resp.status().is_success());
+    let permission: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(permission, serde_json::json!({"principal": "bob", "role": "viewer"}));
+
+    let write = || {
+        test::TestRequest::post()
+            .uri("/sheet/budget")
+            .set_payload(r#"{"column": "A", "row": 1, "value": 5}"#)
+            .insert_header(ContentType::json())
+    };
+    let req = test::TestRequest::get().uri("/sheet/budget");
+    assert!(test::call_service(&app, request(req, &bob))
+        .await
+        .status()
+        .is_success());
+    assert_eq!(
+        test::call_service(&app, request(write(), &bob))
+            .await
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    assert!(test::call_service(&app, request(grant("editor"), &alice))
+        .await
+        .status()
+        .is_success());
+    assert!(test::call_service(&app, request(write(), &bob))
+        .await
+        .status()
+        .is_success());
+    // managing the sheet is left to its owners
+    let resp = test::call_service(&app, request(grant("owner"), &bob)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::get().uri("/sheet/budget/permissions");
+    assert_eq!(test::call_service(&app, request(req, &bob)).await.status(), StatusCode::FORBIDDEN);
+
+    let req = test::TestRequest::get().uri("/sheet/budget/permissions");
+    let resp = test::call_service(&app, request(req, &alice)).await;
+    assert!(resp.status().is_success());
+    let permissions: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        permissions,
+        serde_json::json!({"permissions": [
+            {"principal": "alice", "role": "owner"},
+            {"principal": "bob", "role": "editor"},
+        ]})
+    );
+
+    let req = test::TestRequest::delete().uri("/sheet/budget/permissions/alice");
+    let resp = test::call_service(&app, request(req, &alice)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let req = test::TestRequest::delete().uri("/sheet/budget/permissions/bob");
+    assert!(test::call_service(&app, request(req, &alice))
+        .await
+        .status()
+        .is_success());
+    let req = test::TestRequest::delete().uri("/sheet/budget/permissions/bob");
+    let resp = test::call_service(&app, request(req, &alice)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let req = test::TestRequest::get().uri("/sheet/budget");
+    assert_eq!(test::call_service(&app, request(req, &bob)).await.status(), StatusCode::FORBIDDEN);
+
+    // handing the sheet over leaves its old owner as an editor
+    let req = test::TestRequest::post()
+        .uri("/sheet/budget/owner")
+        .set_payload(r#"{"principal": "carol"}"#)
+        .insert_header(ContentType::json());
+    let resp = test::call_service(&app, request(req, &alice)).await;
+    assert!(resp.status().is_success());
+    let permissions: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        permissions,
+        serde_json::json!({"permissions": [
+            {"principal": "carol", "role": "owner"},
+            {"principal": "alice", "role": "editor"},
+        ]})
+    );
+    let req = test::TestRequest::get().uri("/sheet/budget/permissions");
+    assert_eq!(
+        test::call_service(&app, request(req, &alice))
+            .await
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+    assert!(test::call_service(&app, request(write(), &alice))
+        .await
+        .status()
+        .is_success());
+}
+
+#[actix_web::test]
+async fn test_transfer_ownership() {
+    use actix_web::http::StatusCode;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+
+    let token = |sub: &str, sheets: serde_json::Value| {
+        let exp = crate::db::now_millis() / 1000 + 60;
+        let claims = serde_json::json!({
+            "iss": "https://sso.example.com", "exp": exp, "sub": sub, "sheets": sheets,
+        });
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap()
+    };
+    let creator = token("alice", serde_json::json!({"write": ["*"]}));
+    let alice = token("alice", serde_json::json!({}));
+    let bob = token("bob", serde_json::json!({}));
+    let transfer = |sheet: &str, principal: &str, token: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet}/owner"))
+            .set_payload(serde_json::json!({ "principal": principal }).to_string())
+            .insert_header(ContentType::json())
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(r#"{"id": "budget", "columns": [{"name": "A", "type": "int"}]}"#)
+        .insert_header(ContentType::json())
+        .insert_header(("Authorization", format!("Bearer {creator}")))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // only owners may hand a sheet over
+    let resp = test::call_service(&app, transfer("budget", "bob", &bob)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = test::call_service(&app, transfer("nosuchsheet", "bob", &creator)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    for principal in ["", "  ", "alice"] {
+        let resp = test::call_service(&app, transfer("budget", principal, &alice)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{principal:?}");
+        let json: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(json["code"], "invalid_body");
+    }
+
+    let resp = test::call_service(&app, transfer("budget", "bob", &alice)).await;
+    assert!(resp.status().is_success());
+    let permissions: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        permissions,
+        serde_json::json!({"permissions": [
+            {"principal": "bob", "role": "owner"},
+            {"principal": "alice", "role": "editor"},
+        ]})
+    );
+    let resp = test::call_service(&app, transfer("budget", "carol", &alice)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn test_published() {
+    use actix_web::http::StatusCode;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+    let exp = crate::db::now_millis() / 1000 + 60;
+    let claims = serde_json::json!({
+        "iss": "https://sso.example.com", "exp": exp, "sheets": {"write": ["*"]},
+    });
+    let admin =
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap();
+    let authorized = |req: test::TestRequest| {
+        req.insert_header(("Authorization", format!("Bearer {admin}")))
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    let req = test::TestRequest::post().uri("/sheet").set_payload(
+        r#"{"id": "menu", "columns": [
+            {"name": "Dish", "type": "string"},
+            {"name": "Price", "type": "double"},
+            {"name": "Cost", "type": "double", "hidden": true}
+        ]}"#,
+    );
+    assert!(test::call_service(&app, authorized(req))
+        .await
+        .status()
+        .is_success());
+    for cell in [
+        r#"{"column": "Dish", "row": 1, "value": "Fish & <chips>"}"#,
+        r#"{"column": "Price", "row": 1, "value": 7.5}"#,
+        r#"{"column": "Cost", "row": 1, "value": 2.5}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/sheet/menu")
+            .set_payload(cell);
+        assert!(test::call_service(&app, authorized(req))
+            .await
+            .status()
+            .is_success());
+    }
+
+    // sheets aren't published until they're asked to be
+    let req = test::TestRequest::get().uri("/published/menu").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    let req = test::TestRequest::put()
+        .uri("/sheet/menu/settings")
+        .set_payload(r#"{"published": true}"#);
+    assert!(test::call_service(&app, authorized(req))
+        .await
+        .status()
+        .is_success());
+
+    let req = test::TestRequest::get().uri("/published/menu").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let content: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        content,
+        serde_json::json!({"columns": {
+            "Dish": [{"row": 1, "value": "Fish & <chips>"}],
+            "Price": [{"row": 1, "value": 7.5}],
+        }})
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/published/menu/csv")
+        .to_request();
+    let body = test::read_body(test::call_service(&app, req).await).await;
+    assert_eq!(body, "Dish,Price\nFish & <chips>,7.5\n");
+    let req = test::TestRequest::get()
+        .uri("/published/menu/html")
+        .to_request();
+    let body = test::read_body(test::call_service(&app, req).await).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains("<td>Fish &amp; &lt;chips&gt;</td><td>7.5</td>"), "{body}");
+    assert!(!body.contains("2.5"));
+
+    // numbers can be written for a locale
+    let req = test::TestRequest::get()
+        .uri("/published/menu/csv?locale=de-DE")
+        .to_request();
+    let body = test::read_body(test::call_service(&app, req).await).await;
+    assert_eq!(body, "Dish;Price\nFish & <chips>;7,5\n");
+    let req = test::TestRequest::get()
+        .uri("/published/menu/html?locale=fr")
+        .to_request();
+    let body = test::read_body(test::call_service(&app, req).await).await;
+    assert!(std::str::from_utf8(&body).unwrap().contains("<td>7,5</td>"));
+    let req = test::TestRequest::get()
+        .uri("/published/menu/html?locale=xx-YY")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    // publishing doesn't open up writes
+    let req = test::TestRequest::post()
+        .uri("/published/menu")
+        .set_payload(r#"{"column": "Price", "row": 1, "value": 0}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req)
+        .await
+        .status()
+        .is_client_error());
+    let req = test::TestRequest::post()
+        .uri("/sheet/menu")
+        .set_payload(r#"{"column": "Price", "row": 1, "value": 0}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_export_links() {
+    use actix_web::http::StatusCode;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+    let exp = crate::db::now_millis() / 1000 + 60;
+    let claims = serde_json::json!({
+        "iss": "https://sso.example.com", "exp": exp, "sheets": {"write": ["*"]},
+    });
+    let admin =
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap();
+    let authorized = |req: test::TestRequest| {
+        req.insert_header(("Authorization", format!("Bearer {admin}")))
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+
+    let req = test::TestRequest::post().uri("/sheet").set_payload(
+        r#"{"id": "payroll", "columns": [{"name": "A", "type": "int"}, {"name": "B", "type": "int"}]}"#,
+    );
+    assert!(test::call_service(&app, authorized(req))
+        .await
+        .status()
+        .is_success());
+    for cell in [
+        r#"{"column": "A", "row": 1, "value": 1}"#,
+        r#"{"column": "B", "row": 1, "value": 2}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/sheet/payroll")
+            .set_payload(cell);
+        assert!(test::call_service(&app, authorized(req))
+            .await
+            .status()
+            .is_success());
+    }
+
+    // a link lets anyone read the sheet, so only its owners may hand one out
+    let claims = serde_json::json!({
+        "iss": "https://sso.example.com", "exp": exp, "sheets": {"write": ["payroll"]},
+    });
+    let editor =
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap();
+    let req = test::TestRequest::post()
+        .uri("/sheet/payroll/export-link")
+        .insert_header(("Authorization", format!("Bearer {editor}")))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    let req = test::TestRequest::post().uri("/sheet/payroll/export-link?ttl=60&delimiter=%3B");
+    let resp = test::call_service(&app, authorized(req)).await;
+    assert!(resp.status().is_success());
+    let link: serde_json::Value = test::read_body_json(resp).await;
+    let url = link["url"].as_str().unwrap();
+    assert!(url.starts_with("/exports/payroll?delimiter=%3B&expires="), "{url}");
+    assert!(link["expires_at"].as_i64().unwrap() > crate::db::now_millis());
+
+    // the link works without any credentials, as often as needed
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri(url).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(test::read_body(resp).await, "A;B\n1;2\n");
+    }
+
+    // neither the options nor the expiry can be changed
+    let tampered = url.replace("delimiter=%3B", "delimiter=%2C");
+    let req = test::TestRequest::get().uri(&tampered).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    let expires = link["expires_at"].as_i64().unwrap();
+    let extended = url.replace(&format!("expires={expires}"), &format!("expires={}", expires + 1));
+    let req = test::TestRequest::get().uri(&extended).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::get()
+        .uri("/exports/payroll")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    for query in ["ttl=0", "ttl=99999999", "delimiter=ab"] {
+        let req = test::TestRequest::post().uri(&format!("/sheet/payroll/export-link?{query}"));
+        let resp = test::call_service(&app, authorized(req)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{query}");
+    }
+    let req = test::TestRequest::post().uri("/sheet/nothing-here/export-link");
+    assert_eq!(test::call_service(&app, authorized(req)).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_anonymized_export() {
+    let app = init_service!();
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [
+                {"name": "Name", "type": "string", "pii": true},
+                {"name": "Email", "type": "email", "pii": true},
+                {"name": "Salary", "type": "int"}
+            ]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: PostResponse = test::call_and_read_body_json(&app, req).await;
+    let sheet_id = resp.sheet_id;
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import"))
+        .set_payload("Name,Email,Salary\nAda,ada@example.com,100\nAda,ada@example.org,200\n")
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}/schema"))
+        .to_request();
+    let schema: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(schema["columns"][0]["pii"], true);
+    assert!(schema["columns"][2].get("pii").is_none());
+
+    let export = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/export?{query}"))
+            .to_request()
+    };
+    let file = test::call_and_read_body(&app, export("anonymize=redact")).await;
+    assert_eq!(&file[..], b"Name,Email,Salary\n,,100\n,,200\n");
+
+    let file = test::call_and_read_body(&app, export("anonymize=hash")).await;
+    let file = String::from_utf8(file.to_vec()).unwrap();
+    let records: Vec<Vec<_>> = file
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').collect())
+        .collect();
+    // equal values have equal hashes, but nothing of the values themselves is left
+    assert_eq!(records[0][0], records[1][0]);
+    assert_ne!(records[0][1], records[1][1]);
+    assert!(!file.contains("Ada") && !file.contains("example"), "{file}");
+    assert!(records[0][0].len() == 16 && records[0][0].chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!((records[0][2], records[1][2]), ("100", "200"));
+
+    // the hashes of separate exports can't be matched up
+    let file = test::call_and_read_body(&app, export("anonymize=hash")).await;
+    assert!(!String::from_utf8(file.to_vec())
+        .unwrap()
+        .contains(records[0][0]));
+
+    let resp = test::call_service(&app, export("anonymize=scramble")).await;
+    let json = assert_is_error_response!(resp);
+    assert_eq!(json["code"], "invalid_query");
+}
+
+#[actix_web::test]
+async fn test_localized_export() {
+    let app = init_service!();
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [
+                {"name": "Item", "type": "string"},
+                {"name": "Count", "type": "int"},
+                {"name": "Price", "type": "double"}
+            ]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let resp: PostResponse = test::call_and_read_body_json(&app, req).await;
+    let sheet_id = resp.sheet_id;
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import"))
+        .set_payload("Item,Count,Price\n\"1.5 kg, ground\",1000,1234.5\n")
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let export = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/export?{query}"))
+            .to_request()
+    };
+    let file = test::call_and_read_body(&app, export("")).await;
+    assert_eq!(&file[..], b"Item,Count,Price\n\"1.5 kg, ground\",1000,1234.5\n");
+    // a decimal comma separates the file with semicolons, and only changes doubles
+    let file = test::call_and_read_body(&app, export("locale=de-DE")).await;
+    assert_eq!(&file[..], b"Item;Count;Price\n1.5 kg, ground;1000;1234,5\n");
+    let file = test::call_and_read_body(&app, export("locale=pt_BR&delimiter=,")).await;
+    assert_eq!(&file[..], b"Item,Count,Price\n\"1.5 kg, ground\",1000,\"1234,5\"\n");
+    // regions can write numbers differently from the rest of their language
+    let file = test::call_and_read_body(&app, export("locale=de-CH")).await;
+    assert_eq!(&file[..], b"Item,Count,Price\n\"1.5 kg, ground\",1000,1234.5\n");
+    let file = test::call_and_read_body(&app, export("locale=en-ZA")).await;
+    assert_eq!(&file[..], b"Item;Count;Price\n1.5 kg, ground;1000;1234,5\n");
+
+    for locale in ["xx", "de-DE-1996", "german", ""] {
+        let resp = test::call_service(&app, export(&format!("locale={locale}"))).await;
+        let json = assert_is_error_response!(resp);
+        assert_eq!(json["code"], "invalid_query", "{locale}");
+    }
+}
+
+#[actix_web::test]
+async fn test_batched_import_matches_single_writes() {
+    let app = init_service!();
+    let schema = r#"{"columns": [
+        {"name": "Id", "type": "autoincrement"},
+        {"name": "Name", "type": "string"},
+        {"name": "Score", "type": "int"},
+        {"name": "Ratio", "type": "double"},
+        {"name": "Email", "type": "email"}
+    ]}"#;
+    let imports = [
+        "Name,Score,Ratio,Email\nada,3,0.5,ada@example.com\nbob,1,,\n,7,1.5,\n",
+        // overwrites the minimum and maximum, and mixes in a lookup and a tombstoned cell
+        "Score,Name,Ratio\n2,bea,\"lookup(\"\"Ratio\"\", 3)\"\n5,carl,2.5\n",
+    ];
+
+    let mut sheets = vec![];
+    for _ in 0..2 {
+        let req = test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(schema)
+            .insert_header(ContentType::json())
+            .to_request();
+        let resp: PostResponse = test::call_and_read_body_json(&app, req).await;
+        let req = test::TestRequest::put()
+            .uri(&format!("/sheet/{}/settings", resp.sheet_id))
+            .set_payload(r#"{"row_keys": "alphanumeric"}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{}", resp.sheet_id))
+            .set_payload(r#"{"column": "Score", "row": 2, "value": null}"#)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        sheets.push(resp.sheet_id);
+    }
+
+    // the first sheet is imported, and the same cells are written one by one to the second
+    for file in imports {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{}/import", sheets[0]))
+            .set_payload(file)
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["dry_run"], false, "{body}");
+
+        let schema: crate::sheet::Schema = serde_json::from_str(schema).unwrap();
+        let import =
+            crate::sheet::import::parse_csv(file.as_bytes(), &schema.columns, Default::default());
+        for cell in import.cells {
+            let req = test::TestRequest::post()
+                .uri(&format!("/sheet/{}", sheets[1]))
+                .set_json(&cell)
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+    }
+
+    let read = |sheet_id: &str, endpoint: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}{endpoint}"))
+            .to_request()
+    };
+    for endpoint in ["?include=version", "/stats"] {
+        let mut imported: serde_json::Value =
+            test::call_and_read_body_json(&app, read(&sheets[0], endpoint)).await;
+        let mut written: serde_json::Value =
+            test::call_and_read_body_json(&app, read(&sheets[1], endpoint)).await;
+        // the order of the cells within a column isn't specified
+        for content in [&mut imported, &mut written] {
+            for cells in content["columns"].as_object_mut().unwrap().values_mut() {
+                if let Some(cells) = cells.as_array_mut() {
+                    cells.sort_by_key(|cell| cell["row"].as_i64());
+                }
+            }
+        }
+        assert_eq!(imported, written, "{endpoint}");
+    }
+    let req = read(&sheets[0], "/stats");
+    let stats: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["columns"]["Score"]["min"], 2, "{stats}");
+
+    let events = |sheet_id| read(sheet_id, "/events");
+    let mut imported: serde_json::Value =
+        test::call_and_read_body_json(&app, events(&sheets[0])).await;
+    let mut written: serde_json::Value =
+        test::call_and_read_body_json(&app, events(&sheets[1])).await;
+    for events in [&mut imported, &mut written] {
+        for event in events["events"].as_array_mut().unwrap() {
+            event.as_object_mut().unwrap().remove("at");
+        }
+    }
+    assert_eq!(imported["events"], written["events"]);
+
+    let req = read(&sheets[0], "?include=row_keys");
+    let content: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(content["row_keys"].as_object().unwrap().len(), 3);
+}
+
+#[actix_web::test]
+async fn test_read_memory_budget() {
+    let app = init_service!(AppConfig {
+        read_memory_budget: 20 * 128,
+        ..Default::default()
+    });
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let write = |row: i64| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(format!(r#"{{"column": "B", "row": {row}, "value": {row}}}"#))
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let read = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}{query}"))
+            .to_request()
+    };
+    for row in 1..=10 {
+        assert!(test::call_service(&app, write(row))
+            .await
+            .status()
+            .is_success());
+    }
+    assert!(test::call_service(&app, read(""))
+        .await
+        .status()
+        .is_success());
+
+    for row in 11..=30 {
+        assert!(test::call_service(&app, write(row))
+            .await
+            .status()
+            .is_success());
+    }
+    let resp = test::call_service(&app, read("")).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::PAYLOAD_TOO_LARGE);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "sheet_too_large");
+
+    // exports and published sheets are read in full as well
+    let req = test::TestRequest::put()
+        .uri(&format!("/sheet/{sheet_id}/settings"))
+        .set_payload(r#"{"published": true}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    for uri in [
+        format!("/sheet/{sheet_id}/export"),
+        format!("/published/{sheet_id}"),
+        format!("/published/{sheet_id}/csv"),
+        format!("/published/{sheet_id}/html"),
+    ] {
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::PAYLOAD_TOO_LARGE, "{uri}");
+    }
+
+    // the budget is released after every read, and pages aren't subject to it
+    let resp = test::call_service(&app, read("?limit=1000")).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["columns"]["B"].as_array().unwrap().len(), 30);
+}
+
+#[actix_web::test]
+async fn test_row_bounds() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    let put_settings = |payload: &'static str| {
+        test::TestRequest::put()
+            .uri(&format!("/sheet/{sheet_id}/settings"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let resp =
+        test::call_service(&app, put_settings(r#"{"row_bounds": {"min": 5, "max": 1}}"#)).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["details"][0]["path"], "row_bounds");
+
+    let resp =
+        test::call_service(&app, put_settings(r#"{"row_bounds": {"min": 1, "max": 2}}"#)).await;
+    assert!(resp.status().is_success());
+
+    let write = |payload: &'static str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    for payload in [
+        r#"{"column": "B", "row": 0, "value": 1}"#,
+        r#"{"column": "B", "row": -4, "value": "lookup(\"B\", 1)"}"#,
+        r#"{"column": "B", "row": 3, "value": null}"#,
+    ] {
+        let resp = test::call_service(&app, write(payload)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{payload}");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "row_out_of_bounds", "{payload}");
+    }
+    let resp = test::call_service(&app, write(r#"{"column": "B", "row": 2, "value": 1}"#)).await;
+    assert!(resp.status().is_success());
+
+    // imports are checked cell by cell as well, and nothing is written if one is out of bounds
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{sheet_id}/import"))
+        .set_payload("B\n10\n20\n30\n")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "row_out_of_bounds");
+    assert!(body["error"].as_str().unwrap().starts_with("rows[3].B"), "{body}");
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{sheet_id}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(content.columns["B"].len(), 1);
+}
+
+#[actix_web::test]
+async fn test_grid() {
+    let app = init_service!();
+    let sheet_id = get_standard_sheet(&app).await.unwrap();
+
+    for payload in [
+        r#"{"column": "B", "row": 2, "value": 5}"#,
+        r#"{"column": "B2", "row": 3, "value": "lookup(\"B\", 2)"}"#,
+        r#"{"column": "D", "row": 3, "value": "x"}"#,
+        r#"{"column": "D", "row": 9, "value": "outside"}"#,
+    ] {
+        let req = test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet_id}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let grid = |query: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet_id}/grid?{query}"))
+            .to_request()
+    };
+    let body: serde_json::Value = test::call_and_read_body_json(&app, grid("rows=1-3")).await;
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "columns": ["A", "B", "B2", "C", "D"],
+            "first_row": 1,
+            "rows": [
+                [null, null, null, null, null],
+                [null, 5, null, null, null],
+                [null, null, 5, null, "x"],
+            ],
+        })
+    );
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, grid("rows=-1-0")).await;
+    let empty = [(); 5].map(|_| serde_json::Value::Null);
+    assert_eq!(body["rows"], serde_json::json!([empty, empty]));
+
+    for query in ["rows=3-1", "rows=1", "rows=a-b", "rows=1-10000000", ""] {
+        let resp = test::call_service(&app, grid(query)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{query}");
+    }
+}
+
+#[actix_web::test]
+async fn test_column_references() {
+    let app = init_service!();
+    let create = |payload: String| {
+        test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let req = create(
+        r#"{"columns": [{"name": "id", "type": "string"}, {"name": "email", "type": "email"}]}"#
+            .into(),
+    );
+    let resp: PostResponse = test::call_and_read_body_json(&app, req).await;
+    let customers = resp.sheet_id;
+
+    let resp = test::call_service(
+        &app,
+        create(serde_json::json!({"columns": [
+            {"name": "a", "type": "string", "references": {"sheet": "missing-sheet", "column": "id"}},
+            {"name": "b", "type": "string", "references": {"sheet": customers, "column": "nope"}},
+            {"name": "c", "type": "int", "references": {"sheet": customers, "column": "id"}},
+        ]})
+        .to_string()),
+    )
+    .await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let paths: Vec<_> = body["details"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "columns[0].references",
+            "columns[1].references",
+            "columns[2].references"
+        ]
+    );
+
+    let columns = serde_json::json!([
+        {"name": "customer", "type": "string",
+            "references": {"sheet": customers, "column": "id", "on_delete": "restrict"}},
+        {"name": "contact", "type": "string",
+            "references": {"sheet": customers, "column": "email", "on_delete": "cascade"}},
+    ]);
+    let req = create(serde_json::json!({ "columns": columns }).to_string());
+    let resp: PostResponse = test::call_and_read_body_json(&app, req).await;
+    let orders = resp.sheet_id;
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{orders}/schema"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"], columns);
+
+    let write = |sheet: &str, payload: &'static str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet}"))
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    for payload in [
+        r#"{"column": "id", "row": 0, "value": "c1"}"#,
+        r#"{"column": "id", "row": 1, "value": "c2"}"#,
+        r#"{"column": "email", "row": 0, "value": "a@example.com"}"#,
+    ] {
+        let resp = test::call_service(&app, write(&customers, payload)).await;
+        assert!(resp.status().is_success(), "{payload}");
+    }
+
+    for (payload, reason) in [
+        (r#"{"column": "customer", "row": 0, "value": "c3"}"#, "dangling"),
+        (r#"{"column": "customer", "row": 0, "value": "lookup(\"contact\", 1)"}"#, "lookup"),
+    ] {
+        let resp = test::call_service(&app, write(&orders, payload)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{payload}");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "reference_violation", "{payload}");
+        assert_eq!(body["details"]["reason"], reason, "{payload}");
+    }
+    for payload in [
+        r#"{"column": "customer", "row": 0, "value": "c1"}"#,
+        r#"{"column": "contact", "row": 0, "value": "a@example.com"}"#,
+    ] {
+        let resp = test::call_service(&app, write(&orders, payload)).await;
+        assert!(resp.status().is_success(), "{payload}");
+    }
+
+    // a referenced value can't be removed while it's restricted, unless another row still holds it
+    for payload in [
+        r#"{"column": "id", "row": 0, "value": null}"#,
+        r#"{"column": "id", "row": 0, "value": "c9"}"#,
+    ] {
+        let resp = test::call_service(&app, write(&customers, payload)).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::CONFLICT, "{payload}");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["details"]["reason"], "restricted", "{payload}");
+        assert_eq!(body["details"]["referencing_row"], 0, "{payload}");
+    }
+    for payload in [
+        r#"{"column": "id", "row": 2, "value": "c1"}"#,
+        r#"{"column": "id", "row": 0, "value": "c9"}"#,
+        r#"{"column": "email", "row": 0, "value": null}"#,
+    ] {
+        let resp = test::call_service(&app, write(&customers, payload)).await;
+        assert!(resp.status().is_success(), "{payload}");
+    }
+
+    // the removed email took the contact with it
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{orders}"))
+        .to_request();
+    let content: SheetContent = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(content.columns["customer"][0].value, Some(CellValue::String("c1".into())));
+    assert_eq!(content.columns["contact"][0].value, None);
+}
+
+#[actix_web::test]
+async fn test_join() {
+    let app = init_service!();
+    let create = |payload: &'static str| {
+        test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(payload)
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let req = create(
+        r#"{"columns": [{"name": "customer", "type": "int"}, {"name": "total", "type": "double"}]}"#,
+    );
+    let orders = test::call_and_read_body_json::<_, _, PostResponse>(&app, req)
+        .await
+        .sheet_id;
+    let req = create(
+        r#"{"columns": [{"name": "id", "type": "double"}, {"name": "name", "type": "string"}]}"#,
+    );
+    let customers = test::call_and_read_body_json::<_, _, PostResponse>(&app, req)
+        .await
+        .sheet_id;
+
+    let write = |sheet: &str, column: &str, row: i64, value: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet}"))
+            .set_payload(
+                serde_json::json!({"column": column, "row": row, "value": value}).to_string(),
+            )
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    for (sheet, column, row, value) in [
+        (&orders, "customer", 0, serde_json::json!(1)),
+        (&orders, "total", 0, serde_json::json!(9.5)),
+        (&orders, "customer", 1, serde_json::json!(2)),
+        (&orders, "customer", 2, serde_json::json!(1)),
+        (&orders, "total", 3, serde_json::json!(1.5)),
+        (&customers, "id", 0, serde_json::json!(1.0)),
+        (&customers, "name", 0, serde_json::json!("ada")),
+        (&customers, "id", 5, serde_json::json!(1.5)),
+        (&customers, "name", 5, serde_json::json!("bob")),
+    ] {
+        let resp = test::call_service(&app, write(sheet, column, row, value)).await;
+        assert!(resp.status().is_success());
+    }
+
+    let join = |query: String| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{orders}/join?{query}"))
+            .to_request()
+    };
+    let query = format!("with={customers}&left_on=customer&right_on=id");
+    let body: serde_json::Value = test::call_and_read_body_json(&app, join(query.clone())).await;
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "columns": ["left.customer", "left.total", "right.id", "right.name"],
+            "rows": [
+                {"left_row": 0, "right_row": 0, "values": [1, 9.5, 1.0, "ada"]},
+                {"left_row": 2, "right_row": 0, "values": [1, null, 1.0, "ada"]},
+            ]
+        })
+    );
+
+    // a left join keeps every populated row
+    let body: serde_json::Value =
+        test::call_and_read_body_json(&app, join(format!("{query}&how=left"))).await;
+    let rows: Vec<_> = body["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| (row["left_row"].clone(), row["right_row"].clone()))
+        .collect();
+    assert_eq!(
+        rows,
+        [(0, Some(0)), (1, None), (2, Some(0)), (3, None)]
+            .map(|(left, right)| (serde_json::json!(left), serde_json::json!(right)))
+    );
+    assert_eq!(body["rows"][1]["values"], serde_json::json!([2, null, null, null]));
+
+    for query in [
+        format!("with={customers}&left_on=customer&right_on=nope"),
+        format!("with={customers}&left_on=customer&right_on=id&how=outer"),
+        "with=no&left_on=customer&right_on=id".into(),
+    ] {
+        let resp = test::call_service(&app, join(query.clone())).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{query}");
+    }
+    let resp =
+        test::call_service(&app, join("with=missing-sheet&left_on=customer&right_on=id".into()))
+            .await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_join_needs_access_to_both_sheets() {
+    use actix_web::http::StatusCode;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    let app = init_service!(AppConfig {
+        jwt_issuer: Some("https://sso.example.com".into()),
+        jwt_secret: Some("hunter2".into()),
+        ..Default::default()
+    });
+    let token = |sheets: serde_json::Value| {
+        let exp = crate::db::now_millis() / 1000 + 60;
+        let claims =
+            serde_json::json!({"iss": "https://sso.example.com", "exp": exp, "sheets": sheets});
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(b"hunter2"))
+            .unwrap()
+    };
+    let admin = token(serde_json::json!({"write": ["*"]}));
+    for id in ["orders", "salaries"] {
+        let req = test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(format!(
+                r#"{{"id": "{id}", "columns": [{{"name": "A", "type": "int"}}]}}"#
+            ))
+            .insert_header(ContentType::json())
+            .insert_header(("Authorization", format!("Bearer {admin}")))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let join = |token: &str| {
+        test::TestRequest::get()
+            .uri("/sheet/orders/join?with=salaries&left_on=A&right_on=A")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request()
+    };
+    let reader = token(serde_json::json!({"read": ["orders"]}));
+    assert_eq!(test::call_service(&app, join(&reader)).await.status(), StatusCode::FORBIDDEN);
+    let reader = token(serde_json::json!({"read": ["orders", "salaries"]}));
+    assert!(test::call_service(&app, join(&reader))
+        .await
+        .status()
+        .is_success());
+}
+
+#[actix_web::test]
+async fn test_views() {
+    use std::collections::BTreeMap;
+
+    use actix_web::http::StatusCode;
+
+    let app = init_service!();
+    let req = test::TestRequest::post()
+        .uri("/sheet")
+        .set_payload(
+            r#"{"columns": [{"name": "team", "type": "string"}, {"name": "points", "type": "int"}]}"#,
+        )
+        .insert_header(ContentType::json())
+        .to_request();
+    let source = test::call_and_read_body_json::<_, _, PostResponse>(&app, req)
+        .await
+        .sheet_id;
+    let write = |sheet: &str, column: &str, row: i64, value: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{sheet}"))
+            .set_payload(
+                serde_json::json!({"column": column, "row": row, "value": value}).to_string(),
+            )
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    for (column, row, value) in [
+        ("team", 1, serde_json::json!("b")),
+        ("points", 1, serde_json::json!(3)),
+        ("team", 2, serde_json::json!("a")),
+        ("points", 2, serde_json::json!(1)),
+        ("team", 3, serde_json::json!("b")),
+        ("points", 3, serde_json::json!(4)),
+    ] {
+        let resp = test::call_service(&app, write(&source, column, row, value)).await;
+        assert!(resp.status().is_success());
+    }
+
+    let create = |payload: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{source}/views"))
+            .set_payload(payload.to_string())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let req = create(serde_json::json!({
+        "id": "good-scores",
+        "columns": ["points"],
+        "filter": [{"column": "points", "op": "ge", "value": 3}]
+    }));
+    let filtered = test::call_and_read_body_json::<_, _, PostResponse>(&app, req)
+        .await
+        .sheet_id;
+    assert_eq!(filtered, "good-scores");
+    let req = create(serde_json::json!({
+        "group_by": "team",
+        "aggregates": [{"name": "total", "function": "sum", "column": "points"}],
+        "refresh": "manual"
+    }));
+    let grouped = test::call_and_read_body_json::<_, _, PostResponse>(&app, req)
+        .await
+        .sheet_id;
+
+    let content = |sheet: &str| {
+        test::TestRequest::get()
+            .uri(&format!("/sheet/{sheet}"))
+            .to_request()
+    };
+    // the cells of a column by row, since they aren't returned in any particular order
+    let cells = |body: &serde_json::Value, column: &str| {
+        body["columns"][column]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cell| (cell["row"].as_i64().unwrap(), cell["value"].clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let body: serde_json::Value = test::call_and_read_body_json(&app, content(&filtered)).await;
+    assert_eq!(body["columns"].as_object().unwrap().len(), 1);
+    assert_eq!(
+        cells(&body, "points"),
+        BTreeMap::from([(1, serde_json::json!(3)), (3, serde_json::json!(4))])
+    );
+    let body: serde_json::Value = test::call_and_read_body_json(&app, content(&grouped)).await;
+    assert_eq!(
+        cells(&body, "total"),
+        BTreeMap::from([(1, serde_json::json!(1.0)), (2, serde_json::json!(7.0))])
+    );
+
+    // views can't be written to, only refreshed
+    let resp = test::call_service(&app, write(&filtered, "points", 9, serde_json::json!(5))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "read_only_view");
+
+    // writing to the source refreshes the first view right away, but the second only once it's asked to
+    let resp = test::call_service(&app, write(&source, "points", 2, serde_json::json!(8))).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::call_and_read_body_json(&app, content(&filtered)).await;
+    assert_eq!(cells(&body, "points")[&2], 8);
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{grouped}/view"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["source"], source.as_str());
+    assert_eq!(body["stale"], true);
+    let req = test::TestRequest::post()
+        .uri(&format!("/sheet/{grouped}/refresh"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["stale"], false);
+    let body: serde_json::Value = test::call_and_read_body_json(&app, content(&grouped)).await;
+    assert_eq!(cells(&body, "total")[&1], 8.0);
+
+    let req =
+        create(serde_json::json!({"filter": [{"column": "points", "op": "lt", "value": "x"}]}));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["details"][0]["path"], "filter[0].value");
+    let req = test::TestRequest::get()
+        .uri(&format!("/sheet/{source}/view"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_archive_and_rehydrate() {
+    use actix_web::http::StatusCode;
+
+    let app = init_service!(AppConfig {
+        admin_token: Some("hunter2".into()),
+        ..Default::default()
+    });
+    let create = |payload: &str| {
+        test::TestRequest::post()
+            .uri("/sheet")
+            .set_payload(payload.to_owned())
+            .insert_header(ContentType::json())
+            .to_request()
+    };
+    let req = create(
+        r#"{"id": "cold-sheet", "columns": [{"name": "A", "type": "double"}, {"name": "B", "type": "int", "hidden": true}]}"#,
+    );
+    assert!(test::call_service(&app, req).await.status().is_success());
+    for (column, row, value) in [
+        ("A", 1, serde_json::json!(0.1 + 0.2)),
+        ("B", 2, serde_json::json!(7)),
+        ("A", 3, serde_json::json!("lookup(\"A\", 1)")),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/sheet/cold-sheet")
+            .set_payload(
+                serde_json::json!({"column": column, "row": row, "value": value}).to_string(),
+            )
+            .insert_header(ContentType::json())
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let content = || {
+        test::TestRequest::get()
+            .uri("/sheet/cold-sheet?include_hidden=1&include=version")
+            .to_request()
+    };
+    let before = test::call_and_read_body_json::<_, _, SheetContent>(&app, content())
+        .await
+        .with_sorted_columns();
+
+    // sheets which others depend on can't be archived
+    let req = test::TestRequest::post()
+        .uri("/sheet/cold-sheet/views")
+        .set_payload(r#"{"id": "cold-view"}"#)
+        .insert_header(ContentType::json())
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let archive = |id: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/sheet/{id}/archive"))
+            .to_request()
+    };
+    let resp = test::call_service(&app, archive("cold-sheet")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, archive("cold-view")).await;
+    assert_eq!(body["id"], "cold-view");
+    let body: serde_json::Value = test::call_and_read_body_json(&app, archive("cold-sheet")).await;
+    assert_eq!(body["cells"], 3);
+    assert!(body["size"].as_i64().unwrap() > 0);
+
+    let resp = test::call_service(&app, content()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    // the id stays taken
+    let resp = test::call_service(&app, create(r#"{"id": "cold-sheet", "columns": []}"#)).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let req = test::TestRequest::get()
+        .uri("/admin/archives")
+        .insert_header(("Authorization", "Bearer hunter2"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<_> = body["sheets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|sheet| sheet["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&"cold-sheet") && ids.contains(&"cold-view"));
+
+    let rehydrate = |id: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/admin/archives/{id}/rehydrate"))
+            .insert_header(("Authorization", "Bearer hunter2"))
+            .to_request()
+    };
+    // a view can't come back before its source
+    let resp = test::call_service(&app, rehydrate("cold-view")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    for id in ["cold-sheet", "cold-view"] {
+        let resp = test::call_service(&app, rehydrate(id)).await;
+        assert!(resp.status().is_success(), "{id}");
+    }
+    let after = test::call_and_read_body_json::<_, _, SheetContent>(&app, content())
+        .await
+        .with_sorted_columns();
+    assert_eq!(after, before);
+    let req = test::TestRequest::get()
+        .uri("/sheet/cold-view")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["columns"]["A"].as_array().unwrap().len(), 2);
+
+    let resp = test::call_service(&app, rehydrate("cold-sheet")).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let req = test::TestRequest::get()
+        .uri("/admin/archives")
+        .insert_header(("Authorization", "Bearer hunter2"))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["sheets"], serde_json::json!([]));
+}
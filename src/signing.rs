@@ -0,0 +1,216 @@
+//! Signed requests, for machine-to-machine writes. With a signing secret configured (see
+//! [`crate::config::AppConfig::signing_secret`]), every mutating request to a sheet has to carry an HMAC-SHA256
+//! signature of its timestamp, method, path and body, so that it can't be forged or tampered with. Requests are
+//! only accepted within a window around their timestamp, and every signature is only accepted once within it, so
+//! that captured requests can't be replayed either.
+//!
+//! Export links (see [`ExportLink`]) are signed the same way, so that a sheet's export can be handed to someone
+//! without any credentials until the link expires.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// The time the request was signed at, in seconds since the unix epoch.
+pub const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+/// The signature itself, in hex.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Why a request's signature was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    Expired,
+    Invalid,
+    Replayed,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Missing => "missing or malformed signature headers",
+            Self::Expired => "the signature's timestamp is outside of the accepted window",
+            Self::Invalid => "invalid signature",
+            Self::Replayed => "the signature was already used",
+        })
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// The part of a request which is signed.
+#[derive(Debug, Clone, Copy)]
+pub struct SignedRequest<'a> {
+    pub timestamp: i64,
+    pub method: &'a str,
+    /// The path including the query, as it was sent but normalized like every path (see
+    /// [`actix_web::middleware::NormalizePath::trim`]), i.e. without a trailing slash or repeated slashes.
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+impl SignedRequest<'_> {
+    fn mac(&self, secret: &[u8]) -> Hmac<Sha256> {
+        // hmac accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(format!("{}\n{}\n{}\n", self.timestamp, self.method, self.path).as_bytes());
+        mac.update(self.body);
+        mac
+    }
+
+    /// Signs the request, returning the signature in hex.
+    pub fn sign(&self, secret: &[u8]) -> String {
+        hex::encode(self.mac(secret).finalize().into_bytes())
+    }
+}
+
+/// The signatures accepted within the window, by their timestamp, shared by all workers.
+#[derive(Debug, Default)]
+pub struct SeenSignatures {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl SeenSignatures {
+    /// Checks the signature of a request against the secret, at `now` (in seconds since the unix epoch), and
+    /// remembers it so that it's rejected if it's used again.
+    pub fn verify(
+        &self,
+        secret: &[u8],
+        window: Duration,
+        now: i64,
+        request: &SignedRequest<'_>,
+        signature: &str,
+    ) -> Result<(), SignatureError> {
+        let window = window.as_secs() as i64;
+        if (request.timestamp - now).abs() > window {
+            return Err(SignatureError::Expired);
+        }
+
+        let signature = hex::decode(signature).map_err(|_| SignatureError::Missing)?;
+        // this compares in constant time, so that the signature can't be guessed byte by byte
+        request
+            .mac(secret)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+
+        let mut seen = self.seen.lock().unwrap();
+        // signatures outside of the window are rejected anyway, so there's no need to remember them
+        seen.retain(|_, timestamp| (*timestamp - now).abs() <= window);
+        if seen
+            .insert(hex::encode(signature), request.timestamp)
+            .is_some()
+        {
+            return Err(SignatureError::Replayed);
+        }
+
+        Ok(())
+    }
+}
+
+/// A link for downloading an export of a sheet, which is valid until it expires.
+#[derive(Debug, Clone, Copy)]
+pub struct ExportLink<'a> {
+    pub sheet_id: &'a str,
+    /// The options of the export, as a query string.
+    pub query: &'a str,
+    /// When the link expires, in milliseconds since the unix epoch.
+    pub expires: i64,
+}
+
+impl ExportLink<'_> {
+    fn mac(&self, secret: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(format!("{}\n{}\n{}", self.expires, self.sheet_id, self.query).as_bytes());
+        mac
+    }
+
+    /// Signs the link, returning the signature in hex.
+    pub fn sign(&self, secret: &[u8]) -> String {
+        hex::encode(self.mac(secret).finalize().into_bytes())
+    }
+
+    /// Checks the signature of the link at `now` (in milliseconds since the unix epoch). Unlike requests, links
+    /// can be used any number of times until they expire.
+    pub fn verify(&self, secret: &[u8], now: i64, signature: &str) -> Result<(), SignatureError> {
+        let signature = hex::decode(signature).map_err(|_| SignatureError::Missing)?;
+        self.mac(secret)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+        if now > self.expires {
+            return Err(SignatureError::Expired);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ExportLink, SeenSignatures, SignatureError, SignedRequest};
+
+    const WINDOW: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn signatures_are_checked() {
+        let request = SignedRequest {
+            timestamp: 1_000,
+            method: "POST",
+            path: "/sheet/budget",
+            body: br#"{"column": "A", "row": 1, "value": 5}"#,
+        };
+        let signature = request.sign(b"secret");
+        let seen = SeenSignatures::default();
+
+        let tampered = SignedRequest {
+            body: br#"{"column": "A", "row": 1, "value": 6}"#,
+            ..request
+        };
+        assert_eq!(
+            seen.verify(b"secret", WINDOW, 1_000, &tampered, &signature),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            seen.verify(b"other", WINDOW, 1_000, &request, &signature),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            seen.verify(b"secret", WINDOW, 1_301, &request, &signature),
+            Err(SignatureError::Expired)
+        );
+
+        assert_eq!(seen.verify(b"secret", WINDOW, 1_100, &request, &signature), Ok(()));
+        assert_eq!(
+            seen.verify(b"secret", WINDOW, 1_200, &request, &signature),
+            Err(SignatureError::Replayed)
+        );
+    }
+
+    #[test]
+    fn export_links_are_checked() {
+        let link = ExportLink {
+            sheet_id: "budget",
+            query: "delimiter=%3B",
+            expires: 5_000,
+        };
+        let signature = link.sign(b"secret");
+        assert_eq!(link.verify(b"secret", 4_000, &signature), Ok(()));
+        assert_eq!(link.verify(b"secret", 4_000, &signature), Ok(()));
+        assert_eq!(link.verify(b"secret", 5_001, &signature), Err(SignatureError::Expired));
+
+        let extended = ExportLink {
+            expires: 9_000,
+            ..link
+        };
+        assert_eq!(extended.verify(b"secret", 4_000, &signature), Err(SignatureError::Invalid));
+        let other_sheet = ExportLink {
+            sheet_id: "roadmap",
+            ..link
+        };
+        assert_eq!(other_sheet.verify(b"secret", 4_000, &signature), Err(SignatureError::Invalid));
+        assert_eq!(link.verify(b"secret", 4_000, "zz"), Err(SignatureError::Missing));
+    }
+}
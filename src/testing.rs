@@ -0,0 +1,58 @@
+//! Helpers shared by all of the handler tests.
+
+// this is a macro because frankly writing the return type would be a hassle
+macro_rules! init_service {
+    (data: $data:expr) => {{
+        let _ = ::env_logger::builder()
+            .is_test(true)
+            .filter_level(::log::LevelFilter::max())
+            .try_init();
+        let data: crate::AppData = $data;
+        let data = ::actix_web::web::Data::new(data);
+        ::actix_web::test::init_service(
+            ::actix_web::App::new()
+                .app_data(data.clone())
+                .wrap(crate::middleware::RequireJwt)
+                .wrap(crate::middleware::ResolveAliases)
+                .wrap(crate::middleware::VerifySignatures)
+                .wrap(crate::middleware::ReadOnly)
+                .wrap(crate::middleware::CatchPanic)
+                .wrap(crate::middleware::Timeout(data.config.request_timeout))
+                .wrap(crate::middleware::TrackTraffic)
+                .wrap(::actix_web::middleware::NormalizePath::trim())
+                .configure(|cfg| crate::routes(cfg, &data.config)),
+        )
+        .await
+    }};
+
+    ($config:expr) => {
+        init_service!(data: crate::AppData::new(
+            crate::db::Db::new_memory().await.unwrap(),
+            $config
+        ))
+    };
+
+    () => {
+        init_service!(crate::config::AppConfig::default())
+    };
+}
+
+macro_rules! assert_is_error_response {
+    ($resp:expr) => {{
+        ::std::assert!($resp.status().is_client_error());
+
+        let body = ::actix_web::body::to_bytes($resp.into_body())
+            .await
+            .unwrap();
+        ::std::dbg!(&body);
+        let json: ::serde_json::Value = ::serde_json::from_slice(&body).unwrap();
+        ::std::assert!(json.is_object());
+        ::std::assert!(json
+            .as_object()
+            .unwrap()
+            .keys()
+            .all(|key| ["error", "code", "details"].contains(&key.as_str())));
+        ::std::assert!(json["error"].is_string());
+        json
+    }};
+}
@@ -0,0 +1,128 @@
+//! Per-sheet traffic statistics, for finding the sheets which generate the most load.
+//!
+//! Requests are counted in memory by [`crate::middleware::TrackTraffic`], since writing to the database on every
+//! request would itself be a considerable load. The counts are added to the database every
+//! `STATS_FLUSH_INTERVAL` (see [`crate::config::AppConfig::stats_flush_interval`]), on shutdown, and whenever the
+//! statistics are read, so only the counts since the last flush are lost if the server crashes.
+//!
+//! If `ACCESS_LOG` is set (see [`crate::config::AppConfig::access_log`]), every request is also kept as an
+//! [`AccessEvent`] of its sheet, and written along with the counts.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::Db;
+
+/// The traffic of a single sheet.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SheetTraffic {
+    /// The number of `GET` (and `HEAD`) requests.
+    pub reads: u64,
+    /// The number of all other requests.
+    pub writes: u64,
+    /// The size of the response bodies, not counting streamed ones, whose size isn't known up front.
+    pub bytes_served: u64,
+}
+
+impl SheetTraffic {
+    pub fn requests(&self) -> u64 {
+        self.reads + self.writes
+    }
+}
+
+/// A single request to a sheet, as kept by the access log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccessEvent {
+    /// Who made the request: the subject (`sub`) of its JWT if it had one, otherwise the client's address.
+    pub reader: String,
+    /// When the request was made, in milliseconds since the unix epoch.
+    pub at: i64,
+    pub method: String,
+    /// The path of the request, without the query.
+    pub path: String,
+    pub status: u16,
+}
+
+/// The traffic which hasn't been added to the database yet, by sheet id.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    pending: Mutex<HashMap<String, SheetTraffic>>,
+    /// Access events by sheet id, in the order the requests were made.
+    pending_access: Mutex<Vec<(String, AccessEvent)>>,
+}
+
+impl Traffic {
+    pub fn record(&self, sheet_id: &str, read: bool, bytes: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        // looking up first saves allocating the key for sheets which are already known
+        let traffic = match pending.get_mut(sheet_id) {
+            Some(traffic) => traffic,
+            None => pending.entry(sheet_id.into()).or_default(),
+        };
+
+        if read {
+            traffic.reads += 1;
+        } else {
+            traffic.writes += 1;
+        }
+        traffic.bytes_served += bytes;
+    }
+
+    pub fn record_access(&self, sheet_id: &str, event: AccessEvent) {
+        self.pending_access
+            .lock()
+            .unwrap()
+            .push((sheet_id.into(), event));
+    }
+
+    /// Adds the pending traffic and access events to the database. If that fails, they're kept to be added by the
+    /// next flush.
+    pub async fn flush(&self, db: &Db) -> anyhow::Result<()> {
+        let access = std::mem::take(&mut *self.pending_access.lock().unwrap());
+        if !access.is_empty() {
+            if let Err(why) = db.add_access_events(&access).await {
+                // the events which were recorded in the meantime are newer
+                let mut current = self.pending_access.lock().unwrap();
+                current.splice(0..0, access);
+                return Err(why);
+            }
+        }
+
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(why) = db.add_traffic(&pending).await {
+            let mut current = self.pending.lock().unwrap();
+            for (sheet_id, traffic) in pending {
+                let current = current.entry(sheet_id).or_default();
+                current.reads += traffic.reads;
+                current.writes += traffic.writes;
+                current.bytes_served += traffic.bytes_served;
+            }
+            return Err(why);
+        }
+
+        Ok(())
+    }
+}
+
+/// The traffic of a sheet, as listed by the admin statistics.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TrafficEntry {
+    pub sheet_id: String,
+    #[serde(flatten)]
+    pub traffic: SheetTraffic,
+}
+
+/// What the busiest sheets are ranked by.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficOrder {
+    #[default]
+    Requests,
+    BytesServed,
+}
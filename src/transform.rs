@@ -0,0 +1,108 @@
+//! Hooks for embedding the server as a library, which change the content of sheets after it's read and before
+//! it's sent (see [`crate::AppData::with_read_transformer`]), e.g. to convert units or format values for a
+//! locale.
+//!
+//! Transformers are registered either to run on every read, or under a name, to run only on reads which ask
+//! for them with the [`TRANSFORM_HEADER`]. They apply wherever resolved cells are sent, including exports and
+//! published sheets. Reads of rows, columns, grids, joins and single cells hand them a [`SheetContent`] with only
+//! the cells being read. They don't apply to events or statistics, nor to the content which views derive from
+//! their sources, so they change how a sheet is presented, and are not an access control mechanism.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::db::SheetId;
+use crate::sheet::SheetContent;
+
+/// The header naming the transformers a read asks for, as a comma-separated list. They run in the given order,
+/// after those which run on every read.
+pub const TRANSFORM_HEADER: &str = "X-Read-Transform";
+
+/// Something that changes the content of a sheet before it's sent.
+pub trait ReadTransformer: Send + Sync {
+    /// Changes the content of a sheet, which may have been read a page at a time, or only contain some of its
+    /// cells, such as those which changed since some version. Returning an error fails the request with a `500`.
+    fn transform(&self, sheet_id: &SheetId, content: &mut SheetContent) -> Result<()>;
+}
+
+/// The registered transformers.
+#[derive(Default)]
+pub struct ReadTransformers {
+    /// Those without a name run on every read.
+    transformers: Vec<(Option<String>, Arc<dyn ReadTransformer>)>,
+}
+
+impl ReadTransformers {
+    pub fn add(&mut self, name: Option<String>, transformer: Arc<dyn ReadTransformer>) {
+        self.transformers.push((name, transformer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transformers.is_empty()
+    }
+
+    /// Applies every transformer which runs on every read, followed by the ones named in `selected` (a
+    /// comma-separated list, as in the [`TRANSFORM_HEADER`]).
+    ///
+    /// # Errors
+    /// In case a name doesn't belong to any transformer (an [`UnknownTransformer`], checked before anything
+    /// runs), or a transformer fails (a [`TransformFailed`]).
+    pub fn apply(
+        &self,
+        selected: Option<&str>,
+        sheetid: &SheetId,
+        content: &mut SheetContent,
+    ) -> Result<()> {
+        let mut transformers: Vec<_> = self
+            .transformers
+            .iter()
+            .filter(|(name, _)| name.is_none())
+            .map(|(_, transformer)| transformer)
+            .collect();
+        let selected = selected
+            .into_iter()
+            .flat_map(|names| names.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty());
+        for selected in selected {
+            let transformer = self
+                .transformers
+                .iter()
+                .find(|(name, _)| name.as_deref() == Some(selected))
+                .ok_or_else(|| UnknownTransformer(selected.into()))?;
+            transformers.push(&transformer.1);
+        }
+
+        for transformer in transformers {
+            transformer
+                .transform(sheetid, content)
+                .map_err(|why| TransformFailed(why.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// An error signifying that a read asked for a transformer which doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTransformer(pub String);
+
+impl std::fmt::Display for UnknownTransformer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown read transformer {:?} in {TRANSFORM_HEADER}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownTransformer {}
+
+/// An error signifying that a transformer failed, which is never the client's fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransformFailed(pub String);
+
+impl std::fmt::Display for TransformFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "transforming the sheet failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for TransformFailed {}
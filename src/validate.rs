@@ -0,0 +1,116 @@
+//! External validation of writes. A sheet may have a validator registered, which is a URL that gets
+//! every proposed cell before it's written, and can decline it.
+//!
+//! The validator receives a `POST` with the cell as a JSON body (`{"sheet_id", "column", "row", "value"}`).
+//! Any 2xx response accepts the write, any 4xx response declines it (optionally explaining why with a
+//! `{"reason": "..."}` body), and anything else - including timeouts - counts as the validator being
+//! unavailable, which is handled according to the sheet's [`Validator::fail_open`] policy.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sheet::{Cell, CellValue};
+
+/// The validator timeout used when none is given.
+pub const DEFAULT_TIMEOUT_MS: u64 = 2000;
+/// Validators are on the write path, so they must answer quickly.
+pub const MAX_TIMEOUT_MS: u64 = 10_000;
+
+/// The validator of a single sheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Validator {
+    pub url: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Whether writes are accepted when the validator can't be reached. Defaults to rejecting them.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+fn default_timeout_ms() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+/// Why a write wasn't accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The validator declined the write.
+    Declined { reason: Option<String> },
+    /// The validator couldn't be reached, and the sheet fails closed.
+    Unavailable(String),
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Declined {
+                reason: Some(reason),
+            } => write!(f, "rejected by validator: {reason}"),
+            Self::Declined { reason: None } => write!(f, "rejected by validator"),
+            Self::Unavailable(why) => write!(f, "validator unavailable: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+#[derive(Serialize)]
+struct ProposedCell<'a> {
+    sheet_id: &'a str,
+    column: &'a str,
+    row: i64,
+    value: &'a CellValue,
+}
+
+#[derive(Deserialize)]
+struct DeclineBody {
+    reason: Option<String>,
+}
+
+impl Validator {
+    /// Asks the validator about a proposed write.
+    pub async fn check(
+        &self,
+        client: &reqwest::Client,
+        sheet_id: &str,
+        cell: &Cell,
+    ) -> Result<(), Rejection> {
+        let proposed = ProposedCell {
+            sheet_id,
+            column: &cell.column,
+            row: cell.row,
+            value: &cell.value,
+        };
+
+        let result = client
+            .post(&self.url)
+            .timeout(Duration::from_millis(self.timeout_ms))
+            .json(&proposed)
+            .send()
+            .await;
+
+        let unavailable = |why: String| {
+            if self.fail_open {
+                log::warn!("validator {} is unavailable, accepting write: {why}", self.url);
+                Ok(())
+            } else {
+                Err(Rejection::Unavailable(why))
+            }
+        };
+
+        match result {
+            Ok(resp) if resp.status().is_success() => Ok(()),
+            Ok(resp) if resp.status().is_client_error() => {
+                let reason = resp
+                    .json::<DeclineBody>()
+                    .await
+                    .ok()
+                    .and_then(|body| body.reason);
+                Err(Rejection::Declined { reason })
+            }
+            Ok(resp) => unavailable(format!("responded with {}", resp.status())),
+            Err(why) => unavailable(why.to_string()),
+        }
+    }
+}
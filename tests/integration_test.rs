@@ -40,8 +40,9 @@ impl Drop for KillOnDrop {
 #[tokio::test]
 async fn using_a_client() {
     let handle = KillOnDrop(
-        Command::new("cargo")
-            .arg("run")
+        // the binary cargo built for this test, since a nested `cargo run` sees a different environment and
+        // rebuilds dependencies whose build scripts depend on it, which takes longer than the startup wait below
+        Command::new(env!("CARGO_BIN_EXE_anchor_test"))
             .stdout(Stdio::null())
             .stdin(Stdio::null())
             .stderr(Stdio::null())